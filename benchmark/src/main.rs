@@ -17,9 +17,9 @@ fn get_commit_id() -> String {
         .trim()
         .to_string()
 }
-fn trace_image(tracer: &RayTracer, image: &mut ParallelImage) -> Duration {
+fn trace_image(tracer: &RayTracer, image: &mut ParallelImage, sample_index: u32) -> Duration {
     let start = Instant::now();
-    tracer.trace_image(image);
+    tracer.trace_image(image, sample_index);
     start.elapsed()
 }
 fn calculate_mean(elapsed: &[Duration]) -> Duration {
@@ -29,6 +29,17 @@ fn calculate_mean(elapsed: &[Duration]) -> Duration {
     }
     total / elapsed.len() as u32
 }
+fn build_bvh(tracer: &mut RayTracer) -> Duration {
+    let start = Instant::now();
+    tracer.rebuild_bvh();
+    start.elapsed()
+}
+/// scenarios to benchmark and the number of frames to render for each
+const SCENARIOS: [(&str, usize); 3] = [
+    ("Fast Oct Tree Sinnoh", 100),
+    ("Random Scene", 20),
+    ("Cube Field", 20),
+];
 fn main() {
     let mut write_file = File::options()
         .append(true)
@@ -37,27 +48,36 @@ fn main() {
         .expect("failed to open buffer");
     let commit_id = get_commit_id();
     println!("commit id: \"{}\"", commit_id);
-    let rt = RayTracer::builder().set_scenario("Fast Oct Tree Sinnoh".to_string());
+    for (scenario, num_runs) in SCENARIOS {
+        println!("scenario: \"{}\"", scenario);
+        let rt = RayTracer::builder().set_scenario(scenario.to_string());
 
-    let start = Instant::now();
-    let rt = rt.build();
-    let construct_elapsed = start.elapsed();
-    println!("elapsed time: {}ms", construct_elapsed.as_millis());
-    let mut parallel = ParallelImage::new_black(1024, 1024);
-    let num_runs = 100;
-    let mut elapsed_array = Vec::with_capacity(num_runs);
-    for _ in 0..num_runs {
-        let rendering_elapsed = trace_image(&rt, &mut parallel);
-        elapsed_array.push(rendering_elapsed);
+        let start = Instant::now();
+        let mut rt = rt.build();
+        let construct_elapsed = start.elapsed();
+        println!("elapsed time: {}ms", construct_elapsed.as_millis());
+
+        let bvh_elapsed = (0..10).map(|_| build_bvh(&mut rt)).collect::<Vec<_>>();
+        let mean_bvh = calculate_mean(&bvh_elapsed);
+        println!("bvh build time: {}us", mean_bvh.as_micros());
+
+        let mut parallel = ParallelImage::new_black(1024, 1024);
+        let mut elapsed_array = Vec::with_capacity(num_runs);
+        for run in 0..num_runs {
+            let rendering_elapsed = trace_image(&rt, &mut parallel, run as u32);
+            elapsed_array.push(rendering_elapsed);
+        }
+        let mean_rendering = calculate_mean(&elapsed_array);
+        println!("frame render time: {}", mean_rendering.as_millis());
+        write!(
+            write_file,
+            "\n{}\t{}\t{}\t{}\t{}",
+            commit_id,
+            scenario,
+            construct_elapsed.as_millis(),
+            mean_bvh.as_micros(),
+            mean_rendering.as_millis()
+        )
+        .expect("failed to write log");
     }
-    let mean_rendering = calculate_mean(&elapsed_array);
-    println!("frame render time: {}", mean_rendering.as_millis());
-    write!(
-        write_file,
-        "\n{}\t{}\t{}",
-        commit_id,
-        construct_elapsed.as_millis(),
-        mean_rendering.as_millis()
-    )
-    .expect("failed to write log");
 }
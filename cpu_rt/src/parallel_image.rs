@@ -3,16 +3,17 @@ use cgmath::Point2;
 use crate::{
     prelude::*,
     ray_tracer::{
+        filter::Filter,
         ray_tracer_info::{EntityField, RayTracerInfo},
+        tile_scheduler::{Tile, TileProgress, TileScheduler},
         RayTracer,
     },
 };
 use std::{
-    collections::HashMap,
     path::Path,
     sync::{
-        mpsc::{channel, Receiver, Sender},
-        Arc, Mutex, RwLock,
+        mpsc::{Receiver, Sender, TryRecvError},
+        Arc,
     },
 };
 
@@ -100,77 +101,50 @@ impl ParallelImage {
     pub(crate) fn width(&self) -> usize {
         self.width
     }
-    pub(crate) fn split(&self, num_parts: usize) -> Vec<ParallelImagePart> {
-        let slice_width = self.width() / num_parts;
-        let mut out_images = Vec::with_capacity(num_parts);
-        out_images.reserve(num_parts);
-        for slice in 0..num_parts {
-            let slice_start = slice_width * slice;
-            let slice_end = (slice_start + slice_width).min(self.width());
-            let mut buffer = Vec::with_capacity((slice_end - slice_start) * self.height);
-
-            for y in 0..self.height {
-                for x in slice_start..slice_end {
-                    buffer.push(self.buffer[self.get_idx(x, y)]);
-                }
-            }
-            out_images.push(ParallelImagePart {
-                buffer,
-                width: slice_end - slice_start,
-                height: self.height(),
-                total_width: self.width,
-                offset: Point2::new(slice_start, 0),
-            });
-        }
-        out_images
-    }
-    fn join_container(mut images: Vec<&PartContainer>) -> Self {
-        assert!(!images.is_empty());
-        images.sort_by(|img1, img2| {
-            img1.image
-                .offset
-                .x
-                .partial_cmp(&img2.image.offset.x)
-                .unwrap()
-        });
-        let last = &images.last().as_ref().unwrap().image;
-        let width = last.offset.x + last.width;
-        let mut buffer = vec![RgbColor::BLACK; width * last.height];
-        for img in images.iter() {
-            let image = &img.image;
-            for x in 0..image.width {
-                for y in 0..image.height {
-                    let idx = Self::get_idx_no_self(width, x + img.image.offset.x, y);
-                    buffer[idx] = image.get_xy(x + img.image.offset.x, y + img.image.offset.y)
-                        / img.num_samples as f32;
+    /// Resolves the splatted samples of the parts into an image, each pixel is the sum of the
+    /// weighted samples divided by the sum of the weights. Pixels without samples are black, as
+    /// are pixels where the negative lobes of the filter cancel out most of the weight
+    pub(crate) fn from_parts<'a>(
+        parts: impl Iterator<Item = &'a ParallelImagePart>,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let mut image = Self::new_black(width, height);
+        let mut weights = vec![0.0f32; width * height];
+        let mut absolute_weights = vec![0.0f32; width * height];
+        for part in parts {
+            let region = part.region;
+            for y in region.offset.y..region.offset.y + region.height {
+                for x in region.offset.x..region.offset.x + region.width {
+                    image.add_xy(x, y, part.get_xy(x, y));
+                    let idx = Self::get_idx_no_self(width, x, y);
+                    weights[idx] += part.get_weight(x, y);
+                    absolute_weights[idx] += part.get_absolute_weight(x, y);
                 }
             }
         }
-        Self {
-            buffer,
-            width,
-            height: last.height,
+        for ((color, weight), absolute_weight) in image
+            .buffer
+            .iter_mut()
+            .zip(weights.iter())
+            .zip(absolute_weights.iter())
+        {
+            *color = if *weight > MIN_RELATIVE_WEIGHT * *absolute_weight && *weight > 0.0 {
+                *color / *weight
+            } else {
+                RgbColor::BLACK
+            };
         }
+        image
     }
-    pub(crate) fn join(mut images: Vec<&ParallelImagePart>) -> Self {
+    fn join_container(images: Vec<&PartContainer>) -> Self {
         assert!(!images.is_empty());
-        images.sort_by(|img1, img2| img1.offset.x.partial_cmp(&img2.offset.x).unwrap());
-        let last = images.last().unwrap();
-        let width = last.offset.x + last.width;
-        let mut buffer = vec![RgbColor::BLACK; width * last.height];
-        for img in images.iter() {
-            for x in 0..img.width {
-                for y in 0..img.height {
-                    let idx = Self::get_idx_no_self(width, x + img.offset.x, y);
-                    buffer[idx] = img.get_xy(x + img.offset.x, y + img.offset.y);
-                }
-            }
-        }
-        Self {
-            buffer,
-            width,
-            height: last.height,
-        }
+        let first = &images[0].image;
+        Self::from_parts(
+            images.iter().map(|container| &container.image),
+            first.total_width,
+            first.total_height,
+        )
     }
     pub fn get_xy(&self, x: usize, y: usize) -> RgbColor {
         self.buffer[self.get_idx(x, y)]
@@ -276,189 +250,257 @@ impl std::ops::Div<f32> for ParallelImage {
         }
     }
 }
+/// Pixels whose summed filter weight is below this fraction of the summed magnitudes are black,
+/// dividing by a weight that the negative filter lobes almost cancel out blows up the noise
+const MIN_RELATIVE_WEIGHT: f32 = 0.05;
+/// Samples taken in one tile of the image. The samples are splatted with the reconstruction
+/// filter into a region that reaches past the tile by the filter's margin so neighboring tiles
+/// overlap
 #[derive(Clone)]
 pub struct ParallelImagePart {
+    /// sum of the filter weighted samples of each pixel of the region
     buffer: Vec<RgbColor>,
-    width: usize,
-    height: usize,
+    /// sum of the filter weights of each pixel of the region
+    weights: Vec<f32>,
+    /// sum of the magnitudes of the filter weights of each pixel of the region
+    absolute_weights: Vec<f32>,
+    /// pixels that the samples are taken in
+    tile: Tile,
+    /// pixels that the samples are splatted into, clamped to the image
+    region: Tile,
     total_width: usize,
-    offset: Point2<usize>,
+    total_height: usize,
 }
 impl ParallelImagePart {
+    /// Creates an empty part covering the tile of an image with the given size, samples reach
+    /// `margin` pixels past the tile
+    pub(crate) fn from_tile(
+        tile: &Tile,
+        total_width: usize,
+        total_height: usize,
+        margin: usize,
+    ) -> Self {
+        let start = Point2::new(
+            tile.offset.x.saturating_sub(margin),
+            tile.offset.y.saturating_sub(margin),
+        );
+        let end = Point2::new(
+            (tile.offset.x + tile.width + margin).min(total_width),
+            (tile.offset.y + tile.height + margin).min(total_height),
+        );
+        let region = Tile {
+            offset: start,
+            width: end.x - start.x,
+            height: end.y - start.y,
+        };
+        Self {
+            buffer: vec![RgbColor::BLACK; region.width * region.height],
+            weights: vec![0.0; region.width * region.height],
+            absolute_weights: vec![0.0; region.width * region.height],
+            tile: *tile,
+            region,
+            total_width,
+            total_height,
+        }
+    }
     pub(crate) fn width(&self) -> usize {
-        self.width
+        self.tile.width
     }
     pub(crate) fn height(&self) -> usize {
-        self.height
+        self.tile.height
     }
     pub(crate) fn offset(&self) -> Point2<usize> {
-        self.offset
+        self.tile.offset
     }
     fn get_idx(&self, x: usize, y: usize) -> usize {
-        assert!(x >= self.offset.x);
-        if x >= self.offset.x + self.width {
-            info!(
-                "x: {}, self.offset.x: {}, self.width: {}",
-                x, self.offset.x, self.width
-            );
-        }
-
-        assert!(x < self.offset.x + self.width);
-
-        assert!(y < self.height);
-        (x - self.offset.x) + y * self.width
-    }
-    pub(crate) fn set_black(&mut self) {
-        for pixel in self.buffer.iter_mut() {
-            *pixel = RgbColor::BLACK;
-        }
-    }
-    /// gets with offset
+        let region = self.region;
+        assert!(x >= region.offset.x);
+        assert!(x < region.offset.x + region.width);
+        assert!(y >= region.offset.y);
+        assert!(y < region.offset.y + region.height);
+        (x - region.offset.x) + (y - region.offset.y) * region.width
+    }
+    /// gets the weighted sum with offset
     pub(crate) fn get_xy(&self, x: usize, y: usize) -> RgbColor {
         self.buffer[self.get_idx(x, y)]
     }
+    /// gets the sum of the weights with offset
+    pub(crate) fn get_weight(&self, x: usize, y: usize) -> f32 {
+        self.weights[self.get_idx(x, y)]
+    }
+    /// gets the sum of the magnitudes of the weights with offset
+    pub(crate) fn get_absolute_weight(&self, x: usize, y: usize) -> f32 {
+        self.absolute_weights[self.get_idx(x, y)]
+    }
     pub(crate) fn total_width(&self) -> usize {
         self.total_width
     }
     pub(crate) fn total_height(&self) -> usize {
-        self.height
-    }
-
-    ///adds with offset
-    pub fn add_xy(&mut self, x: usize, y: usize, color: RgbColor) {
-        let idx = self.get_idx(x, y);
-        self.buffer[idx] += color;
-    }
-}
-
-pub(crate) struct ImageReceiver {
-    receiver: Receiver<ParallelImagePart>,
-    num_items: Arc<Mutex<usize>>,
-}
-impl ImageSender {
-    const MAX_ITEMS: usize = 10;
-    pub(crate) fn send(&mut self, image: ParallelImagePart) {
-        let mut num_items = self.num_items.lock().expect("failed to get num items");
-        if *num_items < Self::MAX_ITEMS {
-            self.sender.send(image).expect("failed to send image");
-            *num_items += 1;
+        self.total_height
+    }
+    /// adds the samples of a part covering the same area
+    pub(crate) fn add_part(&mut self, other: &Self) {
+        assert_eq!(self.tile, other.tile);
+        assert_eq!(self.region, other.region);
+        for (pixel, other) in self.buffer.iter_mut().zip(other.buffer.iter()) {
+            *pixel += *other;
+        }
+        for (weight, other) in self.weights.iter_mut().zip(other.weights.iter()) {
+            *weight += *other;
+        }
+        for (weight, other) in self
+            .absolute_weights
+            .iter_mut()
+            .zip(other.absolute_weights.iter())
+        {
+            *weight += *other;
         }
     }
-}
-pub(crate) struct ImageSender {
-    sender: Sender<ParallelImagePart>,
-    num_items: Arc<Mutex<usize>>,
-}
-pub(crate) fn image_channel() -> (ImageSender, ImageReceiver) {
-    let (sender, receiver) = channel();
-    let num_items = Arc::new(Mutex::new(0));
-    (
-        ImageSender {
-            sender,
-            num_items: num_items.clone(),
-        },
-        ImageReceiver {
-            receiver,
-            num_items,
-        },
-    )
-}
-
-impl ImageReceiver {
-    pub(crate) fn try_recv(&mut self) -> Option<ParallelImagePart> {
-        let mut num_items = self.num_items.lock().expect("failed to get num items");
-        let parallel_image_opt = self.receiver.try_recv();
-        match parallel_image_opt {
-            Ok(img) => {
-                *num_items -= 1;
-                Some(img)
+    /// Adds a sample at `(x, y)` in pixel coordinates to every pixel in the filter's radius, the
+    /// center of pixel `(0, 0)` is at `(0.5, 0.5)`
+    pub fn add_sample(&mut self, x: RayScalar, y: RayScalar, color: RgbColor, filter: &Filter) {
+        let radius = filter.radius() as RayScalar;
+        let region = self.region;
+        let x_start = ((x - 0.5 - radius).ceil().max(0.0) as usize).max(region.offset.x);
+        let y_start = ((y - 0.5 - radius).ceil().max(0.0) as usize).max(region.offset.y);
+        let x_end = ((x - 0.5 + radius).floor().max(-1.0) + 1.0) as usize;
+        let y_end = ((y - 0.5 + radius).floor().max(-1.0) + 1.0) as usize;
+        for py in y_start..y_end.min(region.offset.y + region.height) {
+            for px in x_start..x_end.min(region.offset.x + region.width) {
+                let weight = filter.evaluate(
+                    (x - (px as RayScalar + 0.5)) as f32,
+                    (y - (py as RayScalar + 0.5)) as f32,
+                );
+                if weight == 0.0 {
+                    continue;
+                }
+                let idx = self.get_idx(px, py);
+                self.buffer[idx] += weight * color;
+                self.weights[idx] += weight;
+                self.absolute_weights[idx] += weight.abs();
             }
-            Err(e) => match e {
-                std::sync::mpsc::TryRecvError::Disconnected => panic!("channel disconnected"),
-                std::sync::mpsc::TryRecvError::Empty => None,
-            },
         }
     }
 }
+
 #[allow(dead_code)]
 pub(crate) enum RayTracerMessage {
-    SetShader(super::ray_tracer::CurrentShader),
+    /// new state to render, parts rendered from older generations are discarded
+    Snapshot {
+        ray_tracer: Arc<RayTracer>,
+        generation: usize,
+    },
     StopRendering,
     ContinueRendering,
-    SceneChanged,
-    SetCameraData((String, EntityField)),
 }
 struct PartContainer {
     image: ParallelImagePart,
     num_samples: usize,
 }
+/// one sample of a tile, sent from the render threads
+pub(crate) struct RenderedTile {
+    /// generation of the snapshot the tile was rendered from
+    pub generation: usize,
+    pub tile_index: usize,
+    pub image: ParallelImagePart,
+}
 pub struct ParallelImageCollector {
-    receivers: Vec<ImageReceiver>,
+    receiver: Receiver<RenderedTile>,
     message_senders: Vec<Sender<RayTracerMessage>>,
-    images: HashMap<Point2<usize>, PartContainer>,
-    ray_tracer: Arc<RwLock<RayTracer>>,
+    /// sum of the samples received for each tile
+    images: Vec<Option<PartContainer>>,
+    scheduler: Arc<TileScheduler>,
+    /// edits are made here and then sent to the render threads as a new snapshot
+    ray_tracer: RayTracer,
+    generation: usize,
 }
 impl ParallelImageCollector {
     pub(crate) fn new(
-        receivers: Vec<ImageReceiver>,
+        receiver: Receiver<RenderedTile>,
         message_senders: Vec<Sender<RayTracerMessage>>,
-        ray_tracer: Arc<RwLock<RayTracer>>,
+        scheduler: Arc<TileScheduler>,
+        ray_tracer: RayTracer,
     ) -> Self {
         Self {
-            receivers,
+            receiver,
             message_senders,
-            images: HashMap::new(),
+            images: (0..scheduler.tiles().len()).map(|_| None).collect(),
+            scheduler,
             ray_tracer,
+            generation: 0,
         }
     }
     /// resets rendering image to base state
     pub(crate) fn clear(&mut self) {
-        self.images.clear();
+        for image in self.images.iter_mut() {
+            *image = None;
+        }
+    }
+    /// sends the current state to the render threads and clears the image
+    fn publish(&mut self) {
+        self.generation += 1;
+        self.clear();
+        // workers take tiles as soon as they get the snapshot, so the new pass has to start first
+        self.scheduler.reset();
+        let ray_tracer = Arc::new(self.ray_tracer.clone());
+        for sender in self.message_senders.iter() {
+            sender
+                .send(RayTracerMessage::Snapshot {
+                    ray_tracer: ray_tracer.clone(),
+                    generation: self.generation,
+                })
+                .map_err(|e| error!("failed to send snapshot, reason: {}", e))
+                .unwrap();
+        }
     }
     pub fn receive(&mut self) -> Option<ParallelImage> {
-        for recv in self.receivers.iter_mut() {
-            while let Some(image) = recv.try_recv() {
-                let num_samples = self
-                    .images
-                    .get(&image.offset())
-                    .map(|img| img.num_samples + 1)
-                    .unwrap_or(1);
-
-                self.images
-                    .insert(image.offset, PartContainer { image, num_samples });
+        loop {
+            let rendered = match self.receiver.try_recv() {
+                Ok(rendered) => rendered,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => panic!("channel disconnected"),
+            };
+            if rendered.generation != self.generation {
+                continue;
+            }
+            match self.images[rendered.tile_index].as_mut() {
+                Some(container) => {
+                    container.image.add_part(&rendered.image);
+                    container.num_samples += 1;
+                }
+                None => {
+                    self.images[rendered.tile_index] = Some(PartContainer {
+                        image: rendered.image,
+                        num_samples: 1,
+                    })
+                }
             }
         }
-        if !self.images.is_empty() {
-            Some(ParallelImage::join_container(
-                self.images.values().collect(),
-            ))
+        let images = self.images.iter().flatten().collect::<Vec<_>>();
+        if !images.is_empty() {
+            Some(ParallelImage::join_container(images))
         } else {
             None
         }
     }
+    /// gets the number of samples rendered in each tile
+    pub fn tile_progress(&self) -> Vec<TileProgress> {
+        self.scheduler
+            .tiles()
+            .iter()
+            .zip(self.images.iter())
+            .enumerate()
+            .map(|(tile_index, (tile, image))| TileProgress {
+                tile: *tile,
+                num_samples: image.as_ref().map(|image| image.num_samples).unwrap_or(0),
+                active: self.scheduler.is_active(tile_index),
+            })
+            .collect()
+    }
     pub fn load_scenario(&mut self, name: String) {
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::StopRendering)
-                .map_err(|e| error!("failed to send stop rendering message, reason: {}", e))
-                .unwrap();
-        });
         info!("loading scenario");
-        self.clear();
-
-        {
-            let mut write_lock = self.ray_tracer.write().expect("failed to read");
-            write_lock.load_scenario(name.clone());
-        }
-        for sender in self.message_senders.iter() {
-            sender
-                .send(RayTracerMessage::SceneChanged)
-                .expect("failed to send");
-        }
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::ContinueRendering)
-                .map_err(|e| error!("failed to send continue rendering message, reason: {}", e))
-                .unwrap();
-        });
+        self.ray_tracer.load_scenario(name);
+        self.publish();
     }
     pub fn save_file<P: AsRef<Path>>(&mut self, p: P) {
         if let Some(img) = self.receive() {
@@ -475,87 +517,51 @@ impl ParallelImageCollector {
         }
     }
     pub fn set_shader(&mut self, s: super::ray_tracer::CurrentShader) {
-        {
-            let mut write_lock = self.ray_tracer.write().expect("failed to read");
-            write_lock.set_shader(s);
-        }
-        for sender in self.message_senders.iter() {
-            sender
-                .send(RayTracerMessage::SetShader(s))
-                .expect("failed to send shader")
-        }
+        self.ray_tracer.set_shader(s);
+        self.publish();
+    }
+    pub fn set_sampler(&mut self, sampler_kind: SamplerKind) {
+        self.ray_tracer.set_sampler(sampler_kind);
+        self.publish();
+    }
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.ray_tracer.set_filter(filter);
+        self.publish();
     }
     /// saves current scene to file
     pub fn save_scene(&mut self, path: std::path::PathBuf) {
-        let write_lock = self.ray_tracer.read().expect("failed to read");
-        write_lock.save_scene(path)
+        self.ray_tracer.save_scene(path)
     }
     /// Loads scene from file
     pub fn load_scene(&mut self, path: std::path::PathBuf) {
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::StopRendering)
-                .map_err(|e| error!("failed to send stop rendering message, reason: {}", e))
-                .unwrap();
-        });
-        self.clear();
-        {
-            let mut write_lock = self.ray_tracer.write().expect("failed to read");
-            *write_lock = RayTracer::load_scene(path);
+        match RayTracer::load_scene(path) {
+            Ok(ray_tracer) => {
+                self.ray_tracer = ray_tracer;
+                self.publish();
+            }
+            Err(e) => error!("failed to load scene reason: {:?}", e),
         }
-
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::ContinueRendering)
-                .map_err(|e| error!("failed to send continue rendering message, reason: {}", e))
-                .unwrap();
-        });
     }
     pub fn set_camera_data(&mut self, key: String, value: EntityField) {
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::StopRendering)
-                .map_err(|e| error!("failed to send stop rendering message, reason: {}", e))
-                .unwrap();
-        });
-        info!("told ray tracers to stop");
-        self.clear();
-
-        {
-            let mut write_lock = self.ray_tracer.write().expect("failed to read");
-            info!("got write lock for ray tracer now setting data");
-            write_lock.set_camera_data(key.clone(), value.clone());
-        }
-        for sender in self.message_senders.iter() {
-            sender
-                .send(RayTracerMessage::SetCameraData((
-                    key.clone(),
-                    value.clone(),
-                )))
-                .expect("failed to send data");
+        self.ray_tracer.set_camera_data(key, value);
+        self.publish();
+    }
+    /// focuses the camera on the center of the screen
+    pub fn autofocus(&mut self) {
+        match self.ray_tracer.autofocus(0.5, 0.5) {
+            Some(focus_distance) => {
+                info!("focus distance set to {}", focus_distance);
+                self.publish();
+            }
+            None => info!("nothing to focus on"),
         }
     }
     pub fn get_info(&self) -> RayTracerInfo {
         info!("getting ray tracer info");
-        let read_lock = self.ray_tracer.read().expect("failed to get read lock");
-        read_lock.get_info()
+        self.ray_tracer.get_info()
     }
     pub fn set_object_data(&mut self, entity_index: usize, key: String, value: EntityField) {
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::StopRendering)
-                .map_err(|e| error!("failed to send stop rendering message, reason: {}", e))
-                .unwrap();
-        });
-        info!("told ray tracers to stop");
-        self.clear();
-
-        {
-            let mut write_lock = self.ray_tracer.write().expect("failed to read");
-            info!("got write lock for ray tracer now setting data");
-            write_lock.set_entity_data(entity_index, key, value);
-        }
-
-        self.message_senders.iter_mut().for_each(|s| {
-            s.send(RayTracerMessage::ContinueRendering)
-                .map_err(|e| error!("failed to send continue rendering message, reason: {}", e))
-                .unwrap();
-        });
+        self.ray_tracer.set_entity_data(entity_index, key, value);
+        self.publish();
     }
 }
@@ -1,10 +1,12 @@
-pub(crate) use super::parallel_image::{image_channel, ParallelImagePart, RayTracerMessage};
 pub use super::parallel_image::{ParallelImage, ParallelImageCollector};
+pub(crate) use super::parallel_image::{ParallelImagePart, RayTracerMessage, RenderedTile};
 pub use cgmath;
-use cgmath::{num_traits::FloatConst, prelude::*};
+use cgmath::prelude::*;
 pub use rgb_color::RgbColor;
+pub use sampler::{Sampler, SamplerKind};
 use std::iter::Iterator;
 mod rgb_color;
+mod sampler;
 pub(crate) use cgmath::{Point3, Vector3};
 pub use log::{error, info, warn};
 pub fn clamp<T: std::cmp::PartialOrd>(x: T, min: T, max: T) -> T {
@@ -27,6 +29,7 @@ impl IterBox {
             end,
         }
     }
+    #[cfg(test)]
     pub(crate) fn from_xyz(x: u32, y: u32, z: u32) -> Self {
         Self::from_point(Point3 { x, y, z })
     }
@@ -36,9 +39,6 @@ impl IterBox {
             end: self.end,
         }
     }
-    pub(crate) fn start_xyz(self, x: u32, y: u32, z: u32) -> Self {
-        self.start(Point3 { x, y, z })
-    }
     pub(crate) fn iter(&self) -> BoxIter {
         BoxIter {
             current_point: self.start,
@@ -118,6 +118,10 @@ impl OrthoNormalBasis {
     pub fn local(&self, a: Vector3<RayScalar>) -> Vector3<RayScalar> {
         a.x * self.u() + a.y * self.v() + a.z * self.w()
     }
+    /// coordinates of the vector along u, v and w, the inverse of `local`
+    pub fn coordinates(&self, a: Vector3<RayScalar>) -> Vector3<RayScalar> {
+        Vector3::new(a.dot(self.u()), a.dot(self.v()), a.dot(self.w()))
+    }
     pub fn u(&self) -> Vector3<RayScalar> {
         self.axis[0]
     }
@@ -142,15 +146,6 @@ impl std::ops::IndexMut<usize> for OrthoNormalBasis {
         &mut self.axis[index]
     }
 }
-pub fn random_cosine_direction() -> Vector3<RayScalar> {
-    let r1 = rand_scalar(0.0, 1.0);
-    let r2 = rand_scalar(0.0, 1.0);
-    let z = (1.0 - r2).sqrt();
-    let phi = 2.0 * RayScalar::PI() * r1;
-    let x = phi.cos() * r2.sqrt();
-    let y = phi.sin() * r2.sqrt();
-    Vector3 { x, y, z }
-}
 
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
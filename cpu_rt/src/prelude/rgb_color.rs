@@ -1,4 +1,4 @@
-use super::clamp;
+use super::{clamp, Sampler};
 use std::ops::{Add, AddAssign, Div, Mul, Sub};
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RgbColor {
@@ -44,11 +44,11 @@ impl RgbColor {
     pub fn new(red: f32, green: f32, blue: f32) -> Self {
         Self { red, green, blue }
     }
-    pub fn random() -> Self {
+    pub fn random(sampler: &mut Sampler) -> Self {
         Self {
-            red: rand::random(),
-            green: rand::random(),
-            blue: rand::random(),
+            red: sampler.next_scalar() as f32,
+            green: sampler.next_scalar() as f32,
+            blue: sampler.next_scalar() as f32,
         }
     }
     pub fn pow(&self, p: f32) -> Self {
@@ -0,0 +1,295 @@
+mod blue_noise;
+mod halton;
+mod sobol;
+
+use super::RayScalar;
+use cgmath::{num_traits::FloatConst, Vector3};
+
+/// How the numbers of a sampler are picked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// independent uniform random numbers
+    Independent,
+    /// every 16 samples of a pixel cover each sixteenth of every dimension once
+    Stratified,
+    /// Halton sequence with the digits scrambled differently for each pixel
+    Halton,
+    /// Owen scrambled Sobol sequence, scrambled differently for each pixel
+    Sobol,
+    /// Sobol sequence offset by a blue noise mask so the error of neighboring pixels cancels out
+    BlueNoise,
+}
+impl SamplerKind {
+    pub fn names() -> [String; 5] {
+        [
+            "Independent".to_string(),
+            "Stratified".to_string(),
+            "Halton".to_string(),
+            "Sobol".to_string(),
+            "Blue Noise".to_string(),
+        ]
+    }
+}
+impl std::str::FromStr for SamplerKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Independent" => Ok(Self::Independent),
+            "Stratified" => Ok(Self::Stratified),
+            "Halton" => Ok(Self::Halton),
+            "Sobol" => Ok(Self::Sobol),
+            "Blue Noise" => Ok(Self::BlueNoise),
+            _ => Err(format!("invalid name: {}", s)),
+        }
+    }
+}
+/// Deterministic random numbers. Each number is hashed from the seed, the pixel, the sample index
+/// and the dimension (how many numbers were drawn before it), so rendering the same scene with
+/// the same seed gives the same image
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    kind: SamplerKind,
+    /// hash of the seed
+    seed: u64,
+    /// hash of the seed and pixel
+    pixel: u64,
+    /// hash of the seed, pixel and sample index
+    base: u64,
+    x: u32,
+    y: u32,
+    sample_index: u32,
+    dimension: u64,
+}
+impl Sampler {
+    /// number of strata in each dimension used by `SamplerKind::Stratified`
+    const STRATA: u32 = 16;
+    /// creates a sampler that is not tied to a pixel, used for building scenes and tools
+    pub fn new(seed: u64) -> Self {
+        Self::for_pixel(SamplerKind::Independent, seed, 0, 0, 0)
+    }
+    /// creates the sampler used for one sample of a pixel
+    pub fn for_pixel(kind: SamplerKind, seed: u64, x: u32, y: u32, sample_index: u32) -> Self {
+        let seed = hash(seed);
+        let pixel = hash(seed ^ ((x as u64) | ((y as u64) << 32)));
+        Self {
+            kind,
+            seed,
+            pixel,
+            base: hash(pixel ^ sample_index as u64),
+            x,
+            y,
+            sample_index,
+            dimension: 0,
+        }
+    }
+    /// number of values drawn so far
+    pub fn dimension(&self) -> u64 {
+        self.dimension
+    }
+    /// Moves ahead to the given dimension so that parts of a path always use the same dimensions
+    /// of the low discrepancy sequences. Does nothing if the dimension was already used so values
+    /// are never reused
+    pub fn skip_to(&mut self, dimension: u64) {
+        self.dimension = self.dimension.max(dimension);
+    }
+    /// independent random value for the dimension
+    fn random(&self, dimension: u64) -> RayScalar {
+        to_scalar(hash(self.base ^ hash(dimension)))
+    }
+    fn stratified(&self, dimension: u64) -> RayScalar {
+        let block = (self.sample_index / Self::STRATA) as u64;
+        let seed = hash(self.pixel ^ hash(dimension) ^ (block << 32)) as u32;
+        // the permutation only mixes the low bits with each other so it shuffles the strata
+        let stratum =
+            sobol::laine_karras_permutation(self.sample_index % Self::STRATA, seed) % Self::STRATA;
+        (stratum as RayScalar + self.random(dimension)) / Self::STRATA as RayScalar
+    }
+    fn halton(&self, dimension: u64) -> RayScalar {
+        match halton::PRIMES.get(dimension as usize) {
+            Some(base) => halton::scrambled_radical_inverse(
+                *base,
+                self.sample_index,
+                hash(self.pixel ^ hash(dimension)),
+            ),
+            None => self.random(dimension),
+        }
+    }
+    fn sobol(&self, dimension: u64) -> RayScalar {
+        sobol::sample(self.sample_index, dimension, self.pixel) as RayScalar / 2.0f64.powi(32)
+    }
+    fn blue_noise(&self, dimension: u64) -> RayScalar {
+        // every pixel uses the same sequence, the mask is shifted for each dimension so the
+        // dimensions are not correlated
+        let value =
+            sobol::sample(self.sample_index, dimension, self.seed) as RayScalar / 2.0f64.powi(32);
+        let shift = hash(self.seed ^ hash(dimension));
+        let offset = blue_noise::value(
+            self.x.wrapping_add(shift as u32),
+            self.y.wrapping_add((shift >> 32) as u32),
+        );
+        (value + offset).fract()
+    }
+    /// gets a value in [0, 1)
+    pub fn next_scalar(&mut self) -> RayScalar {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.kind {
+            SamplerKind::Independent => self.random(dimension),
+            SamplerKind::Stratified => self.stratified(dimension),
+            SamplerKind::Halton => self.halton(dimension),
+            SamplerKind::Sobol => self.sobol(dimension),
+            SamplerKind::BlueNoise => self.blue_noise(dimension),
+        }
+    }
+    /// gets a value in [min, max)
+    pub fn scalar(&mut self, min: RayScalar, max: RayScalar) -> RayScalar {
+        self.next_scalar() * (max - min) + min
+    }
+    /// gets a value in [min, max), or min if the range is empty
+    pub fn u32(&mut self, min: u32, max: u32) -> u32 {
+        // the dimension is used up either way so later dimensions do not depend on the range
+        let value = self.next_scalar();
+        let range = max.saturating_sub(min);
+        if range == 0 {
+            return min;
+        }
+        ((value * range as RayScalar) as u32).min(range - 1) + min
+    }
+    /// vector with all components in [0, 1)
+    pub fn vec(&mut self) -> Vector3<RayScalar> {
+        Vector3::new(self.next_scalar(), self.next_scalar(), self.next_scalar())
+    }
+    /// random point inside of the unit sphere
+    pub fn unit_vec(&mut self) -> Vector3<RayScalar> {
+        let z = 1.0 - 2.0 * self.next_scalar();
+        let phi = 2.0 * RayScalar::PI() * self.next_scalar();
+        let radius = self.next_scalar().cbrt();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        radius * Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    /// random direction with all directions equally likely
+    pub fn unit_sphere(&mut self) -> Vector3<RayScalar> {
+        let z = 1.0 - 2.0 * self.next_scalar();
+        let phi = 2.0 * RayScalar::PI() * self.next_scalar();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    /// random point inside of the unit circle on the xy plane, uses the concentric mapping so
+    /// that nearby values stay nearby on the disk
+    pub fn unit_disk(&mut self) -> Vector3<RayScalar> {
+        let a = 2.0 * self.next_scalar() - 1.0;
+        let b = 2.0 * self.next_scalar() - 1.0;
+        if a == 0.0 && b == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, RayScalar::FRAC_PI_4() * (b / a))
+        } else {
+            (b, RayScalar::FRAC_PI_2() - RayScalar::FRAC_PI_4() * (a / b))
+        };
+        Vector3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+    /// direction on the hemisphere around +z with a cosine distribution
+    pub fn cosine_direction(&mut self) -> Vector3<RayScalar> {
+        let r1 = self.next_scalar();
+        let r2 = self.next_scalar();
+        let z = (1.0 - r2).sqrt();
+        let phi = 2.0 * RayScalar::PI() * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        Vector3 { x, y, z }
+    }
+}
+/// the top 53 bits fill the mantissa of an f64
+fn to_scalar(value: u64) -> RayScalar {
+    (value >> 11) as RayScalar * (1.0 / (1u64 << 53) as RayScalar)
+}
+/// splitmix64 finalizer
+fn hash(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    const KINDS: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+    #[test]
+    fn reproducible() {
+        for kind in KINDS {
+            let mut a = Sampler::for_pixel(kind, 1, 10, 20, 3);
+            let mut b = Sampler::for_pixel(kind, 1, 10, 20, 3);
+            for _ in 0..100 {
+                assert_eq!(a.next_scalar(), b.next_scalar());
+            }
+            let mut c = Sampler::for_pixel(kind, 1, 10, 20, 4);
+            let mut d = Sampler::for_pixel(kind, 2, 10, 20, 3);
+            let first = Sampler::for_pixel(kind, 1, 10, 20, 3).next_scalar();
+            assert_ne!(first, c.next_scalar());
+            assert_ne!(first, d.next_scalar());
+        }
+    }
+    #[test]
+    fn uniform() {
+        for kind in KINDS {
+            let num_samples = 4096;
+            let mut sum = 0.0;
+            for i in 0..num_samples {
+                let mut sampler = Sampler::for_pixel(kind, 0, i % 7, i % 5, i);
+                sampler.skip_to(i as u64 % 100);
+                let value = sampler.next_scalar();
+                assert!((0.0..1.0).contains(&value));
+                sum += value;
+                assert!((3..7).contains(&sampler.u32(3, 7)));
+            }
+            assert!((sum / num_samples as RayScalar - 0.5).abs() < 0.02);
+        }
+        let mut sampler = Sampler::new(0);
+        for _ in 0..10 {
+            sampler.next_scalar();
+        }
+        sampler.skip_to(5);
+        assert_eq!(sampler.dimension(), 10);
+    }
+    #[test]
+    fn empty_range() {
+        let mut sampler = Sampler::new(0);
+        assert_eq!(sampler.u32(4, 4), 4);
+        assert_eq!(sampler.u32(0, 0), 0);
+        assert_eq!(sampler.dimension(), 2);
+    }
+    /// the low discrepancy samplers should estimate an integral with less error than
+    /// independent random numbers
+    #[test]
+    fn faster_convergence() {
+        let error = |kind: SamplerKind| {
+            let num_samples = 16;
+            let mut total_error = 0.0;
+            for x in 0..16 {
+                for y in 0..16 {
+                    let mut sum = 0.0;
+                    for i in 0..num_samples {
+                        let mut sampler = Sampler::for_pixel(kind, 0, x, y, i);
+                        sampler.skip_to(2);
+                        let (u, v) = (sampler.next_scalar(), sampler.next_scalar());
+                        sum += u * v + (u * 4.0).sin();
+                    }
+                    let exact = 0.25 + (1.0 - (4.0 as RayScalar).cos()) / 4.0;
+                    total_error += (sum / num_samples as RayScalar - exact).powi(2);
+                }
+            }
+            total_error
+        };
+        let independent = error(SamplerKind::Independent);
+        for kind in KINDS.into_iter().skip(1) {
+            assert!(error(kind) < independent / 4.0, "{:?}", kind);
+        }
+    }
+}
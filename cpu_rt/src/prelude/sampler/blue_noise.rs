@@ -0,0 +1,137 @@
+//! Blue noise mask made with Ulichney's void and cluster method, the values are spread so that
+//! neighboring pixels get very different values
+use super::{RayScalar, Sampler};
+use std::sync::OnceLock;
+
+/// width and height of the mask, the mask tiles the image
+const SIZE: usize = 64;
+const SIGMA: RayScalar = 1.5;
+/// the gaussian is cut off after this distance
+const RADIUS: i32 = 5;
+
+/// gets the mask value in [0, 1) at the pixel
+pub(super) fn value(x: u32, y: u32) -> RayScalar {
+    static MASK: OnceLock<Vec<RayScalar>> = OnceLock::new();
+    let mask = MASK.get_or_init(generate);
+    mask[(y as usize % SIZE) * SIZE + x as usize % SIZE]
+}
+/// Binary pattern along with how crowded each pixel's surroundings are. The energy wraps around
+/// the edges so the mask tiles
+#[derive(Clone)]
+struct Pattern {
+    set: Vec<bool>,
+    energy: Vec<RayScalar>,
+    kernel: Vec<(i32, i32, RayScalar)>,
+}
+impl Pattern {
+    fn new() -> Self {
+        let kernel = (-RADIUS..=RADIUS)
+            .flat_map(|x| (-RADIUS..=RADIUS).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let distance_squared = (x * x + y * y) as RayScalar;
+                (x, y, (-distance_squared / (2.0 * SIGMA * SIGMA)).exp())
+            })
+            .collect();
+        Self {
+            set: vec![false; SIZE * SIZE],
+            energy: vec![0.0; SIZE * SIZE],
+            kernel,
+        }
+    }
+    fn toggle(&mut self, index: usize) {
+        self.set[index] = !self.set[index];
+        let sign = if self.set[index] { 1.0 } else { -1.0 };
+        let (x, y) = ((index % SIZE) as i32, (index / SIZE) as i32);
+        for (dx, dy, weight) in self.kernel.iter() {
+            let px = (x + dx).rem_euclid(SIZE as i32) as usize;
+            let py = (y + dy).rem_euclid(SIZE as i32) as usize;
+            self.energy[py * SIZE + px] += sign * weight;
+        }
+    }
+    /// set pixel with the most set pixels around it
+    fn tightest_cluster(&self) -> usize {
+        (0..self.set.len())
+            .filter(|i| self.set[*i])
+            .max_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b]))
+            .expect("pattern is empty")
+    }
+    /// unset pixel with the fewest set pixels around it
+    fn largest_void(&self) -> usize {
+        (0..self.set.len())
+            .filter(|i| !self.set[*i])
+            .min_by(|a, b| self.energy[*a].total_cmp(&self.energy[*b]))
+            .expect("pattern is full")
+    }
+}
+fn generate() -> Vec<RayScalar> {
+    let num_pixels = SIZE * SIZE;
+    let num_initial = num_pixels / 10;
+    let mut sampler = Sampler::new(0);
+    let mut pattern = Pattern::new();
+    let mut num_set = 0;
+    while num_set < num_initial {
+        let index = sampler.u32(0, num_pixels as u32) as usize;
+        if !pattern.set[index] {
+            pattern.toggle(index);
+            num_set += 1;
+        }
+    }
+    // spread out the initial points by moving points from clusters to voids until it is stable
+    for _ in 0..num_pixels {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+    let mut rank = vec![0; num_pixels];
+    // the initial points are ranked by removing them cluster first
+    let mut removed = pattern.clone();
+    for r in (0..num_initial).rev() {
+        let cluster = removed.tightest_cluster();
+        removed.toggle(cluster);
+        rank[cluster] = r;
+    }
+    // then the rest of the pixels are ranked by filling the voids
+    for r in num_initial..num_pixels {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        rank[void] = r;
+    }
+    rank.iter()
+        .map(|r| (*r as RayScalar + 0.5) / num_pixels as RayScalar)
+        .collect()
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn blue_noise_mask() {
+        let mut values = (0..SIZE as u32)
+            .flat_map(|x| (0..SIZE as u32).map(move |y| value(x, y)))
+            .collect::<Vec<_>>();
+        // 8 by 8 blocks have close to the same mean and neighbors are far apart, white noise
+        // would give an error of about 0.036 and a difference of 1/3
+        let mut squared_error = 0.0;
+        for bx in 0..SIZE as u32 / 8 {
+            for by in 0..SIZE as u32 / 8 {
+                let sum = (0..8)
+                    .flat_map(|x| (0..8).map(move |y| value(bx * 8 + x, by * 8 + y)))
+                    .sum::<RayScalar>();
+                squared_error += (sum / 64.0 - 0.5).powi(2);
+            }
+        }
+        assert!((squared_error / 64.0).sqrt() < 0.02);
+        let difference = (0..SIZE as u32)
+            .flat_map(|x| (0..SIZE as u32).map(move |y| (value(x, y) - value(x + 1, y)).abs()))
+            .sum::<RayScalar>();
+        assert!(difference / (SIZE * SIZE) as RayScalar > 0.38);
+        // each rank is used once
+        values.sort_by(|a, b| a.total_cmp(b));
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(*v, (i as RayScalar + 0.5) / (SIZE * SIZE) as RayScalar);
+        }
+    }
+}
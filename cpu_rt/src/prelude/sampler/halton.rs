@@ -0,0 +1,93 @@
+use super::RayScalar;
+
+/// bases of the Halton dimensions, dimensions after these use random numbers
+pub(super) const PRIMES: [u32; 48] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223,
+];
+/// mirrors the digits of `index` in the given base around the decimal point
+#[cfg(test)]
+pub(super) fn radical_inverse(base: u32, mut index: u32) -> RayScalar {
+    let inverse_base = 1.0 / base as RayScalar;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as RayScalar * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+/// Radical inverse with the digits shuffled by a random permutation for each digit position,
+/// without it the high dimensions of the first samples would all be close to zero
+pub(super) fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> RayScalar {
+    let inverse_base = 1.0 / base as RayScalar;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    let mut position = 0;
+    // the zero digits after the last digit of the index are permuted too
+    while factor > RayScalar::EPSILON {
+        let digit = index % base;
+        let permutation_seed = super::hash(seed ^ position) as u32;
+        result += permute(digit, base, permutation_seed) as RayScalar * factor;
+        index /= base;
+        factor *= inverse_base;
+        position += 1;
+    }
+    result.min(1.0 - RayScalar::EPSILON / 2.0)
+}
+/// Kensler's hashed permutation of [0, length), values outside of the range are hashed again until
+/// they are in range
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i + seed) % length
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn van_der_corput() {
+        let values = (0..5).map(|i| radical_inverse(2, i)).collect::<Vec<_>>();
+        assert_eq!(values, [0.0, 0.5, 0.25, 0.75, 0.125]);
+        assert!((radical_inverse(3, 5) - (2.0 / 3.0 + 1.0 / 9.0)).abs() < 1e-12);
+    }
+    #[test]
+    fn permutation() {
+        for length in [2, 3, 7, 64, 223] {
+            let mut values = (0..length)
+                .map(|i| permute(i, length, 12345))
+                .collect::<Vec<_>>();
+            values.sort();
+            assert_eq!(values, (0..length).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Owen scrambled Sobol points using the hash based scrambling from Burley's "Practical Hash-based
+//! Owen Scrambling". Only the first four Sobol dimensions are used, higher dimensions reuse them
+//! with the sample order shuffled for every group of four
+
+/// direction numbers of the first four Sobol dimensions
+const DIRECTIONS: [[u32; 32]; 4] = directions();
+/// builds the direction numbers from the primitive polynomials in Joe and Kuo's table
+const fn directions() -> [[u32; 32]; 4] {
+    // degree, coefficients and initial direction numbers of the polynomials
+    const POLYNOMIALS: [(usize, u32, [u32; 3]); 3] =
+        [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+    let mut out = [[0; 32]; 4];
+    let mut i = 0;
+    // the first dimension is the van der Corput sequence
+    while i < 32 {
+        out[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+    let mut d = 0;
+    while d < POLYNOMIALS.len() {
+        let (degree, coefficients, initial) = POLYNOMIALS[d];
+        let mut i = 0;
+        while i < 32 {
+            out[d + 1][i] = if i < degree {
+                initial[i] << (31 - i)
+            } else {
+                let mut value = out[d + 1][i - degree] ^ (out[d + 1][i - degree] >> degree);
+                let mut k = 1;
+                while k < degree {
+                    value ^= ((coefficients >> (degree - 1 - k)) & 1) * out[d + 1][i - k];
+                    k += 1;
+                }
+                value
+            };
+            i += 1;
+        }
+        d += 1;
+    }
+    out
+}
+/// gets the unscrambled Sobol point as a 0.32 fixed point number
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut bit = 0;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= DIRECTIONS[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+    result
+}
+/// Permutes the bits so that each bit only depends on the bits below it. It is a bijection on
+/// the lowest n bits for every n
+pub(super) fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+/// owen scrambles a 0.32 fixed point number
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+/// Gets the scrambled point for `dimension` of sample `index` as a 0.32 fixed point number, each
+/// seed gives a different scrambling
+pub(super) fn sample(index: u32, dimension: u64, seed: u64) -> u32 {
+    let group_seed = super::hash(seed ^ (dimension / 4)) as u32;
+    let shuffled_index = nested_uniform_scramble(index, group_seed);
+    let value = sobol(shuffled_index, (dimension % 4) as usize);
+    nested_uniform_scramble(value, super::hash(seed ^ !dimension) as u32)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn first_points() {
+        let points = (0..4)
+            .map(|i| (sobol(i, 0), sobol(i, 1)))
+            .collect::<Vec<_>>();
+        let half = 1 << 31;
+        let quarter = 1 << 30;
+        assert_eq!(
+            points,
+            [
+                (0, 0),
+                (half, half),
+                (quarter, half + quarter),
+                (half + quarter, quarter)
+            ]
+        );
+    }
+    #[test]
+    fn scrambling_keeps_strata() {
+        for seed in 0..8 {
+            // every 4 by 4 cell gets one of the first 16 points
+            let mut cells = [0; 16];
+            for i in 0..16 {
+                let x = sample(i, 0, seed) >> 30;
+                let y = sample(i, 1, seed) >> 30;
+                cells[(x * 4 + y) as usize] += 1;
+            }
+            assert!(cells.iter().all(|count| *count == 1));
+        }
+    }
+}
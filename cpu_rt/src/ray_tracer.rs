@@ -1,17 +1,21 @@
+pub mod animation;
 pub mod background;
 mod bloom;
 mod bvh;
 pub mod camera;
+pub mod filter;
 pub mod hittable;
 pub mod logger;
 pub mod material;
 
 mod pdf;
+pub mod phase_function;
 pub mod ray_tracer_info;
 mod save_file;
 mod scenario_info;
 mod sun;
 pub mod texture;
+pub mod tile_scheduler;
 
 pub mod world;
 use ray_tracer_info::{RayTracerInfo, ScenarioInfo};
@@ -25,42 +29,35 @@ use logger::Logger;
 
 use crate::ray_tracer::ray_tracer_info::EntityField;
 
+use animation::Animation;
 use background::{Background, ConstantColor};
 use bvh::Aabb;
 use camera::Camera;
-use cgmath::{InnerSpace, Point3, Vector3};
+use cgmath::{InnerSpace, Point2, Point3};
+use filter::Filter;
 use hittable::{HitRay, HitRecord, Hittable, MaterialEffect};
 #[allow(unused_imports)]
 use material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
 use pdf::ScatterRecord;
 use prelude::RayScalar;
 use save_file::SceneFile;
+pub use save_file::SceneSaveError;
 use scenario_info::LoadScenario;
 use std::{
     collections::HashMap,
-    sync::{mpsc::channel, Arc, RwLock},
+    ops::RangeInclusive,
+    sync::{
+        mpsc::{channel, sync_channel, TryRecvError},
+        Arc,
+    },
     thread,
+    time::Duration,
 };
 #[allow(unused_imports)]
 use texture::{CheckerTexture, DebugV, ImageTexture, MultiplyTexture, Perlin, SolidColor, Texture};
+use tile_scheduler::{make_tiles, Tile, TileOrder, TileScheduler};
 pub use world::{ScenarioCtor, World, WorldInfo};
 
-pub fn rand_unit_vec() -> Vector3<RayScalar> {
-    loop {
-        let v = 2.0 * (rand_vec() - Vector3::new(0.5, 0.5, 0.5));
-        if v.dot(v) < 1.0 {
-            return v;
-        }
-    }
-}
-/// generates random vec with all components in range [0,1)
-pub fn rand_vec() -> Vector3<RayScalar> {
-    Vector3 {
-        x: rand::random(),
-        y: rand::random(),
-        z: rand::random(),
-    }
-}
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub(crate) struct DebugRayTraceStep {
@@ -76,13 +73,34 @@ pub(crate) struct RayColorOutput {
     #[cfg(feature = "debug_tracing")]
     pub(crate) steps: Vec<DebugRayTraceStep>,
 }
+/// maximum number of bounces of a path
+const MAX_DEPTH: u32 = 50;
+/// sampler dimensions used for the pixel position and the camera ray
+const CAMERA_DIMENSIONS: u64 = 8;
+/// Sampler dimensions used by each bounce, the first `HIT_DIMENSIONS` are used while finding the
+/// hit and the rest when picking the next direction. Keeping the same layout for every path lets
+/// the low discrepancy samplers stratify each bounce
+const BOUNCE_DIMENSIONS: u64 = 16;
+const HIT_DIMENSIONS: u64 = 8;
 pub(crate) trait Shader {
-    fn ray_color(&self, ray: Ray, world: &World, depth: u32) -> RayColorOutput;
+    fn ray_color(
+        &self,
+        ray: Ray,
+        world: &World,
+        depth: u32,
+        sampler: &mut Sampler,
+    ) -> RayColorOutput;
 }
 #[derive(Clone)]
 pub struct LightMapShader {}
 impl Shader for LightMapShader {
-    fn ray_color(&self, ray: Ray, world: &World, depth: u32) -> RayColorOutput {
+    fn ray_color(
+        &self,
+        ray: Ray,
+        world: &World,
+        depth: u32,
+        sampler: &mut Sampler,
+    ) -> RayColorOutput {
         if depth == 0 {
             return RayColorOutput {
                 color: RgbColor::BLACK,
@@ -90,13 +108,13 @@ impl Shader for LightMapShader {
                 steps: vec![],
             };
         }
-        if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX) {
+        if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX, sampler) {
             let color = world
                 .lights
                 .iter()
                 .map(|l| {
-                    let area = l.generate_ray_in_area(record.position, record.t);
-                    if let Some(r) = world.nearest_hit(&area.to_area, 0.001, f32::MAX) {
+                    let area = l.generate_ray_in_area(record.position, ray.time, sampler);
+                    if let Some(r) = world.nearest_hit(&area.to_area, 0.001, f32::MAX, sampler) {
                         let at = area.end_point;
                         let t = at - r.position;
                         let m = t.magnitude();
@@ -125,7 +143,13 @@ impl Shader for LightMapShader {
 #[derive(Clone)]
 pub struct DiffuseShader {}
 impl Shader for DiffuseShader {
-    fn ray_color(&self, ray: Ray, world: &World, depth: u32) -> RayColorOutput {
+    fn ray_color(
+        &self,
+        ray: Ray,
+        world: &World,
+        depth: u32,
+        sampler: &mut Sampler,
+    ) -> RayColorOutput {
         if depth == 0 {
             return RayColorOutput {
                 color: RgbColor::BLACK,
@@ -134,7 +158,7 @@ impl Shader for DiffuseShader {
             };
         }
 
-        if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX) {
+        if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX, sampler) {
             match record.material_effect {
                 MaterialEffect::Emmit(color) => RayColorOutput { color },
                 MaterialEffect::Scatter(record) => RayColorOutput {
@@ -157,8 +181,20 @@ impl Shader for DiffuseShader {
 }
 #[derive(Clone)]
 pub struct RayTracingShader {}
+impl RayTracingShader {
+    /// first sampler dimension of the bounce at the given depth
+    fn bounce_dimension(depth: u32) -> u64 {
+        CAMERA_DIMENSIONS + (MAX_DEPTH - depth.min(MAX_DEPTH)) as u64 * BOUNCE_DIMENSIONS
+    }
+}
 impl Shader for RayTracingShader {
-    fn ray_color(&self, ray: Ray, world: &World, depth: u32) -> RayColorOutput {
+    fn ray_color(
+        &self,
+        ray: Ray,
+        world: &World,
+        depth: u32,
+        sampler: &mut Sampler,
+    ) -> RayColorOutput {
         #[cfg(feature = "debug_tracing")]
         fn has_false_front_face(steps: &[DebugRayTraceStep]) -> bool {
             steps.iter().fold(true, |acc, x| acc == x.front_face)
@@ -170,7 +206,10 @@ impl Shader for RayTracingShader {
                 steps: vec![],
             };
         }
-        let output = if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX) {
+        let bounce_dimension = Self::bounce_dimension(depth);
+        sampler.skip_to(bounce_dimension);
+        let output = if let Some(record) = world.nearest_hit(&ray, 0.001, f32::MAX, sampler) {
+            sampler.skip_to(bounce_dimension + HIT_DIMENSIONS);
             #[cfg(feature = "debug_tracing")]
             let front_face = if (record.normal.dot(ray.direction) <= 0.0) != record.front_face {
                 if rand_u32(0, 1_000_000) == 0 {
@@ -199,7 +238,7 @@ impl Shader for RayTracingShader {
                 }
                 MaterialEffect::Scatter(scatter_record) => {
                     if let Some(specular_ray) = scatter_record.specular_ray {
-                        let ray_color = self.ray_color(specular_ray, world, depth - 1);
+                        let ray_color = self.ray_color(specular_ray, world, depth - 1, sampler);
                         let color = scatter_record.attenuation * ray_color.color;
                         #[cfg(feature = "debug_tracing")]
                         let mut steps = {
@@ -230,7 +269,7 @@ impl Shader for RayTracingShader {
                     } else if let Some((pdf_direction, value)) = scatter_record
                         .pdf
                         .expect("if material is not specular there should be a pdf")
-                        .generate(ray, record.position, world)
+                        .generate(ray, record.position, world, sampler)
                     {
                         let scattering_pdf_fn = scatter_record.scattering_pdf;
                         let scattering_pdf = scattering_pdf_fn(
@@ -239,7 +278,7 @@ impl Shader for RayTracingShader {
                             Ray {
                                 origin: record.position,
                                 direction: pdf_direction,
-                                time: record.t,
+                                time: ray.time,
                             },
                         );
 
@@ -258,10 +297,11 @@ impl Shader for RayTracingShader {
                                 Ray {
                                     origin: record.position,
                                     direction: pdf_direction,
-                                    time: record.t,
+                                    time: ray.time,
                                 },
                                 world,
                                 depth - 1,
+                                sampler,
                             );
 
                             let color = scatter_record.attenuation * ray_color.color / value;
@@ -329,11 +369,19 @@ impl std::str::FromStr for CurrentShader {
 }
 pub struct RayTracer {
     scenarios: HashMap<String, Box<dyn ScenarioCtor>>,
-    world: World,
+    /// shared with render threads, edits copy the world if a thread still uses the old one
+    world: Arc<World>,
     current_shader: CurrentShader,
     ray_tracing_shader: RayTracingShader,
     diffuse_shader: DiffuseShader,
     light_map_shader: LightMapShader,
+    num_threads: usize,
+    tile_size: usize,
+    tile_order: TileOrder,
+    /// seed for the random numbers used when rendering
+    seed: u64,
+    sampler_kind: SamplerKind,
+    filter: Filter,
 }
 impl Clone for RayTracer {
     fn clone(&self) -> Self {
@@ -348,6 +396,12 @@ impl Clone for RayTracer {
             ray_tracing_shader: self.ray_tracing_shader.clone(),
             diffuse_shader: self.diffuse_shader.clone(),
             light_map_shader: self.light_map_shader.clone(),
+            num_threads: self.num_threads,
+            tile_size: self.tile_size,
+            tile_order: self.tile_order,
+            seed: self.seed,
+            sampler_kind: self.sampler_kind,
+            filter: self.filter,
         }
     }
 }
@@ -382,11 +436,21 @@ impl RayTracer {
 
         Self {
             scenarios: scenarios.items,
-            world,
+            world: Arc::new(world),
             ray_tracing_shader: RayTracingShader {},
             diffuse_shader: DiffuseShader {},
             light_map_shader: LightMapShader {},
             current_shader,
+            num_threads: builder.num_threads.unwrap_or_else(|| {
+                thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            }),
+            tile_size: builder.tile_size,
+            tile_order: builder.tile_order,
+            seed: builder.seed,
+            sampler_kind: builder.sampler_kind,
+            filter: builder.filter,
         }
     }
     pub fn builder() -> RayTracerBuilder {
@@ -404,60 +468,109 @@ impl RayTracer {
     }
 
     pub fn load_scenario(&mut self, scenario: String) {
-        self.world = self.scenarios[&scenario].build();
+        self.world = Arc::new(self.scenarios[&scenario].build());
+    }
+    /// gets the world for editing, copies it first if it is shared with a render thread
+    fn world_mut(&mut self) -> &mut World {
+        Arc::make_mut(&mut self.world)
     }
     pub fn set_shader(&mut self, shader: CurrentShader) {
         self.current_shader = shader
     }
-    /// Does one ray tracing step and saves result to image
-    pub fn trace_image(&self, rgb_img: &mut ParallelImage) {
-        let mut imgs = rgb_img.split(1);
-        self.trace_part(&mut imgs[0]);
-
-        *rgb_img = ParallelImage::join(imgs.iter().collect());
+    pub fn set_sampler(&mut self, sampler_kind: SamplerKind) {
+        self.sampler_kind = sampler_kind
+    }
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter
+    }
+    /// Does one ray tracing step and saves result to image, `sample_index` picks the random
+    /// numbers used so each step should use a different index
+    pub fn trace_image(&self, rgb_img: &mut ParallelImage, sample_index: u32) {
+        let (width, height) = (rgb_img.width(), rgb_img.height());
+        let tile = Tile {
+            offset: Point2::new(0, 0),
+            width,
+            height,
+        };
+        let mut part = ParallelImagePart::from_tile(&tile, width, height, self.filter.margin());
+        self.trace_part(&mut part, sample_index);
+        let step = ParallelImage::from_parts(std::iter::once(&part), width, height);
+        for x in 0..width {
+            for y in 0..height {
+                rgb_img.add_xy(x, y, step.get_xy(x, y));
+            }
+        }
     }
     pub fn set_camera_data(&mut self, key: String, value: EntityField) {
-        self.world.set_camera_data(key, value)
+        self.world_mut().set_camera_data(key, value)
+    }
+    /// focuses the camera on whatever is seen at the given screen coordinates, returns the new
+    /// focus distance or none if nothing was hit
+    pub fn autofocus(&mut self, u: RayScalar, v: RayScalar) -> Option<RayScalar> {
+        self.world_mut().autofocus(u, v)
     }
     pub fn save_scene(&self, scene_path: std::path::PathBuf) {
         if let Err(e) = SceneFile::builder(scene_path).save(self) {
             error!("failed to save scene reason: {:?}", e)
         }
     }
-    pub fn load_scene(path: std::path::PathBuf) -> Self {
-        Self::builder()
-            .custom_scenario(SceneFile::builder(path).load().unwrap())
-            .build()
+    pub fn load_scene(path: std::path::PathBuf) -> Result<Self, SceneSaveError> {
+        Ok(Self::builder()
+            .custom_scenario(SceneFile::builder(path).load()?)
+            .build())
     }
     pub fn set_entity_data(&mut self, entity_index: usize, key: String, value: EntityField) {
-        self.world.set_entity_data(entity_index, key, value);
+        self.world_mut().set_entity_data(entity_index, key, value);
     }
-    fn trace_part(&self, part: &mut ParallelImagePart) {
+    /// rebuilds the bvh of the current world
+    pub fn rebuild_bvh(&mut self) {
+        self.world_mut().rebuild_bvh();
+    }
+    /// Adds one sample to every pixel of the part. The random numbers for a pixel only depend on
+    /// the seed, the pixel and `sample_index` so the same sample always gives the same color
+    fn trace_part(&self, part: &mut ParallelImagePart, sample_index: u32) {
         let image_width = part.width();
         let image_height = part.height();
         let total_width = part.total_width();
         let total_height = part.total_height();
         let offset = part.offset();
-        let mut total_color = RgbColor::BLACK;
         for x in offset.x..offset.x + image_width {
             for y in offset.y..offset.y + image_height {
-                let u = (x as RayScalar + rand_scalar(0.0, 1.0)) / (total_width as RayScalar - 1.0);
-                let v =
-                    (y as RayScalar + rand_scalar(0.0, 1.0)) / (total_height as RayScalar - 1.0);
-                let r = self.world.camera.get_ray(u, v);
+                let mut sampler = Sampler::for_pixel(
+                    self.sampler_kind,
+                    self.seed,
+                    x as u32,
+                    y as u32,
+                    sample_index,
+                );
+                let sample_x = x as RayScalar + sampler.next_scalar();
+                let sample_y = y as RayScalar + sampler.next_scalar();
+                let u = sample_x / (total_width as RayScalar - 1.0);
+                let v = sample_y / (total_height as RayScalar - 1.0);
+                let (r, weight) = match self.world.camera.get_weighted_ray(u, v, &mut sampler) {
+                    Some(r) => r,
+                    None => continue,
+                };
                 let c = match self.current_shader {
-                    CurrentShader::Diffuse => self.diffuse_shader.ray_color(r, &self.world, 50),
+                    CurrentShader::Diffuse => {
+                        self.diffuse_shader
+                            .ray_color(r, &self.world, MAX_DEPTH, &mut sampler)
+                    }
                     CurrentShader::Raytracing => {
-                        self.ray_tracing_shader.ray_color(r, &self.world, 50)
+                        self.ray_tracing_shader
+                            .ray_color(r, &self.world, MAX_DEPTH, &mut sampler)
+                    }
+                    CurrentShader::LightMap => {
+                        self.light_map_shader
+                            .ray_color(r, &self.world, MAX_DEPTH, &mut sampler)
                     }
-                    CurrentShader::LightMap => self.light_map_shader.ray_color(r, &self.world, 50),
                 };
 
-                if c.color.is_nan() {
+                let color = weight * c.color;
+                if color.is_nan() {
                     error!("ray color retuned NaN");
                 }
-                total_color += c.color;
-                part.add_xy(x, y, c.color);
+                part.add_sample(sample_x, sample_y, color, &self.filter);
             }
         }
     }
@@ -467,77 +580,129 @@ impl RayTracer {
     }
     /// renders current scene to image
     pub fn tracing_loop(&self, parallel_image: &mut ParallelImage, num_samples: usize) {
-        for _ in 0..num_samples {
-            self.trace_image(parallel_image);
+        for sample_index in 0..num_samples {
+            self.trace_image(parallel_image, sample_index as u32);
         }
         let mut post_process = parallel_image.clone() / num_samples as f32;
         self.post_process(&mut post_process);
 
         *parallel_image = post_process;
     }
+    /// Renders the frames of the animation to numbered images in `output_directory`, leaves the
+    /// world at the last frame
+    pub fn render_sequence<P: AsRef<std::path::Path>>(
+        &mut self,
+        animation: &Animation,
+        frames: RangeInclusive<usize>,
+        (width, height): (usize, usize),
+        num_samples: usize,
+        output_directory: P,
+    ) -> std::io::Result<()> {
+        std::fs::create_dir_all(output_directory.as_ref())?;
+        for frame in frames {
+            animation.apply(self.world_mut(), frame as RayScalar);
+            let mut image = ParallelImage::new_black(width, height);
+            self.tracing_loop(&mut image, num_samples);
+            let path = output_directory
+                .as_ref()
+                .join(format!("frame_{:05}.png", frame));
+            info!("saving frame {} to {:?}", frame, path);
+            image.save_image(path, 1);
+        }
+        Ok(())
+    }
 
+    /// Renders on worker threads. The image is split into tiles that the threads take from a
+    /// shared scheduler, each thread renders an immutable snapshot of the ray tracer and picks up
+    /// new snapshots sent by the collector between tiles
     pub fn threaded_render(self, image: ParallelImage) -> ParallelImageCollector {
-        fn loop_try_get(lock: &'_ RwLock<RayTracer>) -> std::sync::RwLockReadGuard<'_, RayTracer> {
-            loop {
-                let t = lock.try_read();
-                match t {
-                    Ok(t) => return t,
-                    Err(e) => match e {
-                        std::sync::TryLockError::Poisoned(_) => {
-                            panic!()
-                        }
-                        std::sync::TryLockError::WouldBlock => {
-                            thread::sleep(std::time::Duration::from_millis(1))
-                        }
-                    },
-                }
-            }
-        }
-        let num_threads = 8;
-        let mut parts = image.split(num_threads);
-        let mut receivers = vec![];
-        let mut senders = vec![];
-        let self_rw_lock = Arc::new(RwLock::new(self.clone()));
-        for part in parts.drain(..) {
-            let (mut sender, receiver) = image_channel();
+        /// how long an idle thread waits for the pass to finish before checking for messages
+        const IDLE_WAIT: Duration = Duration::from_millis(10);
+        let (width, height) = (image.width(), image.height());
+        let scheduler = Arc::new(TileScheduler::new(
+            make_tiles(width, height, self.tile_size, self.tile_order),
+            self.num_threads,
+        ));
+        let (sender, receiver) = sync_channel(4 * self.num_threads);
+        let mut message_senders = vec![];
+        let snapshot = Arc::new(self.clone());
+        for thread_index in 0..self.num_threads {
             let (message_sender, message_receiver) = channel();
-            senders.push(message_sender);
-            let self_rw_lock = self_rw_lock.clone();
+            message_senders.push(message_sender);
+            let mut snapshot = snapshot.clone();
+            let scheduler = scheduler.clone();
+            let sender = sender.clone();
             thread::spawn(move || {
                 let mut render = true;
-                let mut part = part;
-
+                let mut generation = 0;
                 loop {
-                    for msg in message_receiver.try_iter() {
-                        match msg {
-                            RayTracerMessage::SceneChanged => part.set_black(),
-
-                            RayTracerMessage::SetShader(_) => part.set_black(),
-                            RayTracerMessage::StopRendering => {
-                                render = false;
-                            }
-                            RayTracerMessage::ContinueRendering => {
-                                render = true;
+                    // while stopped the thread blocks until it gets a message
+                    let message = if render {
+                        match message_receiver.try_recv() {
+                            Ok(message) => Some(message),
+                            Err(TryRecvError::Empty) => None,
+                            Err(TryRecvError::Disconnected) => return,
+                        }
+                    } else {
+                        match message_receiver.recv() {
+                            Ok(message) => Some(message),
+                            Err(_) => return,
+                        }
+                    };
+                    match message {
+                        Some(RayTracerMessage::Snapshot {
+                            ray_tracer,
+                            generation: new_generation,
+                        }) => {
+                            snapshot = ray_tracer;
+                            generation = new_generation;
+                        }
+                        Some(RayTracerMessage::StopRendering) => render = false,
+                        Some(RayTracerMessage::ContinueRendering) => render = true,
+                        None => {
+                            let pass = scheduler.current_pass();
+                            let (tile_index, tile_pass) = match scheduler.next_tile(thread_index) {
+                                Some(tile) => tile,
+                                None => {
+                                    scheduler.wait_for_pass(pass, IDLE_WAIT);
+                                    continue;
+                                }
+                            };
+                            let mut image = ParallelImagePart::from_tile(
+                                &scheduler.tiles()[tile_index],
+                                width,
+                                height,
+                                snapshot.filter.margin(),
+                            );
+                            snapshot.trace_part(&mut image, tile_pass as u32);
+                            scheduler.finish_tile(thread_index, tile_pass);
+                            let rendered = RenderedTile {
+                                generation,
+                                tile_index,
+                                image,
+                            };
+                            if sender.send(rendered).is_err() {
+                                return;
                             }
-                            RayTracerMessage::SetCameraData(_) => part.set_black(),
-                        };
-                    }
-                    if render {
-                        let self_read_res = loop_try_get(&self_rw_lock);
-                        self_read_res.trace_part(&mut part);
-                        sender.send(part.clone());
+                        }
                     }
                 }
             });
-            receivers.push(receiver);
         }
-        ParallelImageCollector::new(receivers, senders, self_rw_lock)
+        ParallelImageCollector::new(receiver, message_senders, scheduler, self)
     }
 }
 pub struct RayTracerBuilder {
     additional_scenarios: Option<HashMap<String, Box<dyn ScenarioCtor>>>,
     default_scenario: LoadScenario,
     default_shader: Option<CurrentShader>,
+    /// defaults to the available parallelism
+    num_threads: Option<usize>,
+    tile_size: usize,
+    tile_order: TileOrder,
+    seed: u64,
+    sampler_kind: SamplerKind,
+    filter: Filter,
 }
 impl std::default::Default for RayTracerBuilder {
     fn default() -> Self {
@@ -545,6 +710,12 @@ impl std::default::Default for RayTracerBuilder {
             additional_scenarios: None,
             default_scenario: LoadScenario::None,
             default_shader: None,
+            num_threads: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            seed: 0,
+            sampler_kind: SamplerKind::Sobol,
+            filter: Filter::default(),
         }
     }
 }
@@ -570,6 +741,37 @@ impl RayTracerBuilder {
         self.default_shader = Some(shader);
         self
     }
+    /// sets the number of threads used by `threaded_render`
+    pub fn set_num_threads(mut self, num_threads: usize) -> Self {
+        assert!(num_threads > 0, "need at least one thread");
+        self.num_threads = Some(num_threads);
+        self
+    }
+    /// sets the width and height of the tiles used by `threaded_render`
+    pub fn set_tile_size(mut self, tile_size: usize) -> Self {
+        assert!(tile_size > 0, "tile size must be greater than zero");
+        self.tile_size = tile_size;
+        self
+    }
+    pub fn set_tile_order(mut self, tile_order: TileOrder) -> Self {
+        self.tile_order = tile_order;
+        self
+    }
+    /// sets the seed of the random numbers, renders with the same seed and scene are identical
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// sets how the random numbers used for rendering are picked, defaults to `SamplerKind::Sobol`
+    pub fn set_sampler(mut self, sampler_kind: SamplerKind) -> Self {
+        self.sampler_kind = sampler_kind;
+        self
+    }
+    /// sets the reconstruction filter used to weight samples of the pixels around them
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
     pub fn custom_scenario(mut self, scenario: WorldInfo) -> Self {
         self.default_scenario = LoadScenario::Custom(Box::new(scenario));
         self
@@ -578,3 +780,239 @@ impl RayTracerBuilder {
         RayTracer::new(self)
     }
 }
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn edits_do_not_change_snapshot() {
+        let mut ray_tracer = RayTracer::builder()
+            .custom_scenario(world::one_sphere())
+            .build();
+        let snapshot = Arc::new(ray_tracer.clone());
+        assert!(Arc::ptr_eq(&ray_tracer.world, &snapshot.world));
+
+        ray_tracer.set_camera_data("focus_distance".to_string(), EntityField::Float(123.0));
+        assert!(!Arc::ptr_eq(&ray_tracer.world, &snapshot.world));
+        assert_eq!(ray_tracer.world.camera.info().focus_distance, 123.0);
+        assert_ne!(snapshot.world.camera.info().focus_distance, 123.0);
+    }
+    #[test]
+    fn autofocus() {
+        let mut ray_tracer = RayTracer::builder()
+            .custom_scenario(world::one_sphere())
+            .build();
+        // the sphere is in the center of the screen, its surface is half a unit in front of
+        // where the camera was focused
+        let before = ray_tracer.world.camera.info().focus_distance;
+        let focus_distance = ray_tracer.autofocus(0.5, 0.5).unwrap();
+        assert!((focus_distance - (before - 0.5)).abs() < 1e-3);
+        assert_eq!(
+            ray_tracer.world.camera.info().focus_distance,
+            focus_distance
+        );
+        assert!(ray_tracer.autofocus(0.0, 0.0).is_none());
+        assert_eq!(
+            ray_tracer.world.camera.info().focus_distance,
+            focus_distance
+        );
+    }
+    #[test]
+    fn render_sequence() {
+        let mut ray_tracer = RayTracer::builder()
+            .custom_scenario(world::one_sphere())
+            .build();
+        let animation = Animation {
+            camera: animation::CameraAnimation {
+                fov: Some(
+                    animation::Track::new(animation::Interpolation::Linear)
+                        .key(0.0, 20.0)
+                        .key(2.0, 40.0),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let directory = std::env::temp_dir().join("render_sequence_test");
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory).unwrap();
+        }
+        // bloom down samples the image six times so it can not be much smaller
+        ray_tracer
+            .render_sequence(&animation, 0..=2, (128, 128), 1, &directory)
+            .unwrap();
+        let mut frames = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        frames.sort();
+        assert_eq!(
+            frames,
+            ["frame_00000.png", "frame_00001.png", "frame_00002.png"]
+        );
+        for frame in frames {
+            let image = image::open(directory.join(frame)).unwrap();
+            assert_eq!((image.width(), image.height()), (128, 128));
+        }
+        // the world is left at the last frame
+        assert_eq!(ray_tracer.world.camera.info().fov, 40.0);
+    }
+    /// bounced rays stay at the time of the camera ray, so a moving light is only seen where it is
+    /// at that time
+    #[test]
+    fn bounces_keep_shutter_time() {
+        use hittable::{Object, Sphere, Transform, TransformMotion, XZRect};
+        use world::WorldInfo;
+        let far = Transform::identity().translate(Vector3::new(0.0, 1000.0, 0.0));
+        let light = Object::new(
+            Box::new(Sphere {
+                radius: 1.0,
+                origin: Point3::new(0.0, 0.0, 0.0),
+                material: Box::new(DiffuseLight {
+                    emit: Box::new(SolidColor {
+                        color: RgbColor::WHITE,
+                    }),
+                }),
+            }),
+            far,
+        )
+        // above the floors at the start of the shutter and far away after
+        .with_motion(TransformMotion::linear(
+            Transform::identity().translate(Vector3::new(0.0, 3.0, 0.0)),
+            far,
+            0.0,
+            1.0,
+        ));
+        let floor = |x0: RayScalar, material: Box<dyn Material>| {
+            Object::new(
+                Box::new(XZRect::new(x0, x0 + 4.0, -2.0, 2.0, 0.0, material, false)),
+                Transform::identity(),
+            )
+        };
+        let world = WorldInfo {
+            objects: vec![
+                floor(
+                    -8.0,
+                    Box::new(Metal {
+                        albedo: Box::new(SolidColor {
+                            color: RgbColor::WHITE,
+                        }),
+                        fuzz: 0.0,
+                    }),
+                ),
+                floor(
+                    10.0,
+                    Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: RgbColor::WHITE,
+                        }),
+                    }),
+                ),
+                light.clone(),
+            ],
+            lights: vec![light],
+            background: Box::new(ConstantColor {
+                color: RgbColor::BLACK,
+            }),
+            camera: Camera::new(camera::CameraInfo::default()),
+            sun: None,
+        }
+        .build_world();
+        let shader = RayTracingShader {};
+        let mut sampler = Sampler::new(0);
+        // reflects off of the mirror at (-6, 0, 0) into the light
+        let mirror_ray = Ray {
+            origin: Point3::new(-12.0, 3.0, 0.0),
+            direction: Vector3::new(12.0, -6.0, 0.0),
+            time: 0.0,
+        };
+        let color = shader.ray_color(mirror_ray, &world, 5, &mut sampler).color;
+        assert!(color.red > 0.5, "{:?}", color);
+        // the hit distance of the floor is past the end of the shutter
+        let mut diffuse = RgbColor::BLACK;
+        for _ in 0..64 {
+            let ray = Ray {
+                origin: Point3::new(12.0, 20.0, 0.0),
+                direction: Vector3::new(0.0, -1.0, 0.0),
+                time: 0.0,
+            };
+            diffuse += shader.ray_color(ray, &world, 5, &mut sampler).color;
+        }
+        assert!(diffuse.red > 0.0, "{:?}", diffuse);
+    }
+    fn render(seed: u64) -> ParallelImage {
+        let ray_tracer = RayTracer::builder()
+            .custom_scenario(world::one_sphere())
+            .set_seed(seed)
+            .build();
+        let mut image = ParallelImage::new_black(16, 16);
+        for sample_index in 0..2 {
+            ray_tracer.trace_image(&mut image, sample_index);
+        }
+        image
+    }
+    fn pixels(image: &ParallelImage) -> Vec<RgbColor> {
+        (0..image.width())
+            .flat_map(|x| (0..image.height()).map(move |y| image.get_xy(x, y)))
+            .collect()
+    }
+    #[test]
+    fn same_seed_same_image() {
+        assert_eq!(pixels(&render(5)), pixels(&render(5)));
+        assert_ne!(pixels(&render(5)), pixels(&render(6)));
+    }
+    /// samples splatted across tile borders give the same image as rendering in one piece
+    #[test]
+    fn filter_splats_across_tiles() {
+        let filter = Filter::new(filter::FilterKind::Mitchell, 2.0);
+        let ray_tracer = RayTracer::builder()
+            .custom_scenario(world::one_sphere())
+            .set_filter(filter)
+            .build();
+        let (width, height) = (20, 12);
+        let render_tile = |tile: &Tile| {
+            let mut part = ParallelImagePart::from_tile(tile, width, height, filter.margin());
+            ray_tracer.trace_part(&mut part, 0);
+            part
+        };
+        let whole = render_tile(&Tile {
+            offset: Point2::new(0, 0),
+            width,
+            height,
+        });
+        let whole = ParallelImage::from_parts(std::iter::once(&whole), width, height);
+        let tiles = make_tiles(width, height, 8, TileOrder::Scanline)
+            .iter()
+            .map(render_tile)
+            .collect::<Vec<_>>();
+        let tiled = ParallelImage::from_parts(tiles.iter(), width, height);
+        for (a, b) in pixels(&whole).iter().zip(pixels(&tiled).iter()) {
+            let difference = *a - *b;
+            for channel in [difference.red, difference.green, difference.blue] {
+                assert!(channel.abs() < 1e-4);
+            }
+        }
+    }
+    /// pixels where the negative lobes outweigh the positive ones are black instead of blowing up
+    #[test]
+    fn negative_filter_weights() {
+        let filter = Filter::new(filter::FilterKind::Lanczos, 3.0);
+        let tile = Tile {
+            offset: Point2::new(0, 0),
+            width: 4,
+            height: 1,
+        };
+        let mut part = ParallelImagePart::from_tile(&tile, 4, 1, filter.margin());
+        assert!(filter.evaluate(0.9, 0.0) > 0.0);
+        assert!(filter.evaluate(0.9, 0.0) + filter.evaluate(1.5, 0.0) < 0.0);
+        part.add_sample(1.4, 0.5, RgbColor::WHITE, &filter);
+        part.add_sample(2.0, 0.5, RgbColor::BLACK, &filter);
+        let image = ParallelImage::from_parts(std::iter::once(&part), 4, 1);
+        assert_eq!(image.get_xy(0, 0), RgbColor::BLACK);
+        for x in 0..4 {
+            let color = image.get_xy(x, 0);
+            for channel in [color.red, color.green, color.blue] {
+                assert!(channel.abs() <= 1.0 + 1e-4);
+            }
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use super::{
+    camera::{Camera, CameraInfo},
+    hittable::Transform,
+    ray_tracer_info::EntityField,
+    World,
+};
+use crate::prelude::*;
+use cgmath::{num_traits::FloatConst, Point3, Vector3};
+
+/// Value that can be blended between keyframes
+pub trait Lerp: Clone {
+    /// blends between `a` and `b`, `t` may be outside of \[0, 1\] when evaluating splines
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self;
+}
+impl Lerp for RayScalar {
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self {
+        a + (b - a) * t
+    }
+}
+impl Lerp for Point3<RayScalar> {
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self {
+        a + (b - a) * t
+    }
+}
+impl Lerp for Vector3<RayScalar> {
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self {
+        a + (b - a) * t
+    }
+}
+/// Blends the translation, rotation and scale of the transforms. Transforms with shear, or a
+/// blended scale that can not be inverted, snap to the nearest key
+impl Lerp for Transform {
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self {
+        if let (Some(trs_a), Some(trs_b)) = (a.decompose(), b.decompose()) {
+            let (translation_a, rotation_a, scale_a) = trs_a;
+            let (translation_b, rotation_b, scale_b) = trs_b;
+            if let Some(transform) = Transform::try_from_trs(
+                Lerp::lerp(&translation_a, &translation_b, t),
+                rotation_a.slerp(rotation_b, t),
+                Lerp::lerp(&scale_a, &scale_b, t),
+            ) {
+                return transform;
+            }
+        }
+        if t < 0.5 {
+            *a
+        } else {
+            *b
+        }
+    }
+}
+/// Fields that can not be blended snap to the next keyframe half way through
+impl Lerp for EntityField {
+    fn lerp(a: &Self, b: &Self, t: RayScalar) -> Self {
+        match (a, b) {
+            (EntityField::Point3(a), EntityField::Point3(b)) => {
+                EntityField::Point3(Lerp::lerp(a, b, t))
+            }
+            (EntityField::Angle(a), EntityField::Angle(b)) => {
+                EntityField::Angle(Lerp::lerp(a, b, t))
+            }
+            (EntityField::Float(a), EntityField::Float(b)) => {
+                EntityField::Float(Lerp::lerp(a, b, t))
+            }
+            _ => {
+                if t < 0.5 {
+                    a.clone()
+                } else {
+                    b.clone()
+                }
+            }
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// uniform Catmull-Rom spline going through every keyframe
+    CatmullRom,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe<T: Lerp> {
+    pub frame: RayScalar,
+    pub value: T,
+}
+/// Keyframed value, before the first and after the last keyframe the value is held constant
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T: Lerp> {
+    keyframes: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+impl<T: Lerp> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            keyframes: Vec::new(),
+            interpolation,
+        }
+    }
+    /// adds a keyframe, replacing any keyframe already at the same frame
+    pub fn key(mut self, frame: RayScalar, value: T) -> Self {
+        self.insert(frame, value);
+        self
+    }
+    pub fn insert(&mut self, frame: RayScalar, value: T) {
+        match self
+            .keyframes
+            .binary_search_by(|key| key.frame.partial_cmp(&frame).expect("frame is NaN"))
+        {
+            Ok(idx) => self.keyframes[idx].value = value,
+            Err(idx) => self.keyframes.insert(idx, Keyframe { frame, value }),
+        }
+    }
+    pub fn keyframes(&self) -> &[Keyframe<T>] {
+        &self.keyframes
+    }
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+    /// gets the value at the given frame, returns none if the track has no keyframes
+    pub fn sample(&self, frame: RayScalar) -> Option<T> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if frame <= first.frame {
+            return Some(first.value.clone());
+        }
+        if frame >= last.frame {
+            return Some(last.value.clone());
+        }
+        let idx = self.keyframes.partition_point(|key| key.frame <= frame) - 1;
+        let p1 = &self.keyframes[idx];
+        let p2 = &self.keyframes[idx + 1];
+        let t = (frame - p1.frame) / (p2.frame - p1.frame);
+        match self.interpolation {
+            Interpolation::Linear => Some(T::lerp(&p1.value, &p2.value, t)),
+            Interpolation::CatmullRom => {
+                let p0 = if idx > 0 {
+                    &self.keyframes[idx - 1].value
+                } else {
+                    &p1.value
+                };
+                let p3 = if idx + 2 < self.keyframes.len() {
+                    &self.keyframes[idx + 2].value
+                } else {
+                    &p2.value
+                };
+                Some(Self::catmull_rom(p0, &p1.value, &p2.value, p3, t))
+            }
+        }
+    }
+    /// evaluates the spline segment between `p1` and `p2` using the Barry-Goldman pyramid so that
+    /// only `lerp` is needed
+    fn catmull_rom(p0: &T, p1: &T, p2: &T, p3: &T, t: RayScalar) -> T {
+        let a1 = T::lerp(p0, p1, t + 1.0);
+        let a2 = T::lerp(p1, p2, t);
+        let a3 = T::lerp(p2, p3, t - 1.0);
+        let b1 = T::lerp(&a1, &a2, (t + 1.0) / 2.0);
+        let b2 = T::lerp(&a2, &a3, t / 2.0);
+        T::lerp(&b1, &b2, t)
+    }
+}
+/// Keyframed camera parameters, parameters without a track are left unchanged
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraAnimation {
+    pub origin: Option<Track<Point3<RayScalar>>>,
+    pub look_at: Option<Track<Point3<RayScalar>>>,
+    pub fov: Option<Track<RayScalar>>,
+    pub focus_distance: Option<Track<RayScalar>>,
+}
+impl CameraAnimation {
+    /// Orbits the camera around `center` once over the given number of frames while looking at
+    /// the center
+    pub fn turntable(
+        center: Point3<RayScalar>,
+        radius: RayScalar,
+        height: RayScalar,
+        num_frames: usize,
+    ) -> Self {
+        const NUM_KEYS: usize = 32;
+        let mut origin = Track::new(Interpolation::CatmullRom);
+        for i in 0..=NUM_KEYS {
+            let angle = 2.0 * RayScalar::PI() * i as RayScalar / NUM_KEYS as RayScalar;
+            origin.insert(
+                num_frames as RayScalar * i as RayScalar / NUM_KEYS as RayScalar,
+                center + Vector3::new(radius * angle.cos(), height, radius * angle.sin()),
+            );
+        }
+        Self {
+            origin: Some(origin),
+            look_at: Some(Track::new(Interpolation::Linear).key(0.0, center)),
+            fov: None,
+            focus_distance: None,
+        }
+    }
+    /// gets the camera info at the given frame
+    pub fn sample(&self, frame: RayScalar, info: &CameraInfo) -> CameraInfo {
+        let mut info = info.clone();
+        if let Some(origin) = self.origin.as_ref().and_then(|track| track.sample(frame)) {
+            info.origin = origin;
+        }
+        if let Some(look_at) = self.look_at.as_ref().and_then(|track| track.sample(frame)) {
+            info.look_at = look_at;
+        }
+        if let Some(fov) = self.fov.as_ref().and_then(|track| track.sample(frame)) {
+            info.fov = fov;
+        }
+        if let Some(focus_distance) = self
+            .focus_distance
+            .as_ref()
+            .and_then(|track| track.sample(frame))
+        {
+            info.focus_distance = focus_distance;
+        }
+        info
+    }
+}
+/// Keyframed transform of the object at `entity_index` in the world
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformTrack {
+    pub entity_index: usize,
+    pub track: Track<Transform>,
+}
+/// Keyframed field of the object at `entity_index` in the world
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldTrack {
+    pub entity_index: usize,
+    pub field_name: String,
+    pub track: Track<EntityField>,
+}
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Animation {
+    pub camera: CameraAnimation,
+    pub transforms: Vec<TransformTrack>,
+    pub fields: Vec<FieldTrack>,
+}
+impl Animation {
+    /// moves the world to the given frame
+    pub fn apply(&self, world: &mut World, frame: RayScalar) {
+        world.camera = Camera::new(self.camera.sample(frame, world.camera.info()));
+        for transform in self.transforms.iter() {
+            if let Some(value) = transform.track.sample(frame) {
+                world.set_entity_transform(transform.entity_index, value);
+            }
+        }
+        for field in self.fields.iter() {
+            if let Some(value) = field.track.sample(frame) {
+                world.set_entity_data(field.entity_index, field.field_name.clone(), value);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn linear_track() {
+        let track = Track::new(Interpolation::Linear)
+            .key(10.0, 1.0)
+            .key(0.0, 0.0)
+            .key(20.0, 3.0);
+        assert_eq!(track.sample(-5.0), Some(0.0));
+        assert_eq!(track.sample(5.0), Some(0.5));
+        assert_eq!(track.sample(15.0), Some(2.0));
+        assert_eq!(track.sample(25.0), Some(3.0));
+        assert_eq!(
+            Track::<RayScalar>::new(Interpolation::Linear).sample(0.0),
+            None
+        );
+    }
+    #[test]
+    fn catmull_rom_track() {
+        let track = Track::new(Interpolation::CatmullRom)
+            .key(0.0, 0.0)
+            .key(1.0, 1.0)
+            .key(2.0, 2.0)
+            .key(3.0, 3.0);
+        for key in track.keyframes() {
+            assert!((track.sample(key.frame).unwrap() - key.value).abs() < 1e-10);
+        }
+        // evenly spaced keys on a line stay on the line
+        assert!((track.sample(1.5).unwrap() - 1.5).abs() < 1e-10);
+        let track = Track::new(Interpolation::CatmullRom)
+            .key(0.0, 0.0)
+            .key(1.0, 1.0)
+            .key(2.0, 0.0);
+        assert!(track.sample(0.5).unwrap() > 0.5);
+    }
+    #[test]
+    fn choice_field() {
+        let a = EntityField::Choice {
+            selected: "a".to_string(),
+            options: vec!["a".to_string(), "b".to_string()],
+        };
+        let b = EntityField::Choice {
+            selected: "b".to_string(),
+            options: vec!["a".to_string(), "b".to_string()],
+        };
+        assert_eq!(EntityField::lerp(&a, &b, 0.25), a);
+        assert_eq!(EntityField::lerp(&a, &b, 0.75), b);
+    }
+    #[test]
+    fn camera_animation() {
+        let animation = CameraAnimation {
+            fov: Some(
+                Track::new(Interpolation::Linear)
+                    .key(0.0, 20.0)
+                    .key(10.0, 40.0),
+            ),
+            ..Default::default()
+        };
+        let info = animation.sample(5.0, &CameraInfo::default());
+        assert_eq!(info.fov, 30.0);
+        assert_eq!(info.origin, CameraInfo::default().origin);
+    }
+    #[test]
+    fn apply() {
+        let mut world = super::super::world::one_sphere().build_world();
+        let start = world.camera.info().clone();
+        let moved = Transform::identity().translate(Vector3::new(0.0, 10.0, 0.0));
+        let animation = Animation {
+            camera: CameraAnimation {
+                origin: Some(
+                    Track::new(Interpolation::Linear)
+                        .key(0.0, Point3::new(0.0, 0.0, 10.0))
+                        .key(10.0, Point3::new(10.0, 0.0, 10.0)),
+                ),
+                ..Default::default()
+            },
+            transforms: vec![TransformTrack {
+                entity_index: 0,
+                track: Track::new(Interpolation::Linear)
+                    .key(0.0, Transform::identity())
+                    .key(10.0, moved),
+            }],
+            fields: vec![FieldTrack {
+                entity_index: 0,
+                field_name: "radius".to_string(),
+                track: Track::new(Interpolation::Linear)
+                    .key(0.0, EntityField::Float(0.5))
+                    .key(10.0, EntityField::Float(1.5)),
+            }],
+        };
+        animation.apply(&mut world, 5.0);
+        assert_eq!(world.camera.info().origin, Point3::new(5.0, 0.0, 10.0));
+        assert_eq!(world.camera.info().look_at, start.look_at);
+        assert_eq!(
+            world.get_entity_info().entities[0].fields["radius"],
+            EntityField::Float(1.0)
+        );
+
+        animation.apply(&mut world, 10.0);
+        assert_eq!(world.camera.info().origin, Point3::new(10.0, 0.0, 10.0));
+        // the sphere was moved up from (0, 0, -1)
+        let hit = |world: &World, y: RayScalar| {
+            world.nearest_hit(
+                &Ray {
+                    origin: Point3::new(0.0, y, 5.0),
+                    direction: Vector3::new(0.0, 0.0, -1.0),
+                    time: 0.0,
+                },
+                0.001,
+                f32::MAX,
+                &mut Sampler::new(0),
+            )
+        };
+        let moved_hit = hit(&world, 10.0).unwrap();
+        assert!((moved_hit.position.z - 0.5).abs() < 1e-4);
+        assert!(hit(&world, 0.0).is_none());
+    }
+}
@@ -1,5 +1,5 @@
 use super::{
-    hittable::{Hittable, Object},
+    hittable::{Hittable, Object, RayAreaInfo, Transform},
     ray_tracer_info::{Entity, EntityField, EntityInfo},
     HitRecord, Ray,
 };
@@ -7,8 +7,6 @@ use crate::prelude::*;
 
 use cgmath::Point3;
 
-use std::cmp::Ordering;
-
 #[derive(Clone, Copy, Debug)]
 pub struct Aabb {
     pub minimum: Point3<RayScalar>,
@@ -16,6 +14,17 @@ pub struct Aabb {
 }
 impl Aabb {
     pub fn hit(&self, ray: Ray, t_min: RayScalar, t_max: RayScalar) -> bool {
+        self.ray_interval(ray, t_min, t_max).is_some()
+    }
+    /// part of [t_min, t_max] where the ray is inside of the box
+    pub fn ray_interval(
+        &self,
+        ray: Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<(RayScalar, RayScalar)> {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
         for a in 0..3 {
             let inv_d = 1.0 / ray.direction[a];
             let mut t0 = (self.minimum[a] - ray.origin[a]) * inv_d;
@@ -23,13 +32,31 @@ impl Aabb {
             if inv_d < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let t_min = if t0 > t_min { t0 } else { t_min };
-            let t_max = if t1 < t_max { t1 } else { t_max };
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
             if t_max < t_min {
-                return false;
+                return None;
             }
         }
-        true
+        Some((t_min, t_max))
+    }
+    pub fn surface_area(&self) -> RayScalar {
+        let size = self.maximum - self.minimum;
+        2.0 * (size.x * size.y + size.x * size.z + size.y * size.z)
+    }
+    pub fn volume(&self) -> RayScalar {
+        let size = self.maximum - self.minimum;
+        size.x * size.y * size.z
+    }
+    pub fn centroid(&self) -> Point3<RayScalar> {
+        self.minimum + (self.maximum - self.minimum) / 2.0
+    }
+    /// box containing only the point
+    pub fn from_point(point: Point3<RayScalar>) -> Self {
+        Self {
+            minimum: point,
+            maximum: point,
+        }
     }
     pub fn surrounding_box(self, box1: Aabb) -> Self {
         Self {
@@ -69,36 +96,349 @@ impl Aabb {
         total < MAX_TOTAL_DIFFERENCE
     }
 }
+/// Bounding volume hierarchy built with the binned surface area heuristic. The nodes are stored
+/// depth first in one array, the first child of an interior node is the next node in the array.
+/// Objects without a bounding box, like infinite planes, are kept out of the tree and tested on
+/// every ray, so a scene should only have a few of them
 #[derive(Clone)]
 pub struct BvhTree {
     objects: Vec<Object>,
-    root_node: BvhTreeNode,
+    /// indices into `objects`, leaves reference a contiguous range of this array
+    indices: Vec<usize>,
+    /// objects without a bounding box
+    unbounded: Vec<usize>,
+    nodes: Vec<BvhNode>,
+    /// leaf node containing each object
+    leaf_of: Vec<usize>,
+    start_time: RayScalar,
+    end_time: RayScalar,
+}
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    bounding_box: Aabb,
+    kind: BvhNodeKind,
+    /// `None` for the root
+    parent: Option<usize>,
+    /// surface area when the node was built, used to detect when refitting has made the tree
+    /// too loose
+    build_area: RayScalar,
+}
+#[derive(Clone, Copy, Debug)]
+enum BvhNodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { second_child: usize, axis: usize },
+}
+/// per object info used while building
+#[derive(Clone, Copy, Debug)]
+struct BuildItem {
+    bounding_box: Aabb,
+    centroid: Point3<RayScalar>,
+}
+impl BuildItem {
+    fn new(object: &Object, start_time: RayScalar, end_time: RayScalar) -> Self {
+        let bounding_box = object
+            .bounding_box(start_time, end_time)
+            .expect("object does not have bounding box");
+        Self {
+            bounding_box,
+            centroid: bounding_box.centroid(),
+        }
+    }
+    /// item for an object that is not part of the build
+    fn placeholder() -> Self {
+        Self {
+            bounding_box: Aabb::from_point(Point3::new(0.0, 0.0, 0.0)),
+            centroid: Point3::new(0.0, 0.0, 0.0),
+        }
+    }
 }
 impl BvhTree {
+    const NUM_BINS: usize = 12;
+    const MAX_LEAF_SIZE: usize = 4;
+    /// cost of traversing a node relative to intersecting an object
+    const TRAVERSAL_COST: RayScalar = 0.5;
+    /// below this depth nodes are split at the median so the tree depth stays bounded
+    const MAX_SAH_DEPTH: usize = 64;
+    /// the stack holds at most one entry per level plus one
+    const MAX_STACK_SIZE: usize = Self::MAX_SAH_DEPTH + usize::BITS as usize + 1;
+    /// a subtree is rebuilt after refitting once its surface area grows by this factor
+    const REBUILD_AREA_RATIO: RayScalar = 2.0;
     pub fn new(objects: Vec<Object>, start_time: RayScalar, end_time: RayScalar) -> Self {
-        if objects.is_empty() {
-            Self {
-                objects: Vec::new(),
-                root_node: BvhTreeNode::None,
+        let (mut indices, unbounded): (Vec<usize>, Vec<usize>) = (0..objects.len())
+            .partition(|idx| objects[*idx].bounding_box(start_time, end_time).is_some());
+        // unbounded objects keep a placeholder so items can be indexed by object
+        let items = objects
+            .iter()
+            .enumerate()
+            .map(|(idx, object)| {
+                if unbounded.contains(&idx) {
+                    BuildItem::placeholder()
+                } else {
+                    BuildItem::new(object, start_time, end_time)
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut nodes = Vec::with_capacity(2 * indices.len());
+        if !indices.is_empty() {
+            Self::build(&items, &mut indices, 0, 0, None, &mut nodes);
+        }
+        let mut tree = Self {
+            leaf_of: vec![0; objects.len()],
+            objects,
+            indices,
+            unbounded,
+            nodes,
+            start_time,
+            end_time,
+        };
+        tree.update_leaf_of();
+        tree
+    }
+    fn update_leaf_of(&mut self) {
+        for (node_index, node) in self.nodes.iter().enumerate() {
+            if let BvhNodeKind::Leaf { first, count } = node.kind {
+                for idx in self.indices[first..first + count].iter() {
+                    self.leaf_of[*idx] = node_index;
+                }
+            }
+        }
+    }
+    /// builds the node for `indices[offset..]` and its children, returns the index of the node
+    fn build(
+        items: &[BuildItem],
+        indices: &mut [usize],
+        offset: usize,
+        depth: usize,
+        parent: Option<usize>,
+        nodes: &mut Vec<BvhNode>,
+    ) -> usize {
+        let bounding_box = indices
+            .iter()
+            .map(|idx| items[*idx].bounding_box)
+            .reduce(|acc, x| acc.surrounding_box(x))
+            .unwrap();
+        let node_index = nodes.len();
+        nodes.push(BvhNode {
+            bounding_box,
+            kind: BvhNodeKind::Leaf {
+                first: offset,
+                count: indices.len(),
+            },
+            parent,
+            build_area: bounding_box.surface_area(),
+        });
+        if indices.len() == 1 {
+            return node_index;
+        }
+        let split = if depth < Self::MAX_SAH_DEPTH {
+            Self::find_split(items, indices, bounding_box)
+        } else {
+            Some(Self::median_split(items, indices))
+        };
+        let (axis, middle) = match split {
+            Some(split) => split,
+            None => return node_index,
+        };
+        let (left, right) = indices.split_at_mut(middle);
+        Self::build(items, left, offset, depth + 1, Some(node_index), nodes);
+        let second_child = Self::build(
+            items,
+            right,
+            offset + middle,
+            depth + 1,
+            Some(node_index),
+            nodes,
+        );
+        nodes[node_index].kind = BvhNodeKind::Interior { second_child, axis };
+        node_index
+    }
+    /// Finds the cheapest split and partitions `indices` around it, returns the axis and the
+    /// index of the first item on the right side. Returns none if a leaf is cheaper
+    fn find_split(
+        items: &[BuildItem],
+        indices: &mut [usize],
+        bounding_box: Aabb,
+    ) -> Option<(usize, usize)> {
+        let centroid_box = indices
+            .iter()
+            .map(|idx| Aabb::from_point(items[*idx].centroid))
+            .reduce(|acc, x| acc.surrounding_box(x))
+            .unwrap();
+        let extent = centroid_box.maximum - centroid_box.minimum;
+        let axis = Self::longest_axis(centroid_box);
+        if extent[axis] <= 0.0 {
+            // all centroids are in the same place so binning can not separate them
+            if indices.len() <= Self::MAX_LEAF_SIZE {
+                return None;
             }
+            return Some((axis, indices.len() / 2));
+        }
+        let bin_of = |idx: usize| -> usize {
+            let relative = (items[idx].centroid[axis] - centroid_box.minimum[axis]) / extent[axis];
+            ((relative * Self::NUM_BINS as RayScalar) as usize).min(Self::NUM_BINS - 1)
+        };
+        let mut bin_counts = [0usize; Self::NUM_BINS];
+        let mut bin_boxes: [Option<Aabb>; Self::NUM_BINS] = [None; Self::NUM_BINS];
+        for idx in indices.iter() {
+            let bin = bin_of(*idx);
+            bin_counts[bin] += 1;
+            bin_boxes[bin] = Some(match bin_boxes[bin] {
+                Some(bin_box) => bin_box.surrounding_box(items[*idx].bounding_box),
+                None => items[*idx].bounding_box,
+            });
+        }
+        // cost of splitting after bin i, sweeping from the right to get the right side areas
+        let mut right_costs = [0.0; Self::NUM_BINS];
+        let mut right_box: Option<Aabb> = None;
+        let mut right_count = 0;
+        for i in (1..Self::NUM_BINS).rev() {
+            right_box = Self::merge(right_box, bin_boxes[i]);
+            right_count += bin_counts[i];
+            right_costs[i - 1] =
+                right_box.map_or(0.0, |b| b.surface_area()) * right_count as RayScalar;
+        }
+        let mut left_box: Option<Aabb> = None;
+        let mut left_count = 0;
+        let mut best: Option<(usize, RayScalar)> = None;
+        for i in 0..Self::NUM_BINS - 1 {
+            left_box = Self::merge(left_box, bin_boxes[i]);
+            left_count += bin_counts[i];
+            if left_count == 0 || left_count == indices.len() {
+                continue;
+            }
+            let cost = left_box.map_or(0.0, |b| b.surface_area()) * left_count as RayScalar
+                + right_costs[i];
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((i, cost));
+            }
+        }
+        let (best_bin, best_cost) = best?;
+        let split_cost =
+            Self::TRAVERSAL_COST + best_cost / bounding_box.surface_area().max(RayScalar::EPSILON);
+        if indices.len() <= Self::MAX_LEAF_SIZE && split_cost >= indices.len() as RayScalar {
+            return None;
+        }
+        // stable partition keeps builds deterministic
+        let (mut left, right): (Vec<usize>, Vec<usize>) =
+            indices.iter().partition(|idx| bin_of(**idx) <= best_bin);
+        let middle = left.len();
+        left.extend(right);
+        indices.copy_from_slice(&left);
+        Some((axis, middle))
+    }
+    /// sorts along the longest axis and splits in half
+    fn median_split(items: &[BuildItem], indices: &mut [usize]) -> (usize, usize) {
+        let centroid_box = indices
+            .iter()
+            .map(|idx| Aabb::from_point(items[*idx].centroid))
+            .reduce(|acc, x| acc.surrounding_box(x))
+            .unwrap();
+        let axis = Self::longest_axis(centroid_box);
+        indices.sort_by(|a, b| {
+            items[*a].centroid[axis]
+                .partial_cmp(&items[*b].centroid[axis])
+                .expect("centroid is NaN")
+        });
+        (axis, indices.len() / 2)
+    }
+    fn longest_axis(aabb: Aabb) -> usize {
+        let extent = aabb.maximum - aabb.minimum;
+        if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
         } else {
-            let root_node = BvhTreeNode::new(
-                &objects,
-                &objects,
-                0,
-                objects.len(),
-                0,
-                start_time,
-                end_time,
-            );
-            Self { objects, root_node }
+            2
+        }
+    }
+    fn merge(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.surrounding_box(b)),
+            (a, None) => a,
+            (None, b) => b,
         }
     }
-    pub fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
-        self.root_node.hit(&self.objects, ray, t_min, t_max)
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+        let mut t_max = t_max;
+        // media are tested once the nearest surface is known
+        let mut media = Vec::new();
+        // unbounded objects go first so their hits can cull the tree
+        for idx in self.unbounded.iter() {
+            if self.objects[*idx].is_medium() {
+                media.push(*idx);
+            } else if let Some(hit) = self.objects[*idx].hit(ray, t_min, t_max, sampler) {
+                t_max = hit.t;
+                closest = Some(hit);
+            }
+        }
+        let mut stack = [0usize; Self::MAX_STACK_SIZE];
+        let mut stack_size = if self.nodes.is_empty() { 0 } else { 1 };
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_index = stack[stack_size];
+            let node = &self.nodes[node_index];
+            if !node.bounding_box.hit(*ray, t_min, t_max) {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for idx in self.indices[first..first + count].iter() {
+                        if self.objects[*idx].is_medium() {
+                            media.push(*idx);
+                        } else if let Some(hit) = self.objects[*idx].hit(ray, t_min, t_max, sampler)
+                        {
+                            t_max = hit.t;
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                BvhNodeKind::Interior { second_child, axis } => {
+                    let first_child = node_index + 1;
+                    // push the far child first so the near child is visited first
+                    let (near, far) = if ray.direction[axis] < 0.0 {
+                        (second_child, first_child)
+                    } else {
+                        (first_child, second_child)
+                    };
+                    stack[stack_size] = far;
+                    stack[stack_size + 1] = near;
+                    stack_size += 2;
+                }
+            }
+        }
+        // every medium gets the same `t_max` so the result does not depend on the order
+        let surface_t = t_max;
+        for idx in media {
+            if let Some(hit) = self.objects[idx].hit(ray, t_min, surface_t, sampler) {
+                if hit.t < t_max {
+                    t_max = hit.t;
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest
     }
-    pub fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
-        self.root_node.bounding_box(&self.objects, time_0, time_1)
+    /// none if the tree has unbounded objects
+    pub fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        Some(
+            self.nodes
+                .first()
+                .map(|node| node.bounding_box)
+                .unwrap_or(Aabb {
+                    minimum: Point3::new(0.0, 0.0, 0.0),
+                    maximum: Point3::new(0.0, 0.0, 0.0),
+                }),
+        )
     }
     pub fn get_info(&self) -> Vec<EntityInfo> {
         self.objects
@@ -109,6 +449,14 @@ impl BvhTree {
             })
             .collect()
     }
+    pub fn set_transform(&mut self, entity_index: usize, transform: Transform) {
+        self.objects[entity_index].transform = transform;
+        self.refit_entity(entity_index);
+    }
+    /// rebuilds the whole tree
+    pub fn rebuild(&mut self, start_time: RayScalar, end_time: RayScalar) {
+        *self = Self::new(std::mem::take(&mut self.objects), start_time, end_time);
+    }
     pub fn update_entity(
         &mut self,
         entity_index: usize,
@@ -117,181 +465,194 @@ impl BvhTree {
     ) {
         let update_value = self.objects.get_mut(entity_index).unwrap();
         Entity::set_field(update_value, field_name, field_value);
+        self.refit_entity(entity_index);
     }
-}
-#[derive(Clone, Debug)]
-enum BvhTreeNode {
-    None,
-    Child {
-        bounding_box: Aabb,
-        left: Box<BvhTreeNode>,
-        right: Box<BvhTreeNode>,
-    },
-    Leaf {
-        idx: usize,
-    },
-}
-impl BvhTreeNode {
-    fn hit(
-        &self,
-        objects: &[Object],
-        ray: &Ray,
-        t_min: RayScalar,
-        t_max: RayScalar,
-    ) -> Option<HitRecord> {
-        if !self
-            .bounding_box(objects, t_min, t_max)
-            .expect("object does not have bounding box")
-            .hit(*ray, t_min, t_max)
-        {
-            None
-        } else {
-            match self {
-                Self::None => None,
-                Self::Child { left, right, .. } => {
-                    let left_hit = left.hit(objects, ray, t_min, t_max);
-                    if let Some(left_hit) = left_hit {
-                        let right_hit = right.hit(objects, ray, t_min, left_hit.t);
-                        if right_hit.is_some() {
-                            right_hit
-                        } else {
-                            Some(left_hit)
-                        }
-                    } else {
-                        right.hit(objects, ray, t_min, t_max)
-                    }
-                }
-                Self::Leaf { idx } => objects[*idx].hit(ray, t_min, t_max),
-            }
+    /// updates every bounding box in the tree without changing its structure
+    pub fn refit(&mut self) {
+        // children are always stored after their parents
+        for node_index in (0..self.nodes.len()).rev() {
+            self.nodes[node_index].bounding_box = self.node_box(node_index);
         }
     }
-    fn bounding_box(
-        &self,
-        objects: &[Object],
-        time_0: RayScalar,
-        time_1: RayScalar,
-    ) -> Option<Aabb> {
-        match self {
-            Self::None => Some(Aabb {
-                minimum: Point3::new(0.0, 0.0, 0.0),
-                maximum: Point3::new(0.0, 0.0, 0.0),
-            }),
-            Self::Child { bounding_box, .. } => Some(*bounding_box),
-            Self::Leaf { idx } => Some(objects[*idx].bounding_box(time_0, time_1).unwrap()),
-        }
-    }
-    fn box_compare(a: Object, b: Object, axis: usize) -> Ordering {
-        let a_box = a
-            .bounding_box(0.0, 0.0)
-            .expect("bvh node does not have bounding box");
-        let b_box = b
-            .bounding_box(0.0, 0.0)
-            .expect("bvh node does not have bounding box");
-
-        if a_box.minimum[axis] < b_box.minimum[axis] {
-            Ordering::Less
-        } else {
-            Ordering::Greater
+    /// Updates the bounding boxes after one object changed. If a subtree becomes too loose it is
+    /// rebuilt
+    pub fn refit_entity(&mut self, entity_index: usize) {
+        if self.unbounded.contains(&entity_index) {
+            return;
+        }
+        let mut loosest = None;
+        let mut node_index = Some(self.leaf_of[entity_index]);
+        while let Some(idx) = node_index {
+            let bounding_box = self.node_box(idx);
+            let node = &mut self.nodes[idx];
+            node.bounding_box = bounding_box;
+            if bounding_box.surface_area() > Self::REBUILD_AREA_RATIO * node.build_area {
+                loosest = Some(idx);
+            }
+            node_index = node.parent;
+        }
+        if let Some(idx) = loosest {
+            self.rebuild_subtree(idx);
         }
     }
-    fn box_x_compare(a: Object, b: Object) -> Ordering {
-        Self::box_compare(a, b, 0)
-    }
-    fn box_y_compare(a: Object, b: Object) -> Ordering {
-        Self::box_compare(a, b, 1)
+    /// gets the box of a node from its objects or its children's boxes
+    fn node_box(&self, node_index: usize) -> Aabb {
+        match self.nodes[node_index].kind {
+            BvhNodeKind::Leaf { first, count } => self.indices[first..first + count]
+                .iter()
+                .map(|idx| {
+                    self.objects[*idx]
+                        .bounding_box(self.start_time, self.end_time)
+                        .expect("object does not have bounding box")
+                })
+                .reduce(|acc, x| acc.surrounding_box(x))
+                .unwrap(),
+            BvhNodeKind::Interior { second_child, .. } => self.nodes[node_index + 1]
+                .bounding_box
+                .surrounding_box(self.nodes[second_child].bounding_box),
+        }
     }
-    fn box_z_compare(a: Object, b: Object) -> Ordering {
-        Self::box_compare(a, b, 2)
+    /// index one past the last node in the subtree
+    fn subtree_end(&self, node_index: usize) -> usize {
+        match self.nodes[node_index].kind {
+            BvhNodeKind::Leaf { .. } => node_index + 1,
+            BvhNodeKind::Interior { second_child, .. } => self.subtree_end(second_child),
+        }
     }
-    pub fn new(
-        objects: &[Object],
-        objects_full: &[Object],
-        start: usize,
-        end: usize,
-        offset: usize,
-        time_0: RayScalar,
-        time_1: RayScalar,
-    ) -> Self {
-        let axis = rand_u32(0, 2);
-        let span = end - start;
-        let comparator = if axis == 0 {
-            |a: &Object, b: &Object| Self::box_x_compare((*a).clone(), (*b).clone())
-        } else if axis == 1 {
-            |a: &Object, b: &Object| Self::box_y_compare((*a).clone(), (*b).clone())
-        } else {
-            |a: &Object, b: &Object| Self::box_z_compare((*a).clone(), (*b).clone())
-        };
-        let (left, right) = if span == 1 {
+    /// range of `indices` covered by the subtree
+    fn subtree_objects(&self, node_index: usize) -> std::ops::Range<usize> {
+        let mut first_leaf = node_index;
+        while let BvhNodeKind::Interior { .. } = self.nodes[first_leaf].kind {
+            first_leaf += 1;
+        }
+        let last_leaf = self.subtree_end(node_index) - 1;
+        match (self.nodes[first_leaf].kind, self.nodes[last_leaf].kind) {
             (
-                Self::Leaf {
-                    idx: start + offset,
+                BvhNodeKind::Leaf { first, .. },
+                BvhNodeKind::Leaf {
+                    first: last_first,
+                    count,
                 },
-                Self::Leaf {
-                    idx: start + offset,
-                },
-            )
-        } else if span == 2 {
-            if comparator(&objects[start].clone(), &objects[start + 1].clone()) == Ordering::Less {
-                (
-                    Self::Leaf {
-                        idx: start + offset,
-                    },
-                    Self::Leaf {
-                        idx: start + 1 + offset,
-                    },
-                )
-            } else {
-                (
-                    Self::Leaf {
-                        idx: start + 1 + offset,
-                    },
-                    Self::Leaf {
-                        idx: start + offset,
-                    },
-                )
-            }
-        } else {
-            let mut s_vec = (start..end).map(|i| objects[i].clone()).collect::<Vec<_>>();
-            s_vec.sort_by(comparator);
-            let middle = s_vec.len() / 2;
-
-            let left = Self::new(
-                &s_vec[0..middle],
-                objects_full,
-                0,
-                middle,
-                offset,
-                time_0,
-                time_1,
-            );
-            let s = &s_vec[middle..s_vec.len()];
-            let right = Self::new(
-                &s_vec[middle..s_vec.len()],
-                objects_full,
-                0,
-                s.len(),
-                offset + middle,
-                time_0,
-                time_1,
-            );
+            ) => first..last_first + count,
+            _ => unreachable!("subtree does not start and end with leaves"),
+        }
+    }
+    /// rebuilds the subtree in place and updates the nodes after it
+    fn rebuild_subtree(&mut self, node_index: usize) {
+        let node_end = self.subtree_end(node_index);
+        let object_range = self.subtree_objects(node_index);
+        let parent = self.nodes[node_index].parent;
+        let mut depth = 0;
+        let mut ancestor = parent;
+        while let Some(idx) = ancestor {
+            depth += 1;
+            ancestor = self.nodes[idx].parent;
+        }
 
-            (left, right)
+        let mut items = vec![BuildItem::placeholder(); self.objects.len()];
+        for idx in self.indices[object_range.clone()].iter() {
+            items[*idx] = BuildItem::new(&self.objects[*idx], self.start_time, self.end_time);
+        }
+        let mut new_nodes = Vec::with_capacity(2 * object_range.len());
+        Self::build(
+            &items,
+            &mut self.indices[object_range.clone()],
+            object_range.start,
+            depth,
+            None,
+            &mut new_nodes,
+        );
+        // the new nodes were built as their own tree, move them to their place in the array
+        for node in new_nodes.iter_mut() {
+            node.parent = match node.parent {
+                Some(idx) => Some(idx + node_index),
+                None => parent,
+            };
+            if let BvhNodeKind::Interior { second_child, .. } = &mut node.kind {
+                *second_child += node_index;
+            }
+        }
+        let new_end = node_index + new_nodes.len();
+        self.nodes.splice(node_index..node_end, new_nodes);
+        let shift = |idx: &mut usize| {
+            if *idx >= node_end {
+                *idx = *idx + new_end - node_end;
+            }
         };
-        let left_box = left
-            .bounding_box(objects_full, time_0, time_1)
-            .expect("no bounding box for object");
-        let right_box = right
-            .bounding_box(objects_full, time_0, time_1)
-            .expect("no bounding box for object");
-        let bounding_box = left_box.surrounding_box(right_box);
+        for (idx, node) in self.nodes.iter_mut().enumerate() {
+            if idx >= node_index && idx < new_end {
+                continue;
+            }
+            if let Some(parent) = node.parent.as_mut() {
+                shift(parent);
+            }
+            if let BvhNodeKind::Interior { second_child, .. } = &mut node.kind {
+                shift(second_child);
+            }
+        }
+        self.update_leaf_of();
 
-        Self::Child {
-            left: Box::new(left),
-            right: Box::new(right),
-            bounding_box,
+        let mut ancestor = parent;
+        while let Some(idx) = ancestor {
+            self.nodes[idx].bounding_box = self.node_box(idx);
+            ancestor = self.nodes[idx].parent;
         }
     }
 }
+/// Lets a group of objects be used as the shape of an object, so whole groups can be instanced
+/// with the tree acting as the bottom level of a two level hierarchy
+impl Hittable for BvhTree {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        BvhTree::hit(self, ray, t_min, t_max, sampler)
+    }
+    fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
+        BvhTree::bounding_box(self, time_0, time_1)
+    }
+    /// rays are generated towards one object picked uniformly so the probability is the mean
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        self.objects
+            .iter()
+            .map(|object| object.prob(ray, sampler))
+            .sum::<RayScalar>()
+            / self.objects.len() as RayScalar
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        // an empty tree has no surface, the ray has a probability of zero so it is never used
+        if self.objects.is_empty() {
+            let direction = cgmath::Vector3::new(0.0, 1.0, 0.0);
+            return RayAreaInfo {
+                to_area: Ray {
+                    origin,
+                    direction,
+                    time,
+                },
+                area: 0.0,
+                direction,
+                normal: -direction,
+                end_point: origin + direction,
+            };
+        }
+        let idx = sampler.u32(0, self.objects.len() as u32) as usize;
+        self.objects[idx].generate_ray_in_area(origin, time, sampler)
+    }
+    fn name(&self) -> String {
+        "Bvh Tree".to_string()
+    }
+}
 #[cfg(test)]
 mod test {
     use super::*;
@@ -310,4 +671,201 @@ mod test {
         assert!(aabb_zero.approx_eq(aabb_zero));
         assert!(!aabb_zero.approx_eq(aabb));
     }
+    fn sphere_grid() -> Vec<Object> {
+        use crate::ray_tracer::{hittable::Sphere, material::Lambertian, texture::SolidColor};
+        let mut objects = vec![];
+        for x in 0..10 {
+            for y in 0..10 {
+                for z in 0..3 {
+                    let sphere = Sphere {
+                        radius: 0.3 + 0.05 * ((x + y + z) % 4) as RayScalar,
+                        origin: Point3::new(x as RayScalar, y as RayScalar, 2.0 * z as RayScalar),
+                        material: Box::new(Lambertian {
+                            albedo: Box::new(SolidColor {
+                                color: RgbColor::WHITE,
+                            }),
+                        }),
+                    };
+                    objects.push(Object::new(Box::new(sphere), Transform::identity()));
+                }
+            }
+        }
+        objects
+    }
+    /// checks the tree against testing every object
+    fn check_brute_force(tree: &BvhTree) {
+        for i in 0..200 {
+            let ray = Ray {
+                origin: Point3::new(-5.0, 4.5, -5.0),
+                direction: cgmath::Vector3::new(
+                    1.0,
+                    (i % 20) as RayScalar / 20.0 - 0.5,
+                    (i / 20) as RayScalar / 10.0,
+                ),
+                time: 0.0,
+            };
+            let expected = tree
+                .objects
+                .iter()
+                .filter_map(|object| object.hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0)))
+                .map(|hit| hit.t)
+                .reduce(RayScalar::min);
+            let actual = tree
+                .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+                .map(|hit| hit.t);
+            assert_eq!(expected, actual);
+        }
+    }
+    #[test]
+    fn matches_brute_force() {
+        check_brute_force(&BvhTree::new(sphere_grid(), 0.0, 0.0));
+    }
+    #[test]
+    fn refit_moved_object() {
+        let mut tree = BvhTree::new(sphere_grid(), 0.0, 0.0);
+        let num_nodes = tree.nodes.len();
+        tree.set_transform(
+            10,
+            Transform::identity().translate(cgmath::Vector3::new(0.2, 0.1, 0.0)),
+        );
+        // small moves only refit the boxes
+        assert_eq!(tree.nodes.len(), num_nodes);
+        check_brute_force(&tree);
+
+        let ray = Ray {
+            origin: Point3::new(-5.0, 23.0, 2.0),
+            direction: cgmath::Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        assert!(tree
+            .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .is_none());
+        // moving far away makes the tree too loose so part of it is rebuilt
+        tree.set_transform(
+            10,
+            Transform::identity().translate(cgmath::Vector3::new(0.0, 20.0, 0.0)),
+        );
+        check_brute_force(&tree);
+        let hit = tree
+            .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .unwrap();
+        assert!((hit.position.y - 23.0).abs() < 1e-6);
+        for (node_index, node) in tree.nodes.iter().enumerate() {
+            if let BvhNodeKind::Interior { second_child, .. } = node.kind {
+                assert_eq!(tree.nodes[node_index + 1].parent, Some(node_index));
+                assert_eq!(tree.nodes[second_child].parent, Some(node_index));
+            }
+        }
+
+        tree.refit();
+        check_brute_force(&tree);
+    }
+    #[test]
+    fn deterministic_build() {
+        let a = BvhTree::new(sphere_grid(), 0.0, 0.0);
+        let b = BvhTree::new(sphere_grid(), 0.0, 0.0);
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.nodes.len(), b.nodes.len());
+        for (node_a, node_b) in a.nodes.iter().zip(b.nodes.iter()) {
+            assert!(node_a.bounding_box.approx_eq(node_b.bounding_box));
+        }
+    }
+    #[test]
+    fn empty_tree() {
+        let tree = BvhTree::new(vec![], 0.0, 0.0);
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: cgmath::Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        assert!(tree
+            .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .is_none());
+    }
+    /// infinite planes are kept out of the nodes but are still hit
+    #[test]
+    fn unbounded_objects() {
+        use crate::ray_tracer::{
+            hittable::InfinitePlane, material::Lambertian, texture::SolidColor,
+        };
+        let plane = || {
+            Object::new(
+                Box::new(InfinitePlane {
+                    point: Point3::new(0.0, 0.2, 0.0),
+                    normal: cgmath::Vector3::unit_y(),
+                    material: Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: RgbColor::WHITE,
+                        }),
+                    }),
+                }),
+                Transform::identity(),
+            )
+        };
+        let mut objects = sphere_grid();
+        objects.push(plane());
+        let plane_index = objects.len() - 1;
+        let mut tree = BvhTree::new(objects, 0.0, 0.0);
+        assert_eq!(tree.unbounded, vec![plane_index]);
+        assert_eq!(
+            tree.nodes.len(),
+            BvhTree::new(sphere_grid(), 0.0, 0.0).nodes.len()
+        );
+        assert!(tree.bounding_box(0.0, 0.0).is_none());
+        check_brute_force(&tree);
+        tree.set_transform(
+            plane_index,
+            Transform::identity().translate(cgmath::Vector3::new(0.0, 2.0, 0.0)),
+        );
+        check_brute_force(&tree);
+        // a tree with only unbounded objects has no nodes
+        check_brute_force(&BvhTree::new(vec![plane()], 0.0, 0.0));
+    }
+    #[test]
+    fn instanced_tree() {
+        use std::sync::Arc;
+        let group: Arc<dyn Hittable + Send> = Arc::new(BvhTree::new(sphere_grid(), 0.0, 0.0));
+        let offset = cgmath::Vector3::new(0.0, 0.0, 10.0);
+        let objects = vec![
+            Object::instance(group.clone(), Transform::identity()),
+            Object::instance(group, Transform::identity().translate(offset)),
+        ];
+        let top_level = BvhTree::new(objects, 0.0, 0.0);
+        let mut flat = sphere_grid();
+        flat.extend(sphere_grid().into_iter().map(|mut object| {
+            object.transform = Transform::identity().translate(offset);
+            object
+        }));
+        let flat = BvhTree::new(flat, 0.0, 0.0);
+        for i in 0..200 {
+            let ray = Ray {
+                origin: Point3::new(-5.0, 4.5, -5.0),
+                direction: cgmath::Vector3::new(
+                    1.0,
+                    (i % 20) as RayScalar / 20.0 - 0.5,
+                    (i / 20) as RayScalar / 5.0,
+                ),
+                time: 0.0,
+            };
+            let expected = flat
+                .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+                .map(|hit| hit.t);
+            let actual = top_level
+                .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+                .map(|hit| hit.t);
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => assert!((expected - actual).abs() < 1e-9),
+                (None, None) => {}
+                _ => panic!("instanced tree does not match"),
+            }
+        }
+    }
+    #[test]
+    fn empty_tree_light() {
+        let tree = BvhTree::new(Vec::new(), 0.0, 0.0);
+        let mut sampler = Sampler::new(0);
+        let area_info =
+            Hittable::generate_ray_in_area(&tree, Point3::new(0.0, 0.0, 0.0), 0.0, &mut sampler);
+        assert_eq!(Hittable::prob(&tree, area_info.to_area, &mut sampler), 0.0);
+    }
 }
@@ -1,12 +1,15 @@
 use super::{
     ray_tracer_info::{Entity, EntityField},
-    save_file::{traits::Savable, SceneSaveError},
+    save_file::{
+        traits::{add_missing_column, Savable},
+        SceneSaveError,
+    },
 };
 use crate::prelude::*;
 
 use cgmath::{num_traits::FloatConst, InnerSpace, Point3, Vector3};
 use rusqlite::Connection;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, path::Path, str::FromStr, sync::Arc};
 use uuid::Uuid;
 
 /// info used to construct camera
@@ -21,6 +24,250 @@ pub struct CameraInfo {
     pub focus_distance: RayScalar,
     pub start_time: RayScalar,
     pub end_time: RayScalar,
+    pub projection: Projection,
+    /// shape of the lens opening, determines the shape of out of focus highlights
+    pub aperture_shape: ApertureShape,
+    /// lateral chromatic aberration, the red channel is magnified by `1 + chromatic_aberration`
+    /// and the blue channel by `1 - chromatic_aberration`
+    pub chromatic_aberration: RayScalar,
+    /// radial lens distortion, positive values give barrel distortion and negative values give
+    /// pincushion distortion
+    pub distortion: RayScalar,
+}
+impl Default for CameraInfo {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: 1.0,
+            fov: 40.0,
+            origin: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance: 1.0,
+            start_time: 0.0,
+            end_time: 0.0,
+            projection: Projection::Perspective,
+            aperture_shape: ApertureShape::Circle,
+            chromatic_aberration: 0.0,
+            distortion: 0.0,
+        }
+    }
+}
+/// Shape of the camera's aperture, the size is set by `CameraInfo::aperture`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApertureShape {
+    Circle,
+    /// regular polygon made by the aperture blades, rotation is in radians
+    Polygon {
+        blades: u32,
+        rotation: RayScalar,
+    },
+    /// arbitrary shape given by a grayscale mask
+    Mask(ApertureMask),
+}
+impl ApertureShape {
+    /// number of blades used when switching to a polygon aperture
+    const DEFAULT_BLADES: u32 = 6;
+    /// shapes that can be picked without extra data, masks have to be set up in code
+    pub fn names() -> [String; 2] {
+        [
+            Self::Circle.to_string(),
+            Self::default_polygon().to_string(),
+        ]
+    }
+    fn default_polygon() -> Self {
+        Self::Polygon {
+            blades: Self::DEFAULT_BLADES,
+            rotation: 0.0,
+        }
+    }
+    /// samples a point on the aperture, the point is inside of the unit square
+    fn sample(&self, sampler: &mut Sampler) -> Vector3<RayScalar> {
+        match self {
+            Self::Circle => sampler.unit_disk(),
+            Self::Polygon { blades, rotation } => {
+                if *blades < 3 {
+                    return sampler.unit_disk();
+                }
+                let blade = sampler.u32(0, *blades);
+                let angle_a =
+                    rotation + 2.0 * RayScalar::PI() * blade as RayScalar / *blades as RayScalar;
+                let angle_b = angle_a + 2.0 * RayScalar::PI() / *blades as RayScalar;
+                let mut r1 = sampler.next_scalar();
+                let mut r2 = sampler.next_scalar();
+                if r1 + r2 > 1.0 {
+                    r1 = 1.0 - r1;
+                    r2 = 1.0 - r2;
+                }
+                r1 * Vector3::new(angle_a.cos(), angle_a.sin(), 0.0)
+                    + r2 * Vector3::new(angle_b.cos(), angle_b.sin(), 0.0)
+            }
+            Self::Mask(mask) => mask.sample(sampler),
+        }
+    }
+}
+impl fmt::Display for ApertureShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Circle => write!(f, "Circle"),
+            Self::Polygon { .. } => write!(f, "Polygon"),
+            Self::Mask(_) => write!(f, "Mask"),
+        }
+    }
+}
+/// Grayscale image used as the aperture, a value of 1 is fully open and 0 is closed
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    values: Arc<[f32]>,
+}
+impl ApertureMask {
+    /// number of rejection sampling attempts before giving up and sampling the center
+    const MAX_SAMPLE_ATTEMPTS: usize = 64;
+    pub fn new(width: usize, height: usize, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), width * height);
+        Self {
+            width,
+            height,
+            values: values.into(),
+        }
+    }
+    /// builds the mask from a function taking x and y in the range \[-1, 1\]
+    pub fn from_fn<F: Fn(RayScalar, RayScalar) -> f32>(width: usize, height: usize, f: F) -> Self {
+        let mut values = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                values.push(f(
+                    2.0 * (x as RayScalar + 0.5) / width as RayScalar - 1.0,
+                    2.0 * (y as RayScalar + 0.5) / height as RayScalar - 1.0,
+                ));
+            }
+        }
+        Self::new(width, height, values)
+    }
+    /// loads the mask from an image, the luminance of the image is used
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_luma8();
+        let values = image
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / 255.0)
+            .collect::<Vec<_>>();
+        Ok(Self::new(
+            image.width() as usize,
+            image.height() as usize,
+            values,
+        ))
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+    fn get(&self, x: RayScalar, y: RayScalar) -> f32 {
+        let x = (((x + 1.0) / 2.0 * self.width as RayScalar) as usize).min(self.width - 1);
+        let y = (((y + 1.0) / 2.0 * self.height as RayScalar) as usize).min(self.height - 1);
+        self.values[y * self.width + x]
+    }
+    fn sample(&self, sampler: &mut Sampler) -> Vector3<RayScalar> {
+        for _ in 0..Self::MAX_SAMPLE_ATTEMPTS {
+            let x = sampler.scalar(-1.0, 1.0);
+            let y = sampler.scalar(-1.0, 1.0);
+            if sampler.next_scalar() < self.get(x, y) as RayScalar {
+                return Vector3::new(x, y, 0.0);
+            }
+        }
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+}
+/// How screen coordinates are mapped to rays
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    /// thin lens perspective camera
+    Perspective,
+    /// parallel rays, the view is the same size as the perspective view at the focus distance
+    Orthographic,
+    /// full 360 degree panorama, u maps to longitude and v to latitude
+    Equirectangular,
+    /// fisheye where the distance from the center of the image is proportional to the angle,
+    /// `fov` is the angle covered by the image circle
+    FisheyeEquidistant,
+    /// fisheye that preserves solid angle, `fov` is the angle covered by the image circle
+    FisheyeEquisolid,
+    /// one 90 degree face of a cube map centered on the camera
+    CubeMap(CubeMapFace),
+}
+impl Projection {
+    const ALL: [Self; 11] = [
+        Self::Perspective,
+        Self::Orthographic,
+        Self::Equirectangular,
+        Self::FisheyeEquidistant,
+        Self::FisheyeEquisolid,
+        Self::CubeMap(CubeMapFace::Front),
+        Self::CubeMap(CubeMapFace::Back),
+        Self::CubeMap(CubeMapFace::Left),
+        Self::CubeMap(CubeMapFace::Right),
+        Self::CubeMap(CubeMapFace::Up),
+        Self::CubeMap(CubeMapFace::Down),
+    ];
+    pub fn names() -> [String; 11] {
+        Self::ALL.map(|projection| projection.to_string())
+    }
+}
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Perspective => write!(f, "Perspective"),
+            Self::Orthographic => write!(f, "Orthographic"),
+            Self::Equirectangular => write!(f, "Equirectangular"),
+            Self::FisheyeEquidistant => write!(f, "Fisheye Equidistant"),
+            Self::FisheyeEquisolid => write!(f, "Fisheye Equisolid"),
+            Self::CubeMap(face) => write!(f, "Cube Map {:?}", face),
+        }
+    }
+}
+impl FromStr for Projection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|projection| projection.to_string() == s)
+            .copied()
+            .ok_or(format!("invalid projection: {}", s))
+    }
+}
+/// Face of a cube map, directions are relative to the camera's orientation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubeMapFace {
+    Front,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+impl CubeMapFace {
+    /// gets the (forward, right, up) vectors of the face
+    fn basis(
+        self,
+        forward: Vector3<RayScalar>,
+        right: Vector3<RayScalar>,
+        up: Vector3<RayScalar>,
+    ) -> (Vector3<RayScalar>, Vector3<RayScalar>, Vector3<RayScalar>) {
+        match self {
+            Self::Front => (forward, right, up),
+            Self::Back => (-forward, -right, up),
+            Self::Left => (-right, forward, up),
+            Self::Right => (right, -forward, up),
+            Self::Up => (up, right, -forward),
+            Self::Down => (-up, right, forward),
+        }
+    }
 }
 #[derive(Clone, Debug, PartialEq)]
 pub struct Camera {
@@ -30,6 +277,7 @@ pub struct Camera {
     vertical: Vector3<RayScalar>,
     u: Vector3<RayScalar>,
     v: Vector3<RayScalar>,
+    w: Vector3<RayScalar>,
     look_at: Point3<RayScalar>,
     lens_radius: RayScalar,
     start_time: RayScalar,
@@ -63,6 +311,7 @@ impl Camera {
                 - info.focus_distance * w,
             u,
             v,
+            w,
             lens_radius: info.aperture / 2.0,
             start_time: info.start_time,
             end_time: info.end_time,
@@ -84,26 +333,127 @@ impl Camera {
         let v = w.cross(u);
         (w, u, v)
     }
-    /// gets the ray for  the given screen coordinates
-    pub fn get_ray(&self, u: RayScalar, v: RayScalar) -> Ray {
-        let rd = self.lens_radius * Self::random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
-        Ray {
-            origin: self.origin,
-            direction: self.lower_left_corner + u * self.horizontal + v * self.vertical
-                - self.origin
-                - offset,
-            time: rand_scalar(self.start_time, self.end_time),
+    /// gets the ray for  the given screen coordinates, returns none if the coordinates are not
+    /// covered by the projection
+    pub fn get_ray(&self, u: RayScalar, v: RayScalar, sampler: &mut Sampler) -> Option<Ray> {
+        let (u, v) = self.distort(u, v, 1.0);
+        let lens = self.lens_radius * self.info.aperture_shape.sample(sampler);
+        let time = sampler.scalar(self.start_time, self.end_time);
+        self.project(u, v, lens, time)
+    }
+    /// gets the ray for the given screen coordinates along with the color it carries, if
+    /// there is chromatic aberration each ray only carries one color channel
+    pub fn get_weighted_ray(
+        &self,
+        u: RayScalar,
+        v: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<(Ray, RgbColor)> {
+        if self.info.chromatic_aberration == 0.0 {
+            return self
+                .get_ray(u, v, sampler)
+                .map(|ray| (ray, RgbColor::WHITE));
+        }
+        let (scale, weight) = match sampler.u32(0, 3) {
+            0 => (1.0 + self.info.chromatic_aberration, RgbColor::RED),
+            1 => (1.0, RgbColor::GREEN),
+            _ => (1.0 - self.info.chromatic_aberration, RgbColor::BLUE),
+        };
+        let (u, v) = self.distort(u, v, scale);
+        let lens = self.lens_radius * self.info.aperture_shape.sample(sampler);
+        let time = sampler.scalar(self.start_time, self.end_time);
+        self.project(u, v, lens, time)
+            .map(|ray| (ray, 3.0 * weight))
+    }
+    /// gets the ray through the center of the lens, used for focusing
+    pub fn get_focus_ray(&self, u: RayScalar, v: RayScalar) -> Option<Ray> {
+        let (u, v) = self.distort(u, v, 1.0);
+        self.project(u, v, Vector3::new(0.0, 0.0, 0.0), self.start_time)
+    }
+    /// applies radial distortion and magnification to the screen coordinates
+    fn distort(&self, u: RayScalar, v: RayScalar, scale: RayScalar) -> (RayScalar, RayScalar) {
+        if self.info.distortion == 0.0 && scale == 1.0 {
+            return (u, v);
         }
+        let x = u - 0.5;
+        let y = v - 0.5;
+        let radius_squared = (2.0 * x * self.info.aspect_ratio).powi(2) + (2.0 * y).powi(2);
+        let factor = (1.0 + self.info.distortion * radius_squared) / scale;
+        (0.5 + x * factor, 0.5 + y * factor)
     }
-    fn random_in_unit_disk() -> Vector3<RayScalar> {
-        loop {
-            let p = Vector3::new(rand_scalar(-1.0, 1.0), rand_scalar(-1.0, 1.0), 0.0);
-            if p.dot(p) < 1.0 {
-                return p;
+    fn project(
+        &self,
+        u: RayScalar,
+        v: RayScalar,
+        lens: Vector3<RayScalar>,
+        time: RayScalar,
+    ) -> Option<Ray> {
+        match self.info.projection {
+            Projection::Perspective => {
+                // thin lens, the ray starts at the sampled point on the lens and goes through
+                // the point on the focus plane
+                let offset = self.u * lens.x + self.v * lens.y;
+                Some(Ray {
+                    origin: self.origin + offset,
+                    direction: self.lower_left_corner + u * self.horizontal + v * self.vertical
+                        - self.origin
+                        - offset,
+                    time,
+                })
+            }
+            Projection::Orthographic => Some(Ray {
+                origin: self.lower_left_corner
+                    + u * self.horizontal
+                    + v * self.vertical
+                    + self.focus_distance * self.w,
+                direction: -self.w,
+                time,
+            }),
+            Projection::Equirectangular => {
+                let longitude = (u - 0.5) * 2.0 * RayScalar::PI();
+                let latitude = (v - 0.5) * RayScalar::PI();
+                Some(Ray {
+                    origin: self.origin,
+                    direction: latitude.cos()
+                        * (longitude.sin() * self.u - longitude.cos() * self.w)
+                        + latitude.sin() * self.v,
+                    time,
+                })
+            }
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                let x = (u - 0.5) * 2.0 * self.info.aspect_ratio;
+                let y = (v - 0.5) * 2.0;
+                let radius = (x * x + y * y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let max_angle = self.info.fov * RayScalar::PI() / 360.0;
+                let angle = if self.info.projection == Projection::FisheyeEquidistant {
+                    radius * max_angle
+                } else {
+                    2.0 * (radius * (max_angle / 2.0).sin()).asin()
+                };
+                let azimuth = y.atan2(x);
+                Some(Ray {
+                    origin: self.origin,
+                    direction: angle.sin() * (azimuth.cos() * self.u + azimuth.sin() * self.v)
+                        - angle.cos() * self.w,
+                    time,
+                })
+            }
+            Projection::CubeMap(face) => {
+                let (forward, right, up) = face.basis(-self.w, self.u, self.v);
+                Some(Ray {
+                    origin: self.origin,
+                    direction: forward + (2.0 * u - 1.0) * right + (2.0 * v - 1.0) * up,
+                    time,
+                })
             }
         }
     }
+    pub fn info(&self) -> &CameraInfo {
+        &self.info
+    }
     pub fn start_time(&self) -> RayScalar {
         self.start_time
     }
@@ -120,6 +470,69 @@ impl Camera {
         info.origin = origin;
         *self = Self::new(info);
     }
+    fn set_projection(&mut self, projection: Projection) {
+        let mut info = self.info.clone();
+        info.projection = projection;
+        *self = Self::new(info);
+    }
+    pub fn set_focus_distance(&mut self, focus_distance: RayScalar) {
+        let mut info = self.info.clone();
+        info.focus_distance = focus_distance;
+        *self = Self::new(info);
+    }
+    fn set_aperture(&mut self, aperture: RayScalar) {
+        let mut info = self.info.clone();
+        info.aperture = aperture;
+        *self = Self::new(info);
+    }
+    fn set_aperture_shape(&mut self, name: &str) {
+        if name == self.info.aperture_shape.to_string() {
+            return;
+        }
+        self.info.aperture_shape = match name {
+            "Circle" => ApertureShape::Circle,
+            "Polygon" => ApertureShape::default_polygon(),
+            _ => {
+                error!("can not switch aperture shape to {}", name);
+                return;
+            }
+        };
+    }
+    fn set_aperture_blades(&mut self, blades: RayScalar) {
+        let blades = blades.round().max(3.0) as u32;
+        self.info.aperture_shape = match self.info.aperture_shape {
+            ApertureShape::Polygon { rotation, .. } => ApertureShape::Polygon { blades, rotation },
+            _ => ApertureShape::Polygon {
+                blades,
+                rotation: 0.0,
+            },
+        };
+    }
+    fn set_aperture_rotation(&mut self, rotation: RayScalar) {
+        self.info.aperture_shape = match self.info.aperture_shape {
+            ApertureShape::Polygon { blades, .. } => ApertureShape::Polygon { blades, rotation },
+            _ => ApertureShape::Polygon {
+                blades: ApertureShape::DEFAULT_BLADES,
+                rotation,
+            },
+        };
+    }
+    fn set_chromatic_aberration(&mut self, chromatic_aberration: RayScalar) {
+        self.info.chromatic_aberration = chromatic_aberration;
+    }
+    fn set_distortion(&mut self, distortion: RayScalar) {
+        self.info.distortion = distortion;
+    }
+    /// moves the focus plane so that it goes through the given point, returns the new focus
+    /// distance or none if the point is behind the camera
+    pub fn focus_on(&mut self, point: Point3<RayScalar>) -> Option<RayScalar> {
+        let focus_distance = (point - self.origin).dot(-self.w);
+        if focus_distance <= 0.0 {
+            return None;
+        }
+        self.set_focus_distance(focus_distance);
+        Some(focus_distance)
+    }
 }
 impl Entity for Camera {
     fn name(&self) -> String {
@@ -132,6 +545,50 @@ impl Entity for Camera {
             "look_at".to_string(),
             EntityField::Point3(self.info.look_at),
         );
+        map.insert(
+            "projection".to_string(),
+            EntityField::Choice {
+                selected: self.info.projection.to_string(),
+                options: Projection::names().to_vec(),
+            },
+        );
+        map.insert(
+            "focus_distance".to_string(),
+            EntityField::Float(self.info.focus_distance),
+        );
+        map.insert(
+            "aperture".to_string(),
+            EntityField::Float(self.info.aperture),
+        );
+        let mut aperture_shapes = ApertureShape::names().to_vec();
+        if let ApertureShape::Mask(_) = self.info.aperture_shape {
+            aperture_shapes.push(self.info.aperture_shape.to_string());
+        }
+        map.insert(
+            "aperture_shape".to_string(),
+            EntityField::Choice {
+                selected: self.info.aperture_shape.to_string(),
+                options: aperture_shapes,
+            },
+        );
+        if let ApertureShape::Polygon { blades, rotation } = self.info.aperture_shape {
+            map.insert(
+                "aperture_blades".to_string(),
+                EntityField::Float(blades as RayScalar),
+            );
+            map.insert(
+                "aperture_rotation".to_string(),
+                EntityField::Float(rotation),
+            );
+        }
+        map.insert(
+            "chromatic_aberration".to_string(),
+            EntityField::Float(self.info.chromatic_aberration),
+        );
+        map.insert(
+            "distortion".to_string(),
+            EntityField::Float(self.info.distortion),
+        );
         map
     }
     fn set_field(&mut self, key: String, value: EntityField) {
@@ -144,6 +601,41 @@ impl Entity for Camera {
                 EntityField::Point3(p) => self.set_look_at(p),
                 _ => panic!("invalid field type"),
             },
+            "projection" => match value {
+                EntityField::Choice { selected, .. } => match Projection::from_str(&selected) {
+                    Ok(projection) => self.set_projection(projection),
+                    Err(error) => error!("{}", error),
+                },
+                _ => panic!("invalid field type"),
+            },
+            "focus_distance" => match value {
+                EntityField::Float(v) => self.set_focus_distance(v),
+                _ => panic!("invalid field type"),
+            },
+            "aperture" => match value {
+                EntityField::Float(v) => self.set_aperture(v),
+                _ => panic!("invalid field type"),
+            },
+            "aperture_shape" => match value {
+                EntityField::Choice { selected, .. } => self.set_aperture_shape(&selected),
+                _ => panic!("invalid field type"),
+            },
+            "aperture_blades" => match value {
+                EntityField::Float(v) => self.set_aperture_blades(v),
+                _ => panic!("invalid field type"),
+            },
+            "aperture_rotation" => match value {
+                EntityField::Float(v) => self.set_aperture_rotation(v),
+                _ => panic!("invalid field type"),
+            },
+            "chromatic_aberration" => match value {
+                EntityField::Float(v) => self.set_chromatic_aberration(v),
+                _ => panic!("invalid field type"),
+            },
+            "distortion" => match value {
+                EntityField::Float(v) => self.set_distortion(v),
+                _ => panic!("invalid field type"),
+            },
             _ => panic!("invalid field: {}", key),
         };
     }
@@ -173,6 +665,10 @@ impl Savable for Camera {
         todo!()
     }
 
+    fn migrate(version: u32, connection: &Connection) -> Result<(), SceneSaveError> {
+        CameraInfo::migrate(version, connection)
+    }
+
     fn save(&self, connection: &Connection) -> Result<Uuid, SceneSaveError> {
         let info_uuid = self.info.save(connection)?;
         let self_uuid = Uuid::new_v4();
@@ -240,7 +736,16 @@ impl Savable for CameraInfo {
                 aperture REAL NOT NULL,\
                 focus_distance REAL NOT NULL,\
                 start_time REAL NOT NULL,\
-                end_time REAL NOT NULL
+                end_time REAL NOT NULL,\
+                projection TEXT NOT NULL,\
+                \
+                aperture_blades INTEGER NOT NULL,\
+                aperture_rotation REAL NOT NULL,\
+                aperture_mask_width INTEGER,\
+                aperture_mask_height INTEGER,\
+                aperture_mask BLOB,\
+                chromatic_aberration REAL NOT NULL,\
+                distortion REAL NOT NULL
             ) STRICT",
             self_name = <Self as Savable>::database_name()
         );
@@ -252,8 +757,48 @@ impl Savable for CameraInfo {
         todo!()
     }
 
+    fn migrate(version: u32, connection: &Connection) -> Result<(), SceneSaveError> {
+        if version < 1 {
+            add_missing_column(
+                connection,
+                Self::database_name(),
+                "projection",
+                &format!("TEXT NOT NULL DEFAULT '{}'", Projection::Perspective),
+            )?;
+        }
+        if version < 2 {
+            for (column, definition) in [
+                ("aperture_blades", "INTEGER NOT NULL DEFAULT 0"),
+                ("aperture_rotation", "REAL NOT NULL DEFAULT 0.0"),
+                ("aperture_mask_width", "INTEGER"),
+                ("aperture_mask_height", "INTEGER"),
+                ("aperture_mask", "BLOB"),
+                ("chromatic_aberration", "REAL NOT NULL DEFAULT 0.0"),
+                ("distortion", "REAL NOT NULL DEFAULT 0.0"),
+            ] {
+                add_missing_column(connection, Self::database_name(), column, definition)?;
+            }
+        }
+        Ok(())
+    }
+
     fn save(&self, connection: &Connection) -> Result<Uuid, SceneSaveError> {
         let info_uuid = Uuid::new_v4();
+        let (aperture_blades, aperture_rotation) = match self.aperture_shape {
+            ApertureShape::Polygon { blades, rotation } => (blades, rotation),
+            _ => (0, 0.0),
+        };
+        let aperture_mask = match &self.aperture_shape {
+            ApertureShape::Mask(mask) => Some((
+                mask.width() as u32,
+                mask.height() as u32,
+                mask.values()
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            )),
+            _ => None,
+        };
         let sql = format!(
             "INSERT INTO {self_name}(\
                 {self_name}_id,\
@@ -271,7 +816,15 @@ impl Savable for CameraInfo {
                 aperture,\
                 focus_distance,\
                 start_time,\
-                end_time
+                end_time,\
+                projection,\
+                aperture_blades,\
+                aperture_rotation,\
+                aperture_mask_width,\
+                aperture_mask_height,\
+                aperture_mask,\
+                chromatic_aberration,\
+                distortion
             ) VALUES (\
                 ?1,\
                 ?2,\
@@ -288,13 +841,21 @@ impl Savable for CameraInfo {
                 ?13,\
                 ?14,\
                 ?15,\
-                ?16\
+                ?16,\
+                ?17,\
+                ?18,\
+                ?19,\
+                ?20,\
+                ?21,\
+                ?22,\
+                ?23,\
+                ?24\
             );",
             self_name = Self::database_name()
         );
         connection.execute(
             &sql,
-            (
+            rusqlite::params![
                 info_uuid,
                 self.aspect_ratio,
                 self.fov,
@@ -311,7 +872,15 @@ impl Savable for CameraInfo {
                 self.focus_distance,
                 self.start_time,
                 self.end_time,
-            ),
+                self.projection.to_string(),
+                aperture_blades,
+                aperture_rotation,
+                aperture_mask.as_ref().map(|(width, _, _)| *width),
+                aperture_mask.as_ref().map(|(_, height, _)| *height),
+                aperture_mask.as_ref().map(|(_, _, values)| values),
+                self.chromatic_aberration,
+                self.distortion,
+            ],
         )?;
         Ok(info_uuid)
     }
@@ -332,7 +901,15 @@ impl Savable for CameraInfo {
             aperture, \
             focus_distance, \
             start_time, \
-            end_time \
+            end_time, \
+            projection, \
+            aperture_blades, \
+            aperture_rotation, \
+            aperture_mask_width, \
+            aperture_mask_height, \
+            aperture_mask, \
+            chromatic_aberration, \
+            distortion \
             FROM {self_name} WHERE \
             {self_name}_id = ?1",
             self_name = Self::database_name()
@@ -349,6 +926,39 @@ impl Savable for CameraInfo {
                 focus_distance: row.get(12)?,
                 start_time: row.get(13)?,
                 end_time: row.get(14)?,
+                projection: Projection::from_str(&row.get::<_, String>(15)?).unwrap_or_else(
+                    |error| {
+                        error!("{}, using perspective projection", error);
+                        Projection::Perspective
+                    },
+                ),
+                aperture_shape: match (
+                    row.get::<_, Option<u32>>(18)?,
+                    row.get::<_, Option<u32>>(19)?,
+                    row.get::<_, Option<Vec<u8>>>(20)?,
+                ) {
+                    (Some(width), Some(height), Some(bytes))
+                        if bytes.len() == 4 * width as usize * height as usize =>
+                    {
+                        ApertureShape::Mask(ApertureMask::new(
+                            width as usize,
+                            height as usize,
+                            bytes
+                                .chunks_exact(4)
+                                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+                                .collect(),
+                        ))
+                    }
+                    _ => match row.get::<_, u32>(16)? {
+                        blades if blades >= 3 => ApertureShape::Polygon {
+                            blades,
+                            rotation: row.get(17)?,
+                        },
+                        _ => ApertureShape::Circle,
+                    },
+                },
+                chromatic_aberration: row.get(21)?,
+                distortion: row.get(22)?,
             })
         })?;
         Ok(statement_map
@@ -362,3 +972,193 @@ impl Savable for CameraInfo {
             .collect::<Vec<_>>())
     }
 }
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn projection_names() {
+        for name in Projection::names() {
+            assert_eq!(Projection::from_str(&name).unwrap().to_string(), name);
+        }
+        assert!(Projection::from_str("not a projection").is_err());
+    }
+    #[test]
+    fn center_ray_points_forward() {
+        let forward = Vector3::new(0.0, 0.0, -1.0);
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Equirectangular,
+            Projection::FisheyeEquidistant,
+            Projection::FisheyeEquisolid,
+            Projection::CubeMap(CubeMapFace::Front),
+        ] {
+            let camera = Camera::new(CameraInfo {
+                projection,
+                ..Default::default()
+            });
+            let ray = camera.get_ray(0.5, 0.5, &mut Sampler::new(0)).unwrap();
+            assert!((ray.direction.normalize() - forward).magnitude() < 1e-4);
+        }
+    }
+    #[test]
+    fn cube_map_faces() {
+        for (face, direction) in [
+            (CubeMapFace::Back, Vector3::new(0.0, 0.0, 1.0)),
+            (CubeMapFace::Left, Vector3::new(-1.0, 0.0, 0.0)),
+            (CubeMapFace::Right, Vector3::new(1.0, 0.0, 0.0)),
+            (CubeMapFace::Up, Vector3::new(0.0, 1.0, 0.0)),
+            (CubeMapFace::Down, Vector3::new(0.0, -1.0, 0.0)),
+        ] {
+            let camera = Camera::new(CameraInfo {
+                projection: Projection::CubeMap(face),
+                ..Default::default()
+            });
+            let ray = camera.get_ray(0.5, 0.5, &mut Sampler::new(0)).unwrap();
+            assert!((ray.direction.normalize() - direction).magnitude() < 1e-6);
+        }
+    }
+    #[test]
+    fn polygon_aperture() {
+        let aperture = ApertureShape::Polygon {
+            blades: 4,
+            rotation: RayScalar::PI() / 4.0,
+        };
+        let half_side = (0.5 as RayScalar).sqrt() + 1e-6;
+        let mut sampler = Sampler::new(0);
+        for _ in 0..1000 {
+            let p = aperture.sample(&mut sampler);
+            assert!(p.x.abs() <= half_side && p.y.abs() <= half_side);
+        }
+    }
+    #[test]
+    fn mask_aperture() {
+        let mask = ApertureMask::from_fn(16, 16, |x, _y| if x > 0.0 { 1.0 } else { 0.0 });
+        let aperture = ApertureShape::Mask(mask);
+        let mut sampler = Sampler::new(0);
+        for _ in 0..1000 {
+            assert!(aperture.sample(&mut sampler).x >= 0.0);
+        }
+    }
+    #[test]
+    fn distortion() {
+        let camera = Camera::new(CameraInfo {
+            distortion: 0.1,
+            ..Default::default()
+        });
+        assert_eq!(camera.distort(0.5, 0.5, 1.0), (0.5, 0.5));
+        let (u, v) = camera.distort(1.0, 0.5, 1.0);
+        assert!(u > 1.0);
+        assert_eq!(v, 0.5);
+    }
+    #[test]
+    fn focus_on() {
+        let mut camera = Camera::new(CameraInfo::default());
+        assert_eq!(camera.focus_on(Point3::new(1.0, 0.0, -5.0)), Some(5.0));
+        assert_eq!(camera.info.focus_distance, 5.0);
+        assert_eq!(camera.focus_on(Point3::new(0.0, 0.0, 5.0)), None);
+    }
+    #[test]
+    fn fisheye_outside_circle() {
+        let camera = Camera::new(CameraInfo {
+            projection: Projection::FisheyeEquidistant,
+            ..Default::default()
+        });
+        assert!(camera.get_ray(0.0, 0.0, &mut Sampler::new(0)).is_none());
+        assert!(camera.get_ray(0.5, 1.0, &mut Sampler::new(0)).is_some());
+    }
+    /// camera info table as written by version 0 of the scene file
+    fn version_0_table() -> (Connection, Uuid) {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE camera_info(camera_info_id BLOB PRIMARY KEY NOT NULL,\
+                aspect_ratio REAL NOT NULL,fov REAL NOT NULL,\
+                origin_x REAL NOT NULL,origin_y REAL NOT NULL,origin_z REAL NOT NULL,\
+                look_at_x REAL NOT NULL,look_at_y REAL NOT NULL,look_at_z REAL NOT NULL,\
+                up_vector_x REAL NOT NULL,up_vector_y REAL NOT NULL,up_vector_z REAL NOT NULL,\
+                aperture REAL NOT NULL,focus_distance REAL NOT NULL,\
+                start_time REAL NOT NULL,end_time REAL NOT NULL) STRICT",
+                (),
+            )
+            .unwrap();
+        let id = Uuid::new_v4();
+        connection
+            .execute(
+                "INSERT INTO camera_info VALUES (?1, 1.5, 40.0, 1.0, 2.0, 3.0, 0.0, 0.0, 0.0, \
+                0.0, 1.0, 0.0, 0.1, 5.0, 0.0, 0.0)",
+                [id],
+            )
+            .unwrap();
+        (connection, id)
+    }
+    #[test]
+    fn migrate_version_0() {
+        let (connection, id) = version_0_table();
+        CameraInfo::migrate(0, &connection).unwrap();
+        let projection: String = connection
+            .query_row(
+                "SELECT projection FROM camera_info WHERE camera_info_id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            Projection::from_str(&projection).unwrap(),
+            Projection::Perspective
+        );
+        // running it again does not add the column twice
+        CameraInfo::migrate(0, &connection).unwrap();
+        let info = CameraInfo::load_one(id, &connection).unwrap();
+        assert_eq!(info.origin, Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(info.aperture, 0.1);
+        assert_eq!(info.aperture_shape, ApertureShape::Circle);
+        assert_eq!(info.chromatic_aberration, 0.0);
+        assert_eq!(info.distortion, 0.0);
+    }
+    #[test]
+    fn aperture_fields() {
+        let mut camera = Camera::new(CameraInfo::default());
+        assert!(!camera.fields().contains_key("aperture_blades"));
+        camera.set_field("aperture".to_string(), EntityField::Float(0.5));
+        assert_eq!(camera.lens_radius, 0.25);
+        camera.set_field(
+            "aperture_shape".to_string(),
+            EntityField::Choice {
+                selected: "Polygon".to_string(),
+                options: ApertureShape::names().to_vec(),
+            },
+        );
+        camera.set_field("aperture_blades".to_string(), EntityField::Float(5.2));
+        camera.set_field("aperture_rotation".to_string(), EntityField::Float(0.3));
+        assert_eq!(
+            camera.info.aperture_shape,
+            ApertureShape::Polygon {
+                blades: 5,
+                rotation: 0.3
+            }
+        );
+        assert_eq!(camera.fields()["aperture_blades"], EntityField::Float(5.0));
+        // masks can not be picked from the gui
+        camera.set_field(
+            "aperture_shape".to_string(),
+            EntityField::Choice {
+                selected: "Mask".to_string(),
+                options: ApertureShape::names().to_vec(),
+            },
+        );
+        assert!(matches!(
+            camera.info.aperture_shape,
+            ApertureShape::Polygon { .. }
+        ));
+        let mask = ApertureShape::Mask(ApertureMask::from_fn(4, 4, |_, _| 1.0));
+        camera.info.aperture_shape = mask.clone();
+        match &camera.fields()["aperture_shape"] {
+            EntityField::Choice { selected, options } => {
+                assert_eq!(selected, "Mask");
+                assert!(options.contains(selected));
+            }
+            _ => panic!("invalid value type"),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::prelude::*;
+use cgmath::num_traits::FloatConst;
+
+/// Shape of the reconstruction filter used to weight samples of the pixels around them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    /// each sample only counts for the pixel it is in
+    Box,
+    /// weight falls off linearly with distance
+    Tent,
+    /// gaussian with a standard deviation of a third of the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3
+    Mitchell,
+    /// sinc windowed by a sinc that reaches zero at the radius
+    Lanczos,
+}
+impl FilterKind {
+    pub fn names() -> [String; 5] {
+        [
+            "Box".to_string(),
+            "Tent".to_string(),
+            "Gaussian".to_string(),
+            "Mitchell".to_string(),
+            "Lanczos".to_string(),
+        ]
+    }
+    /// radius in pixels the filter is usually used with
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.0,
+            Self::Lanczos => 3.0,
+        }
+    }
+}
+impl std::str::FromStr for FilterKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Box" => Ok(Self::Box),
+            "Tent" => Ok(Self::Tent),
+            "Gaussian" => Ok(Self::Gaussian),
+            "Mitchell" => Ok(Self::Mitchell),
+            "Lanczos" => Ok(Self::Lanczos),
+            _ => Err(format!("invalid name: {}", s)),
+        }
+    }
+}
+/// Reconstruction filter, samples are splatted into every pixel whose center is within the radius
+/// and each pixel is the weighted average of the samples around it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    kind: FilterKind,
+    radius: f32,
+}
+impl Filter {
+    /// creates a filter with the given radius in pixels
+    pub fn new(kind: FilterKind, radius: f32) -> Self {
+        assert!(radius > 0.0, "filter radius must be greater than zero");
+        Self { kind, radius }
+    }
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+    /// number of pixels outside of a tile that samples in the tile can reach
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).ceil().max(0.0) as usize
+    }
+    /// weight of a sample offset by `(x, y)` pixels from the pixel center
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        match self.kind {
+            // half open so a sample on the edge between two pixels only counts for one of them
+            FilterKind::Box => {
+                if (-r..r).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            FilterKind::Tent => (r - x.abs()).max(0.0),
+            FilterKind::Gaussian => {
+                let gaussian = |x: f32| {
+                    let sigma = r / 3.0;
+                    (-x * x / (2.0 * sigma * sigma)).exp()
+                };
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+                let x = (2.0 * x / r).abs();
+                let value = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x.powi(3)
+                        + (-18.0 + 12.0 * B + 6.0 * C) * x.powi(2)
+                        + (6.0 - 2.0 * B)
+                } else if x < 2.0 {
+                    (-B - 6.0 * C) * x.powi(3)
+                        + (6.0 * B + 30.0 * C) * x.powi(2)
+                        + (-12.0 * B - 48.0 * C) * x
+                        + (8.0 * B + 24.0 * C)
+                } else {
+                    0.0
+                };
+                value / 6.0
+            }
+            FilterKind::Lanczos => {
+                if x.abs() < r {
+                    sinc(x) * sinc(x / r)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::Box, FilterKind::Box.default_radius())
+    }
+}
+fn sinc(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1e-5 {
+        1.0
+    } else {
+        (f32::PI() * x).sin() / (f32::PI() * x)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+    #[test]
+    fn filter_support() {
+        for kind in KINDS {
+            let filter = Filter::new(kind, kind.default_radius());
+            assert!(filter.evaluate(0.0, 0.0) > 0.0, "{:?}", kind);
+            let r = filter.radius();
+            assert_eq!(filter.evaluate(r + 0.01, 0.0), 0.0, "{:?}", kind);
+            assert_eq!(filter.evaluate(0.0, -r - 0.01), 0.0, "{:?}", kind);
+            // symmetric apart from the edge of the box
+            assert_eq!(filter.evaluate(0.3, 0.2), filter.evaluate(-0.3, -0.2));
+        }
+    }
+    #[test]
+    fn box_covers_one_pixel() {
+        let filter = Filter::default();
+        assert_eq!(filter.margin(), 0);
+        assert_eq!(filter.evaluate(-0.5, 0.0), 1.0);
+        assert_eq!(filter.evaluate(0.5, 0.0), 0.0);
+        assert_eq!(Filter::new(FilterKind::Mitchell, 2.0).margin(), 2);
+    }
+}
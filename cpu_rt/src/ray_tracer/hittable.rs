@@ -1,44 +1,84 @@
 mod constant_medium;
+mod csg;
+mod cylinder;
 mod fast_oct_tree;
+mod heightfield;
+mod heterogeneous_medium;
+mod quad;
 mod rect;
 mod render_box;
+mod sdf;
 mod sphere;
+mod subsurface_volume;
+mod torus;
+mod vdb;
 
 use super::{
+    animation::{Interpolation, Lerp, Track},
     ray_tracer_info::{Entity, EntityField},
     Aabb, Material, Ray,
 };
 use std::collections::HashMap;
 
-use cgmath::{InnerSpace, Matrix3, Matrix4, Point2, Point3, SquareMatrix, Vector3, Vector4};
+use cgmath::{
+    InnerSpace, Matrix, Matrix3, Matrix4, Point2, Point3, Quaternion, SquareMatrix, Vector3,
+    Vector4,
+};
 
 use crate::{
-    prelude::{RayScalar, RgbColor},
+    prelude::{RayScalar, RgbColor, Sampler},
     ray_tracer::{hittable::MaterialEffect::NoEmmit, pdf::ScatterRecord},
 };
 
 pub use constant_medium::ConstantMedium;
-use dyn_clone::{clone_box, DynClone};
+pub use csg::{Csg, CsgOperation};
+pub use cylinder::{Cone, Cylinder};
+use dyn_clone::DynClone;
 pub use fast_oct_tree::{
-    ArenaStats, FastOctTreeStats, SolidVoxel, VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
+    ArenaStats, CompactionStats, FastOctTreeStats, PastePolicy, SolidVoxel, VolumeEdgeEffect,
+    VolumeVoxel, Voxel, VoxelGrid,
 };
+pub use heightfield::Heightfield;
+pub use heterogeneous_medium::{DensityField, FbmDensity, GridDensity, HeterogeneousMedium};
 use log::error;
 
+pub use quad::{Disk, InfinitePlane, Quad};
 pub use rect::{XYRect, XZRect, YZRect};
 pub use render_box::RenderBox;
+pub use sdf::{Sdf, SdfShape};
 pub use sphere::{MovingSphere, Sphere};
-use std::ops::Deref;
+use std::sync::Arc;
+pub use subsurface_volume::SubsurfaceVolume;
+pub use torus::Torus;
+pub use vdb::{VdbError, VdbGrid};
 
 ///Objects that can be hit
 pub mod hittable_objects {
     pub use super::rect::{XYRect, XZRect, YZRect};
 }
 pub trait Hittable: Send + Sync + DynClone {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord>;
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord>;
     fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb>;
     /// probability of hitting the box for given ray going towards point
-    fn prob(&self, ray: Ray) -> RayScalar;
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo;
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar;
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo;
+    /// Media are tested after every surface of the bvh with `t_max` at the nearest surface, so
+    /// light passing through them stops in front of the surfaces inside whatever order the
+    /// objects are visited in
+    fn is_medium(&self) -> bool {
+        false
+    }
     /// gets the name of the object
     fn name(&self) -> String {
         "N/A".to_string()
@@ -61,7 +101,7 @@ impl<T: Hittable> Entity for T {
         Hittable::set_field(self, key, value)
     }
 }
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Transform {
     pub world_transform: Matrix4<RayScalar>,
 }
@@ -89,6 +129,114 @@ impl Transform {
     pub fn rotate_y(self, rotation_deg: RayScalar) -> Self {
         self * Self::from_matrix(Matrix4::from_angle_y(cgmath::Deg(rotation_deg)))
     }
+    pub fn rotate_z(self, rotation_deg: RayScalar) -> Self {
+        self * Self::from_matrix(Matrix4::from_angle_z(cgmath::Deg(rotation_deg)))
+    }
+    /// rotates around an arbitrary axis, uses the same direction convention as `rotate_x`
+    pub fn rotate_axis(self, axis: Vector3<RayScalar>, rotation_deg: RayScalar) -> Self {
+        self * Self::from_matrix(Matrix4::from_axis_angle(
+            axis.normalize(),
+            cgmath::Deg(rotation_deg),
+        ))
+    }
+    pub fn scale(self, scale: RayScalar) -> Self {
+        self.scale_non_uniform(Vector3::new(scale, scale, scale))
+    }
+    pub fn scale_non_uniform(self, scale: Vector3<RayScalar>) -> Self {
+        self * Self::from_matrix(Matrix4::from_nonuniform_scale(
+            1.0 / scale.x,
+            1.0 / scale.y,
+            1.0 / scale.z,
+        ))
+    }
+    /// places the object at `origin` with its negative z axis pointing at `target` and its y
+    /// axis pointing as close to `up` as possible
+    pub fn look_at(
+        self,
+        origin: Point3<RayScalar>,
+        target: Point3<RayScalar>,
+        up: Vector3<RayScalar>,
+    ) -> Self {
+        self * Self::from_matrix(Matrix4::look_at_rh(origin, target, up))
+    }
+    /// Builds the transform that scales, then rotates, then translates the object
+    pub fn from_trs(
+        translation: Vector3<RayScalar>,
+        rotation: Quaternion<RayScalar>,
+        scale: Vector3<RayScalar>,
+    ) -> Self {
+        Self::try_from_trs(translation, rotation, scale).expect("transform is not invertible")
+    }
+    /// same as `from_trs`, returns none if the scale is too close to zero to invert
+    pub(crate) fn try_from_trs(
+        translation: Vector3<RayScalar>,
+        rotation: Quaternion<RayScalar>,
+        scale: Vector3<RayScalar>,
+    ) -> Option<Self> {
+        let object_to_world = Matrix4::from_translation(translation)
+            * Matrix4::from(rotation)
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+        object_to_world.invert().map(Self::from_matrix)
+    }
+    /// Splits the transform into translation, rotation and scale so that
+    /// `Transform::from_trs(translation, rotation, scale)` gives back the same transform. Returns
+    /// none if the transform has shear or projection
+    pub fn decompose(
+        &self,
+    ) -> Option<(
+        Vector3<RayScalar>,
+        Quaternion<RayScalar>,
+        Vector3<RayScalar>,
+    )> {
+        const TOLERANCE: RayScalar = 1e-6;
+        let object_to_world = self.world_transform.invert()?;
+        let row = object_to_world.row(3);
+        if row.x.abs() > TOLERANCE
+            || row.y.abs() > TOLERANCE
+            || row.z.abs() > TOLERANCE
+            || (row.w - 1.0).abs() > TOLERANCE
+        {
+            return None;
+        }
+        let translation = object_to_world.w.truncate();
+        let linear = Self::linear_part(&object_to_world);
+        let mut scale = Vector3::new(
+            linear.x.magnitude(),
+            linear.y.magnitude(),
+            linear.z.magnitude(),
+        );
+        if linear.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let rotation =
+            Matrix3::from_cols(linear.x / scale.x, linear.y / scale.y, linear.z / scale.z);
+        // the columns of a rotation are orthogonal, if not there is shear
+        if rotation.x.dot(rotation.y).abs() > TOLERANCE
+            || rotation.x.dot(rotation.z).abs() > TOLERANCE
+            || rotation.y.dot(rotation.z).abs() > TOLERANCE
+        {
+            return None;
+        }
+        Some((translation, Quaternion::from(rotation), scale))
+    }
+    /// gets the upper left 3x3 of the matrix
+    fn linear_part(m: &Matrix4<RayScalar>) -> Matrix3<RayScalar> {
+        Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate())
+    }
+    /// transforms a direction, unlike `Transform * Vector3` the translation is not applied
+    pub fn transform_direction(&self, direction: Vector3<RayScalar>) -> Vector3<RayScalar> {
+        Self::linear_part(&self.world_transform) * direction
+    }
+    /// transforms an object space normal to a world space normal using the inverse transpose
+    pub fn normal_to_world(&self, normal: Vector3<RayScalar>) -> Vector3<RayScalar> {
+        (Self::linear_part(&self.world_transform).transpose() * normal).normalize()
+    }
+    /// how much an object space area with the given normal is scaled when moved to world space
+    pub fn area_scale(&self, normal: Vector3<RayScalar>) -> RayScalar {
+        let linear = Self::linear_part(&self.world_transform);
+        (linear.transpose() * normal).magnitude()
+            / (linear.determinant().abs() * normal.magnitude())
+    }
     fn mul_ray(&self, ray: Ray) -> Ray {
         let direction_world = ray.origin + ray.direction;
         let direction_end = self.world_transform * direction_world.to_homogeneous();
@@ -181,55 +329,189 @@ impl std::ops::Mul<Vector4<RayScalar>> for Transform {
     }
 }
 
-pub struct Object {
-    pub shape: Box<dyn Hittable + Send>,
-    pub transform: Transform,
+/// Transform that changes over time, used for motion blur. Keys are blended by translation,
+/// rotation and scale
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformMotion {
+    keys: Track<Transform>,
 }
-impl Clone for Object {
-    fn clone(&self) -> Self {
+impl TransformMotion {
+    /// number of extra samples per key interval used to bound curved motion
+    const CURVE_BOX_SAMPLES: usize = 8;
+    pub fn new(interpolation: Interpolation) -> Self {
         Self {
-            shape: clone_box(self.shape.deref()),
-            transform: self.transform,
+            keys: Track::new(interpolation),
+        }
+    }
+    /// moves linearly from `start` at `time_0` to `end` at `time_1`
+    pub fn linear(start: Transform, end: Transform, time_0: RayScalar, time_1: RayScalar) -> Self {
+        Self::new(Interpolation::Linear)
+            .key(time_0, start)
+            .key(time_1, end)
+    }
+    pub fn key(mut self, time: RayScalar, transform: Transform) -> Self {
+        self.keys.insert(time, transform);
+        self
+    }
+    /// gets the transform at the given time, returns none if there are no keys
+    pub fn transform_at(&self, time: RayScalar) -> Option<Transform> {
+        self.keys.sample(time)
+    }
+    /// Points move along curves if the keys are splined or if the rotation or scale changes
+    /// between keys, otherwise they move in straight lines
+    fn is_curved(&self) -> bool {
+        self.keys.interpolation() == Interpolation::CatmullRom
+            || self.keys.keyframes().windows(2).any(|keys| {
+                Transform::linear_part(&keys[0].value.world_transform)
+                    != Transform::linear_part(&keys[1].value.world_transform)
+            })
+    }
+    /// Sorted times the motion has to be evaluated at to bound it over \[time_0, time_1\]. For
+    /// straight motion the keys are enough, curves are sampled between the keys as well
+    fn bounding_times(&self, time_0: RayScalar, time_1: RayScalar) -> Vec<RayScalar> {
+        let mut times = vec![time_0, time_1];
+        let keyframes = self.keys.keyframes();
+        let is_curved = self.is_curved();
+        for (i, key) in keyframes.iter().enumerate() {
+            if key.frame > time_0 && key.frame < time_1 {
+                times.push(key.frame);
+            }
+            if is_curved {
+                if let Some(next) = keyframes.get(i + 1) {
+                    for j in 1..Self::CURVE_BOX_SAMPLES {
+                        let time = Lerp::lerp(
+                            &key.frame,
+                            &next.frame,
+                            j as RayScalar / Self::CURVE_BOX_SAMPLES as RayScalar,
+                        );
+                        if time > time_0 && time < time_1 {
+                            times.push(time);
+                        }
+                    }
+                }
+            }
         }
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times
     }
 }
+/// Instance of a shape. The shape is shared between clones so the same geometry can be placed
+/// many times without copying it
+#[derive(Clone)]
+pub struct Object {
+    pub shape: Arc<dyn Hittable + Send>,
+    pub transform: Transform,
+    /// if set it is used instead of `transform`
+    pub motion: Option<TransformMotion>,
+    /// if set it replaces the material of the shape
+    pub material: Option<Arc<dyn Material>>,
+}
 
 impl Object {
     pub fn new(shape: Box<dyn Hittable + Send>, transform: Transform) -> Self {
-        Self { shape, transform }
+        Self::instance(shape.into(), transform)
+    }
+    /// creates an object sharing the geometry of `shape`
+    pub fn instance(shape: Arc<dyn Hittable + Send>, transform: Transform) -> Self {
+        Self {
+            shape,
+            transform,
+            motion: None,
+            material: None,
+        }
+    }
+    pub fn with_material(mut self, material: Box<dyn Material>) -> Self {
+        self.material = Some(material.into());
+        self
+    }
+    pub fn with_motion(mut self, motion: TransformMotion) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+    fn transform_at(&self, time: RayScalar) -> Transform {
+        self.motion
+            .as_ref()
+            .and_then(|motion| motion.transform_at(time))
+            .unwrap_or(self.transform)
+    }
+    /// gets the world space bounding box of the shape's box under the given transform
+    fn transform_box(aabb: Aabb, transform: Transform) -> Aabb {
+        Self::world_corners(aabb, transform)
+            .into_iter()
+            .map(Aabb::from_point)
+            .reduce(|acc, x| acc.surrounding_box(x))
+            .unwrap()
+    }
+    /// world space corners of the shape's box under the given transform
+    fn world_corners(aabb: Aabb, transform: Transform) -> [Point3<RayScalar>; 8] {
+        let inv = transform.get_inverse();
+        std::array::from_fn(|i| {
+            inv * Point3::new(
+                if i & 1 == 0 {
+                    aabb.minimum.x
+                } else {
+                    aabb.maximum.x
+                },
+                if i & 2 == 0 {
+                    aabb.minimum.y
+                } else {
+                    aabb.maximum.y
+                },
+                if i & 4 == 0 {
+                    aabb.minimum.z
+                } else {
+                    aabb.maximum.z
+                },
+            )
+        })
     }
 }
 
 impl Hittable for Object {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
-        let shape_ray = &self.transform * ray;
-        fn get_three(m: &Matrix4<RayScalar>) -> Matrix3<RayScalar> {
-            let v1 = m[0];
-            let v2 = m[1];
-            let v3 = m[2];
-            let t = [v1.x, v1.y, v1.z, v2.x, v2.y, v2.z, v3.x, v3.y, v3.z];
-            let m: &Matrix3<RayScalar> = (&t).into();
-            *m
-        }
-        if let Some(hit) = self.shape.hit(&shape_ray, t_min, t_max) {
-            let three = get_three(&self.transform.world_transform);
-            let three_inv = three.invert().unwrap();
-            let inv = self.transform.get_inverse();
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let transform = self.transform_at(ray.time);
+        let shape_ray = &transform * ray;
+        if let Some(hit) = self.shape.hit(&shape_ray, t_min, t_max, sampler) {
+            let inv = transform.get_inverse();
             let world_position = inv * hit.position;
 
-            let normal_world = three_inv * hit.normal;
-            //let normal_world = hit.normal;
+            let normal_world = transform.normal_to_world(hit.normal);
+            if let Some(material) = self.material.as_ref() {
+                return Some(HitRecord::new(
+                    ray,
+                    world_position,
+                    normal_world,
+                    hit.t,
+                    hit.uv,
+                    material.as_ref(),
+                    sampler,
+                ));
+            }
             let front_face = ray.direction.dot(normal_world) <= 0.0;
-            //let normal_world = inv * Vector4::new(hit.normal.x, hit.normal.y, hit.normal.z, 0.0);
-            //let normal_world = Vector3::new(normal_world.x, normal_world.y, normal_world.z);
-            //let normal_world = hit.normal;
+            // rays and pdfs that leave the shape have to be moved back into world space
+            let material_effect = match hit.material_effect {
+                MaterialEffect::Scatter(record) => MaterialEffect::Scatter(ScatterRecord {
+                    specular_ray: record.specular_ray.map(|specular_ray| inv * specular_ray),
+                    pdf: record
+                        .pdf
+                        .map(|pdf| pdf.to_world(&transform).unwrap_or(pdf)),
+                    ..record
+                }),
+                material_effect => material_effect,
+            };
             Some(HitRecord {
                 position: world_position,
                 normal: normal_world,
                 t: hit.t,
                 front_face,
                 uv: hit.uv,
-                material_effect: hit.material_effect,
+                material_effect,
             })
         } else {
             None
@@ -237,71 +519,88 @@ impl Hittable for Object {
     }
 
     fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
-        if let Some(aabb) = self.shape.bounding_box(time_0, time_1) {
-            let inv = self.transform.get_inverse();
-            let corner_a = inv * aabb.minimum;
-            let corner_b = inv * aabb.maximum;
-            let min_x = if corner_a.x < corner_b.x {
-                corner_a.x
-            } else {
-                corner_b.x
-            };
-            let min_y = if corner_a.y < corner_b.y {
-                corner_a.y
-            } else {
-                corner_b.y
-            };
-            let min_z = if corner_a.z < corner_b.z {
-                corner_a.z
-            } else {
-                corner_b.z
-            };
-
-            let max_x = if corner_a.x > corner_b.x {
-                corner_a.x
-            } else {
-                corner_b.x
-            };
-            let max_y = if corner_a.y > corner_b.y {
-                corner_a.y
-            } else {
-                corner_b.y
-            };
-            let max_z = if corner_a.z > corner_b.z {
-                corner_a.z
-            } else {
-                corner_b.z
-            };
-            Some(Aabb {
-                minimum: Point3::new(min_x, min_y, min_z),
-                maximum: Point3::new(max_x, max_y, max_z),
-            })
-        } else {
-            None
+        let aabb = self.shape.bounding_box(time_0, time_1)?;
+        match self.motion.as_ref() {
+            Some(motion) => {
+                let corners = motion
+                    .bounding_times(time_0, time_1)
+                    .into_iter()
+                    .map(|time| Self::world_corners(aabb, self.transform_at(time)))
+                    .collect::<Vec<_>>();
+                let motion_box = corners
+                    .iter()
+                    .flatten()
+                    .map(|corner| Aabb::from_point(*corner))
+                    .reduce(|acc, x| acc.surrounding_box(x))?;
+                if !motion.is_curved() {
+                    return Some(motion_box);
+                }
+                // Between two samples a corner stays closer to the line joining them than the
+                // distance it moved, so padding by the largest move covers the curve
+                let padding = corners
+                    .windows(2)
+                    .flat_map(|pair| {
+                        pair[0]
+                            .iter()
+                            .zip(pair[1].iter())
+                            .map(|(a, b)| (a - b).magnitude())
+                    })
+                    .fold(0.0, RayScalar::max);
+                let padding = Vector3::new(padding, padding, padding);
+                Some(Aabb {
+                    minimum: motion_box.minimum - padding,
+                    maximum: motion_box.maximum + padding,
+                })
+            }
+            None => Some(Self::transform_box(aabb, self.transform)),
         }
     }
 
-    fn prob(&self, ray: Ray) -> RayScalar {
-        let inv = self.transform.get_inverse();
-        self.shape.prob(inv * ray)
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        let transform = self.transform_at(ray.time);
+        let shape_ray = transform * ray;
+        let shape_prob = self.shape.prob(shape_ray, sampler);
+        if shape_prob == 0.0 {
+            return shape_prob;
+        }
+        // the shape gives the probability per object space solid angle, convert it to world
+        // space using the ratio of the solid angles the hit area covers
+        match self.shape.hit(&shape_ray, 0.0001, RayScalar::MAX, sampler) {
+            Some(hit) => {
+                let normal_world = transform.normal_to_world(hit.normal);
+                let cos_object = shape_ray
+                    .direction
+                    .normalize()
+                    .dot(hit.normal.normalize())
+                    .abs();
+                let cos_world = ray.direction.normalize().dot(normal_world).abs();
+                let distance_ratio = ray.direction.magnitude2() / shape_ray.direction.magnitude2();
+                shape_prob * cos_object * distance_ratio
+                    / (transform.area_scale(hit.normal) * cos_world)
+            }
+            None => shape_prob,
+        }
     }
 
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let transform = self.transform_at(time);
         let out_area_info = self
             .shape
-            .generate_ray_in_area(self.transform * origin, time);
+            .generate_ray_in_area(transform * origin, time, sampler);
 
-        let inv = self.transform.get_inverse();
+        let inv = transform.get_inverse();
         let end_point = inv * out_area_info.end_point;
         let to_area = inv * out_area_info.to_area;
-        let scaling = inv * Point3::new(1.0, 0.0, 0.0) - inv * Point3::new(0.0, 0.0, 0.0);
-        let scaling = scaling.magnitude().abs();
-        let area = out_area_info.area * scaling;
+        let area = out_area_info.area * transform.area_scale(out_area_info.normal);
 
         let direction_end = inv * (out_area_info.to_area.origin + out_area_info.direction);
         let direction = direction_end - to_area.origin;
-        let normal_end = inv * (out_area_info.to_area.origin + out_area_info.normal);
-        let normal = normal_end - to_area.origin;
+        let normal = transform.normal_to_world(out_area_info.normal);
         RayAreaInfo {
             to_area,
             area,
@@ -310,14 +609,18 @@ impl Hittable for Object {
             end_point,
         }
     }
+    fn is_medium(&self) -> bool {
+        self.shape.is_medium()
+    }
     fn name(&self) -> String {
         self.shape.name()
     }
     fn fields(&self) -> HashMap<String, EntityField> {
         self.shape.fields()
     }
+    /// the shape is copied first if other objects share it so only this object is changed
     fn set_field(&mut self, key: String, value: EntityField) {
-        self.shape.set_field(key, value)
+        dyn_clone::arc_make_mut(&mut self.shape).set_field(key, value)
     }
 }
 #[derive(Clone, Debug)]
@@ -371,8 +674,9 @@ impl HitRecord {
         t: RayScalar,
         uv: Point2<RayScalar>,
         material: M,
+        sampler: &mut Sampler,
     ) -> Self {
-        Self::new_ref(ray, position, normal, t, uv, &material)
+        Self::new_ref(ray, position, normal, t, uv, &material, sampler)
     }
     pub fn new_ref<M: Material>(
         ray: &Ray,
@@ -381,6 +685,7 @@ impl HitRecord {
         t: RayScalar,
         uv: Point2<RayScalar>,
         material: &M,
+        sampler: &mut Sampler,
     ) -> Self {
         let front_face = ray.direction.dot(normal) <= 0.0;
 
@@ -396,7 +701,7 @@ impl HitRecord {
         let emit_option = material.emmit(&hit_ray);
         let material_effect = if let Some(emit) = emit_option {
             MaterialEffect::Emmit(emit)
-        } else if let Some(scatter) = material.scatter(*ray, &hit_ray) {
+        } else if let Some(scatter) = material.scatter(*ray, &hit_ray, sampler) {
             MaterialEffect::Scatter(scatter)
         } else {
             MaterialEffect::NoEmmit
@@ -418,6 +723,7 @@ impl HitRecord {
         t: RayScalar,
         uv: Point2<RayScalar>,
         material: &dyn Material,
+        sampler: &mut Sampler,
     ) -> Self {
         let front_face = ray.direction.dot(normal) <= 0.0;
 
@@ -431,7 +737,7 @@ impl HitRecord {
         let emit_option = material.emmit(&hit_ray);
         let material_effect = if let Some(emit) = emit_option {
             MaterialEffect::Emmit(emit)
-        } else if let Some(scatter) = material.scatter(*ray, &hit_ray) {
+        } else if let Some(scatter) = material.scatter(*ray, &hit_ray, sampler) {
             MaterialEffect::Scatter(scatter)
         } else {
             NoEmmit
@@ -454,3 +760,374 @@ pub struct RayAreaInfo {
     pub normal: Vector3<RayScalar>,
     pub end_point: Point3<RayScalar>,
 }
+impl RayAreaInfo {
+    /// ray from the origin to a point picked on a surface of the given area
+    fn to_point(
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        end_point: Point3<RayScalar>,
+        normal: Vector3<RayScalar>,
+        area: RayScalar,
+    ) -> Self {
+        Self {
+            to_area: Ray {
+                origin,
+                direction: (end_point - origin).normalize(),
+                time,
+            },
+            area,
+            direction: end_point - origin,
+            normal,
+            end_point,
+        }
+    }
+}
+/// Probability per solid angle of sampling the direction of the ray by picking a point uniformly
+/// on a surface. `next_hit` gives the distance and normal of the first crossing after `t`, shapes
+/// that the ray crosses more than once can generate the direction from any of the crossings
+fn surface_prob(
+    ray: &Ray,
+    area: RayScalar,
+    mut next_hit: impl FnMut(RayScalar) -> Option<(RayScalar, Vector3<RayScalar>)>,
+) -> RayScalar {
+    const MAX_CROSSINGS: usize = 8;
+    let direction = ray.direction.normalize();
+    let mut t = 0.0;
+    let mut prob = 0.0;
+    for _ in 0..MAX_CROSSINGS {
+        let (hit_t, normal) = match next_hit(t) {
+            Some(hit) => hit,
+            None => break,
+        };
+        // crossings that graze the surface are rarely sampled but still can be
+        let cos_alpha = direction.dot(normal.normalize()).abs();
+        if cos_alpha > 0.0 {
+            prob += hit_t * hit_t * ray.direction.magnitude2() / (cos_alpha * area);
+        }
+        t = hit_t + 0.00001;
+    }
+    prob
+}
+/// Probability per solid angle of sampling the direction of the ray by picking a point uniformly
+/// in the bounds, used for shapes without a closed form area. Every direction that reaches the
+/// shape can be picked since the shape is inside of its bounds. Bounds without a volume are
+/// never sampled
+fn volume_prob(ray: &Ray, bounds: &Aabb) -> RayScalar {
+    let volume = bounds.volume();
+    if volume <= 0.0 {
+        return 0.0;
+    }
+    let speed = ray.direction.magnitude();
+    match bounds.ray_interval(*ray, 0.0, RayScalar::MAX) {
+        Some((t_start, t_end)) => {
+            ((t_end * speed).powi(3) - (t_start * speed).powi(3)) / (3.0 * volume)
+        }
+        None => 0.0,
+    }
+}
+/// ray to a point picked uniformly in the bounds, see `volume_prob`
+fn volume_ray(
+    origin: Point3<RayScalar>,
+    time: RayScalar,
+    bounds: &Aabb,
+    sampler: &mut Sampler,
+) -> RayAreaInfo {
+    let size = bounds.maximum - bounds.minimum;
+    let offset = sampler.vec();
+    let end_point =
+        bounds.minimum + Vector3::new(size.x * offset.x, size.y * offset.y, size.z * offset.z);
+    RayAreaInfo::to_point(
+        origin,
+        time,
+        end_point,
+        (origin - end_point).normalize(),
+        bounds.volume(),
+    )
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{material::Lambertian, texture::SolidColor};
+    fn unit_sphere() -> Sphere {
+        Sphere {
+            radius: 1.0,
+            origin: Point3::new(0.0, 0.0, 0.0),
+            material: Box::new(Lambertian {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+            }),
+        }
+    }
+    #[test]
+    fn rotated_bounding_box() {
+        let object = Object::new(
+            Box::new(unit_sphere()),
+            Transform::identity().rotate_z(45.0),
+        );
+        let half_diagonal = (2.0 as RayScalar).sqrt();
+        assert!(object.bounding_box(0.0, 0.0).unwrap().approx_eq(Aabb {
+            minimum: Point3::new(-half_diagonal, -half_diagonal, -1.0),
+            maximum: Point3::new(half_diagonal, half_diagonal, 1.0),
+        }));
+    }
+    #[test]
+    fn non_uniform_scale_normal() {
+        let object = Object::new(
+            Box::new(unit_sphere()),
+            Transform::identity().scale_non_uniform(Vector3::new(4.0, 1.0, 1.0)),
+        );
+        let direction = Vector3::new(-1.0, -1.0, 0.0);
+        let hit = object
+            .hit(
+                &Ray {
+                    origin: Point3::new(0.0, 0.0, 0.0) - 10.0 * direction,
+                    direction,
+                    time: 0.0,
+                },
+                0.001,
+                100.0,
+                &mut Sampler::new(0),
+            )
+            .unwrap();
+        // the gradient of (x / 4)^2 + y^2 = 1
+        let expected = Vector3::new(hit.position.x / 16.0, hit.position.y, 0.0).normalize();
+        assert!((hit.normal - expected).magnitude() < 1e-6);
+    }
+    #[test]
+    fn decompose() {
+        let transform = Transform::identity()
+            .scale_non_uniform(Vector3::new(1.0, 2.0, 3.0))
+            .rotate_axis(Vector3::new(1.0, 1.0, 0.0), 30.0)
+            .translate(Vector3::new(5.0, -2.0, 1.0));
+        let (translation, rotation, scale) = transform.decompose().unwrap();
+        assert!((translation - Vector3::new(5.0, -2.0, 1.0)).magnitude() < 1e-6);
+        assert!((scale - Vector3::new(1.0, 2.0, 3.0)).magnitude() < 1e-6);
+        let rebuilt = Transform::from_trs(translation, rotation, scale);
+        let point = Point3::new(1.0, 2.0, 3.0);
+        assert!((rebuilt * point - transform * point).magnitude() < 1e-6);
+
+        let sheared = Transform::identity()
+            .scale_non_uniform(Vector3::new(1.0, 2.0, 1.0))
+            .rotate_z(30.0)
+            .scale_non_uniform(Vector3::new(3.0, 1.0, 1.0));
+        assert!(sheared.decompose().is_none());
+    }
+    #[test]
+    fn area_scale() {
+        let transform = Transform::identity().scale_non_uniform(Vector3::new(2.0, 3.0, 5.0));
+        assert!((transform.area_scale(Vector3::new(0.0, 0.0, 1.0)) - 6.0).abs() < 1e-6);
+        assert!((transform.area_scale(Vector3::new(1.0, 0.0, 0.0)) - 15.0).abs() < 1e-6);
+        let transform = Transform::identity().rotate_y(30.0).scale(2.0);
+        assert!((transform.area_scale(Vector3::new(0.0, 1.0, 0.0)) - 4.0).abs() < 1e-6);
+    }
+    #[test]
+    fn look_at() {
+        let transform = Transform::identity().look_at(
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, -5.0),
+            Vector3::unit_y(),
+        );
+        let inv = transform.get_inverse();
+        assert!(
+            (inv * Point3::new(0.0, 0.0, -1.0) - Point3::new(1.0, 0.0, -1.0)).magnitude() < 1e-6
+        );
+    }
+    fn moving_sphere() -> Object {
+        let sphere = unit_sphere();
+        Object::new(Box::new(sphere), Transform::identity()).with_motion(TransformMotion::linear(
+            Transform::identity(),
+            Transform::identity().translate(Vector3::new(10.0, 0.0, 0.0)),
+            0.0,
+            1.0,
+        ))
+    }
+    #[test]
+    fn motion_bounding_box() {
+        let object = moving_sphere();
+        let aabb = object.bounding_box(0.0, 1.0).unwrap();
+        assert!(aabb.approx_eq(Aabb {
+            minimum: Point3::new(-1.0, -1.0, -1.0),
+            maximum: Point3::new(11.0, 1.0, 1.0),
+        }));
+        let aabb = object.bounding_box(0.0, 0.5).unwrap();
+        assert!(aabb.approx_eq(Aabb {
+            minimum: Point3::new(-1.0, -1.0, -1.0),
+            maximum: Point3::new(6.0, 1.0, 1.0),
+        }));
+    }
+    #[test]
+    fn half_turn_motion() {
+        let motion = TransformMotion::linear(
+            Transform::identity(),
+            Transform::identity().rotate_y(180.0),
+            0.0,
+            1.0,
+        );
+        // blending the matrices would give a singular matrix half way
+        let inv = motion.transform_at(0.5).unwrap().get_inverse();
+        let x_axis = inv * Point3::new(1.0, 0.0, 0.0);
+        assert!(x_axis.x.abs() < 1e-6);
+        assert!(((x_axis - Point3::new(0.0, 0.0, 0.0)).magnitude() - 1.0).abs() < 1e-6);
+        // blending a mirrored transform passes through a scale of zero
+        let mirrored = Transform::identity().scale_non_uniform(Vector3::new(-1.0, 1.0, 1.0));
+        let motion = TransformMotion::linear(Transform::identity(), mirrored, 0.0, 1.0);
+        assert_eq!(motion.transform_at(0.5), Some(mirrored));
+    }
+    #[test]
+    fn spline_motion_bounding_box() {
+        // the sphere swings below x = 0 before moving to x = 10
+        let object = Object::new(Box::new(unit_sphere()), Transform::identity()).with_motion(
+            TransformMotion::new(Interpolation::CatmullRom)
+                .key(0.0, Transform::identity())
+                .key(1.0, Transform::identity())
+                .key(
+                    2.0,
+                    Transform::identity().translate(Vector3::new(10.0, 0.0, 0.0)),
+                )
+                .key(
+                    3.0,
+                    Transform::identity().translate(Vector3::new(10.0, 0.0, 0.0)),
+                ),
+        );
+        let aabb = object.bounding_box(0.0, 3.0).unwrap();
+        let shape_box = object.shape.bounding_box(0.0, 3.0).unwrap();
+        for i in 0..=3000 {
+            let time = i as RayScalar / 1000.0;
+            let moved = Object::transform_box(shape_box, object.transform_at(time));
+            assert!(aabb.contains_point(moved.minimum));
+            assert!(aabb.contains_point(moved.maximum));
+        }
+    }
+    #[test]
+    fn motion_hit() {
+        let object = moving_sphere();
+        let ray = |time| Ray {
+            origin: Point3::new(10.0, 0.0, -5.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+            time,
+        };
+        assert!(object
+            .hit(&ray(0.0), 0.001, 100.0, &mut Sampler::new(0))
+            .is_none());
+        let hit = object
+            .hit(&ray(1.0), 0.001, 100.0, &mut Sampler::new(0))
+            .unwrap();
+        assert!((hit.position - Point3::new(10.0, 0.0, -1.0)).magnitude() < 1e-6);
+    }
+    #[test]
+    fn shared_instance() {
+        let shape: Arc<dyn Hittable + Send> = Arc::new(unit_sphere());
+        let a = Object::instance(shape.clone(), Transform::identity());
+        let mut b = Object::instance(
+            shape.clone(),
+            Transform::identity().translate(Vector3::new(5.0, 0.0, 0.0)),
+        );
+        assert!(Arc::ptr_eq(&a.shape, &b.shape));
+        assert!(b.bounding_box(0.0, 0.0).unwrap().approx_eq(Aabb {
+            minimum: Point3::new(4.0, -1.0, -1.0),
+            maximum: Point3::new(6.0, 1.0, 1.0),
+        }));
+        // editing one instance does not change the others
+        Entity::set_field(&mut b, "radius".to_string(), EntityField::Float(2.0));
+        assert!(!Arc::ptr_eq(&a.shape, &b.shape));
+        assert_eq!(Entity::fields(&a)["radius"], EntityField::Float(1.0));
+        assert_eq!(Entity::fields(&b)["radius"], EntityField::Float(2.0));
+    }
+    #[test]
+    fn material_override() {
+        use crate::ray_tracer::material::DiffuseLight;
+        let object = Object::new(Box::new(unit_sphere()), Transform::identity()).with_material(
+            Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: RgbColor::RED,
+                }),
+            }),
+        );
+        let ray = Ray {
+            origin: Point3::new(-5.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let hit = object
+            .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .unwrap();
+        assert!((hit.position.x + 1.0).abs() < 1e-6);
+        match hit.material_effect {
+            MaterialEffect::Emmit(color) => assert_eq!(color, RgbColor::RED),
+            _ => panic!("override material was not used"),
+        }
+    }
+    /// reflected rays leave from the surface of the moved object, not the original shape
+    #[test]
+    fn specular_ray_in_world_space() {
+        use crate::ray_tracer::material::Metal;
+        let mirror = Sphere {
+            material: Box::new(Metal {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+                fuzz: 0.0,
+            }),
+            ..unit_sphere()
+        };
+        let object = Object::new(
+            Box::new(mirror),
+            Transform::identity().translate(Vector3::new(10.0, 0.0, 0.0)),
+        );
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let hit = object
+            .hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .unwrap();
+        match hit.material_effect {
+            MaterialEffect::Scatter(record) => {
+                let specular_ray = record.specular_ray.unwrap();
+                assert!((specular_ray.origin - hit.position).magnitude() < 1e-6);
+                assert!((specular_ray.direction.normalize() - -ray.direction).magnitude() < 1e-6);
+            }
+            _ => panic!("mirror should scatter"),
+        }
+    }
+    /// diffuse bounces are sampled around the normal of the moved object
+    #[test]
+    fn diffuse_pdf_in_world_space() {
+        let world = crate::ray_tracer::pdf::test::test_world(false, None);
+        let object = Object::new(
+            Box::new(unit_sphere()),
+            Transform::identity()
+                .rotate_z(90.0)
+                .scale_non_uniform(Vector3::new(1.0, 3.0, 1.0)),
+        );
+        let ray = Ray {
+            origin: Point3::new(-5.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let mut sampler = Sampler::new(0);
+        let hit = object
+            .hit(&ray, 0.001, RayScalar::MAX, &mut sampler)
+            .unwrap();
+        assert!((hit.normal - Vector3::new(-1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        let pdf = match hit.material_effect {
+            MaterialEffect::Scatter(record) => record.pdf.unwrap(),
+            _ => panic!("lambertian should scatter"),
+        };
+        for _ in 0..1000 {
+            let (direction, value) = pdf
+                .generate(ray, hit.position, &world, &mut sampler)
+                .unwrap();
+            assert!(direction.dot(hit.normal) >= 0.0);
+            let value_ray = Ray {
+                origin: hit.position,
+                direction,
+                time: 0.0,
+            };
+            let expected = pdf.value(&value_ray, &world, &mut sampler).unwrap();
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+}
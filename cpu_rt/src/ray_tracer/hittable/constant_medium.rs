@@ -32,10 +32,20 @@ impl ConstantMedium {
     }
 }
 impl Hittable for ConstantMedium {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
-        let mut hit1 = self.boundary.hit(ray, -10000000000.0, 10000000000.0)?;
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let mut hit1 = self
+            .boundary
+            .hit(ray, -10000000000.0, 10000000000.0, sampler)?;
 
-        let mut hit2 = self.boundary.hit(ray, hit1.t + 0.0001, 10000000000.0)?;
+        let mut hit2 = self
+            .boundary
+            .hit(ray, hit1.t + 0.0001, 10000000000.0, sampler)?;
 
         if hit1.t < t_min {
             hit1.t = t_min;
@@ -57,7 +67,7 @@ impl Hittable for ConstantMedium {
         };
 
         let distance_inside_boundary = (hit2.t - hit1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * rand_scalar(0.0, 1.0).ln();
+        let hit_distance = self.neg_inv_density * sampler.next_scalar().ln();
         if hit_distance > distance_inside_boundary {
             return None;
         }
@@ -74,7 +84,7 @@ impl Hittable for ConstantMedium {
         let material_effect = if let Some(lighting) = lighting {
             MaterialEffect::Emmit(lighting)
         } else {
-            let diffuse = self.phase_function.scatter(*ray, &hit_ray);
+            let diffuse = self.phase_function.scatter(*ray, &hit_ray, sampler);
             if let Some(diffuse) = diffuse {
                 MaterialEffect::Scatter(diffuse)
             } else {
@@ -95,11 +105,16 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
         self.boundary.bounding_box(time_0, time_1)
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
-        self.boundary.prob(ray)
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        self.boundary.prob(ray, sampler)
     }
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
-        self.boundary.generate_ray_in_area(origin, time)
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        self.boundary.generate_ray_in_area(origin, time, sampler)
     }
     fn name(&self) -> String {
         "Constant Medium".to_string()
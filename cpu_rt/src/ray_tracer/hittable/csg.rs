@@ -0,0 +1,434 @@
+use super::{Aabb, HitRecord, Hittable, Material, RayAreaInfo};
+use crate::{prelude::*, ray_tracer::ray_tracer_info::EntityField};
+use cgmath::Point3;
+use dyn_clone::clone_box;
+use log::error;
+use std::{collections::HashMap, fmt, ops::Deref, str::FromStr};
+
+/// How the two shapes of a `Csg` are combined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// the first shape with the second cut out of it
+    Difference,
+}
+impl CsgOperation {
+    const ALL: [Self; 3] = [Self::Union, Self::Intersection, Self::Difference];
+    pub fn names() -> [String; 3] {
+        Self::ALL.map(|operation| operation.to_string())
+    }
+    /// if a point inside or outside of each shape is inside of the combined shape
+    fn contains(self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            Self::Union => inside_a || inside_b,
+            Self::Intersection => inside_a && inside_b,
+            Self::Difference => inside_a && !inside_b,
+        }
+    }
+}
+impl fmt::Display for CsgOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Union => write!(f, "Union"),
+            Self::Intersection => write!(f, "Intersection"),
+            Self::Difference => write!(f, "Difference"),
+        }
+    }
+}
+impl FromStr for CsgOperation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|operation| operation.to_string() == s)
+            .copied()
+            .ok_or(format!("invalid csg operation: {}", s))
+    }
+}
+/// Constructive solid geometry, combines two closed shapes. The surface of the result is found
+/// by walking every surface the ray crosses in both shapes. Surfaces of the second shape that are
+/// cut out of the first face the other way, so the whole solid uses one material that is applied
+/// after the normals are flipped
+pub struct Csg {
+    operation: CsgOperation,
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+    material: Box<dyn Material>,
+}
+impl Clone for Csg {
+    fn clone(&self) -> Self {
+        Self {
+            operation: self.operation,
+            a: clone_box(self.a.deref()),
+            b: clone_box(self.b.deref()),
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Csg {
+    /// distance the shapes are searched for crossings before the start of the ray, so the ray
+    /// knows which shapes it starts in
+    const SEARCH_DISTANCE: RayScalar = 10000000000.0;
+    /// gap left after each crossing so the same surface is not found again
+    const CROSSING_GAP: RayScalar = 0.0001;
+    /// limit on crossings of one shape, in case a shape keeps returning the same surface
+    const MAX_CROSSINGS: usize = 64;
+    pub fn new(
+        operation: CsgOperation,
+        a: Box<dyn Hittable>,
+        b: Box<dyn Hittable>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        Self {
+            operation,
+            a,
+            b,
+            material,
+        }
+    }
+    pub fn union(a: Box<dyn Hittable>, b: Box<dyn Hittable>, material: Box<dyn Material>) -> Self {
+        Self::new(CsgOperation::Union, a, b, material)
+    }
+    pub fn intersection(
+        a: Box<dyn Hittable>,
+        b: Box<dyn Hittable>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        Self::new(CsgOperation::Intersection, a, b, material)
+    }
+    pub fn difference(
+        a: Box<dyn Hittable>,
+        b: Box<dyn Hittable>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        Self::new(CsgOperation::Difference, a, b, material)
+    }
+    /// every surface of the shape along the whole line of the ray, in order
+    fn crossings(shape: &dyn Hittable, ray: &Ray, sampler: &mut Sampler) -> Vec<HitRecord> {
+        let mut crossings = Vec::new();
+        let mut t = -Self::SEARCH_DISTANCE;
+        while crossings.len() < Self::MAX_CROSSINGS {
+            match shape.hit(ray, t, Self::SEARCH_DISTANCE, sampler) {
+                Some(hit) => {
+                    t = hit.t + Self::CROSSING_GAP;
+                    crossings.push(hit);
+                }
+                None => break,
+            }
+        }
+        crossings
+    }
+}
+impl Hittable for Csg {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let crossings_a = Self::crossings(self.a.as_ref(), ray, sampler);
+        if crossings_a.is_empty() && self.operation != CsgOperation::Union {
+            return None;
+        }
+        let crossings_b = Self::crossings(self.b.as_ref(), ray, sampler);
+        // a ray that leaves a shape first started inside of it
+        let mut inside_a = crossings_a.first().is_some_and(|hit| !hit.front_face);
+        let mut inside_b = crossings_b.first().is_some_and(|hit| !hit.front_face);
+        let mut inside = self.operation.contains(inside_a, inside_b);
+        let (mut a, mut b) = (crossings_a.iter().peekable(), crossings_b.iter().peekable());
+        loop {
+            let from_a = match (a.peek(), b.peek()) {
+                (Some(hit_a), Some(hit_b)) => hit_a.t <= hit_b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let crossing = if from_a {
+                let crossing = a.next().expect("peeked");
+                inside_a = crossing.front_face;
+                crossing
+            } else {
+                let crossing = b.next().expect("peeked");
+                inside_b = crossing.front_face;
+                crossing
+            };
+            if crossing.t > t_max {
+                return None;
+            }
+            let now_inside = self.operation.contains(inside_a, inside_b);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+            if crossing.t < t_min {
+                continue;
+            }
+            // the normal points out of the combined shape, which is the other way for surfaces
+            // that are entered when the shape they belong to is left
+            let normal = if now_inside == crossing.front_face {
+                crossing.normal
+            } else {
+                -crossing.normal
+            };
+            return Some(HitRecord::new(
+                ray,
+                crossing.position,
+                normal,
+                crossing.t,
+                crossing.uv,
+                self.material.as_ref(),
+                sampler,
+            ));
+        }
+    }
+    fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
+        let a = self.a.bounding_box(time_0, time_1);
+        let b = self.b.bounding_box(time_0, time_1);
+        match self.operation {
+            CsgOperation::Union => match (a, b) {
+                (Some(a), Some(b)) => Some(a.surrounding_box(b)),
+                _ => None,
+            },
+            CsgOperation::Intersection => match (a, b) {
+                (Some(a), Some(b)) => {
+                    let minimum = a.minimum.zip(b.minimum, RayScalar::max);
+                    // boxes that do not overlap leave an empty box at the corner
+                    let maximum = a
+                        .maximum
+                        .zip(b.maximum, RayScalar::min)
+                        .zip(minimum, RayScalar::max);
+                    Some(Aabb { minimum, maximum })
+                }
+                (a, b) => a.or(b),
+            },
+            CsgOperation::Difference => a,
+        }
+    }
+    /// Lights are sampled through the first shape or both shapes for a union. The sampled
+    /// directions may miss the combined shape, they still have the right probability
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        match self.operation {
+            CsgOperation::Union => 0.5 * (self.a.prob(ray, sampler) + self.b.prob(ray, sampler)),
+            _ => self.a.prob(ray, sampler),
+        }
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        if self.operation == CsgOperation::Union && sampler.next_scalar() < 0.5 {
+            self.b.generate_ray_in_area(origin, time, sampler)
+        } else {
+            self.a.generate_ray_in_area(origin, time, sampler)
+        }
+    }
+    fn name(&self) -> String {
+        "CSG".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert(
+            "operation".to_string(),
+            EntityField::Choice {
+                selected: self.operation.to_string(),
+                options: CsgOperation::names().to_vec(),
+            },
+        );
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "operation" => match value {
+                EntityField::Choice { selected, .. } => match CsgOperation::from_str(&selected) {
+                    Ok(operation) => self.operation = operation,
+                    Err(error) => error!("{}", error),
+                },
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        hittable::{MaterialEffect, Object, RenderBox, Sphere, Transform},
+        material::{DiffuseLight, Lambertian},
+        texture::SolidColor,
+    };
+    use cgmath::{InnerSpace, Vector3};
+
+    fn material() -> Box<dyn Material> {
+        Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::WHITE,
+            }),
+        })
+    }
+    fn sphere(x: RayScalar, radius: RayScalar) -> Box<dyn Hittable> {
+        Box::new(Sphere {
+            radius,
+            origin: Point3::new(x, 0.0, 0.0),
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: RgbColor::RED,
+                }),
+            }),
+        })
+    }
+    /// ray along the x axis from `x`
+    fn ray(x: RayScalar, direction: RayScalar) -> Ray {
+        Ray {
+            origin: Point3::new(x, 0.0, 0.0),
+            direction: Vector3::new(direction, 0.0, 0.0),
+            time: 0.0,
+        }
+    }
+    /// every surface hit along the ray as (x, normal.x, front face)
+    fn surfaces(shape: &dyn Hittable, ray: Ray) -> Vec<(RayScalar, RayScalar, bool)> {
+        let mut sampler = Sampler::new(0);
+        let mut t = 0.001;
+        let mut surfaces = Vec::new();
+        while let Some(hit) = shape.hit(&ray, t, RayScalar::MAX, &mut sampler) {
+            assert_eq!(hit.front_face, ray.direction.dot(hit.normal) <= 0.0);
+            surfaces.push((hit.position.x, hit.normal.x, hit.front_face));
+            t = hit.t + 0.001;
+        }
+        surfaces
+    }
+    fn assert_surfaces(shape: &dyn Hittable, ray: Ray, expected: &[(RayScalar, RayScalar, bool)]) {
+        let surfaces = surfaces(shape, ray);
+        assert_eq!(surfaces.len(), expected.len(), "{:?}", surfaces);
+        for (surface, expected) in surfaces.iter().zip(expected) {
+            assert!((surface.0 - expected.0).abs() < 1e-6, "{:?}", surfaces);
+            assert!((surface.1 - expected.1).abs() < 1e-6, "{:?}", surfaces);
+            assert_eq!(surface.2, expected.2, "{:?}", surfaces);
+        }
+    }
+    #[test]
+    fn union() {
+        let csg = Csg::union(sphere(0.0, 1.0), sphere(1.5, 1.0), material());
+        assert_surfaces(
+            &csg,
+            ray(-5.0, 1.0),
+            &[(-1.0, -1.0, true), (2.5, 1.0, false)],
+        );
+        assert_surfaces(
+            &csg,
+            ray(5.0, -1.0),
+            &[(2.5, 1.0, true), (-1.0, -1.0, false)],
+        );
+    }
+    /// a lens is the intersection of two spheres
+    #[test]
+    fn intersection() {
+        let csg = Csg::intersection(sphere(-1.5, 2.0), sphere(1.5, 2.0), material());
+        assert_surfaces(
+            &csg,
+            ray(-5.0, 1.0),
+            &[(-0.5, -1.0, true), (0.5, 1.0, false)],
+        );
+        // starting inside of the lens only the far surface is hit
+        assert_surfaces(&csg, ray(0.2, 1.0), &[(0.5, 1.0, false)]);
+        let bounds = csg.bounding_box(0.0, 0.0).unwrap();
+        assert!(bounds.approx_eq(Aabb {
+            minimum: Point3::new(-0.5, -2.0, -2.0),
+            maximum: Point3::new(0.5, 2.0, 2.0),
+        }));
+    }
+    /// a hollow sphere has four surfaces along a line through its center, the inner ones face
+    /// into the hole
+    #[test]
+    fn difference() {
+        let csg = Csg::difference(sphere(0.0, 2.0), sphere(0.0, 1.0), material());
+        assert_surfaces(
+            &csg,
+            ray(-5.0, 1.0),
+            &[
+                (-2.0, -1.0, true),
+                (-1.0, 1.0, false),
+                (1.0, -1.0, true),
+                (2.0, 1.0, false),
+            ],
+        );
+        // from inside of the hole
+        assert_surfaces(&csg, ray(0.0, 1.0), &[(1.0, -1.0, true), (2.0, 1.0, false)]);
+        assert!(csg
+            .bounding_box(0.0, 0.0)
+            .unwrap()
+            .approx_eq(sphere(0.0, 2.0).bounding_box(0.0, 0.0).unwrap()));
+        // nothing is left if the hole is bigger
+        let csg = Csg::difference(sphere(0.0, 1.0), sphere(0.0, 2.0), material());
+        assert_surfaces(&csg, ray(-5.0, 1.0), &[]);
+    }
+    /// the material of the csg node is used and the ray is limited to `t_max`
+    #[test]
+    fn material_and_range() {
+        let csg = Csg::difference(sphere(0.0, 2.0), sphere(0.0, 1.0), material());
+        let mut sampler = Sampler::new(0);
+        let hit = csg.hit(&ray(0.0, 1.0), 0.001, 5.0, &mut sampler).unwrap();
+        match hit.material_effect {
+            MaterialEffect::Scatter(record) => assert!(record.pdf.is_some()),
+            _ => panic!("csg material should be lambertian"),
+        }
+        assert!(csg.hit(&ray(0.0, 1.0), 0.001, 0.9, &mut sampler).is_none());
+    }
+    /// a box with a round hole through it, moved with an object
+    #[test]
+    fn nested_in_object() {
+        let window = Csg::difference(
+            Box::new(RenderBox::new(
+                Point3::new(-0.1, -2.0, -2.0),
+                Point3::new(0.1, 2.0, 2.0),
+                material(),
+            )),
+            sphere(0.0, 1.0),
+            material(),
+        );
+        let object = Object::new(
+            Box::new(window),
+            Transform::identity().translate(Vector3::new(3.0, 0.0, 0.0)),
+        );
+        let mut sampler = Sampler::new(0);
+        assert!(object
+            .hit(&ray(-5.0, 1.0), 0.001, RayScalar::MAX, &mut sampler)
+            .is_none());
+        let through_wall = Ray {
+            origin: Point3::new(-5.0, 1.5, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let hit = object
+            .hit(&through_wall, 0.001, RayScalar::MAX, &mut sampler)
+            .unwrap();
+        assert!((hit.position - Point3::new(2.9, 1.5, 0.0)).magnitude() < 1e-6);
+        assert!(hit.front_face);
+    }
+    #[test]
+    fn operation_field() {
+        let mut csg = Csg::union(sphere(0.0, 1.0), sphere(0.0, 2.0), material());
+        csg.set_field(
+            "operation".to_string(),
+            EntityField::Choice {
+                selected: "Difference".to_string(),
+                options: CsgOperation::names().to_vec(),
+            },
+        );
+        assert_eq!(csg.operation, CsgOperation::Difference);
+        match csg.fields().remove("operation").unwrap() {
+            EntityField::Choice { selected, options } => {
+                assert_eq!(selected, "Difference");
+                assert_eq!(options.len(), 3);
+            }
+            _ => panic!("operation should be a choice"),
+        }
+        for name in CsgOperation::names() {
+            assert_eq!(CsgOperation::from_str(&name).unwrap().to_string(), name);
+        }
+    }
+}
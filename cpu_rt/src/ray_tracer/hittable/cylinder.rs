@@ -0,0 +1,577 @@
+use super::{surface_prob, Aabb, HitRecord, Hittable, Material, RayAreaInfo};
+use crate::{prelude::*, ray_tracer::ray_tracer_info::EntityField};
+use cgmath::{num_traits::FloatConst, prelude::*, Point2, Point3, Vector3};
+use dyn_clone::clone_box;
+use std::{collections::HashMap, ops::Deref};
+
+/// distance, normal and uv of a hit in the frame of the shape
+type LocalHit = (RayScalar, Vector3<RayScalar>, Point2<RayScalar>);
+
+/// Ray in the frame of a shape standing on `base` with the height along z, nearer hits replace
+/// the current one
+struct LocalRay {
+    basis: OrthoNormalBasis,
+    origin: Vector3<RayScalar>,
+    direction: Vector3<RayScalar>,
+    t_min: RayScalar,
+    t_max: RayScalar,
+    nearest: Option<LocalHit>,
+}
+impl LocalRay {
+    fn new(
+        ray: &Ray,
+        base: Point3<RayScalar>,
+        axis: Vector3<RayScalar>,
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Self {
+        let basis = OrthoNormalBasis::build_from_w(axis);
+        Self {
+            origin: basis.coordinates(ray.origin - base),
+            direction: basis.coordinates(ray.direction),
+            basis,
+            t_min,
+            t_max,
+            nearest: None,
+        }
+    }
+    fn at(&self, t: RayScalar) -> Vector3<RayScalar> {
+        self.origin + t * self.direction
+    }
+    fn consider(&mut self, t: RayScalar, normal: Vector3<RayScalar>, uv: Point2<RayScalar>) {
+        let nearer = self.nearest.is_none_or(|(nearest, _, _)| t < nearest);
+        if t >= self.t_min && t <= self.t_max && nearer {
+            self.nearest = Some((t, normal, uv));
+        }
+    }
+    /// flat cap at `height` facing along `normal_z`
+    fn cap(&mut self, height: RayScalar, radius: RayScalar, normal_z: RayScalar) {
+        if self.direction.z == 0.0 {
+            return;
+        }
+        let t = (height - self.origin.z) / self.direction.z;
+        let point = self.at(t);
+        let distance = (point.x * point.x + point.y * point.y).sqrt();
+        if distance <= radius {
+            self.consider(
+                t,
+                Vector3::new(0.0, 0.0, normal_z),
+                Point2::new(angle_u(point), distance / radius),
+            );
+        }
+    }
+    /// nearest hit with the normal in world space
+    fn finish(self) -> Option<LocalHit> {
+        let basis = self.basis;
+        self.nearest
+            .map(|(t, normal, uv)| (t, basis.local(normal).normalize(), uv))
+    }
+}
+/// angle around the z axis scaled to [0, 1)
+fn angle_u(point: Vector3<RayScalar>) -> RayScalar {
+    (point.y.atan2(point.x) / (2.0 * RayScalar::PI())).rem_euclid(1.0)
+}
+/// roots of a x² + 2 half_b x + c
+fn quadratic_roots(a: RayScalar, half_b: RayScalar, c: RayScalar) -> Vec<RayScalar> {
+    if a.abs() < 1e-12 {
+        if half_b == 0.0 {
+            return vec![];
+        }
+        return vec![-c / (2.0 * half_b)];
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    let sqrt_d = discriminant.sqrt();
+    vec![(-half_b - sqrt_d) / a, (-half_b + sqrt_d) / a]
+}
+/// box around a disk of `radius` centered on `center` facing along `axis`
+fn disk_box(center: Point3<RayScalar>, axis: Vector3<RayScalar>, radius: RayScalar) -> Aabb {
+    let extent = axis
+        .normalize()
+        .map(|a| radius * (1.0 - a * a).max(0.0).sqrt());
+    Aabb {
+        minimum: center - extent,
+        maximum: center + extent,
+    }
+}
+/// Cylinder closed at both ends, from `base` to `base + axis`. On the side the uv coordinates are
+/// the angle around the axis and the height, on the caps the angle and the distance from the axis
+pub struct Cylinder {
+    pub base: Point3<RayScalar>,
+    pub axis: Vector3<RayScalar>,
+    pub radius: RayScalar,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Cylinder {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base,
+            axis: self.axis,
+            radius: self.radius,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Cylinder {
+    fn side_area(&self) -> RayScalar {
+        2.0 * RayScalar::PI() * self.radius * self.axis.magnitude()
+    }
+    fn cap_area(&self) -> RayScalar {
+        RayScalar::PI() * self.radius.powi(2)
+    }
+    fn area(&self) -> RayScalar {
+        self.side_area() + 2.0 * self.cap_area()
+    }
+    fn intersect(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<LocalHit> {
+        let height = self.axis.magnitude();
+        let mut local = LocalRay::new(ray, self.base, self.axis, t_min, t_max);
+        let (origin, direction) = (local.origin, local.direction);
+        for t in quadratic_roots(
+            direction.x * direction.x + direction.y * direction.y,
+            origin.x * direction.x + origin.y * direction.y,
+            origin.x * origin.x + origin.y * origin.y - self.radius.powi(2),
+        ) {
+            let point = local.at(t);
+            if (0.0..=height).contains(&point.z) {
+                local.consider(
+                    t,
+                    Vector3::new(point.x, point.y, 0.0),
+                    Point2::new(angle_u(point), point.z / height),
+                );
+            }
+        }
+        local.cap(0.0, self.radius, -1.0);
+        local.cap(height, self.radius, 1.0);
+        local.finish()
+    }
+}
+impl Hittable for Cylinder {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (t, normal, uv) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord::new(
+            ray,
+            ray.at(t),
+            normal,
+            t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        Some(
+            disk_box(self.base, self.axis, self.radius).surrounding_box(disk_box(
+                self.base + self.axis,
+                self.axis,
+                self.radius,
+            )),
+        )
+    }
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        surface_prob(&ray, self.area(), |t| {
+            self.intersect(&ray, t, RayScalar::MAX)
+                .map(|(t, normal, _)| (t, normal))
+        })
+    }
+    /// picks a point uniformly on the side and both caps
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let basis = OrthoNormalBasis::build_from_w(self.axis);
+        let height = self.axis.magnitude();
+        let part = sampler.next_scalar() * self.area();
+        let (local_point, local_normal) = if part < self.side_area() {
+            let phi = 2.0 * RayScalar::PI() * sampler.next_scalar();
+            let normal = Vector3::new(phi.cos(), phi.sin(), 0.0);
+            (
+                self.radius * normal + Vector3::new(0.0, 0.0, height * sampler.next_scalar()),
+                normal,
+            )
+        } else {
+            let top = part > self.side_area() + self.cap_area();
+            let z = if top { height } else { 0.0 };
+            (
+                self.radius * sampler.unit_disk() + Vector3::new(0.0, 0.0, z),
+                Vector3::new(0.0, 0.0, if top { 1.0 } else { -1.0 }),
+            )
+        };
+        RayAreaInfo::to_point(
+            origin,
+            time,
+            self.base + basis.local(local_point),
+            basis.local(local_normal),
+            self.area(),
+        )
+    }
+    fn name(&self) -> String {
+        "Cylinder".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("base".to_string(), EntityField::Point3(self.base));
+        map.insert("radius".to_string(), EntityField::Float(self.radius));
+        map.insert(
+            "height".to_string(),
+            EntityField::Float(self.axis.magnitude()),
+        );
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "base" => match value {
+                EntityField::Point3(p) => self.base = p,
+                _ => panic!("invalid value type"),
+            },
+            "radius" => match value {
+                EntityField::Float(v) => self.radius = v,
+                _ => panic!("invalid value type"),
+            },
+            "height" => match value {
+                EntityField::Float(v) => self.axis = self.axis.normalize() * v,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+/// Cone with its base of `radius` on `base` and the tip at `base + axis`, closed at the base. The
+/// uv coordinates are the same as for `Cylinder`
+pub struct Cone {
+    pub base: Point3<RayScalar>,
+    pub axis: Vector3<RayScalar>,
+    pub radius: RayScalar,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Cone {
+    fn clone(&self) -> Self {
+        Self {
+            base: self.base,
+            axis: self.axis,
+            radius: self.radius,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Cone {
+    fn side_area(&self) -> RayScalar {
+        RayScalar::PI() * self.radius * (self.radius.powi(2) + self.axis.magnitude2()).sqrt()
+    }
+    fn base_area(&self) -> RayScalar {
+        RayScalar::PI() * self.radius.powi(2)
+    }
+    fn area(&self) -> RayScalar {
+        self.side_area() + self.base_area()
+    }
+    /// normal of the side at a point in the frame of the cone, pointing up at the tip
+    fn side_normal(&self, point: Vector3<RayScalar>) -> Vector3<RayScalar> {
+        let slope = self.radius / self.axis.magnitude();
+        let distance = (point.x * point.x + point.y * point.y).sqrt();
+        if distance < 1e-12 {
+            return Vector3::unit_z();
+        }
+        Vector3::new(point.x / distance, point.y / distance, slope)
+    }
+    fn intersect(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<LocalHit> {
+        let height = self.axis.magnitude();
+        let slope_squared = (self.radius / height).powi(2);
+        let mut local = LocalRay::new(ray, self.base, self.axis, t_min, t_max);
+        let (origin, direction) = (local.origin, local.direction);
+        let to_tip = height - origin.z;
+        // the double cone x² + y² = slope² (height - z)², cut to the part below the tip
+        for t in quadratic_roots(
+            direction.x * direction.x + direction.y * direction.y
+                - slope_squared * direction.z * direction.z,
+            origin.x * direction.x + origin.y * direction.y + slope_squared * to_tip * direction.z,
+            origin.x * origin.x + origin.y * origin.y - slope_squared * to_tip * to_tip,
+        ) {
+            let point = local.at(t);
+            if (0.0..=height).contains(&point.z) {
+                local.consider(
+                    t,
+                    self.side_normal(point),
+                    Point2::new(angle_u(point), point.z / height),
+                );
+            }
+        }
+        local.cap(0.0, self.radius, -1.0);
+        local.finish()
+    }
+}
+impl Hittable for Cone {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (t, normal, uv) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord::new(
+            ray,
+            ray.at(t),
+            normal,
+            t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        Some(
+            disk_box(self.base, self.axis, self.radius)
+                .surrounding_box(Aabb::from_point(self.base + self.axis)),
+        )
+    }
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        surface_prob(&ray, self.area(), |t| {
+            self.intersect(&ray, t, RayScalar::MAX)
+                .map(|(t, normal, _)| (t, normal))
+        })
+    }
+    /// picks a point uniformly on the side and the base, the side gets wider linearly from the
+    /// tip so the distance from the tip is picked with the square root
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let basis = OrthoNormalBasis::build_from_w(self.axis);
+        let height = self.axis.magnitude();
+        let (local_point, local_normal) = if sampler.next_scalar() * self.area() < self.side_area()
+        {
+            let from_tip = sampler.next_scalar().sqrt();
+            let phi = 2.0 * RayScalar::PI() * sampler.next_scalar();
+            let point = Vector3::new(
+                self.radius * from_tip * phi.cos(),
+                self.radius * from_tip * phi.sin(),
+                height * (1.0 - from_tip),
+            );
+            (point, self.side_normal(point))
+        } else {
+            (self.radius * sampler.unit_disk(), -Vector3::unit_z())
+        };
+        RayAreaInfo::to_point(
+            origin,
+            time,
+            self.base + basis.local(local_point),
+            basis.local(local_normal).normalize(),
+            self.area(),
+        )
+    }
+    fn name(&self) -> String {
+        "Cone".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("base".to_string(), EntityField::Point3(self.base));
+        map.insert("radius".to_string(), EntityField::Float(self.radius));
+        map.insert(
+            "height".to_string(),
+            EntityField::Float(self.axis.magnitude()),
+        );
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "base" => match value {
+                EntityField::Point3(p) => self.base = p,
+                _ => panic!("invalid value type"),
+            },
+            "radius" => match value {
+                EntityField::Float(v) => self.radius = v,
+                _ => panic!("invalid value type"),
+            },
+            "height" => match value {
+                EntityField::Float(v) => self.axis = self.axis.normalize() * v,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        material::DiffuseLight,
+        pdf::{
+            test::{check_pdf, light_world},
+            LightPdf,
+        },
+        texture::SolidColor,
+    };
+
+    fn light() -> Box<dyn Material> {
+        Box::new(DiffuseLight {
+            emit: Box::new(SolidColor {
+                color: RgbColor::WHITE,
+            }),
+        })
+    }
+    fn cylinder() -> Cylinder {
+        Cylinder {
+            base: Point3::new(-1.0, 1.0, 0.5),
+            axis: Vector3::new(0.5, 1.0, 0.2),
+            radius: 0.7,
+            material: light(),
+        }
+    }
+    fn cone() -> Cone {
+        Cone {
+            base: Point3::new(0.5, 0.6, -0.3),
+            axis: Vector3::new(0.3, 1.5, 0.5),
+            radius: 1.0,
+            material: light(),
+        }
+    }
+    /// every surface hit along the ray as (position, normal, front face)
+    fn surfaces(
+        shape: &dyn Hittable,
+        origin: Point3<RayScalar>,
+        direction: Vector3<RayScalar>,
+    ) -> Vec<(Point3<RayScalar>, Vector3<RayScalar>, bool)> {
+        let ray = Ray {
+            origin,
+            direction,
+            time: 0.0,
+        };
+        let mut sampler = Sampler::new(0);
+        let mut t = 0.001;
+        let mut surfaces = Vec::new();
+        while let Some(hit) = shape.hit(&ray, t, RayScalar::MAX, &mut sampler) {
+            surfaces.push((hit.position, hit.normal, hit.front_face));
+            t = hit.t + 0.001;
+        }
+        surfaces
+    }
+    fn assert_near(a: Vector3<RayScalar>, b: Vector3<RayScalar>) {
+        assert!((a - b).magnitude() < 1e-6, "{:?} != {:?}", a, b);
+    }
+    #[test]
+    fn cylinder_hit() {
+        let cylinder = Cylinder {
+            base: Point3::new(0.0, 0.0, 0.0),
+            axis: Vector3::new(0.0, 2.0, 0.0),
+            radius: 1.0,
+            material: light(),
+        };
+        // through the side
+        let side = surfaces(
+            &cylinder,
+            Point3::new(-5.0, 1.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        );
+        assert_eq!(side.len(), 2);
+        assert_near(side[0].0.to_vec(), Vector3::new(-1.0, 1.0, 0.0));
+        assert_near(side[0].1, Vector3::new(-1.0, 0.0, 0.0));
+        assert!(side[0].2 && !side[1].2);
+        assert_near(side[1].1, Vector3::new(1.0, 0.0, 0.0));
+        // through both caps
+        let caps = surfaces(
+            &cylinder,
+            Point3::new(0.5, 5.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+        );
+        assert_eq!(caps.len(), 2);
+        assert_near(caps[0].0.to_vec(), Vector3::new(0.5, 2.0, 0.0));
+        assert_near(caps[0].1, Vector3::new(0.0, 1.0, 0.0));
+        assert_near(caps[1].1, Vector3::new(0.0, -1.0, 0.0));
+        // in through the cap and out the side
+        let mixed = surfaces(
+            &cylinder,
+            Point3::new(0.0, 2.5, 0.0),
+            Vector3::new(1.0, -1.0, 0.0),
+        );
+        assert_eq!(mixed.len(), 2);
+        assert_near(mixed[0].1, Vector3::new(0.0, 1.0, 0.0));
+        assert_near(mixed[1].0.to_vec(), Vector3::new(1.0, 1.5, 0.0));
+        // missing above the cylinder
+        assert!(surfaces(
+            &cylinder,
+            Point3::new(-5.0, 2.5, 0.0),
+            Vector3::new(1.0, 0.0, 0.0)
+        )
+        .is_empty());
+    }
+    #[test]
+    fn cone_hit() {
+        let cone = Cone {
+            base: Point3::new(0.0, 0.0, 0.0),
+            axis: Vector3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            material: light(),
+        };
+        let side = surfaces(
+            &cone,
+            Point3::new(-5.0, 0.5, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+        );
+        assert_eq!(side.len(), 2);
+        assert_near(side[0].0.to_vec(), Vector3::new(-0.5, 0.5, 0.0));
+        assert_near(side[0].1, Vector3::new(-1.0, 1.0, 0.0).normalize());
+        // the mirror image of the cone above the tip is not part of it
+        assert!(surfaces(
+            &cone,
+            Point3::new(-5.0, 1.5, 0.0),
+            Vector3::new(1.0, 0.0, 0.0)
+        )
+        .is_empty());
+        let base = surfaces(
+            &cone,
+            Point3::new(0.2, -5.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+        assert_eq!(base.len(), 2);
+        assert_near(base[0].1, Vector3::new(0.0, -1.0, 0.0));
+        assert_near(base[1].0.to_vec(), Vector3::new(0.2, 0.8, 0.0));
+    }
+    #[test]
+    fn bounding_boxes() {
+        let mut sampler = Sampler::new(0);
+        let shapes: [Box<dyn Hittable>; 2] = [Box::new(cylinder()), Box::new(cone())];
+        for shape in shapes {
+            let bounds = shape.bounding_box(0.0, 0.0).unwrap();
+            for _ in 0..1000 {
+                let point = shape
+                    .generate_ray_in_area(Point3::origin(), 0.0, &mut sampler)
+                    .end_point;
+                assert!(bounds.contains_point(point), "{:?}", point);
+            }
+        }
+    }
+    #[test]
+    fn light_pdf() {
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(cylinder())),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(cone())),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+    #[test]
+    fn height() {
+        let mut cone = cone();
+        cone.set_field("height".to_string(), EntityField::Float(3.0));
+        assert!((cone.axis.magnitude() - 3.0).abs() < 1e-9);
+        match cone.fields()["height"] {
+            EntityField::Float(height) => assert!((height - 3.0).abs() < 1e-9),
+            _ => panic!("height should be a float"),
+        }
+    }
+}
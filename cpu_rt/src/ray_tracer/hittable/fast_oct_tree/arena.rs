@@ -1,5 +1,5 @@
 use std::{collections::BTreeSet, mem::size_of};
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ArenaIndex {
     index: usize,
     generation: u32,
@@ -22,6 +22,8 @@ pub struct ArenaStats {
 pub struct Arena<T: Clone + std::fmt::Debug> {
     data: Vec<ArenaNode<T>>,
     deleted_indices: BTreeSet<usize>,
+    /// slots below this were frozen by `freeze` and are never changed or reused
+    frozen_len: usize,
 }
 impl<T: Clone + std::fmt::Debug> Arena<T> {
     const BASE_GENERATION: u32 = ArenaNode::<T>::BASE_GENERATION;
@@ -29,6 +31,7 @@ impl<T: Clone + std::fmt::Debug> Arena<T> {
         Self {
             data: Vec::new(),
             deleted_indices: BTreeSet::new(),
+            frozen_len: 0,
         }
     }
     /// inserts a value, If the arena is empty the root node is set
@@ -103,6 +106,24 @@ impl<T: Clone + std::fmt::Debug> Arena<T> {
             })
         }
     }
+    /// Keeps the current values from being changed, so they can be referenced from several places.
+    /// The root can still be updated
+    pub fn freeze(&mut self) {
+        self.frozen_len = self.data.len();
+    }
+    pub fn is_frozen(&self, index: ArenaIndex) -> bool {
+        index.index < self.frozen_len
+    }
+    /// Updates the value in place unless it is frozen, then the value is inserted in a new slot.
+    /// Returns where the value is stored
+    pub fn write(&mut self, index: ArenaIndex, data: T) -> ArenaIndex {
+        if self.is_frozen(index) {
+            self.insert(data)
+        } else {
+            self.update(index, data);
+            index
+        }
+    }
     pub fn delete(&mut self, index: ArenaIndex) {
         assert!(self.key_exists(index));
         self.deleted_indices.insert(index.index);
@@ -4,17 +4,26 @@ use super::{
     FastOctTree, Voxel,
 };
 
-use crate::prelude::{Ray, RayScalar};
+use crate::prelude::{Ray, RayScalar, Sampler};
 use cgmath::{prelude::*, Point2, Point3, Vector3};
 impl Hittable for FastOctTree<Voxel> {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let aabb = self.bounding_box(t_min, t_max).unwrap();
         if aabb.hit(*ray, t_min, t_max) {
-            if let Some(hit_info) = self.trace_ray(Ray {
-                origin: ray.origin,
-                time: ray.time,
-                direction: ray.direction.normalize(),
-            }) {
+            if let Some(hit_info) = self.trace_ray(
+                Ray {
+                    origin: ray.origin,
+                    time: ray.time,
+                    direction: ray.direction.normalize(),
+                },
+                sampler,
+            ) {
                 match hit_info {
                     HitInfo::Solid {
                         hit_value,
@@ -33,6 +42,7 @@ impl Hittable for FastOctTree<Voxel> {
                                 t,
                                 Point2::new(0.5, 0.5),
                                 hit_value,
+                                sampler,
                             ))
                         } else {
                             None
@@ -53,6 +63,7 @@ impl Hittable for FastOctTree<Voxel> {
                                 t,
                                 Point2::origin(),
                                 hit_value,
+                                sampler,
                             ))
                         } else {
                             None
@@ -77,10 +88,15 @@ impl Hittable for FastOctTree<Voxel> {
             None
         }
     }
-    fn prob(&self, _ray: Ray) -> RayScalar {
+    fn prob(&self, _ray: Ray, _sampler: &mut Sampler) -> RayScalar {
         todo!("prob")
     }
-    fn generate_ray_in_area(&self, _origin: Point3<RayScalar>, _time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        _origin: Point3<RayScalar>,
+        _time: RayScalar,
+        _sampler: &mut Sampler,
+    ) -> RayAreaInfo {
         todo!("generate in area")
     }
 }
@@ -1,15 +1,110 @@
-use super::voxel::{Voxel, VoxelMaterial};
+use super::voxel::{SolidVoxel, VolumeEdgeEffect, Voxel, VoxelMaterial};
+use crate::{prelude::RgbColor, ray_tracer::phase_function::PhaseFunction};
 use cgmath::Point3;
-use std::clone::Clone;
+use std::{clone::Clone, hash::Hash};
 pub trait Leafable: Clone + PartialEq + std::fmt::Debug {
     type Material;
+    /// Identifies the leaf exactly, leaves with the same key are stored once by
+    /// `FastOctTree::compact`. Floats are compared by their bits
+    type Key: Eq + Hash;
+    fn key(&self) -> Self::Key;
 }
 impl Leafable for Voxel {
     type Material = VoxelMaterial;
+    type Key = VoxelKey;
+    fn key(&self) -> VoxelKey {
+        let mut key = VoxelKey::default();
+        match self {
+            Voxel::Solid(solid) => {
+                key.push(0);
+                key.push_solid(solid);
+            }
+            Voxel::Volume(volume) => {
+                key.push(1);
+                key.push(volume.density.to_bits());
+                key.push_color(volume.color);
+                match volume.edge_effect {
+                    VolumeEdgeEffect::None => key.push(0),
+                    VolumeEdgeEffect::Solid {
+                        hit_probability,
+                        solid_material,
+                    } => {
+                        key.push(1);
+                        key.push(hit_probability.to_bits() as u64);
+                        key.push_solid(&solid_material);
+                    }
+                }
+                match volume.phase_function {
+                    PhaseFunction::Isotropic => key.push(0),
+                    PhaseFunction::HenyeyGreenstein { g } => {
+                        key.push(1);
+                        key.push(g.to_bits());
+                    }
+                    PhaseFunction::DoubleHenyeyGreenstein {
+                        forward_g,
+                        backward_g,
+                        forward_weight,
+                    } => {
+                        key.push(2);
+                        key.push(forward_g.to_bits());
+                        key.push(backward_g.to_bits());
+                        key.push(forward_weight.to_bits());
+                    }
+                    PhaseFunction::Rayleigh => key.push(3),
+                    PhaseFunction::Mie { g } => {
+                        key.push(4);
+                        key.push(g.to_bits());
+                    }
+                }
+            }
+        }
+        key
+    }
 }
 impl Leafable for u32 {
     type Material = ();
+    type Key = u32;
+    fn key(&self) -> u32 {
+        *self
+    }
 }
 impl Leafable for Point3<u32> {
     type Material = ();
+    type Key = [u32; 3];
+    fn key(&self) -> [u32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+/// bits of every field of a voxel, unused words stay zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VoxelKey {
+    words: [u64; Self::MAX_WORDS],
+    len: usize,
+}
+impl VoxelKey {
+    /// the longest voxel is a volume with a solid edge and a double Henyey-Greenstein phase
+    /// function
+    const MAX_WORDS: usize = 16;
+    fn push(&mut self, word: u64) {
+        self.words[self.len] = word;
+        self.len += 1;
+    }
+    fn push_color(&mut self, color: RgbColor) {
+        for channel in [color.red, color.green, color.blue] {
+            self.push(channel.to_bits() as u64);
+        }
+    }
+    fn push_solid(&mut self, solid: &SolidVoxel) {
+        match solid {
+            SolidVoxel::Lambertian { albedo } => {
+                self.push(0);
+                self.push_color(*albedo);
+            }
+            SolidVoxel::Reflect { albedo, fuzz } => {
+                self.push(1);
+                self.push_color(*albedo);
+                self.push(fuzz.to_bits() as u64);
+            }
+        }
+    }
 }
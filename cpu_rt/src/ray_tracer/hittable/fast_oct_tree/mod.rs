@@ -16,7 +16,8 @@ pub type TreePosition = Point3<IndexType>;
 use arena::{Arena, ArenaIndex};
 
 pub use arena::ArenaStats;
-pub use stats::FastOctTreeStats;
+pub use operations::PastePolicy;
+pub use stats::{CompactionStats, FastOctTreeStats};
 pub use voxel::{SolidVoxel, VolumeEdgeEffect, VolumeVoxel, Voxel};
 pub type VoxelGrid = FastOctTree<Voxel>;
 
@@ -39,6 +40,42 @@ impl<T: Leafable> Node<T> {
     fn is_empty(&self) -> bool {
         matches!(self.data, NodeData::Empty)
     }
+    /// turns a leaf or empty node into a parent with eight children of the same value and returns
+    /// the children
+    fn split(&mut self, arena: &mut Arena<Self>) -> [ArenaIndex; 8] {
+        match &self.data {
+            NodeData::Parent { children } => *children,
+            data => {
+                let child = Node {
+                    data: data.clone(),
+                    size: self.size - 1,
+                };
+                let children = [(); 8].map(|_| arena.insert(child.clone()));
+                self.data = NodeData::Parent { children };
+                children
+            }
+        }
+    }
+    /// if all children are the same leaf or are empty the parent takes their value and the
+    /// children are deleted, frozen children can be shared so they are left for `compact`
+    fn merge_children(mut self, arena: &mut Arena<Self>) -> Self {
+        if let NodeData::Parent { children } = self.data {
+            let child0 = arena.get(children[0]).unwrap().clone();
+            if (child0.is_leaf() || child0.is_empty())
+                && children[1..]
+                    .iter()
+                    .all(|child| *arena.get(*child).unwrap() == child0)
+            {
+                self.data = child0.data;
+                for child in children {
+                    if !arena.is_frozen(child) {
+                        arena.delete(child);
+                    }
+                }
+            }
+        }
+        self
+    }
     //sets children and returns  a copy of modified version of self
     fn set_child(mut self, value: T, position: TreePosition, arena: &mut Arena<Self>) -> Self {
         if self.size == 0 {
@@ -47,124 +84,17 @@ impl<T: Leafable> Node<T> {
 
             self
         } else {
-            match self.data {
-                // if self is not a parent setting value to parent
-                NodeData::Empty => {
-                    let children = [
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Empty,
-                            size: self.size - 1,
-                        }),
-                    ];
-                    self.data = NodeData::Parent { children };
-                }
-                NodeData::Leaf(leaf) => {
-                    let children = [
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf.clone()),
-                            size: self.size - 1,
-                        }),
-                        arena.insert(Node {
-                            data: NodeData::Leaf(leaf),
-                            size: self.size - 1,
-                        }),
-                    ];
-                    self.data = NodeData::Parent { children };
-                }
-                NodeData::Parent { .. } => {}
-            };
-
-            match self.data {
-                NodeData::Parent { children } => {
-                    let index = Node::<T>::world_pos_to_child_index(position, self.size) as usize;
-                    let pos_in_child = Self::world_pos_to_child_pos(position, self.size);
-
-                    let child = arena.get(children[index]).expect("should exist").clone();
+            let mut children = self.split(arena);
+            let index = Node::<T>::world_pos_to_child_index(position, self.size) as usize;
+            let pos_in_child = Self::world_pos_to_child_pos(position, self.size);
 
-                    let child_clone = child.clone().set_child(value, pos_in_child, arena);
+            let child = arena.get(children[index]).expect("should exist").clone();
 
-                    arena.update(children[index], child_clone.clone());
+            let child_clone = child.set_child(value, pos_in_child, arena);
 
-                    let child0 = arena.get(children[0]).unwrap().clone();
-                    let child1 = arena.get(children[1]).unwrap().clone();
-                    let child2 = arena.get(children[2]).unwrap().clone();
-                    let child3 = arena.get(children[3]).unwrap().clone();
-                    let child4 = arena.get(children[4]).unwrap().clone();
-                    let child5 = arena.get(children[5]).unwrap().clone();
-                    let child6 = arena.get(children[6]).unwrap().clone();
-                    let child7 = arena.get(children[7]).unwrap().clone();
-                    if child0 == child1
-                        && child0 == child2
-                        && child0 == child3
-                        && child0 == child4
-                        && child0 == child5
-                        && child0 == child6
-                        && child0 == child7
-                        && (child0.is_leaf() || child0.is_empty())
-                    {
-                        self.data = child0.data.clone();
-                        for child in children {
-                            arena.delete(child);
-                        }
-                        self
-                    } else {
-                        self
-                    }
-                }
-                _ => panic!("must be parent"),
-            }
+            children[index] = arena.write(children[index], child_clone);
+            self.data = NodeData::Parent { children };
+            self.merge_children(arena)
         }
     }
 
@@ -191,6 +121,15 @@ impl<T: Leafable> Node<T> {
             size,
         }
     }
+    /// position of the child with the index in units of the child size, the inverse of
+    /// `pos_to_index`
+    const fn index_to_pos(index: usize) -> TreePosition {
+        Point3 {
+            x: (index as IndexType >> 2) & 1,
+            y: (index as IndexType >> 1) & 1,
+            z: index as IndexType & 1,
+        }
+    }
     /// returns the index of the position
     const fn pos_to_index(position: TreePosition) -> usize {
         match position {
@@ -303,6 +242,7 @@ mod test {
         let indices = [0, 1, 2, 3, 4, 5, 6, 7];
         for (position, index) in positions.iter().zip(indices) {
             assert_eq!(Node::<u32>::pos_to_index(*position), index);
+            assert_eq!(Node::<u32>::index_to_pos(index), *position);
         }
     }
     #[test]
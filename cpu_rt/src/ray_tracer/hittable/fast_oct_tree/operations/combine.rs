@@ -1,26 +1,16 @@
-use super::{FastOctTree, Leafable};
-use cgmath::{Point3, Vector3};
+use super::{FastOctTree, Leafable, PastePolicy};
+use cgmath::Vector3;
 impl<T: Leafable> FastOctTree<T> {
+    /// pastes `other` moved by `offset` over this tree
     pub(crate) fn combine(mut self, other: &Self, offset: Vector3<i32>) -> Self {
-        for x in 0..other.world_size() {
-            for y in 0..other.world_size() {
-                for z in 0..other.world_size() {
-                    let get_position = Point3::new(x, y, z);
-                    let set_position = Point3::new(x as i32, y as i32, z as i32) + offset;
-                    if set_position.x >= 0 && set_position.y >= 0 && set_position.z >= 0 {
-                        if let Some(value) = other.get(get_position) {
-                            self.set(value, set_position.map(|v| v as u32));
-                        }
-                    }
-                }
-            }
-        }
+        self.paste(other, offset, PastePolicy::Replace);
         self
     }
 }
 #[cfg(test)]
 mod test {
     use super::*;
+    use cgmath::Point3;
     #[test]
     fn two_empty() {
         let e0 = FastOctTree::<u32>::new();
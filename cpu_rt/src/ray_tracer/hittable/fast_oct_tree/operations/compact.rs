@@ -0,0 +1,218 @@
+use super::{
+    super::{arena::ArenaIndex, CompactionStats},
+    Arena, FastOctTree, Leafable, Node, NodeData,
+};
+use std::collections::HashMap;
+
+/// Copies the nodes that can be reached from the root into a new arena
+struct Rebuild<'a, T: Leafable> {
+    old: &'a Arena<Node<T>>,
+    new: Arena<Node<T>>,
+    /// leaf and empty nodes already in the new arena by their size and leaf key
+    uniform: HashMap<(u32, Option<T::Key>), ArenaIndex>,
+    /// parents already in the new arena by their size and children
+    parents: HashMap<(u32, [ArenaIndex; 8]), ArenaIndex>,
+}
+impl<'a, T: Leafable> Rebuild<'a, T> {
+    fn new(old: &'a Arena<Node<T>>) -> Self {
+        Self {
+            old,
+            new: Arena::new(),
+            uniform: HashMap::new(),
+            parents: HashMap::new(),
+        }
+    }
+    /// The root always stays at the start of the arena. The other nodes are stored depth first
+    /// so a subtree is close together in memory
+    fn run(mut self) -> Arena<Node<T>> {
+        if let Some(root) = self.old.get_root_ref() {
+            // keeps the first slot for the root
+            self.new.insert(Node::empty());
+            let root = self.copy(root);
+            self.new.update_root(root);
+        }
+        self.new
+    }
+    /// copy of the node with its children moved into the new arena
+    fn copy(&mut self, node: &Node<T>) -> Node<T> {
+        match &node.data {
+            NodeData::Parent { children } => {
+                let children = children.map(|child| {
+                    let child = self.old.get(child).expect("should have child");
+                    let child = self.copy(child);
+                    self.insert(child)
+                });
+                Node {
+                    data: NodeData::Parent { children },
+                    size: node.size,
+                }
+            }
+            _ => node.clone(),
+        }
+    }
+    /// stores the node once, identical nodes get the index of the first copy
+    fn insert(&mut self, node: Node<T>) -> ArenaIndex {
+        match node.data {
+            NodeData::Parent { children } => {
+                let new = &mut self.new;
+                *self
+                    .parents
+                    .entry((node.size, children))
+                    .or_insert_with(|| new.insert(node))
+            }
+            NodeData::Leaf(ref leaf) => {
+                let key = (node.size, Some(leaf.key()));
+                let new = &mut self.new;
+                *self.uniform.entry(key).or_insert_with(|| new.insert(node))
+            }
+            NodeData::Empty => {
+                let new = &mut self.new;
+                *self
+                    .uniform
+                    .entry((node.size, None))
+                    .or_insert_with(|| new.insert(node))
+            }
+        }
+    }
+}
+impl<T: Leafable> FastOctTree<T> {
+    /// Rebuilds the arena with only the nodes that can still be reached from the root, edits
+    /// leave the children of merged nodes behind. Identical subtrees are stored once, which
+    /// shrinks repetitive maps a lot. The nodes are frozen afterwards, so later edits copy the
+    /// nodes on the path to the edited voxels instead of changing the shared ones
+    pub fn compact(&mut self) -> CompactionStats {
+        let before = self.stats();
+        self.arena = Rebuild::new(&self.arena).run();
+        self.arena.freeze();
+        CompactionStats {
+            before,
+            after: self.stats(),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::IterBox;
+    use cgmath::Point3;
+
+    /// repeating 2x2x2 tiles, so a lot of the subtrees are the same
+    fn tiled(size: u32) -> FastOctTree<u32> {
+        let mut tree = FastOctTree::new();
+        tree.fill_with(Point3::new(0, 0, 0), Point3::new(size, size, size), |p| {
+            Some(p.x % 2 + 2 * (p.y % 2) + 4 * (p.z % 2))
+        });
+        tree
+    }
+    fn voxels(tree: &FastOctTree<u32>, size: u32) -> Vec<Option<u32>> {
+        IterBox::from_point(Point3::new(size, size, size))
+            .iter()
+            .map(|p| tree.get(p))
+            .collect()
+    }
+    #[test]
+    fn compact_empty() {
+        let mut tree = FastOctTree::<u32>::new();
+        tree.compact();
+        assert_eq!(tree.reachable_nodes(), 0);
+        assert!(tree.get(Point3::new(0, 0, 0)).is_none());
+        tree.set(3, Point3::new(1, 2, 3));
+        assert_eq!(tree.get(Point3::new(1, 2, 3)), Some(3));
+    }
+    #[test]
+    fn removes_garbage() {
+        let mut tree = tiled(16);
+        tree.clear_aabb(Point3::new(0, 0, 0), Point3::new(16, 16, 8));
+        let expected = voxels(&tree, 16);
+        let reachable_before = tree.reachable_nodes();
+        let stats = tree.compact();
+        assert!(stats.before.arena_stats.data_len > reachable_before);
+        assert_eq!(stats.after.arena_stats.num_deleted_elements, 0);
+        assert_eq!(stats.after.arena_stats.data_len, tree.reachable_nodes());
+        assert_eq!(stats.after, tree.stats());
+        assert_eq!(voxels(&tree, 16), expected);
+    }
+    #[test]
+    fn deduplicates_subtrees() {
+        let mut tree = tiled(32);
+        let expected = voxels(&tree, 32);
+        let reachable_before = tree.reachable_nodes();
+        let stats = tree.compact();
+        // one copy of the 8 leaves, one tile and one parent per level above it
+        assert_eq!(tree.reachable_nodes(), 8 + 1 + 4);
+        assert!(stats.after.arena_stats.data_len < reachable_before / 100);
+        assert_eq!(voxels(&tree, 32), expected);
+    }
+    #[test]
+    fn edit_after_compact() {
+        let mut tree = tiled(16);
+        let mut expected = voxels(&tree, 16);
+        tree.compact();
+        let index = |p: Point3<u32>| (p.x * 16 * 16 + p.y * 16 + p.z) as usize;
+        let changed = Point3::new(5, 9, 2);
+        tree.set(100, changed);
+        expected[index(changed)] = Some(100);
+        let removed = Point3::new(12, 0, 7);
+        tree.remove(removed);
+        expected[index(removed)] = None;
+        assert_eq!(voxels(&tree, 16), expected);
+        // compacting again drops the nodes that are no longer used
+        let reachable_before = tree.reachable_nodes();
+        let stats = tree.compact();
+        assert!(tree.reachable_nodes() <= reachable_before);
+        assert_eq!(stats.after.arena_stats.data_len, tree.reachable_nodes());
+        assert_eq!(voxels(&tree, 16), expected);
+    }
+    #[test]
+    fn edit_copies_only_the_path() {
+        let mut tree = tiled(32);
+        let mut expected = voxels(&tree, 32);
+        let stats = tree.compact();
+        let changed = Point3::new(17, 3, 30);
+        tree.set(100, changed);
+        expected[(changed.x * 32 * 32 + changed.y * 32 + changed.z) as usize] = Some(100);
+        // the root is updated in place, the 4 parents below it and the leaf are copied
+        assert_eq!(
+            tree.stats().arena_stats.data_len,
+            stats.after.arena_stats.data_len + 5
+        );
+        assert_eq!(voxels(&tree, 32), expected);
+        let reachable_before = tree.reachable_nodes();
+        tree.fill_aabb(Point3::new(0, 0, 0), Point3::new(2, 2, 2), 7);
+        assert!(tree.reachable_nodes() <= reachable_before + 4);
+        assert_eq!(tree.get(Point3::new(1, 1, 1)), Some(7));
+        assert_eq!(
+            tree.get(Point3::new(2, 2, 2)),
+            expected[2 * 32 * 32 + 2 * 32 + 2]
+        );
+        assert_eq!(tree.get(changed), Some(100));
+    }
+    #[test]
+    fn voxel_keys() {
+        use super::super::super::{SolidVoxel, VolumeEdgeEffect, VolumeVoxel, Voxel};
+        use crate::{prelude::RgbColor, ray_tracer::phase_function::PhaseFunction};
+        let albedo = RgbColor::new(0.2, 0.4, 0.6);
+        let volume = |density| {
+            Voxel::Volume(VolumeVoxel {
+                density,
+                color: albedo,
+                edge_effect: VolumeEdgeEffect::Solid {
+                    hit_probability: 0.5,
+                    solid_material: SolidVoxel::Reflect { albedo, fuzz: 0.1 },
+                },
+                phase_function: PhaseFunction::DoubleHenyeyGreenstein {
+                    forward_g: 0.7,
+                    backward_g: -0.3,
+                    forward_weight: 0.8,
+                },
+            })
+        };
+        assert!(volume(0.5).key() == volume(0.5).key());
+        // close enough to compare equal but still stored separately
+        assert!(volume(0.5) == volume(0.50001));
+        assert!(volume(0.5).key() != volume(0.50001).key());
+        let lambertian = Voxel::Solid(SolidVoxel::Lambertian { albedo });
+        let reflect = Voxel::Solid(SolidVoxel::Reflect { albedo, fuzz: 0.0 });
+        assert!(lambertian.key() != reflect.key());
+    }
+}
@@ -0,0 +1,573 @@
+use super::{Arena, FastOctTree, IndexType, Leafable, Node, NodeData, TreePosition};
+use crate::prelude::{IterBox, RayScalar};
+use cgmath::{prelude::*, Point3, Vector3};
+
+/// how a node lies relative to the region being edited
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overlap {
+    Inside,
+    Outside,
+    Partial,
+}
+impl Overlap {
+    fn invert(self) -> Self {
+        match self {
+            Self::Inside => Self::Outside,
+            Self::Outside => Self::Inside,
+            Self::Partial => Self::Partial,
+        }
+    }
+}
+/// overlap of the node at `origin` that is `width` voxels wide with the box from `start` up to
+/// but not including `end`
+fn box_overlap(
+    origin: TreePosition,
+    width: IndexType,
+    start: TreePosition,
+    end: TreePosition,
+) -> Overlap {
+    let node_end = origin.map(|v| v + width);
+    if (0..3).any(|axis| origin[axis] >= end[axis] || node_end[axis] <= start[axis]) {
+        Overlap::Outside
+    } else if (0..3).all(|axis| origin[axis] >= start[axis] && node_end[axis] <= end[axis]) {
+        Overlap::Inside
+    } else {
+        Overlap::Partial
+    }
+}
+/// overlap with the voxels whose centers are inside of the sphere
+fn sphere_overlap(
+    origin: TreePosition,
+    width: IndexType,
+    center: Point3<RayScalar>,
+    radius: RayScalar,
+) -> Overlap {
+    let mut nearest = 0.0;
+    let mut farthest = 0.0;
+    for axis in 0..3 {
+        let low = origin[axis] as RayScalar + 0.5;
+        let high = (origin[axis] + width) as RayScalar - 0.5;
+        let c = center[axis];
+        nearest += (c - c.clamp(low, high)).powi(2);
+        farthest += (c - low).abs().max((c - high).abs()).powi(2);
+    }
+    if farthest <= radius * radius {
+        Overlap::Inside
+    } else if nearest > radius * radius {
+        Overlap::Outside
+    } else {
+        Overlap::Partial
+    }
+}
+/// How voxels pasted into a tree are combined with the voxels already in it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PastePolicy {
+    /// pasted voxels overwrite the existing ones
+    Replace,
+    /// pasted voxels only go where the tree is empty
+    KeepExisting,
+    /// pasted voxels only overwrite voxels that are already set
+    ReplaceExisting,
+}
+impl PastePolicy {
+    fn merge<T: Leafable>(self, existing: Option<&T>, pasted: &T) -> Option<T> {
+        match (self, existing) {
+            (Self::Replace, _) | (Self::KeepExisting, None) | (Self::ReplaceExisting, Some(_)) => {
+                Some(pasted.clone())
+            }
+            (Self::KeepExisting, Some(existing)) => Some(existing.clone()),
+            (Self::ReplaceExisting, None) => None,
+        }
+    }
+}
+impl<T: Leafable> Node<T> {
+    fn value(&self) -> Option<&T> {
+        match &self.data {
+            NodeData::Leaf(leaf) => Some(leaf),
+            _ => None,
+        }
+    }
+    /// Replaces every value in the region with `f(value)`. `origin` is the lowest corner of the
+    /// node and `classify` gets the origin and width of a node. Nodes are only split where the
+    /// region partially covers them and are merged again afterwards
+    fn apply<C, F>(
+        mut self,
+        origin: TreePosition,
+        classify: &C,
+        f: &F,
+        arena: &mut Arena<Self>,
+    ) -> Self
+    where
+        C: Fn(TreePosition, IndexType) -> Overlap,
+        F: Fn(Option<&T>) -> Option<T>,
+    {
+        let overlap = classify(origin, self.get_world_size());
+        if overlap == Overlap::Outside {
+            return self;
+        }
+        if !matches!(self.data, NodeData::Parent { .. }) {
+            let value = f(self.value());
+            if value.as_ref() == self.value() {
+                return self;
+            }
+            if overlap == Overlap::Inside {
+                self.data = match value {
+                    Some(value) => NodeData::Leaf(value),
+                    None => NodeData::Empty,
+                };
+                return self;
+            }
+        }
+        // a single voxel is always inside or outside so only bigger nodes get here
+        let mut children = self.split(arena);
+        let child_width = 1 << (self.size - 1);
+        for (index, child_index) in children.iter_mut().enumerate() {
+            let child_origin = origin + Self::index_to_pos(index).map(|v| v * child_width).to_vec();
+            let old_child = arena.get(*child_index).expect("should exist").clone();
+            let child = old_child.clone().apply(child_origin, classify, f, arena);
+            // unchanged children stay shared
+            if child != old_child {
+                *child_index = arena.write(*child_index, child);
+            }
+        }
+        self.data = NodeData::Parent { children };
+        self.merge_children(arena)
+    }
+    /// calls `visit` with the start, end and value of the leaf and empty nodes in the region
+    fn visit_blocks<C, V>(
+        &self,
+        origin: TreePosition,
+        classify: &C,
+        visit: &mut V,
+        arena: &Arena<Self>,
+    ) where
+        C: Fn(TreePosition, IndexType) -> Overlap,
+        V: FnMut(TreePosition, TreePosition, Option<&T>),
+    {
+        let width = self.get_world_size();
+        if classify(origin, width) == Overlap::Outside {
+            return;
+        }
+        match &self.data {
+            NodeData::Parent { children } => {
+                let child_width = width / 2;
+                for (index, child) in children.iter().enumerate() {
+                    let child_origin =
+                        origin + Self::index_to_pos(index).map(|v| v * child_width).to_vec();
+                    arena.get(*child).expect("should have child").visit_blocks(
+                        child_origin,
+                        classify,
+                        visit,
+                        arena,
+                    );
+                }
+            }
+            _ => visit(origin, origin.map(|v| v + width), self.value()),
+        }
+    }
+}
+impl<T: Leafable> FastOctTree<T> {
+    fn apply<C, F>(&mut self, classify: C, f: F)
+    where
+        C: Fn(TreePosition, IndexType) -> Overlap,
+        F: Fn(Option<&T>) -> Option<T>,
+    {
+        if let Some(root) = self.arena.get_root() {
+            let new_root = root.apply(Point3::new(0, 0, 0), &classify, &f, &mut self.arena);
+            self.arena.update_root(new_root);
+        }
+    }
+    fn visit_blocks<C, V>(&self, classify: C, mut visit: V)
+    where
+        C: Fn(TreePosition, IndexType) -> Overlap,
+        V: FnMut(TreePosition, TreePosition, Option<&T>),
+    {
+        if let Some(root) = self.arena.get_root_ref() {
+            root.visit_blocks(Point3::new(0, 0, 0), &classify, &mut visit, &self.arena);
+        }
+    }
+    /// empties the voxel at the position
+    pub fn remove(&mut self, position: TreePosition) {
+        self.clear_aabb(position, position.map(|v| v + 1));
+    }
+    /// sets every voxel from `start` up to but not including `end`
+    pub fn fill_aabb(&mut self, start: TreePosition, end: TreePosition, value: T) {
+        if (0..3).any(|axis| start[axis] >= end[axis]) {
+            return;
+        }
+        self.grow_to(end.map(|v| v - 1));
+        self.apply(
+            |origin, width| box_overlap(origin, width, start, end),
+            |_| Some(value.clone()),
+        );
+    }
+    /// empties every voxel from `start` up to but not including `end`
+    pub fn clear_aabb(&mut self, start: TreePosition, end: TreePosition) {
+        self.apply(
+            |origin, width| box_overlap(origin, width, start, end),
+            |_| None,
+        );
+    }
+    /// sets every voxel whose center is inside of the sphere, voxel `v` is centered on `v + 0.5`
+    pub fn fill_sphere(&mut self, center: Point3<RayScalar>, radius: RayScalar, value: T) {
+        // the last voxel with its center in the sphere
+        let last = center.map(|v| (v + radius - 0.5).floor());
+        if last.x < 0.0 || last.y < 0.0 || last.z < 0.0 {
+            return;
+        }
+        self.grow_to(last.map(|v| v as IndexType));
+        self.apply(
+            |origin, width| sphere_overlap(origin, width, center, radius),
+            |_| Some(value.clone()),
+        );
+    }
+    /// Sets every voxel from `start` up to but not including `end` to the value returned by `f`,
+    /// voxels where `f` returns `None` are left as they are
+    pub fn fill_with<F: Fn(TreePosition) -> Option<T>>(
+        &mut self,
+        start: TreePosition,
+        end: TreePosition,
+        f: F,
+    ) {
+        if (0..3).any(|axis| start[axis] >= end[axis]) {
+            return;
+        }
+        for position in IterBox::from_point(end).start(start).iter() {
+            if let Some(value) = f(position) {
+                self.set(value, position);
+            }
+        }
+    }
+    /// copies the voxels from `start` up to but not including `end` into a new tree that starts
+    /// at `start`
+    pub fn extract(&self, start: TreePosition, end: TreePosition) -> Self {
+        let mut tree = Self::new();
+        self.visit_blocks(
+            |origin, width| box_overlap(origin, width, start, end),
+            |block_start, block_end, value| {
+                if let Some(value) = value {
+                    let clip_start = Point3::new(
+                        block_start.x.max(start.x),
+                        block_start.y.max(start.y),
+                        block_start.z.max(start.z),
+                    );
+                    let clip_end = Point3::new(
+                        block_end.x.min(end.x),
+                        block_end.y.min(end.y),
+                        block_end.z.min(end.z),
+                    );
+                    tree.fill_aabb(
+                        clip_start - start.to_vec(),
+                        clip_end - start.to_vec(),
+                        value.clone(),
+                    );
+                }
+            },
+        );
+        tree
+    }
+    /// Pastes the voxels of `other` moved by `offset`, voxels that would end up at a negative
+    /// position are dropped
+    pub fn paste(&mut self, other: &Self, offset: Vector3<i32>, policy: PastePolicy) {
+        other.visit_blocks(
+            |_, _| Overlap::Inside,
+            |block_start, block_end, value| {
+                if let Some(value) = value {
+                    let start = block_start.map(|v| v as i32) + offset;
+                    let end = block_end.map(|v| v as i32) + offset;
+                    if end.x <= 0 || end.y <= 0 || end.z <= 0 {
+                        return;
+                    }
+                    let start = start.map(|v| v.max(0) as IndexType);
+                    let end = end.map(|v| v as IndexType);
+                    self.grow_to(end.map(|v| v - 1));
+                    self.apply(
+                        |origin, width| box_overlap(origin, width, start, end),
+                        |existing| policy.merge(existing, value),
+                    );
+                }
+            },
+        );
+    }
+    /// voxels that are in either tree, where both are set the value of `self` is kept
+    pub fn union(mut self, other: &Self) -> Self {
+        self.paste(other, Vector3::new(0, 0, 0), PastePolicy::KeepExisting);
+        self
+    }
+    /// voxels of `self` that are also set in `other`
+    pub fn intersection(mut self, other: &Self) -> Self {
+        let other_size = other.world_size();
+        self.apply(
+            |origin, width| {
+                box_overlap(
+                    origin,
+                    width,
+                    Point3::new(0, 0, 0),
+                    Point3::new(other_size, other_size, other_size),
+                )
+                .invert()
+            },
+            |_| None,
+        );
+        other.visit_blocks(
+            |_, _| Overlap::Inside,
+            |start, end, value| {
+                if value.is_none() {
+                    self.clear_aabb(start, end);
+                }
+            },
+        );
+        self
+    }
+    /// voxels of `self` that are not set in `other`
+    pub fn difference(mut self, other: &Self) -> Self {
+        other.visit_blocks(
+            |_, _| Overlap::Inside,
+            |start, end, value| {
+                if value.is_some() {
+                    self.clear_aabb(start, end);
+                }
+            },
+        );
+        self
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::MetricSpace;
+
+    /// checks that no parent has eight children that could have been merged
+    fn assert_merged<T: Leafable>(tree: &FastOctTree<T>) {
+        fn check<T: Leafable>(node: &Node<T>, arena: &Arena<Node<T>>) {
+            if let NodeData::Parent { children } = &node.data {
+                let children = children
+                    .iter()
+                    .map(|child| arena.get(*child).expect("child exists"))
+                    .collect::<Vec<_>>();
+                assert!(
+                    !((children[0].is_leaf() || children[0].is_empty())
+                        && children.iter().all(|child| *child == children[0])),
+                    "children are not merged"
+                );
+                for child in children {
+                    check(child, arena);
+                }
+            }
+        }
+        if let Some(root) = tree.arena.get_root_ref() {
+            check(root, &tree.arena);
+        }
+    }
+    /// compares every voxel in the box of `size` against `expected`
+    fn assert_voxels<F: Fn(TreePosition) -> Option<u32>>(
+        tree: &FastOctTree<u32>,
+        size: u32,
+        expected: F,
+    ) {
+        for position in IterBox::from_xyz(size, size, size).iter() {
+            assert_eq!(tree.get(position), expected(position), "at {:?}", position);
+        }
+        assert_merged(tree);
+    }
+    fn in_box(position: TreePosition, start: TreePosition, end: TreePosition) -> bool {
+        (0..3).all(|axis| position[axis] >= start[axis] && position[axis] < end[axis])
+    }
+    /// scattered voxels with a few different values
+    fn pattern(position: TreePosition) -> Option<u32> {
+        let hash = position.x * 7 + position.y * 13 + position.z * 5;
+        if hash.is_multiple_of(3) {
+            Some(hash % 4)
+        } else {
+            None
+        }
+    }
+    fn pattern_tree(size: u32) -> FastOctTree<u32> {
+        let mut tree = FastOctTree::new();
+        tree.fill_with(Point3::new(0, 0, 0), Point3::new(size, size, size), pattern);
+        tree
+    }
+    #[test]
+    fn remove() {
+        let mut tree = FastOctTree::new();
+        tree.fill_aabb(Point3::new(0, 0, 0), Point3::new(4, 4, 4), 1u32);
+        tree.remove(Point3::new(1, 2, 3));
+        assert_voxels(&tree, 4, |position| {
+            (position != Point3::new(1, 2, 3)).then_some(1)
+        });
+        for position in IterBox::from_xyz(4, 4, 4).iter() {
+            tree.remove(position);
+        }
+        assert_eq!(tree.arena.get_root().unwrap().data, NodeData::Empty);
+        // removing outside of the tree does not grow it
+        tree.remove(Point3::new(100, 0, 0));
+        assert_eq!(tree.world_size(), 4);
+        FastOctTree::<u32>::new().remove(Point3::new(1, 1, 1));
+    }
+    #[test]
+    fn fill_aabb() {
+        let (start, end) = (Point3::new(1, 2, 0), Point3::new(5, 6, 7));
+        let mut tree = pattern_tree(8);
+        tree.fill_aabb(start, end, 9);
+        assert_voxels(&tree, 8, |position| {
+            if in_box(position, start, end) {
+                Some(9)
+            } else {
+                pattern(position)
+            }
+        });
+        tree.clear_aabb(start, end);
+        assert_voxels(&tree, 8, |position| {
+            if in_box(position, start, end) {
+                None
+            } else {
+                pattern(position)
+            }
+        });
+        // a filled cube merges into a single leaf
+        let mut tree = FastOctTree::new();
+        tree.fill_aabb(Point3::new(0, 0, 0), Point3::new(16, 16, 16), 10u32);
+        let root = tree.arena.get_root().unwrap();
+        assert_eq!(root.size, 4);
+        assert_eq!(root.data, NodeData::Leaf(10));
+        // an empty box does nothing
+        tree.fill_aabb(Point3::new(20, 0, 0), Point3::new(20, 30, 30), 1);
+        assert_eq!(tree.world_size(), 16);
+    }
+    #[test]
+    fn fill_sphere() {
+        let radius = 5;
+        let mut tree = FastOctTree::new();
+        let center = Point3::new(radius, radius, radius).map(|v| v as RayScalar);
+        tree.fill_sphere(center, radius as RayScalar, 1u32);
+        let expected = FastOctTree::sphere(radius, 1u32);
+        assert_eq!(tree.world_size(), expected.world_size());
+        assert_voxels(&tree, 16, |position| expected.get(position));
+
+        let center = Point3::new(3.2, 4.0, 2.7);
+        let mut tree = pattern_tree(8);
+        tree.fill_sphere(center, 2.5, 7);
+        assert_voxels(&tree, 8, |position| {
+            if position.map(|v| v as RayScalar + 0.5).distance(center) <= 2.5 {
+                Some(7)
+            } else {
+                pattern(position)
+            }
+        });
+        // spheres below zero are cut off
+        let mut tree = FastOctTree::new();
+        tree.fill_sphere(Point3::new(-5.0, 1.0, 1.0), 2.0, 1u32);
+        assert_eq!(tree.world_size(), 0);
+    }
+    #[test]
+    fn fill_with() {
+        let mut tree = FastOctTree::new();
+        tree.fill_aabb(Point3::new(0, 0, 0), Point3::new(8, 8, 8), 20u32);
+        tree.fill_with(Point3::new(2, 0, 0), Point3::new(8, 8, 8), pattern);
+        assert_voxels(&tree, 8, |position| {
+            if position.x >= 2 {
+                pattern(position).or(Some(20))
+            } else {
+                Some(20)
+            }
+        });
+        // an empty box does not call the function
+        let mut tree = FastOctTree::new();
+        for (start, end) in [
+            (Point3::new(0, 5, 0), Point3::new(4, 2, 4)),
+            (Point3::new(3, 0, 0), Point3::new(3, 4, 4)),
+            (Point3::new(0, 0, 6), Point3::new(4, 4, 1)),
+        ] {
+            tree.fill_with(start, end, |position| -> Option<u32> {
+                panic!("called for {:?}", position)
+            });
+        }
+        assert_eq!(tree.world_size(), 0);
+    }
+    #[test]
+    fn extract() {
+        let tree = pattern_tree(16);
+        let (start, end) = (Point3::new(3, 5, 2), Point3::new(11, 9, 14));
+        let region = tree.extract(start, end);
+        assert_voxels(&region, 16, |position| {
+            if in_box(position, Point3::new(0, 0, 0), end - start.to_vec()) {
+                pattern(position + start.to_vec())
+            } else {
+                None
+            }
+        });
+        assert_eq!(
+            FastOctTree::<u32>::new().extract(start, end).world_size(),
+            0
+        );
+    }
+    #[test]
+    fn paste() {
+        let mut cube = FastOctTree::new();
+        cube.fill_aabb(Point3::new(0, 0, 0), Point3::new(4, 4, 4), 10u32);
+        let offset = Vector3::new(3, -1, 2);
+        // the part of the cube below zero is dropped
+        let in_cube = |position| in_box(position, Point3::new(3, 0, 2), Point3::new(7, 3, 6));
+        for policy in [
+            PastePolicy::Replace,
+            PastePolicy::KeepExisting,
+            PastePolicy::ReplaceExisting,
+        ] {
+            let mut tree = pattern_tree(8);
+            tree.paste(&cube, offset, policy);
+            assert_voxels(&tree, 8, |position| {
+                if in_cube(position) {
+                    policy.merge(pattern(position).as_ref(), &10)
+                } else {
+                    pattern(position)
+                }
+            });
+        }
+        // the combined map of the loader keeps working the same way
+        let combined = pattern_tree(8).combine(&cube, offset);
+        assert_voxels(&combined, 8, |position| {
+            if in_cube(position) {
+                Some(10)
+            } else {
+                pattern(position)
+            }
+        });
+    }
+    #[test]
+    fn booleans() {
+        let sphere = {
+            let mut tree = FastOctTree::new();
+            tree.fill_sphere(Point3::new(6.0, 5.0, 6.0), 4.5, 100u32);
+            tree
+        };
+        let in_sphere = |position: TreePosition| sphere.get(position).is_some();
+        let union = pattern_tree(8).union(&sphere);
+        assert_voxels(&union, 16, |position| {
+            pattern(position)
+                .filter(|_| position.x < 8 && position.y < 8 && position.z < 8)
+                .or(sphere.get(position))
+        });
+        let intersection = pattern_tree(8).intersection(&sphere);
+        assert_voxels(&intersection, 16, |position| {
+            pattern(position).filter(|_| {
+                position.x < 8 && position.y < 8 && position.z < 8 && in_sphere(position)
+            })
+        });
+        let difference = pattern_tree(8).difference(&sphere);
+        assert_voxels(&difference, 16, |position| {
+            pattern(position).filter(|_| {
+                position.x < 8 && position.y < 8 && position.z < 8 && !in_sphere(position)
+            })
+        });
+        // intersecting with a smaller tree removes everything outside of it
+        let mut small = FastOctTree::new();
+        small.fill_aabb(Point3::new(0, 0, 0), Point3::new(2, 2, 2), 1u32);
+        let intersection = pattern_tree(8).intersection(&small);
+        assert_voxels(&intersection, 8, |position| {
+            pattern(position)
+                .filter(|_| in_box(position, Point3::new(0, 0, 0), Point3::new(2, 2, 2)))
+        });
+        let intersection = pattern_tree(8).intersection(&FastOctTree::new());
+        assert_voxels(&intersection, 8, |_| None);
+    }
+}
@@ -4,6 +4,7 @@ use super::{
 };
 use crate::prelude::RgbColor;
 use cgmath::{Point3, Vector3};
+use log::info;
 use serde::Deserialize;
 use std::{
     collections::{HashMap, HashSet},
@@ -61,6 +62,12 @@ impl FastOctTree<Voxel> {
                 }
             }
         }
+        // combining tiles leaves a lot of garbage and maps repeat the same tiles
+        let stats = tree.compact();
+        info!(
+            "compacted map from {} to {} bytes",
+            stats.before.arena_stats.data_len_bytes, stats.after.arena_stats.data_len_bytes
+        );
         Ok(tree)
     }
     pub(crate) fn load_vox<P: AsRef<Path>>(load_path: P) -> Result<Self, IoError> {
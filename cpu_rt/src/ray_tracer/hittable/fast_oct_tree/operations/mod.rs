@@ -1,4 +1,8 @@
 mod combine;
+mod compact;
+mod edit;
 mod load;
 mod set;
-use super::{FastOctTree, Leafable, Node, NodeData, TreePosition};
+use super::{Arena, FastOctTree, IndexType, Leafable, Node, NodeData, TreePosition};
+
+pub use edit::PastePolicy;
@@ -1,13 +1,15 @@
 use super::{FastOctTree, Leafable, Node, NodeData, TreePosition};
 impl<T: Leafable> FastOctTree<T> {
-    /// sets the value of the item at leaf. Automatically resizes as needed
-    pub fn set(&mut self, value: T, position: TreePosition) {
+    /// adds levels above the root until the position is inside of the tree
+    pub(crate) fn grow_to(&mut self, position: TreePosition) {
         if let Some(root) = self.arena.get_root() {
             let world_size = root.get_world_size();
-            if position.x < world_size && position.y < world_size && position.z < world_size {
-                let new_root = root.set_child(value, position, &mut self.arena);
-                self.arena.update_root(new_root);
-            } else {
+            if position.x >= world_size || position.y >= world_size || position.z >= world_size {
+                if root.is_empty() {
+                    // an empty root stays a single node so there are no children to merge
+                    self.arena.update_root(Node::empty_size(root.size + 1));
+                    return self.grow_to(position);
+                }
                 let old_root_size = root.size;
                 let old_root = self.arena.insert(root);
 
@@ -27,13 +29,20 @@ impl<T: Leafable> FastOctTree<T> {
                     size: old_root_size + 1,
                 };
                 self.arena.update_root(new_root);
-                self.set(value, position)
+                self.grow_to(position)
             }
         } else {
             self.arena.insert(Node::empty());
-            self.set(value, position)
+            self.grow_to(position)
         }
     }
+    /// sets the value of the item at leaf. Automatically resizes as needed
+    pub fn set(&mut self, value: T, position: TreePosition) {
+        self.grow_to(position);
+        let root = self.arena.get_root().expect("tree has grown");
+        let new_root = root.set_child(value, position, &mut self.arena);
+        self.arena.update_root(new_root);
+    }
 }
 #[cfg(test)]
 mod test {
@@ -4,10 +4,7 @@ use super::{
     voxel::{VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelMaterial},
     FastOctTree, Node, NodeData,
 };
-use crate::{
-    prelude::{Ray, RayScalar},
-    rand_scalar,
-};
+use crate::prelude::{Ray, RayScalar, Sampler};
 use cgmath::{prelude::*, Point3, Vector3};
 use log::{error, warn};
 use std::ops::Neg;
@@ -88,6 +85,7 @@ impl FastOctTree<Voxel> {
         block_coordinates: Point3<i32>,
         ray: Ray,
         initial_normal: Vector3<RayScalar>,
+        sampler: &mut Sampler,
     ) -> Option<HitInfo<Voxel>> {
         #[derive(Clone, Copy, Debug)]
         struct VolumeDistanceLeftInfo {
@@ -120,6 +118,7 @@ impl FastOctTree<Voxel> {
             mut rt_state: RayTraceState,
             direction: Vector3<RayScalar>,
             initial_normal: Vector3<RayScalar>,
+            sampler: &mut Sampler,
         ) -> HitOutput {
             if let Some(dist_info) = rt_state.volume_distance_left {
                 // calculating ratio of previous materials density to starting density
@@ -156,7 +155,7 @@ impl FastOctTree<Voxel> {
                         hit_probability,
                         solid_material,
                     } => {
-                        let random_number = rand_scalar(0., 1.) as f32;
+                        let random_number = sampler.next_scalar() as f32;
                         if random_number < hit_probability {
                             return HitOutput::StopIterationSolid {
                                 hit_material: solid_material.to_material(),
@@ -165,7 +164,7 @@ impl FastOctTree<Voxel> {
                         }
                     }
                 }
-                let distance_left = rand_scalar(0., 1.).ln() / (volume_material.density.neg());
+                let distance_left = sampler.next_scalar().ln() / (volume_material.density.neg());
 
                 rt_state.volume_distance_left = Some(VolumeDistanceLeftInfo {
                     distance_left,
@@ -204,6 +203,7 @@ impl FastOctTree<Voxel> {
             rt_state: &RayTraceState,
             ray: &Ray,
             normal: Vector3<RayScalar>,
+            sampler: &mut Sampler,
         ) -> HitOutput {
             if let Some(voxel) = node_leaf {
                 match voxel {
@@ -212,7 +212,7 @@ impl FastOctTree<Voxel> {
                         normal,
                     },
                     Voxel::Volume(volume) => {
-                        handle_volume(volume, *rt_state, ray.direction, normal)
+                        handle_volume(volume, *rt_state, ray.direction, normal, sampler)
                     }
                 }
             } else {
@@ -224,6 +224,7 @@ impl FastOctTree<Voxel> {
             ray: &Ray,
             rt_state: &mut RayTraceState,
             sign_vec: Vector3<RayScalar>,
+            sampler: &mut Sampler,
         ) -> HitOutput {
             let step_size = tree.get_step_size(rt_state.block_coordinates);
 
@@ -289,7 +290,7 @@ impl FastOctTree<Voxel> {
                             NodeData::Empty => None,
                             NodeData::Parent { .. } => panic!("should not be parent node"),
                         };
-                        handle_hit(node_leaf, rt_state, ray, Vector3::new(-1., 0., 0.))
+                        handle_hit(node_leaf, rt_state, ray, Vector3::new(-1., 0., 0.), sampler)
                     } else {
                         HitOutput::OutOfRange
                     }
@@ -324,7 +325,7 @@ impl FastOctTree<Voxel> {
                         NodeData::Parent { .. } => panic!("should not be parent node"),
                     };
 
-                    handle_hit(node_leaf, rt_state, ray, Vector3::new(1., 0., 0.))
+                    handle_hit(node_leaf, rt_state, ray, Vector3::new(1., 0., 0.), sampler)
                 } else {
                     HitOutput::OutOfRange
                 }
@@ -360,7 +361,7 @@ impl FastOctTree<Voxel> {
                             NodeData::Parent { .. } => panic!("should not be parent node"),
                         };
 
-                        handle_hit(node_leaf, rt_state, ray, Vector3::new(0., -1., 0.))
+                        handle_hit(node_leaf, rt_state, ray, Vector3::new(0., -1., 0.), sampler)
                     } else {
                         HitOutput::OutOfRange
                     }
@@ -391,7 +392,7 @@ impl FastOctTree<Voxel> {
                         NodeData::Parent { .. } => panic!("should not be parent node"),
                     };
 
-                    handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 1., 0.))
+                    handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 1., 0.), sampler)
                 } else {
                     HitOutput::OutOfRange
                 }
@@ -426,7 +427,7 @@ impl FastOctTree<Voxel> {
                             NodeData::Empty => None,
                             NodeData::Parent { .. } => panic!("should not be parent node"),
                         };
-                        handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 0., -1.))
+                        handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 0., -1.), sampler)
                     } else {
                         HitOutput::OutOfRange
                     }
@@ -458,7 +459,7 @@ impl FastOctTree<Voxel> {
                         NodeData::Parent { .. } => panic!("should not be parent node"),
                     };
 
-                    handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 0., 1.))
+                    handle_hit(node_leaf, rt_state, ray, Vector3::new(0., 0., 1.), sampler)
                 } else {
                     HitOutput::OutOfRange
                 }
@@ -481,7 +482,7 @@ impl FastOctTree<Voxel> {
         if let Some(leaf) = leaf {
             match leaf {
                 Voxel::Volume(volume) => {
-                    match handle_volume(volume, rt_state, ray.direction, initial_normal) {
+                    match handle_volume(volume, rt_state, ray.direction, initial_normal, sampler) {
                         HitOutput::ContinueIteration(new_state) => rt_state = new_state,
                         HitOutput::OutOfRange => return None,
                         HitOutput::StopIterationVolume {
@@ -528,7 +529,7 @@ impl FastOctTree<Voxel> {
         );
 
         for _ in 0..MAX_NUMBER_RAY_ITERATIONS {
-            match inner_loop(self, &ray, &mut rt_state, sign_vec) {
+            match inner_loop(self, &ray, &mut rt_state, sign_vec, sampler) {
                 HitOutput::OutOfRange => return None,
                 HitOutput::ContinueIteration(new_rt_state) => rt_state = new_rt_state,
                 HitOutput::StopIterationVolume {
@@ -558,7 +559,7 @@ impl FastOctTree<Voxel> {
         );
         None
     }
-    pub fn trace_ray(&self, ray: Ray) -> Option<HitInfo<Voxel>> {
+    pub fn trace_ray(&self, ray: Ray, sampler: &mut Sampler) -> Option<HitInfo<Voxel>> {
         // uses slab algorithm to trace voxels.
         const BLOCK_OFFSETS: [Vector3<i32>; 6] = [
             Vector3::new(0i32, 0, 0),
@@ -596,6 +597,7 @@ impl FastOctTree<Voxel> {
                     time: ray.time,
                 },
                 Vector3::unit_x(),
+                sampler,
             )
         } else {
             let values = [
@@ -663,6 +665,7 @@ impl FastOctTree<Voxel> {
                                 time: 0.,
                             },
                             normal,
+                            sampler,
                         ),
                     }
                 } else {
@@ -674,6 +677,7 @@ impl FastOctTree<Voxel> {
                             time: 0.,
                         },
                         normal,
+                        sampler,
                     )
                 }
             } else {
@@ -1,13 +1,43 @@
 use super::arena::ArenaStats;
-use super::{FastOctTree, Leafable};
+use super::{FastOctTree, Leafable, NodeData};
+use std::collections::HashSet;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct FastOctTreeStats {
     pub arena_stats: ArenaStats,
 }
+/// size of the tree before and after `FastOctTree::compact`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionStats {
+    pub before: FastOctTreeStats,
+    pub after: FastOctTreeStats,
+}
 impl<T: Leafable> FastOctTree<T> {
     pub fn stats(&self) -> FastOctTreeStats {
         FastOctTreeStats {
             arena_stats: self.arena.stats(),
         }
     }
+    /// Nodes that can be reached from the root, the rest of the arena is garbage left by edits.
+    /// Walks the whole tree, unlike `stats`
+    pub fn reachable_nodes(&self) -> usize {
+        let root = match self.arena.get_root_ref() {
+            Some(root) => root,
+            None => return 0,
+        };
+        // shared nodes are only counted once
+        let mut visited = HashSet::new();
+        let mut stack = vec![root];
+        let mut count = 1;
+        while let Some(node) = stack.pop() {
+            if let NodeData::Parent { children } = &node.data {
+                for child in children {
+                    if visited.insert(*child) {
+                        count += 1;
+                        stack.push(self.arena.get(*child).expect("should have child"));
+                    }
+                }
+            }
+        }
+        count
+    }
 }
@@ -1,10 +1,7 @@
 use super::super::{HitRay, HitRecord, Material, ScatterRecord};
 use crate::{
-    prelude::{Ray, RayScalar, RgbColor},
-    ray_tracer::{
-        pdf::{IsotropicPdf, LambertianPDF},
-        rand_unit_vec,
-    },
+    prelude::{Ray, RayScalar, RgbColor, Sampler},
+    ray_tracer::{pdf::LambertianPDF, phase_function::PhaseFunction},
     reflect,
 };
 use cgmath::{num_traits::FloatConst, prelude::*};
@@ -40,6 +37,7 @@ pub struct VolumeVoxel {
     pub density: RayScalar,
     pub color: RgbColor,
     pub edge_effect: VolumeEdgeEffect,
+    pub phase_function: PhaseFunction,
 }
 impl PartialEq for VolumeVoxel {
     fn eq(&self, rhs: &VolumeVoxel) -> bool {
@@ -48,18 +46,30 @@ impl PartialEq for VolumeVoxel {
         (self.density - rhs.density).abs() < VOLUME_ERROR_MARGIN
             && (self.color.distance(rhs.color)) < COLOR_ERROR_MARGIN
             && self.edge_effect == rhs.edge_effect
+            && self.phase_function == rhs.phase_function
     }
 }
 impl VolumeVoxel {
     pub(crate) fn volume_material(&self) -> VoxelMaterial {
-        VoxelMaterial::Volume { color: self.color }
+        VoxelMaterial::Volume {
+            color: self.color,
+            phase_function: self.phase_function,
+        }
     }
 }
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum VoxelMaterial {
-    Lambertian { color: RgbColor },
-    Reflect { albedo: RgbColor, fuzz: f32 },
-    Volume { color: RgbColor },
+    Lambertian {
+        color: RgbColor,
+    },
+    Reflect {
+        albedo: RgbColor,
+        fuzz: f32,
+    },
+    Volume {
+        color: RgbColor,
+        phase_function: PhaseFunction,
+    },
 }
 impl VoxelMaterial {
     fn scattering_pdf_fn(
@@ -74,13 +84,25 @@ impl VoxelMaterial {
             Some(cosine / RayScalar::PI())
         }
     }
+    fn volume_scattering_pdf_fn(
+        _ray_in: Ray,
+        _record_in: &HitRecord,
+        _scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        panic!("volume directions are sampled from the phase function")
+    }
 }
 impl Material for VoxelMaterial {
     fn name(&self) -> &'static str {
         "Voxel Material"
     }
 
-    fn scatter(&self, ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         match self {
             Self::Lambertian { color } => Some(ScatterRecord {
                 specular_ray: None,
@@ -88,18 +110,26 @@ impl Material for VoxelMaterial {
                 pdf: Some(Rc::new(LambertianPDF::new(record_in.normal()))),
                 scattering_pdf: Self::scattering_pdf_fn,
             }),
-            Self::Volume { color, .. } => Some(ScatterRecord {
-                specular_ray: None,
+            // the direction follows the phase function exactly so only the color weights it
+            Self::Volume {
+                color,
+                phase_function,
+            } => Some(ScatterRecord {
+                specular_ray: Some(Ray {
+                    origin: record_in.position(),
+                    direction: phase_function.sample(ray_in.direction, sampler),
+                    time: ray_in.time,
+                }),
                 attenuation: *color,
-                pdf: Some(Rc::new(IsotropicPdf {})),
-                scattering_pdf: Self::scattering_pdf_fn,
+                pdf: None,
+                scattering_pdf: Self::volume_scattering_pdf_fn,
             }),
             Self::Reflect { albedo, fuzz } => {
                 let reflected = reflect(ray_in.direction.normalize(), record_in.normal());
                 if reflected.dot(record_in.normal()) > 0.0 {
                     let out_ray = Ray {
                         origin: record_in.position(),
-                        direction: reflected + *fuzz as f64 * rand_unit_vec(),
+                        direction: reflected + *fuzz as f64 * sampler.unit_vec(),
                         time: ray_in.time,
                     };
 
@@ -118,10 +148,102 @@ impl Material for VoxelMaterial {
 
     fn scattering_pdf(
         &self,
-        _ray_in: Ray,
-        _record_in: &HitRecord,
-        _scattered_ray: Ray,
+        ray_in: Ray,
+        record_in: &HitRecord,
+        scattered_ray: Ray,
     ) -> Option<RayScalar> {
-        None
+        match self {
+            Self::Lambertian { .. } => Self::scattering_pdf_fn(ray_in, record_in, scattered_ray),
+            Self::Volume { phase_function, .. } => {
+                Some(phase_function.value(ray_in.direction, scattered_ray.direction))
+            }
+            Self::Reflect { .. } => None,
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{hittable::MaterialEffect, pdf::test::test_world};
+    use cgmath::{Point2, Point3, Vector3};
+    fn hit(material: &VoxelMaterial, ray: &Ray, sampler: &mut Sampler) -> HitRecord {
+        HitRecord::new(
+            ray,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            Point2::new(0.0, 0.0),
+            material,
+            sampler,
+        )
+    }
+    /// `Material::scattering_pdf` matches the function in the scatter record
+    #[test]
+    fn scattering_pdf() {
+        let world = test_world(false, None);
+        let mut sampler = Sampler::new(0);
+        let ray = Ray {
+            origin: Point3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.2, -1.0, 0.1),
+            time: 0.0,
+        };
+        let material = VoxelMaterial::Lambertian {
+            color: RgbColor::WHITE,
+        };
+        let record = hit(&material, &ray, &mut sampler);
+        let scatter_record = match record.material_effect.clone() {
+            MaterialEffect::Scatter(scatter_record) => scatter_record,
+            _ => panic!("{:?} should scatter", material),
+        };
+        let pdf = scatter_record.pdf.expect("material should have a pdf");
+        for _ in 0..100 {
+            let (direction, _value) = pdf
+                .generate(ray, record.position, &world, &mut sampler)
+                .expect("failed to generate direction");
+            let scattered = Ray {
+                origin: record.position,
+                direction,
+                time: 0.0,
+            };
+            assert_eq!(
+                material.scattering_pdf(ray, &record, scattered),
+                (scatter_record.scattering_pdf)(ray, &record, scattered)
+            );
+        }
+    }
+    /// volumes scatter along the phase function and keep all of the light if they are white
+    #[test]
+    fn volume_scatter() {
+        let mut sampler = Sampler::new(0);
+        let ray = Ray {
+            origin: Point3::new(0.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            time: 0.0,
+        };
+        let material = VoxelMaterial::Volume {
+            color: RgbColor::WHITE,
+            phase_function: PhaseFunction::HenyeyGreenstein { g: 0.9 },
+        };
+        let mut mean_cos = 0.0;
+        for _ in 0..1000 {
+            let record = hit(&material, &ray, &mut sampler);
+            let scatter_record = match record.material_effect.clone() {
+                MaterialEffect::Scatter(scatter_record) => scatter_record,
+                _ => panic!("volume should scatter"),
+            };
+            assert_eq!(scatter_record.attenuation, RgbColor::WHITE);
+            let scattered = scatter_record
+                .specular_ray
+                .expect("volume should sample a direction");
+            mean_cos += scattered.direction.normalize().dot(ray.direction) / 1000.0;
+            let expected = PhaseFunction::HenyeyGreenstein { g: 0.9 }
+                .value(ray.direction, scattered.direction);
+            assert_eq!(
+                material.scattering_pdf(ray, &record, scattered),
+                Some(expected)
+            );
+        }
+        // mostly scattered forward
+        assert!(mean_cos > 0.8, "mean cosine {}", mean_cos);
     }
 }
@@ -0,0 +1,745 @@
+use super::{
+    volume_prob, volume_ray, Aabb, HitRecord, Hittable, Material, RayAreaInfo, SolidVoxel,
+    Transform, Voxel, VoxelGrid,
+};
+use crate::{prelude::*, ray_tracer::ray_tracer_info::EntityField};
+use cgmath::{prelude::*, Matrix4, Point2, Point3, Vector3};
+use dyn_clone::clone_box;
+use std::{collections::HashMap, ops::Deref, path::Path, sync::Arc};
+
+/// lowest and highest height over blocks of cells
+#[derive(Clone, Debug)]
+struct MipLevel {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f32, f32)>,
+}
+impl MipLevel {
+    fn range(&self, x: usize, z: usize) -> (f32, f32) {
+        self.ranges[z * self.width + x]
+    }
+    /// merges blocks of 2 by 2 entries
+    fn next(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let depth = self.depth.div_ceil(2);
+        let mut ranges = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let range = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .map(|(dx, dz)| (2 * x + dx, 2 * z + dz))
+                    .filter(|(x, z)| *x < self.width && *z < self.depth)
+                    .map(|(x, z)| self.range(x, z))
+                    .fold((f32::MAX, f32::MIN), |acc, range| {
+                        (acc.0.min(range.0), acc.1.max(range.1))
+                    });
+                ranges.push(range);
+            }
+        }
+        Self {
+            width,
+            depth,
+            ranges,
+        }
+    }
+}
+/// Terrain over a grid of height samples. Sample `(x, z)` sits at
+/// `origin + (x * size.x / (width - 1), height * size.y, z * size.z / (depth - 1))`. Each cell is
+/// split into two triangles that are shaded with normals interpolated between the samples. Rays
+/// walk down a min-max mipmap of the cells so only cells near the ray get tested. The uv
+/// coordinates go from 0 to 1 across the terrain
+pub struct Heightfield {
+    pub origin: Point3<RayScalar>,
+    pub size: Vector3<RayScalar>,
+    width: usize,
+    depth: usize,
+    heights: Arc<Vec<f32>>,
+    /// level 0 has the range of every cell, the last level has one entry for the whole terrain
+    mip_levels: Arc<Vec<MipLevel>>,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Heightfield {
+    fn clone(&self) -> Self {
+        Self {
+            origin: self.origin,
+            size: self.size,
+            width: self.width,
+            depth: self.depth,
+            heights: self.heights.clone(),
+            mip_levels: self.mip_levels.clone(),
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+/// ray hitting one of the triangles of a cell
+struct TriangleHit {
+    t: RayScalar,
+    /// weights of the three corners of the triangle
+    weights: [RayScalar; 3],
+    corners: [(usize, usize); 3],
+    normal: Vector3<RayScalar>,
+}
+impl Heightfield {
+    /// `heights` is indexed by x first then z
+    pub fn new(
+        origin: Point3<RayScalar>,
+        size: Vector3<RayScalar>,
+        width: usize,
+        depth: usize,
+        heights: Vec<f32>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        assert!(
+            width >= 2 && depth >= 2,
+            "heightfield needs at least two samples along each axis"
+        );
+        assert_eq!(
+            heights.len(),
+            width * depth,
+            "number of heights does not match the dimensions of the heightfield"
+        );
+        let sample = |x: usize, z: usize| heights[z * width + x];
+        let mut cells = Vec::with_capacity((width - 1) * (depth - 1));
+        for z in 0..depth - 1 {
+            for x in 0..width - 1 {
+                let corners = [
+                    sample(x, z),
+                    sample(x + 1, z),
+                    sample(x, z + 1),
+                    sample(x + 1, z + 1),
+                ];
+                cells.push(corners.iter().fold((f32::MAX, f32::MIN), |acc, h| {
+                    (acc.0.min(*h), acc.1.max(*h))
+                }));
+            }
+        }
+        let mut mip_levels = vec![MipLevel {
+            width: width - 1,
+            depth: depth - 1,
+            ranges: cells,
+        }];
+        loop {
+            let last = mip_levels.last().expect("there is always a level");
+            if last.width == 1 && last.depth == 1 {
+                break;
+            }
+            let next = last.next();
+            mip_levels.push(next);
+        }
+        Self {
+            origin,
+            size,
+            width,
+            depth,
+            heights: Arc::new(heights),
+            mip_levels: Arc::new(mip_levels),
+            material,
+        }
+    }
+    /// Samples `height` at every point of the grid. It gets the world x and z of the sample and
+    /// returns the height above the origin, so the heights are not limited to `size.y`
+    pub fn from_fn<F: Fn(RayScalar, RayScalar) -> RayScalar>(
+        origin: Point3<RayScalar>,
+        size: Vector3<RayScalar>,
+        width: usize,
+        depth: usize,
+        height: F,
+        material: Box<dyn Material>,
+    ) -> Self {
+        let mut heights = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let world_x = origin.x + size.x * x as RayScalar / (width - 1) as RayScalar;
+                let world_z = origin.z + size.z * z as RayScalar / (depth - 1) as RayScalar;
+                heights.push((height(world_x, world_z) / size.y) as f32);
+            }
+        }
+        Self::new(origin, size, width, depth, heights, material)
+    }
+    /// Loads a grayscale image where white is `size.y` above the origin. 16 bit images keep their
+    /// full precision. Pixel rows go along z, the image needs at least two pixels along each axis
+    pub fn from_image<P: AsRef<Path>>(
+        path: P,
+        origin: Point3<RayScalar>,
+        size: Vector3<RayScalar>,
+        material: Box<dyn Material>,
+    ) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_luma16();
+        if image.width() < 2 || image.height() < 2 {
+            return Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(
+                    format!(
+                        "heightfield needs at least 2x2 pixels, image is {}x{}",
+                        image.width(),
+                        image.height()
+                    ),
+                )),
+            ));
+        }
+        let heights = image
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u16::MAX as f32)
+            .collect();
+        Ok(Self::new(
+            origin,
+            size,
+            image.width() as usize,
+            image.height() as usize,
+            heights,
+            material,
+        ))
+    }
+    fn cell_size(&self) -> (RayScalar, RayScalar) {
+        (
+            self.size.x / (self.width - 1) as RayScalar,
+            self.size.z / (self.depth - 1) as RayScalar,
+        )
+    }
+    fn sample(&self, x: usize, z: usize) -> RayScalar {
+        self.heights[z * self.width + x] as RayScalar
+    }
+    fn point(&self, x: usize, z: usize) -> Point3<RayScalar> {
+        let (cell_x, cell_z) = self.cell_size();
+        self.origin
+            + Vector3::new(
+                x as RayScalar * cell_x,
+                self.sample(x, z) * self.size.y,
+                z as RayScalar * cell_z,
+            )
+    }
+    /// smooth normal at a sample from the slope to its neighbors
+    fn sample_normal(&self, x: usize, z: usize) -> Vector3<RayScalar> {
+        let (cell_x, cell_z) = self.cell_size();
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let slope_x = (self.sample(x1, z) - self.sample(x0, z)) * self.size.y
+            / ((x1 - x0) as RayScalar * cell_x);
+        let slope_z = (self.sample(x, z1) - self.sample(x, z0)) * self.size.y
+            / ((z1 - z0) as RayScalar * cell_z);
+        Vector3::new(-slope_x, 1.0, -slope_z).normalize()
+    }
+    /// world height of the surface above the point, `None` outside of the terrain
+    pub fn height_at(&self, x: RayScalar, z: RayScalar) -> Option<RayScalar> {
+        let (cell_x, cell_z) = self.cell_size();
+        let grid_x = (x - self.origin.x) / cell_x;
+        let grid_z = (z - self.origin.z) / cell_z;
+        let (max_x, max_z) = ((self.width - 1) as RayScalar, (self.depth - 1) as RayScalar);
+        if !(0.0..=max_x).contains(&grid_x) || !(0.0..=max_z).contains(&grid_z) {
+            return None;
+        }
+        Some(self.height_at_grid(grid_x, grid_z))
+    }
+    /// height at a point given in samples from the origin, the point has to be on the terrain
+    fn height_at_grid(&self, grid_x: RayScalar, grid_z: RayScalar) -> RayScalar {
+        let cell = (
+            (grid_x.floor() as usize).min(self.width - 2),
+            (grid_z.floor() as usize).min(self.depth - 2),
+        );
+        let (fx, fz) = (grid_x - cell.0 as RayScalar, grid_z - cell.1 as RayScalar);
+        let h = |dx, dz| self.sample(cell.0 + dx, cell.1 + dz);
+        // the diagonal of the cell goes from the first to the last corner
+        let height = if fx >= fz {
+            h(0, 0) + fx * (h(1, 0) - h(0, 0)) + fz * (h(1, 1) - h(1, 0))
+        } else {
+            h(0, 0) + fz * (h(0, 1) - h(0, 0)) + fx * (h(1, 1) - h(0, 1))
+        };
+        self.origin.y + height * self.size.y
+    }
+    /// box around the cells of an entry of the mipmap, padded so flat cells still have a volume
+    fn node_box(&self, level: usize, x: usize, z: usize) -> Aabb {
+        let padding = 1e-6 * self.size.magnitude();
+        let padding = Vector3::new(padding, padding, padding);
+        let (cell_x, cell_z) = self.cell_size();
+        let cells = &self.mip_levels[0];
+        let (min, max) = self.mip_levels[level].range(x, z);
+        let start = (x << level, z << level);
+        let end = (
+            ((x + 1) << level).min(cells.width),
+            ((z + 1) << level).min(cells.depth),
+        );
+        Aabb {
+            minimum: self.origin
+                + Vector3::new(
+                    start.0 as RayScalar * cell_x,
+                    min as RayScalar * self.size.y,
+                    start.1 as RayScalar * cell_z,
+                )
+                - padding,
+            maximum: self.origin
+                + Vector3::new(
+                    end.0 as RayScalar * cell_x,
+                    max as RayScalar * self.size.y,
+                    end.1 as RayScalar * cell_z,
+                )
+                + padding,
+        }
+    }
+    fn hit_triangle(
+        &self,
+        ray: &Ray,
+        corners: [(usize, usize); 3],
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<TriangleHit> {
+        let [a, b, c] = corners.map(|(x, z)| self.point(x, z));
+        let (edge_1, edge_2) = (b - a, c - a);
+        let p = ray.direction.cross(edge_2);
+        let determinant = edge_1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let offset = ray.origin - a;
+        let u = offset.dot(p) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(edge_1);
+        let v = ray.direction.dot(q) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge_2.dot(q) / determinant;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let normal = edge_1.cross(edge_2).normalize();
+        Some(TriangleHit {
+            t,
+            weights: [1.0 - u - v, u, v],
+            corners,
+            normal: if normal.y < 0.0 { -normal } else { normal },
+        })
+    }
+    fn hit_cell(
+        &self,
+        ray: &Ray,
+        (x, z): (usize, usize),
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<TriangleHit> {
+        let first = self.hit_triangle(ray, [(x, z), (x + 1, z), (x + 1, z + 1)], t_min, t_max);
+        let t_max = first.as_ref().map(|hit| hit.t).unwrap_or(t_max);
+        self.hit_triangle(ray, [(x, z), (x + 1, z + 1), (x, z + 1)], t_min, t_max)
+            .or(first)
+    }
+    /// walks the children of the entry that the ray passes through from front to back
+    fn hit_node(
+        &self,
+        ray: &Ray,
+        level: usize,
+        (x, z): (usize, usize),
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<TriangleHit> {
+        if level == 0 {
+            return self.hit_cell(ray, (x, z), t_min, t_max);
+        }
+        let children_level = &self.mip_levels[level - 1];
+        let mut children = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(dx, dz)| (2 * x + dx, 2 * z + dz))
+            .filter(|(x, z)| *x < children_level.width && *z < children_level.depth)
+            .filter_map(|child| {
+                self.node_box(level - 1, child.0, child.1)
+                    .ray_interval(*ray, t_min, t_max)
+                    .map(|(enter, _exit)| (enter, child))
+            })
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("interval is not nan"));
+        let mut closest: Option<TriangleHit> = None;
+        for (enter, child) in children {
+            let t_max = closest.as_ref().map(|hit| hit.t).unwrap_or(t_max);
+            if enter > t_max {
+                break;
+            }
+            if let Some(hit) = self.hit_node(ray, level - 1, child, t_min, t_max) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+    fn bounds(&self) -> Aabb {
+        self.node_box(self.mip_levels.len() - 1, 0, 0)
+    }
+    /// Fills voxels of `voxel_size` below the surface. Each column only goes down to the lowest
+    /// of its neighbors, which hides the inside of the terrain while keeping the voxel grid
+    /// small. The voxel grid starts at the lowest point of the terrain, so the returned transform
+    /// is needed to put it where the heightfield is
+    pub fn to_voxel_grid(
+        &self,
+        voxel_size: RayScalar,
+        voxel: SolidVoxel,
+    ) -> (VoxelGrid, Transform) {
+        let bottom = self.bounds().minimum.y;
+        let columns_x = (self.size.x / voxel_size).ceil() as usize;
+        let columns_z = (self.size.z / voxel_size).ceil() as usize;
+        let (cell_x, cell_z) = self.cell_size();
+        let mut column_heights = Vec::with_capacity(columns_x * columns_z);
+        for z in 0..columns_z {
+            for x in 0..columns_x {
+                // the last columns can reach past the terrain, they use its edge. Clamping in
+                // samples keeps rounding from moving the edge off of the terrain
+                let height = self.height_at_grid(
+                    ((x as RayScalar + 0.5) * voxel_size / cell_x)
+                        .min((self.width - 1) as RayScalar),
+                    ((z as RayScalar + 0.5) * voxel_size / cell_z)
+                        .min((self.depth - 1) as RayScalar),
+                );
+                column_heights.push(((height - bottom) / voxel_size).round().max(1.0) as u32);
+            }
+        }
+        let column = |x: isize, z: isize| {
+            if x < 0 || z < 0 || x >= columns_x as isize || z >= columns_z as isize {
+                0
+            } else {
+                column_heights[z as usize * columns_x + x as usize]
+            }
+        };
+        let mut voxel_grid = VoxelGrid::new();
+        for z in 0..columns_z as isize {
+            for x in 0..columns_x as isize {
+                let top = column(x, z);
+                let lowest_neighbor = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .map(|(dx, dz)| column(x + dx, z + dz))
+                    .min()
+                    .expect("there are four neighbors");
+                for y in lowest_neighbor.min(top).saturating_sub(1)..top {
+                    voxel_grid.set(Voxel::Solid(voxel), Point3::new(x as u32, y, z as u32));
+                }
+            }
+        }
+        let corner = Vector3::new(self.origin.x, bottom, self.origin.z);
+        let transform = Transform {
+            world_transform: (Matrix4::from_translation(corner) * Matrix4::from_scale(voxel_size))
+                .invert()
+                .expect("voxel size is not zero"),
+        };
+        (voxel_grid, transform)
+    }
+}
+impl Hittable for Heightfield {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let top = self.mip_levels.len() - 1;
+        self.bounds().ray_interval(*ray, t_min, t_max)?;
+        let hit = self.hit_node(ray, top, (0, 0), t_min, t_max)?;
+        let position = ray.at(hit.t);
+        let smooth_normal = hit
+            .corners
+            .iter()
+            .zip(hit.weights)
+            .map(|((x, z), weight)| weight * self.sample_normal(*x, *z))
+            .fold(Vector3::zero(), |acc, n| acc + n)
+            .normalize();
+        // the smooth normal can face the other way than the triangle at grazing angles
+        let normal =
+            if (smooth_normal.dot(ray.direction) < 0.0) == (hit.normal.dot(ray.direction) < 0.0) {
+                smooth_normal
+            } else {
+                hit.normal
+            };
+        let uv = Point2::new(
+            (position.x - self.origin.x) / self.size.x,
+            (position.z - self.origin.z) / self.size.z,
+        );
+        Some(HitRecord::new(
+            ray,
+            position,
+            normal,
+            hit.t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        Some(self.bounds())
+    }
+    /// lights are sampled through the volume of the bounds like sdf shapes
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        volume_prob(&ray, &self.bounds())
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        volume_ray(origin, time, &self.bounds(), sampler)
+    }
+    fn name(&self) -> String {
+        "Heightfield".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("origin".to_string(), EntityField::Point3(self.origin));
+        map.insert("height".to_string(), EntityField::Float(self.size.y));
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "origin" => match value {
+                EntityField::Point3(p) => self.origin = p,
+                _ => panic!("invalid value type"),
+            },
+            "height" => match value {
+                EntityField::Float(v) => self.size.y = v,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        material::Lambertian,
+        pdf::{
+            test::{check_pdf, light_world},
+            LightPdf,
+        },
+        texture::SolidColor,
+    };
+    use image::{ImageBuffer, Luma};
+
+    fn material() -> Box<dyn Material> {
+        Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::WHITE,
+            }),
+        })
+    }
+    fn hills() -> Heightfield {
+        Heightfield::from_fn(
+            Point3::new(-2.0, 0.5, -3.0),
+            Vector3::new(4.0, 2.0, 6.0),
+            23,
+            37,
+            |x, z| 1.0 + (2.0 * x).sin() * (1.5 * z).cos() + 0.3 * (5.0 * x + z).sin(),
+            material(),
+        )
+    }
+    fn down(x: RayScalar, z: RayScalar) -> Ray {
+        Ray {
+            origin: Point3::new(x, 10.0, z),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+            time: 0.0,
+        }
+    }
+    #[test]
+    fn slope() {
+        let field = Heightfield::from_fn(
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(4.0, 1.0, 4.0),
+            9,
+            9,
+            |x, _z| 0.5 * x,
+            material(),
+        );
+        let mut sampler = Sampler::new(0);
+        let hit = field
+            .hit(&down(1.3, 2.7), 0.001, RayScalar::MAX, &mut sampler)
+            .unwrap();
+        assert!((hit.position.y - 0.65).abs() < 1e-6);
+        assert!((hit.normal - Vector3::new(-0.5, 1.0, 0.0).normalize()).magnitude() < 1e-6);
+        assert!((hit.uv - Point2::new(1.3 / 4.0, 2.7 / 4.0)).magnitude() < 1e-6);
+        assert!(hit.front_face);
+        assert!((field.height_at(1.3, 2.7).unwrap() - hit.position.y).abs() < 1e-9);
+        assert!(field
+            .hit(&down(4.5, 2.0), 0.001, RayScalar::MAX, &mut sampler)
+            .is_none());
+        assert!(field.height_at(4.5, 2.0).is_none());
+    }
+    /// walking the mipmap finds the same hit as testing every cell
+    #[test]
+    fn matches_all_cells() {
+        let field = hills();
+        let top = &field.mip_levels[field.mip_levels.len() - 1];
+        assert_eq!((top.width, top.depth), (1, 1));
+        let (min, max) = field.heights.iter().fold((f32::MAX, f32::MIN), |acc, h| {
+            (acc.0.min(*h), acc.1.max(*h))
+        });
+        assert_eq!(top.range(0, 0), (min, max));
+
+        let mut sampler = Sampler::new(0);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let origin = Point3::new(
+                sampler.scalar(-5.0, 5.0),
+                sampler.scalar(0.0, 6.0),
+                sampler.scalar(-5.0, 5.0),
+            );
+            let target = Point3::new(
+                sampler.scalar(-2.0, 2.0),
+                sampler.scalar(0.0, 3.0),
+                sampler.scalar(-3.0, 3.0),
+            );
+            let ray = Ray {
+                origin,
+                direction: target - origin,
+                time: 0.0,
+            };
+            let expected = (0..field.depth - 1)
+                .flat_map(|z| (0..field.width - 1).map(move |x| (x, z)))
+                .filter_map(|cell| field.hit_cell(&ray, cell, 0.001, RayScalar::MAX))
+                .map(|hit| hit.t)
+                .reduce(RayScalar::min);
+            let hit = field.hit(&ray, 0.001, RayScalar::MAX, &mut sampler);
+            match (hit, expected) {
+                (Some(hit), Some(t)) => {
+                    assert!((hit.t - t).abs() < 1e-9);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (hit, expected) => panic!("hit: {:?}, expected: {:?}", hit.map(|h| h.t), expected),
+            }
+        }
+        assert!(hits > 100);
+    }
+    #[test]
+    fn smooth_normals() {
+        let field = hills();
+        let mut sampler = Sampler::new(0);
+        // at a sample the normal is the one of the sample
+        let point = field.point(5, 7);
+        let hit = field
+            .hit(&down(point.x, point.z), 0.001, RayScalar::MAX, &mut sampler)
+            .unwrap();
+        assert!((hit.normal - field.sample_normal(5, 7)).magnitude() < 1e-6);
+        // and it changes smoothly across the edges of the triangles
+        let (cell_x, cell_z) = field.cell_size();
+        let normal = |x, z| {
+            field
+                .hit(&down(x, z), 0.001, RayScalar::MAX, &mut Sampler::new(0))
+                .unwrap()
+                .normal
+        };
+        let z = point.z + 0.3 * cell_z;
+        assert!((normal(point.x - 1e-7, z) - normal(point.x + 1e-7, z)).magnitude() < 1e-5);
+        let (x, z) = (point.x + 0.5 * cell_x, point.z + 0.5 * cell_z);
+        assert!((normal(x + 1e-7, z - 1e-7) - normal(x - 1e-7, z + 1e-7)).magnitude() < 1e-5);
+        assert!((normal(x, z) - hit.normal).magnitude() > 1e-3);
+    }
+    #[test]
+    fn from_image() {
+        let directory = std::env::temp_dir();
+        let image = ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 20000 + y * 5000) as u16]));
+        let path = directory.join("heightfield_test_16.png");
+        image.save(&path).unwrap();
+        let field = Heightfield::from_image(
+            &path,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.0, 3.0, 1.0),
+            material(),
+        )
+        .unwrap();
+        assert_eq!((field.width, field.depth), (3, 2));
+        let expected = 3.0 * 45000.0 / u16::MAX as RayScalar;
+        assert!((field.height_at(2.0, 1.0).unwrap() - expected).abs() < 1e-6);
+
+        let image = ImageBuffer::from_fn(2, 2, |x, _y| Luma([(x * 255) as u8]));
+        let path = directory.join("heightfield_test_8.png");
+        image.save(&path).unwrap();
+        let field = Heightfield::from_image(
+            &path,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            material(),
+        )
+        .unwrap();
+        assert!((field.height_at(1.0, 0.5).unwrap() - 1.0).abs() < 1e-6);
+        assert!(Heightfield::from_image(
+            directory.join("heightfield_missing.png"),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            material(),
+        )
+        .is_err());
+        // a single row has no cells
+        let image = ImageBuffer::from_fn(4, 1, |x, _y| Luma([(x * 50) as u8]));
+        let path = directory.join("heightfield_test_row.png");
+        image.save(&path).unwrap();
+        assert!(Heightfield::from_image(
+            &path,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            material(),
+        )
+        .is_err());
+    }
+    #[test]
+    fn voxel_grid() {
+        // climbs half a voxel every voxel along x
+        let field = Heightfield::from_fn(
+            Point3::new(1.0, 2.0, 3.0),
+            Vector3::new(4.0, 1.0, 2.0),
+            5,
+            3,
+            |x, _z| 0.5 * (x - 1.0),
+            material(),
+        );
+        let voxel = SolidVoxel::Lambertian {
+            albedo: RgbColor::WHITE,
+        };
+        let (grid, transform) = field.to_voxel_grid(0.5, voxel);
+        let solid = |x, y, z| grid.get(Point3::new(x, y, z)).is_some();
+        for (x, height) in [1, 1, 1, 2, 2, 3, 3, 4].iter().enumerate() {
+            let x = x as u32;
+            assert!(solid(x, height - 1, 1));
+            assert!(!solid(x, *height, 1));
+            // the edge of the terrain is filled to the bottom
+            assert!(solid(x, 0, 0));
+        }
+        // the inside of the terrain is hidden by the neighbors of the column and left empty
+        assert!(solid(5, 1, 1));
+        assert!(!solid(5, 0, 1));
+        let corner = Point3::from_homogeneous(
+            transform.world_transform * Point3::new(1.0, 2.0, 3.0).to_homogeneous(),
+        );
+        assert!(corner.distance(Point3::new(0.0, 0.0, 0.0)) < 1e-4);
+    }
+    /// voxels that do not divide the terrain put the last column past its edge
+    #[test]
+    fn voxel_grid_partial_column() {
+        let field = Heightfield::from_fn(
+            Point3::new(12.345, 0.0, 7.1),
+            Vector3::new(0.3, 1.0, 1.3),
+            17,
+            6,
+            |_x, _z| 1.0,
+            material(),
+        );
+        let voxel = SolidVoxel::Lambertian {
+            albedo: RgbColor::WHITE,
+        };
+        let (grid, _transform) = field.to_voxel_grid(0.9, voxel);
+        for z in 0..2 {
+            assert!(grid.get(Point3::new(0, 0, z)).is_some());
+        }
+        assert!(grid.get(Point3::new(1, 0, 0)).is_none());
+    }
+    #[test]
+    fn fields() {
+        let mut field = hills();
+        let top = field.bounding_box(0.0, 0.0).unwrap().maximum.y;
+        field.set_field("height".to_string(), EntityField::Float(4.0));
+        assert_eq!(field.fields()["height"], EntityField::Float(4.0));
+        let doubled = field.bounding_box(0.0, 0.0).unwrap().maximum.y;
+        assert!((doubled - 0.5 - 2.0 * (top - 0.5)).abs() < 1e-4);
+    }
+    #[test]
+    fn light_pdf() {
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(hills())),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+}
@@ -0,0 +1,659 @@
+use super::{Aabb, HitRay, HitRecord, Hittable, Material, MaterialEffect, RayAreaInfo};
+use crate::{
+    prelude::*,
+    ray_tracer::{pdf::ScatterRecord, texture::Perlin},
+};
+use cgmath::{prelude::*, Point2, Point3, Vector3};
+use dyn_clone::{clone_box, DynClone};
+use std::ops::Deref;
+
+/// Density of a heterogeneous medium at every point. The density scales the scattering and
+/// absorption coefficients of the medium
+pub trait DensityField: Send + Sync + DynClone {
+    fn density(&self, position: Point3<RayScalar>) -> RayScalar;
+    /// upper bound of the density everywhere, used as the majorant when tracking through the
+    /// medium so it should be tight
+    fn max_density(&self) -> RayScalar;
+}
+/// Densities stored at the points of a regular grid and interpolated trilinearly between them,
+/// the density is zero outside of the grid
+#[derive(Clone)]
+pub struct GridDensity {
+    origin: Point3<RayScalar>,
+    cell_size: RayScalar,
+    dimensions: Vector3<usize>,
+    values: Vec<f32>,
+    max_density: RayScalar,
+}
+impl GridDensity {
+    /// `dimensions` is the number of points along each axis, `values` is indexed by x first then
+    /// y then z
+    pub fn new(
+        origin: Point3<RayScalar>,
+        cell_size: RayScalar,
+        dimensions: Vector3<usize>,
+        values: Vec<f32>,
+    ) -> Self {
+        assert!(
+            dimensions.x >= 2 && dimensions.y >= 2 && dimensions.z >= 2,
+            "grid needs at least two points along each axis"
+        );
+        assert_eq!(
+            values.len(),
+            dimensions.x * dimensions.y * dimensions.z,
+            "number of values does not match the dimensions of the grid"
+        );
+        let max_density = values.iter().fold(0.0, |acc: f32, v| acc.max(*v)) as RayScalar;
+        Self {
+            origin,
+            cell_size,
+            dimensions,
+            values,
+            max_density,
+        }
+    }
+    /// fills the grid by evaluating `f` at every point of the grid
+    pub fn from_fn<F: Fn(Point3<RayScalar>) -> f32>(
+        origin: Point3<RayScalar>,
+        cell_size: RayScalar,
+        dimensions: Vector3<usize>,
+        f: F,
+    ) -> Self {
+        let mut values = Vec::with_capacity(dimensions.x * dimensions.y * dimensions.z);
+        for z in 0..dimensions.z {
+            for y in 0..dimensions.y {
+                for x in 0..dimensions.x {
+                    values.push(f(origin
+                        + cell_size
+                            * Vector3::new(x as RayScalar, y as RayScalar, z as RayScalar)));
+                }
+            }
+        }
+        Self::new(origin, cell_size, dimensions, values)
+    }
+    fn get(&self, x: usize, y: usize, z: usize) -> RayScalar {
+        self.values[x + self.dimensions.x * (y + self.dimensions.y * z)] as RayScalar
+    }
+}
+impl DensityField for GridDensity {
+    fn density(&self, position: Point3<RayScalar>) -> RayScalar {
+        let local = (position - self.origin) / self.cell_size;
+        let last = self.dimensions.map(|v| (v - 1) as RayScalar);
+        if local.x < 0.0
+            || local.y < 0.0
+            || local.z < 0.0
+            || local.x > last.x
+            || local.y > last.y
+            || local.z > last.z
+        {
+            return 0.0;
+        }
+        let corner = local.map(|v| v.floor()).zip(last, |v, l| v.min(l - 1.0));
+        let fraction = local - corner;
+        let (x, y, z) = (corner.x as usize, corner.y as usize, corner.z as usize);
+        let weight = |offset, fraction| {
+            if offset == 0 {
+                1.0 - fraction
+            } else {
+                fraction
+            }
+        };
+        let mut density = 0.0;
+        for dx in 0..2 {
+            for dy in 0..2 {
+                for dz in 0..2 {
+                    let weight =
+                        weight(dx, fraction.x) * weight(dy, fraction.y) * weight(dz, fraction.z);
+                    density += weight * self.get(x + dx, y + dy, z + dz);
+                }
+            }
+        }
+        density
+    }
+    fn max_density(&self) -> RayScalar {
+        self.max_density
+    }
+}
+/// Cloud of fractal Perlin noise that fills an ellipsoid, it is densest in the middle and fades
+/// out towards the edge of the ellipsoid
+#[derive(Clone)]
+pub struct FbmDensity {
+    pub noise: Perlin,
+    pub center: Point3<RayScalar>,
+    pub radii: Vector3<RayScalar>,
+    /// number of noise features per unit length
+    pub frequency: RayScalar,
+    pub octaves: u32,
+    /// density of the densest parts of the cloud
+    pub density: RayScalar,
+}
+impl DensityField for FbmDensity {
+    fn density(&self, position: Point3<RayScalar>) -> RayScalar {
+        let offset = (position - self.center).div_element_wise(self.radii);
+        let falloff = 1.0 - offset.magnitude2();
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+        let noise = self.noise.turbulence(
+            Point3::from_vec(self.frequency * position.to_vec()),
+            self.octaves,
+        );
+        // the turbulence is rarely above 0.2 so it is stretched to cover the full density, small
+        // values are cut off to leave gaps between the puffs
+        self.density * (falloff * (6.0 * noise - 0.1)).clamp(0.0, 1.0)
+    }
+    fn max_density(&self) -> RayScalar {
+        self.density
+    }
+}
+/// Medium whose density changes from point to point. Scattering is sampled with delta tracking
+/// and the absorption is estimated with ratio tracking, so rays that pass through without
+/// scattering are dimmed by the absorption and pick up the light emitted along the way.
+///
+/// There is no separate shadow test, rays sampled towards lights are traced like every other
+/// bounce. Crossing the medium is a specular pass-through to the exit or to the nearest surface
+/// inside, which dims the ray by the absorption and uses up one bounce of the depth, so light
+/// that has to pass through many media can be cut off by the maximum depth
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable>,
+    phase_function: Box<dyn Material>,
+    density: Box<dyn DensityField>,
+    scattering: RayScalar,
+    absorption: RgbColor,
+    emission: RgbColor,
+    temperature: Option<Box<dyn DensityField>>,
+}
+impl Clone for HeterogeneousMedium {
+    fn clone(&self) -> Self {
+        Self {
+            boundary: clone_box(self.boundary.deref()),
+            phase_function: clone_box(self.phase_function.deref()),
+            density: clone_box(self.density.deref()),
+            scattering: self.scattering,
+            absorption: self.absorption,
+            emission: self.emission,
+            temperature: self
+                .temperature
+                .as_ref()
+                .map(|temperature| clone_box(temperature.deref())),
+        }
+    }
+}
+impl HeterogeneousMedium {
+    /// the boundary must be convex, the medium only scatters light until absorption or emission
+    /// are added
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        phase_function: Box<dyn Material>,
+        density: Box<dyn DensityField>,
+    ) -> Self {
+        Self {
+            boundary,
+            phase_function,
+            density,
+            scattering: 1.0,
+            absorption: RgbColor::BLACK,
+            emission: RgbColor::BLACK,
+            temperature: None,
+        }
+    }
+    /// scattering coefficient at a density of one
+    pub fn with_scattering(mut self, scattering: RayScalar) -> Self {
+        self.scattering = scattering;
+        self
+    }
+    /// absorption coefficient of each channel at a density of one
+    pub fn with_absorption(mut self, absorption: RgbColor) -> Self {
+        self.absorption = absorption;
+        self
+    }
+    /// radiance emitted by the absorbing parts of the medium, like fire it only glows where it
+    /// absorbs
+    pub fn with_emission(mut self, emission: RgbColor) -> Self {
+        self.emission = emission;
+        self
+    }
+    /// scales the emission at every point, like the temperature grid of a fire simulation
+    pub fn with_temperature(mut self, temperature: Box<dyn DensityField>) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+    /// gap left in front of surfaces inside of the medium so the ray that continues after passing
+    /// through still hits them. The bvh gives the medium `t_max` at the nearest surface, see
+    /// `Hittable::is_medium`
+    const SURFACE_GAP: RayScalar = 0.01;
+    fn pass_through_pdf_fn(
+        _ray_in: Ray,
+        _record_in: &HitRecord,
+        _scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        panic!("passing through the medium is specular")
+    }
+    /// delta tracking, gets where the light is first scattered between `t_start` and `t_end`
+    fn sample_scattering(
+        &self,
+        ray: &Ray,
+        t_start: RayScalar,
+        t_end: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<RayScalar> {
+        let majorant = self.scattering * self.density.max_density() * ray.direction.magnitude();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let mut t = t_start;
+        loop {
+            t -= (1.0 - sampler.next_scalar()).ln() / majorant;
+            if t >= t_end {
+                return None;
+            }
+            let scattering =
+                self.scattering * self.density.density(ray.at(t)) * ray.direction.magnitude();
+            if sampler.next_scalar() * majorant < scattering {
+                return Some(t);
+            }
+        }
+    }
+    /// ratio tracking, estimates the fraction of light that is not absorbed between `t_start` and
+    /// `t_end` and the light emitted towards the start of the ray
+    fn ratio_tracking(
+        &self,
+        ray: &Ray,
+        t_start: RayScalar,
+        t_end: RayScalar,
+        sampler: &mut Sampler,
+    ) -> (RgbColor, RgbColor) {
+        let max_absorption = self
+            .absorption
+            .red
+            .max(self.absorption.green)
+            .max(self.absorption.blue) as RayScalar;
+        let majorant = max_absorption * self.density.max_density() * ray.direction.magnitude();
+        let mut transmittance = RgbColor::WHITE;
+        let mut emitted = RgbColor::BLACK;
+        if majorant <= 0.0 {
+            return (transmittance, emitted);
+        }
+        let mut t = t_start;
+        loop {
+            t -= (1.0 - sampler.next_scalar()).ln() / majorant;
+            if t >= t_end {
+                return (transmittance, emitted);
+            }
+            let position = ray.at(t);
+            let absorbed = self.absorption
+                * (self.density.density(position) / self.density.max_density() / max_absorption);
+            let temperature = self
+                .temperature
+                .as_ref()
+                .map_or(1.0, |temperature| temperature.density(position));
+            emitted += transmittance * absorbed * self.emission * temperature;
+            transmittance = transmittance * (RgbColor::WHITE - absorbed);
+        }
+    }
+}
+impl Hittable for HeterogeneousMedium {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let enter = self
+            .boundary
+            .hit(ray, -10000000000.0, 10000000000.0, sampler)?;
+        let exit = self
+            .boundary
+            .hit(ray, enter.t + 0.0001, 10000000000.0, sampler)?;
+
+        let t_start = enter.t.max(t_min);
+        let t_end = if exit.t > t_max {
+            t_max - Self::SURFACE_GAP
+        } else {
+            exit.t
+        };
+        if t_start >= t_end {
+            return None;
+        }
+        let scatter_t = self.sample_scattering(ray, t_start, t_end, sampler);
+        let t = scatter_t.unwrap_or(t_end);
+        let (transmittance, emitted) = self.ratio_tracking(ray, t_start, t, sampler);
+        let position = ray.at(t);
+
+        let scatter_record = if scatter_t.is_some() {
+            let hit_ray = HitRay {
+                position,
+                direction: ray.direction,
+                normal: Vector3::unit_x(),
+                front_face: true,
+                uv: Point2::origin(),
+            };
+            self.phase_function
+                .scatter(*ray, &hit_ray, sampler)
+                .map(|record| ScatterRecord {
+                    attenuation: transmittance * record.attenuation,
+                    ..record
+                })
+        } else {
+            Some(ScatterRecord {
+                specular_ray: Some(Ray {
+                    origin: position,
+                    direction: ray.direction,
+                    time: ray.time,
+                }),
+                attenuation: transmittance,
+                pdf: None,
+                scattering_pdf: Self::pass_through_pdf_fn,
+            })
+        };
+        // a hit can only emit or scatter so one of them is picked at random and weighted to
+        // keep the sum of both unbiased
+        let material_effect = match scatter_record {
+            Some(record) if emitted != RgbColor::BLACK => {
+                if sampler.next_scalar() < 0.5 {
+                    MaterialEffect::Emmit(2.0 * emitted)
+                } else {
+                    MaterialEffect::Scatter(ScatterRecord {
+                        attenuation: 2.0 * record.attenuation,
+                        ..record
+                    })
+                }
+            }
+            Some(record) => MaterialEffect::Scatter(record),
+            None if emitted != RgbColor::BLACK => MaterialEffect::Emmit(emitted),
+            None => MaterialEffect::NoEmmit,
+        };
+
+        Some(HitRecord {
+            position,
+            normal: Vector3::new(1.0, 0.0, 0.0),
+            t,
+            front_face: false,
+            uv: Point2::new(0.0, 0.0),
+            material_effect,
+        })
+    }
+
+    fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
+        self.boundary.bounding_box(time_0, time_1)
+    }
+    fn is_medium(&self) -> bool {
+        true
+    }
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        self.boundary.prob(ray, sampler)
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        self.boundary.generate_ray_in_area(origin, time, sampler)
+    }
+    fn name(&self) -> String {
+        "Heterogeneous Medium".to_string()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        hittable::RenderBox,
+        material::{Lambertian, Medium},
+        phase_function::PhaseFunction,
+        texture::SolidColor,
+    };
+    /// density that is lower than its majorant so tracking has to reject collisions
+    #[derive(Clone)]
+    struct LooseDensity {
+        density: RayScalar,
+    }
+    impl DensityField for LooseDensity {
+        fn density(&self, _position: Point3<RayScalar>) -> RayScalar {
+            self.density
+        }
+        fn max_density(&self) -> RayScalar {
+            4.0 * self.density
+        }
+    }
+    /// slab of medium two units thick along x
+    fn slab(density: Box<dyn DensityField>) -> HeterogeneousMedium {
+        let material = || {
+            Box::new(Lambertian {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+            })
+        };
+        HeterogeneousMedium::new(
+            Box::new(RenderBox::new(
+                Point3::new(0.0, -10.0, -10.0),
+                Point3::new(2.0, 10.0, 10.0),
+                material(),
+            )),
+            Box::new(Medium {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+                phase_function: PhaseFunction::Isotropic,
+            }),
+            density,
+        )
+    }
+    fn ray() -> Ray {
+        Ray {
+            origin: Point3::new(-1.0, 0.0, 0.0),
+            // not normalized to check that distances are measured along the ray
+            direction: Vector3::new(2.0, 0.0, 0.0),
+            time: 0.0,
+        }
+    }
+    #[test]
+    fn grid_interpolation() {
+        let grid = GridDensity::from_fn(
+            Point3::new(1.0, 0.0, 0.0),
+            0.5,
+            Vector3::new(3, 2, 2),
+            |p| (p.x + 2.0 * p.y + 3.0 * p.z) as f32,
+        );
+        for position in [
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.25, 0.1, 0.3),
+            Point3::new(1.8, 0.5, 0.5),
+        ] {
+            let expected = position.x + 2.0 * position.y + 3.0 * position.z;
+            assert!((grid.density(position) - expected).abs() < 1e-5);
+        }
+        assert_eq!(grid.density(Point3::new(0.9, 0.0, 0.0)), 0.0);
+        assert_eq!(grid.density(Point3::new(2.1, 0.0, 0.0)), 0.0);
+        assert!((grid.max_density() - 4.5).abs() < 1e-5);
+    }
+    #[test]
+    fn fbm_bounded() {
+        let fbm = FbmDensity {
+            noise: Perlin::with_seed(1),
+            center: Point3::new(0.0, 0.0, 0.0),
+            radii: Vector3::new(2.0, 4.0, 2.0),
+            frequency: 1.5,
+            octaves: 5,
+            density: 0.7,
+        };
+        let mut sampler = Sampler::new(0);
+        let mut max: RayScalar = 0.0;
+        for _ in 0..10_000 {
+            let position = Point3::from_vec(sampler.unit_vec().mul_element_wise(fbm.radii));
+            let density = fbm.density(position);
+            assert!((0.0..=fbm.max_density()).contains(&density));
+            max = max.max(density);
+        }
+        assert!(max > 0.5 * fbm.max_density(), "cloud is empty");
+        assert_eq!(fbm.density(Point3::new(0.0, 4.1, 0.0)), 0.0);
+    }
+    /// the fraction of rays scattered in the slab should follow Beer's law
+    #[test]
+    fn delta_tracking() {
+        let medium = slab(Box::new(LooseDensity { density: 0.3 }));
+        let mut sampler = Sampler::new(0);
+        let num_samples = 20_000;
+        let scattered = (0..num_samples)
+            .filter(|_| {
+                medium
+                    .sample_scattering(&ray(), 0.5, 1.5, &mut sampler)
+                    .is_some()
+            })
+            .count();
+        let expected = 1.0 - (-0.3 * 2.0 as RayScalar).exp();
+        let fraction = scattered as RayScalar / num_samples as RayScalar;
+        assert!(
+            (fraction - expected).abs() < 0.01,
+            "fraction: {}, expected: {}",
+            fraction,
+            expected
+        );
+    }
+    /// absorption is different for every channel and the light emitted by a purely absorbing slab
+    /// is the emission times the fraction of light it absorbs
+    #[test]
+    fn ratio_tracking() {
+        let absorption = RgbColor::new(0.1, 0.5, 1.0);
+        let emission = RgbColor::new(2.0, 1.0, 0.5);
+        let medium = slab(Box::new(LooseDensity { density: 0.5 }))
+            .with_scattering(0.0)
+            .with_absorption(absorption)
+            .with_emission(emission);
+        let mut sampler = Sampler::new(0);
+        let num_samples = 20_000;
+        let (transmittance, emitted) = (0..num_samples)
+            .map(|_| medium.ratio_tracking(&ray(), 0.5, 1.5, &mut sampler))
+            .fold((RgbColor::BLACK, RgbColor::BLACK), |acc, x| {
+                (acc.0 + x.0, acc.1 + x.1)
+            });
+        let transmittance = transmittance / num_samples as f32;
+        let emitted = emitted / num_samples as f32;
+        let expected = (absorption * -1.0f32).exp();
+        assert!(
+            transmittance.distance(expected) < 0.01,
+            "transmittance: {}, expected: {}",
+            transmittance,
+            expected
+        );
+        let expected = (RgbColor::WHITE - expected) * emission;
+        assert!(
+            emitted.distance(expected) < 0.02,
+            "emitted: {}, expected: {}",
+            emitted,
+            expected
+        );
+    }
+    /// the temperature scales the emission but not the absorption
+    #[test]
+    fn temperature() {
+        let medium = slab(Box::new(LooseDensity { density: 0.5 }))
+            .with_scattering(0.0)
+            .with_absorption(RgbColor::new(1.0, 1.0, 1.0))
+            .with_emission(RgbColor::new(1.0, 1.0, 1.0));
+        let hot = medium
+            .clone()
+            .with_temperature(Box::new(LooseDensity { density: 2.0 }));
+        // the temperature does not change the random numbers used so both take the same steps
+        for seed in 0..100 {
+            let (transmittance, emitted) =
+                medium.ratio_tracking(&ray(), 0.5, 1.5, &mut Sampler::new(seed));
+            let (hot_transmittance, hot_emitted) =
+                hot.ratio_tracking(&ray(), 0.5, 1.5, &mut Sampler::new(seed));
+            assert_eq!(transmittance, hot_transmittance);
+            assert!(hot_emitted.distance(emitted * 2.0f32) < 1e-5);
+        }
+    }
+    /// rays that do not scatter continue on the other side of the slab, dimmed by absorption
+    #[test]
+    fn pass_through() {
+        let medium = slab(Box::new(LooseDensity { density: 0.5 }))
+            .with_scattering(0.0)
+            .with_absorption(RgbColor::new(1.0, 1.0, 1.0));
+        let record = medium
+            .hit(&ray(), 0.001, RayScalar::MAX, &mut Sampler::new(0))
+            .unwrap();
+        assert!((record.position.x - 2.0).abs() < 1e-6);
+        match record.material_effect {
+            MaterialEffect::Scatter(scatter) => {
+                let specular_ray = scatter.specular_ray.unwrap();
+                assert_eq!(specular_ray.direction, ray().direction);
+            }
+            _ => panic!("ray should pass through"),
+        }
+        // a surface inside of the medium still gets hit by the ray after it
+        let record = medium
+            .hit(&ray(), 0.001, 1.25, &mut Sampler::new(0))
+            .unwrap();
+        assert!(record.t < 1.25);
+    }
+    /// light is absorbed up to an opaque box inside of the medium whichever of them the bvh tests
+    /// first
+    #[test]
+    fn surface_inside() {
+        use crate::ray_tracer::{
+            bvh::BvhTree,
+            hittable::{Object, Transform},
+        };
+        let medium = || {
+            Object::new(
+                Box::new(
+                    slab(Box::new(LooseDensity { density: 0.5 }))
+                        .with_scattering(0.0)
+                        .with_absorption(RgbColor::new(1.0, 1.0, 1.0)),
+                ),
+                Transform::identity(),
+            )
+        };
+        let surface = || {
+            Object::new(
+                Box::new(RenderBox::new(
+                    Point3::new(0.9, -1.0, -1.0),
+                    Point3::new(1.1, 1.0, 1.0),
+                    Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: RgbColor::WHITE,
+                        }),
+                    }),
+                )),
+                Transform::identity(),
+            )
+        };
+        let reversed = Ray {
+            origin: Point3::new(3.0, 0.0, 0.0),
+            direction: Vector3::new(-2.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        for ray in [ray(), reversed] {
+            let records = [
+                BvhTree::new(vec![medium(), surface()], 0.0, 0.0),
+                BvhTree::new(vec![surface(), medium()], 0.0, 0.0),
+            ]
+            .map(|tree| {
+                tree.hit(&ray, 0.001, RayScalar::MAX, &mut Sampler::new(0))
+                    .unwrap()
+            });
+            for record in records.iter() {
+                // the surface is hit at t = 0.95 from either side
+                let expected = 0.95 - HeterogeneousMedium::SURFACE_GAP;
+                assert!((record.t - expected).abs() < 1e-6, "t: {}", record.t);
+                match &record.material_effect {
+                    MaterialEffect::Scatter(scatter) => {
+                        assert!(scatter.specular_ray.is_some());
+                        assert!(scatter.attenuation.red < 1.0);
+                    }
+                    _ => panic!("ray should pass through"),
+                }
+            }
+            match (&records[0].material_effect, &records[1].material_effect) {
+                (MaterialEffect::Scatter(a), MaterialEffect::Scatter(b)) => {
+                    assert_eq!(a.attenuation, b.attenuation)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use super::{surface_prob, Aabb, HitRecord, Hittable, Material, RayAreaInfo};
+use crate::{prelude::*, ray_tracer::ray_tracer_info::EntityField};
+use cgmath::{num_traits::FloatConst, prelude::*, Point2, Point3, Vector3};
+use dyn_clone::clone_box;
+use std::{collections::HashMap, ops::Deref};
+
+/// thickness given to the bounding boxes of flat shapes
+const BOX_THICKNESS: RayScalar = 0.001;
+
+/// distance along the ray to the plane through `point`
+fn plane_t(
+    ray: &Ray,
+    point: Point3<RayScalar>,
+    normal: Vector3<RayScalar>,
+    t_min: RayScalar,
+    t_max: RayScalar,
+) -> Option<RayScalar> {
+    let denominator = normal.dot(ray.direction);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    let t = normal.dot(point - ray.origin) / denominator;
+    (t_min..=t_max).contains(&t).then_some(t)
+}
+/// angle around the w axis of the basis scaled to [0, 1)
+fn polar_u(basis: &OrthoNormalBasis, offset: Vector3<RayScalar>) -> RayScalar {
+    let local = basis.coordinates(offset);
+    (local.y.atan2(local.x) / (2.0 * RayScalar::PI())).rem_euclid(1.0)
+}
+/// Parallelogram spanned by `u` and `v` from `corner`, facing along `u × v`. The uv coordinates
+/// follow the edges
+pub struct Quad {
+    pub corner: Point3<RayScalar>,
+    pub u: Vector3<RayScalar>,
+    pub v: Vector3<RayScalar>,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Quad {
+    fn clone(&self) -> Self {
+        Self {
+            corner: self.corner,
+            u: self.u,
+            v: self.v,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Quad {
+    fn normal(&self) -> Vector3<RayScalar> {
+        self.u.cross(self.v).normalize()
+    }
+    fn area(&self) -> RayScalar {
+        self.u.cross(self.v).magnitude()
+    }
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<(RayScalar, Point2<RayScalar>)> {
+        let normal = self.u.cross(self.v);
+        let t = plane_t(ray, self.corner, normal, t_min, t_max)?;
+        let planar = ray.at(t) - self.corner;
+        let w = normal / normal.magnitude2();
+        let alpha = w.dot(planar.cross(self.v));
+        let beta = w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some((t, Point2::new(alpha, beta)))
+    }
+}
+impl Hittable for Quad {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (t, uv) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord::new(
+            ray,
+            ray.at(t),
+            self.normal(),
+            t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        let corners = [self.u, self.v, self.u + self.v]
+            .into_iter()
+            .map(|offset| Aabb::from_point(self.corner + offset))
+            .fold(Aabb::from_point(self.corner), Aabb::surrounding_box);
+        let padding = Vector3::new(BOX_THICKNESS, BOX_THICKNESS, BOX_THICKNESS);
+        Some(Aabb {
+            minimum: corners.minimum - padding,
+            maximum: corners.maximum + padding,
+        })
+    }
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        surface_prob(&ray, self.area(), |t| {
+            self.intersect(&ray, t, RayScalar::MAX)
+                .map(|(t, _)| (t, self.normal()))
+        })
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let end_point =
+            self.corner + sampler.next_scalar() * self.u + sampler.next_scalar() * self.v;
+        RayAreaInfo::to_point(origin, time, end_point, self.normal(), self.area())
+    }
+    fn name(&self) -> String {
+        "Quad".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("corner".to_string(), EntityField::Point3(self.corner));
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "corner" => match value {
+                EntityField::Point3(p) => self.corner = p,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+/// Flat disk facing along `normal`, the uv coordinates are the angle around the center and the
+/// distance from it
+pub struct Disk {
+    pub center: Point3<RayScalar>,
+    pub normal: Vector3<RayScalar>,
+    pub radius: RayScalar,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Disk {
+    fn clone(&self) -> Self {
+        Self {
+            center: self.center,
+            normal: self.normal,
+            radius: self.radius,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Disk {
+    fn area(&self) -> RayScalar {
+        RayScalar::PI() * self.radius.powi(2)
+    }
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<(RayScalar, Point2<RayScalar>)> {
+        let t = plane_t(ray, self.center, self.normal, t_min, t_max)?;
+        let offset = ray.at(t) - self.center;
+        if offset.magnitude2() > self.radius.powi(2) {
+            return None;
+        }
+        let basis = OrthoNormalBasis::build_from_w(self.normal);
+        Some((
+            t,
+            Point2::new(polar_u(&basis, offset), offset.magnitude() / self.radius),
+        ))
+    }
+}
+impl Hittable for Disk {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (t, uv) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord::new(
+            ray,
+            ray.at(t),
+            self.normal.normalize(),
+            t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        let normal = self.normal.normalize();
+        let extent = normal.map(|n| self.radius * (1.0 - n * n).max(0.0).sqrt() + BOX_THICKNESS);
+        Some(Aabb {
+            minimum: self.center - extent,
+            maximum: self.center + extent,
+        })
+    }
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        surface_prob(&ray, self.area(), |t| {
+            self.intersect(&ray, t, RayScalar::MAX)
+                .map(|(t, _)| (t, self.normal))
+        })
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let basis = OrthoNormalBasis::build_from_w(self.normal);
+        let end_point = self.center + self.radius * basis.local(sampler.unit_disk());
+        RayAreaInfo::to_point(origin, time, end_point, basis.w(), self.area())
+    }
+    fn name(&self) -> String {
+        "Disk".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("center".to_string(), EntityField::Point3(self.center));
+        map.insert("radius".to_string(), EntityField::Float(self.radius));
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "center" => match value {
+                EntityField::Point3(p) => self.center = p,
+                _ => panic!("invalid value type"),
+            },
+            "radius" => match value {
+                EntityField::Float(v) => self.radius = v,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+/// Plane through `point` facing along `normal`. The uv coordinates repeat every unit. The plane
+/// does not have an area so it can not be sampled as a light. It has no bounding box either, so
+/// the bvh tests it on every ray apart from the tree
+pub struct InfinitePlane {
+    pub point: Point3<RayScalar>,
+    pub normal: Vector3<RayScalar>,
+    pub material: Box<dyn Material>,
+}
+impl Clone for InfinitePlane {
+    fn clone(&self) -> Self {
+        Self {
+            point: self.point,
+            normal: self.normal,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Hittable for InfinitePlane {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let t = plane_t(ray, self.point, self.normal, t_min, t_max)?;
+        let position = ray.at(t);
+        let local = OrthoNormalBasis::build_from_w(self.normal).coordinates(position - self.point);
+        Some(HitRecord::new(
+            ray,
+            position,
+            self.normal.normalize(),
+            t,
+            Point2::new(local.x.rem_euclid(1.0), local.y.rem_euclid(1.0)),
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        None
+    }
+    fn prob(&self, _ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        0.0
+    }
+    /// points at the closest point on the plane, the direction is never used as its probability
+    /// is zero
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        _sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let normal = self.normal.normalize();
+        let end_point = origin - normal.dot(origin - self.point) * normal;
+        RayAreaInfo::to_point(origin, time, end_point, normal, RayScalar::INFINITY)
+    }
+    fn name(&self) -> String {
+        "Infinite Plane".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("point".to_string(), EntityField::Point3(self.point));
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "point" => match value {
+                EntityField::Point3(p) => self.point = p,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        material::DiffuseLight,
+        pdf::{
+            test::{check_pdf, light_world},
+            LightPdf,
+        },
+        texture::SolidColor,
+    };
+
+    fn light() -> Box<dyn Material> {
+        Box::new(DiffuseLight {
+            emit: Box::new(SolidColor {
+                color: RgbColor::WHITE,
+            }),
+        })
+    }
+    fn quad() -> Quad {
+        Quad {
+            corner: Point3::new(-1.0, 2.0, -0.5),
+            u: Vector3::new(2.0, 0.0, 0.0),
+            v: Vector3::new(0.3, 0.4, 1.5),
+            material: light(),
+        }
+    }
+    fn disk() -> Disk {
+        Disk {
+            center: Point3::new(0.5, 1.5, 0.2),
+            normal: Vector3::new(0.2, -1.0, 0.3),
+            radius: 1.2,
+            material: light(),
+        }
+    }
+    fn ray(origin: Point3<RayScalar>, direction: Vector3<RayScalar>) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+    #[test]
+    fn quad_hit() {
+        let quad = quad();
+        let mut sampler = Sampler::new(0);
+        let target = quad.corner + 0.25 * quad.u + 0.75 * quad.v;
+        let hit = quad
+            .hit(
+                &ray(Point3::new(0.0, 0.0, 0.0), target.to_vec()),
+                0.001,
+                RayScalar::MAX,
+                &mut sampler,
+            )
+            .unwrap();
+        assert!((hit.position - target).magnitude() < 1e-9);
+        assert!((hit.uv - Point2::new(0.25, 0.75)).magnitude() < 1e-9);
+        assert!((hit.normal - Vector3::new(0.0, -3.0, 0.8).normalize()).magnitude() < 1e-9);
+        let outside = quad.corner + 1.1 * quad.u + 0.5 * quad.v;
+        assert!(quad
+            .hit(
+                &ray(Point3::new(0.0, 0.0, 0.0), outside.to_vec()),
+                0.001,
+                RayScalar::MAX,
+                &mut sampler,
+            )
+            .is_none());
+        let bounds = quad.bounding_box(0.0, 0.0).unwrap();
+        for corner in [
+            quad.corner,
+            quad.corner + quad.u,
+            quad.corner + quad.v,
+            quad.corner + quad.u + quad.v,
+        ] {
+            assert!(bounds.contains_point(corner));
+        }
+    }
+    #[test]
+    fn disk_hit() {
+        let disk = disk();
+        let mut sampler = Sampler::new(0);
+        let hit = disk
+            .hit(
+                &ray(Point3::new(0.0, 0.0, 0.0), disk.center.to_vec()),
+                0.001,
+                RayScalar::MAX,
+                &mut sampler,
+            )
+            .unwrap();
+        assert!((hit.position - disk.center).magnitude() < 1e-9);
+        assert!(hit.uv.y < 1e-9);
+        // the normal faces the origin
+        assert!(hit.front_face);
+        let bounds = disk.bounding_box(0.0, 0.0).unwrap();
+        for _ in 0..100 {
+            let point = disk.generate_ray_in_area(Point3::origin(), 0.0, &mut sampler);
+            assert!(bounds.contains_point(point.end_point));
+        }
+    }
+    #[test]
+    fn plane_hit() {
+        let plane = InfinitePlane {
+            point: Point3::new(0.0, -1.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+            material: light(),
+        };
+        let mut sampler = Sampler::new(0);
+        let hit = plane
+            .hit(
+                &ray(Point3::new(0.0, 0.0, 0.0), Vector3::new(1000.0, -0.01, 0.0)),
+                0.001,
+                RayScalar::MAX,
+                &mut sampler,
+            )
+            .unwrap();
+        assert!((hit.position.x - 100_000.0).abs() < 1e-6);
+        assert!((0.0..1.0).contains(&hit.uv.x) && (0.0..1.0).contains(&hit.uv.y));
+        assert!(plane
+            .hit(
+                &ray(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.01, 0.0)),
+                0.001,
+                RayScalar::MAX,
+                &mut sampler,
+            )
+            .is_none());
+        assert_eq!(
+            plane.prob(
+                ray(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -1.0, 0.0)),
+                &mut sampler
+            ),
+            0.0
+        );
+    }
+    #[test]
+    fn light_pdf() {
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(quad())),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(disk())),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+    #[test]
+    fn fields() {
+        let mut disk = disk();
+        disk.set_field("radius".to_string(), EntityField::Float(2.0));
+        disk.set_field(
+            "center".to_string(),
+            EntityField::Point3(Point3::new(1.0, 2.0, 3.0)),
+        );
+        assert_eq!(disk.fields()["radius"], EntityField::Float(2.0));
+        assert_eq!(
+            disk.fields()["center"],
+            EntityField::Point3(Point3::new(1.0, 2.0, 3.0))
+        );
+    }
+}
@@ -4,6 +4,28 @@ use cgmath::{prelude::*, Point2, Point3, Vector3};
 use dyn_clone::clone_box;
 use std::ops::Deref;
 
+/// Probability per solid angle of sampling the direction of the ray by picking a point uniformly
+/// on a rectangle. The coordinates are ordered so that the rectangle spans [a0, a1] on x and
+/// [b0, b1] on y and lies at z = k
+fn rect_prob(
+    origin: Point3<RayScalar>,
+    direction: Vector3<RayScalar>,
+    (a0, a1, b0, b1, k): (RayScalar, RayScalar, RayScalar, RayScalar, RayScalar),
+) -> RayScalar {
+    let cos_alpha = direction.z.abs() / direction.magnitude();
+    if cos_alpha < 0.00001 {
+        return 0.0;
+    }
+    let t = (k - origin.z) / direction.z;
+    let a = origin.x + t * direction.x;
+    let b = origin.y + t * direction.y;
+    if t <= 0.0 || a < a0 || a > a1 || b < b0 || b > b1 {
+        return 0.0;
+    }
+    let distance_squared = t * t * direction.magnitude2();
+    distance_squared / (cos_alpha * (a1 - a0) * (b1 - b0))
+}
+
 pub struct XYRect {
     pub material: Box<dyn Material>,
     pub x0: RayScalar,
@@ -60,7 +82,13 @@ impl XYRect {
     }
 }
 impl Hittable for XYRect {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.z) / ray.direction.z;
         if t < t_min || t > t_max {
             return None;
@@ -82,6 +110,7 @@ impl Hittable for XYRect {
             t,
             uv,
             self.material.as_ref(),
+            sampler,
         ))
     }
 
@@ -91,24 +120,23 @@ impl Hittable for XYRect {
             maximum: Point3::new(self.x1, self.y1, self.k + 0.001),
         })
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
-        let center =
-            0.5 * (Point3::new(self.x0, self.y0, self.k) + Vector3::new(self.x1, self.y1, self.k));
-        let to_light = center - ray.origin;
-        let cos_alpha = to_light.normalize().z.abs();
-        if cos_alpha < 0.00001 {
-            return 0.0;
-        }
-
-        let distance_squared = to_light.dot(to_light);
-
-        distance_squared / (cos_alpha * self.area())
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        rect_prob(
+            ray.origin,
+            ray.direction,
+            (self.x0, self.x1, self.y0, self.y1, self.k),
+        )
     }
 
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
         let end_point = Point3::new(
-            rand_scalar(self.x0, self.x1),
-            rand_scalar(self.y0, self.y1),
+            sampler.scalar(self.x0, self.x1),
+            sampler.scalar(self.y0, self.y1),
             self.k,
         );
         let direction = (end_point - origin).normalize();
@@ -182,7 +210,13 @@ impl XZRect {
     }
 }
 impl Hittable for XZRect {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.y) / ray.direction.y;
         if t < t_min || t > t_max {
             return None;
@@ -202,6 +236,7 @@ impl Hittable for XZRect {
                 (z - self.z0) / (self.z1 - self.z0),
             ),
             self.material.as_ref(),
+            sampler,
         ))
     }
 
@@ -211,26 +246,24 @@ impl Hittable for XZRect {
             maximum: Point3::new(self.x1, self.k + 0.001, self.z1),
         })
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
-        let center =
-            0.5 * (Point3::new(self.x0, self.k, self.z0) + Vector3::new(self.x1, self.k, self.z1));
-        let to_light = center - ray.origin;
-        let cos_alpha = to_light.normalize().y.abs();
-        if cos_alpha < 0.00001 {
-            return 0.0;
-        }
-        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
-
-        let distance_squared = to_light.dot(to_light);
-
-        distance_squared / (cos_alpha * area)
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        rect_prob(
+            Point3::new(ray.origin.x, ray.origin.z, ray.origin.y),
+            Vector3::new(ray.direction.x, ray.direction.z, ray.direction.y),
+            (self.x0, self.x1, self.z0, self.z1, self.k),
+        )
     }
 
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
         let end_point = Point3::new(
-            rand_scalar(self.x0, self.x1),
+            sampler.scalar(self.x0, self.x1),
             self.k,
-            rand_scalar(self.z0, self.z1),
+            sampler.scalar(self.z0, self.z1),
         );
         let direction = (end_point - origin).normalize();
         RayAreaInfo {
@@ -306,7 +339,13 @@ impl YZRect {
     }
 }
 impl Hittable for YZRect {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let t = (self.k - ray.origin.x) / ray.direction.x;
         if t < t_min || t > t_max {
             return None;
@@ -327,6 +366,7 @@ impl Hittable for YZRect {
                 (z - self.z0) / (self.z1 - self.z0),
             ),
             self.material.as_ref(),
+            sampler,
         ))
     }
 
@@ -336,25 +376,24 @@ impl Hittable for YZRect {
             maximum: Point3::new(self.k + 0.001, self.y1, self.z1),
         })
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
-        let center =
-            0.5 * (Point3::new(self.k, self.y0, self.z0) + Vector3::new(self.k, self.y1, self.z1));
-        let to_light = center - ray.origin;
-        let cos_alpha = to_light.normalize().x.abs();
-        if cos_alpha < 0.00001 {
-            return 0.0;
-        }
-
-        let distance_squared = to_light.dot(to_light);
-
-        distance_squared / (cos_alpha * self.area())
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        rect_prob(
+            Point3::new(ray.origin.y, ray.origin.z, ray.origin.x),
+            Vector3::new(ray.direction.y, ray.direction.z, ray.direction.x),
+            (self.y0, self.y1, self.z0, self.z1, self.k),
+        )
     }
 
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
         let end_point = Point3::new(
             self.k,
-            rand_scalar(self.y0, self.y1),
-            rand_scalar(self.z0, self.z1),
+            sampler.scalar(self.y0, self.y1),
+            sampler.scalar(self.z0, self.z1),
         );
         let direction = (end_point - origin).normalize();
         RayAreaInfo {
@@ -114,16 +114,22 @@ impl RenderBox {
     }
 }
 impl Hittable for RenderBox {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let mut closest_hit: Option<HitRecord> = None;
-        Self::check_hit(&mut closest_hit, self.xyp.hit(ray, t_min, t_max));
-        Self::check_hit(&mut closest_hit, self.xym.hit(ray, t_min, t_max));
+        Self::check_hit(&mut closest_hit, self.xyp.hit(ray, t_min, t_max, sampler));
+        Self::check_hit(&mut closest_hit, self.xym.hit(ray, t_min, t_max, sampler));
 
-        Self::check_hit(&mut closest_hit, self.xzp.hit(ray, t_min, t_max));
-        Self::check_hit(&mut closest_hit, self.xzm.hit(ray, t_min, t_max));
+        Self::check_hit(&mut closest_hit, self.xzp.hit(ray, t_min, t_max, sampler));
+        Self::check_hit(&mut closest_hit, self.xzm.hit(ray, t_min, t_max, sampler));
 
-        Self::check_hit(&mut closest_hit, self.yzp.hit(ray, t_min, t_max));
-        Self::check_hit(&mut closest_hit, self.yzm.hit(ray, t_min, t_max));
+        Self::check_hit(&mut closest_hit, self.yzp.hit(ray, t_min, t_max, sampler));
+        Self::check_hit(&mut closest_hit, self.yzm.hit(ray, t_min, t_max, sampler));
 
         closest_hit
     }
@@ -134,46 +140,51 @@ impl Hittable for RenderBox {
             maximum: self.box_max,
         })
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
         let mut area = 0.0;
         if ray.direction.x >= 0.0 {
-            area += self.yzm.prob(ray);
+            area += self.yzm.prob(ray, sampler);
         } else {
-            area += self.yzp.prob(ray);
+            area += self.yzp.prob(ray, sampler);
         }
         if ray.direction.y >= 0.0 {
-            area += self.xzm.prob(ray)
+            area += self.xzm.prob(ray, sampler)
         } else {
-            area += self.xzp.prob(ray);
+            area += self.xzp.prob(ray, sampler);
         }
         if ray.direction.z >= 0.0 {
-            area += self.xym.prob(ray);
+            area += self.xym.prob(ray, sampler);
         } else {
-            area += self.xyp.prob(ray)
+            area += self.xyp.prob(ray, sampler)
         }
         area
     }
 
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
-        let face = rand_u32(0, 3);
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let face = sampler.u32(0, 3);
         let to_self = self.calc_origin() - origin;
         if face == 0 {
             if to_self.x >= 0.0 {
-                self.yzm.generate_ray_in_area(origin, time)
+                self.yzm.generate_ray_in_area(origin, time, sampler)
             } else {
-                self.yzp.generate_ray_in_area(origin, time)
+                self.yzp.generate_ray_in_area(origin, time, sampler)
             }
         } else if face == 1 {
             if to_self.y >= 0.0 {
-                self.xzm.generate_ray_in_area(origin, time)
+                self.xzm.generate_ray_in_area(origin, time, sampler)
             } else {
-                self.xzp.generate_ray_in_area(origin, time)
+                self.xzp.generate_ray_in_area(origin, time, sampler)
             }
         } else if face == 2 {
             if to_self.z >= 0.0 {
-                self.xym.generate_ray_in_area(origin, time)
+                self.xym.generate_ray_in_area(origin, time, sampler)
             } else {
-                self.xyp.generate_ray_in_area(origin, time)
+                self.xyp.generate_ray_in_area(origin, time, sampler)
             }
         } else {
             panic!()
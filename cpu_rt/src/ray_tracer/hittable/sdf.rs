@@ -0,0 +1,565 @@
+use super::{volume_prob, volume_ray, Aabb, HitRecord, Hittable, Material, RayAreaInfo};
+use crate::prelude::*;
+use cgmath::{prelude::*, Point2, Point3, Vector2, Vector3};
+use dyn_clone::clone_box;
+use std::ops::Deref;
+
+/// Signed distance field, negative inside of the shape. Twists and fractals can overestimate the
+/// distance, `SdfShape::with_step_scale` shortens the steps to not pass through the surface
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sdf {
+    Sphere {
+        radius: RayScalar,
+    },
+    /// box centered on the origin
+    Cuboid {
+        half_size: Vector3<RayScalar>,
+    },
+    /// torus around the y axis
+    Torus {
+        major_radius: RayScalar,
+        minor_radius: RayScalar,
+    },
+    /// line segment with rounded ends
+    Capsule {
+        start: Point3<RayScalar>,
+        end: Point3<RayScalar>,
+        radius: RayScalar,
+    },
+    /// closed cylinder along the y axis
+    Cylinder {
+        radius: RayScalar,
+        half_height: RayScalar,
+    },
+    /// everything below the plane `normal · p = offset`
+    Plane {
+        normal: Vector3<RayScalar>,
+        offset: RayScalar,
+    },
+    /// mandelbulb fractal, fits in a sphere of radius 1.2 for a power of 8
+    Mandelbulb {
+        power: RayScalar,
+        iterations: usize,
+    },
+    Translate {
+        shape: Box<Sdf>,
+        offset: Vector3<RayScalar>,
+    },
+    /// union with the seam rounded over `smoothness`
+    SmoothUnion {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: RayScalar,
+    },
+    /// `a` with `b` cut out of it
+    SmoothSubtraction {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: RayScalar,
+    },
+    SmoothIntersection {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: RayScalar,
+    },
+    /// infinite copies of the shape, axes with a period of zero are not repeated
+    Repeat {
+        shape: Box<Sdf>,
+        period: Vector3<RayScalar>,
+    },
+    /// rotates the shape around the y axis by `rate` radians per unit of height
+    Twist {
+        shape: Box<Sdf>,
+        rate: RayScalar,
+    },
+}
+impl Sdf {
+    pub fn translate(self, offset: Vector3<RayScalar>) -> Self {
+        Self::Translate {
+            shape: Box::new(self),
+            offset,
+        }
+    }
+    pub fn smooth_union(self, other: Self, smoothness: RayScalar) -> Self {
+        Self::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+    pub fn smooth_subtraction(self, other: Self, smoothness: RayScalar) -> Self {
+        Self::SmoothSubtraction {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+    pub fn smooth_intersection(self, other: Self, smoothness: RayScalar) -> Self {
+        Self::SmoothIntersection {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+    pub fn repeat(self, period: Vector3<RayScalar>) -> Self {
+        Self::Repeat {
+            shape: Box::new(self),
+            period,
+        }
+    }
+    pub fn twist(self, rate: RayScalar) -> Self {
+        Self::Twist {
+            shape: Box::new(self),
+            rate,
+        }
+    }
+    pub fn distance(&self, point: Point3<RayScalar>) -> RayScalar {
+        match self {
+            Self::Sphere { radius } => point.to_vec().magnitude() - radius,
+            Self::Cuboid { half_size } => {
+                let q = point.to_vec().map(RayScalar::abs) - half_size;
+                let outside = q.map(|v| v.max(0.0)).magnitude();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            Self::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let q = Vector2::new(
+                    Vector2::new(point.x, point.z).magnitude() - major_radius,
+                    point.y,
+                );
+                q.magnitude() - minor_radius
+            }
+            Self::Capsule { start, end, radius } => {
+                let to_point = point - start;
+                let segment = end - start;
+                let h = (to_point.dot(segment) / segment.magnitude2()).clamp(0.0, 1.0);
+                (to_point - segment * h).magnitude() - radius
+            }
+            Self::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let d = Vector2::new(
+                    Vector2::new(point.x, point.z).magnitude() - radius,
+                    point.y.abs() - half_height,
+                );
+                d.x.max(d.y).min(0.0) + d.map(|v| v.max(0.0)).magnitude()
+            }
+            Self::Plane { normal, offset } => point.to_vec().dot(normal.normalize()) - offset,
+            Self::Mandelbulb { power, iterations } => mandelbulb(point, *power, *iterations),
+            Self::Translate { shape, offset } => shape.distance(point - offset),
+            Self::SmoothUnion { a, b, smoothness } => {
+                let (a, b) = (a.distance(point), b.distance(point));
+                if *smoothness <= 0.0 {
+                    return a.min(b);
+                }
+                let h = (0.5 + 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+                lerp(b, a, h) - smoothness * h * (1.0 - h)
+            }
+            Self::SmoothSubtraction { a, b, smoothness } => {
+                let (a, b) = (a.distance(point), b.distance(point));
+                if *smoothness <= 0.0 {
+                    return a.max(-b);
+                }
+                let h = (0.5 - 0.5 * (a + b) / smoothness).clamp(0.0, 1.0);
+                lerp(a, -b, h) + smoothness * h * (1.0 - h)
+            }
+            Self::SmoothIntersection { a, b, smoothness } => {
+                let (a, b) = (a.distance(point), b.distance(point));
+                if *smoothness <= 0.0 {
+                    return a.max(b);
+                }
+                let h = (0.5 - 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+                lerp(b, a, h) + smoothness * h * (1.0 - h)
+            }
+            Self::Repeat { shape, period } => {
+                let repeated = point.to_vec().zip(*period, |p, period| {
+                    if period > 0.0 {
+                        p - period * (p / period).round()
+                    } else {
+                        p
+                    }
+                });
+                shape.distance(Point3::from_vec(repeated))
+            }
+            Self::Twist { shape, rate } => {
+                let (sin, cos) = (rate * point.y).sin_cos();
+                shape.distance(Point3::new(
+                    cos * point.x - sin * point.z,
+                    point.y,
+                    sin * point.x + cos * point.z,
+                ))
+            }
+        }
+    }
+    /// direction the distance grows fastest in, found with the tetrahedron of samples around the
+    /// point
+    pub fn gradient(&self, point: Point3<RayScalar>, step: RayScalar) -> Vector3<RayScalar> {
+        [
+            Vector3::new(1.0, -1.0, -1.0),
+            Vector3::new(-1.0, -1.0, 1.0),
+            Vector3::new(-1.0, 1.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .map(|k| k * self.distance(point + k * step))
+        .sum::<Vector3<RayScalar>>()
+        .normalize()
+    }
+}
+fn lerp(a: RayScalar, b: RayScalar, t: RayScalar) -> RayScalar {
+    a + (b - a) * t
+}
+/// distance estimate of the mandelbulb from the rate the iteration escapes at
+fn mandelbulb(point: Point3<RayScalar>, power: RayScalar, iterations: usize) -> RayScalar {
+    const ESCAPE_RADIUS: RayScalar = 2.0;
+    let c = point.to_vec();
+    let mut z = c;
+    let mut derivative = 1.0;
+    let mut r = z.magnitude();
+    for _ in 0..iterations {
+        if r > ESCAPE_RADIUS || r == 0.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        derivative = r.powf(power - 1.0) * power * derivative + 1.0;
+        z = r.powf(power)
+            * Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            )
+            + c;
+        r = z.magnitude();
+    }
+    if r == 0.0 {
+        // the origin never escapes
+        return 0.0;
+    }
+    0.5 * r.ln() * r / derivative
+}
+/// Shape defined by a signed distance field, found by sphere tracing: the ray steps forward by
+/// the distance to the closest surface until it is within `epsilon` of it. The field is only
+/// marched inside of `bounds`, which must contain the whole surface
+pub struct SdfShape {
+    sdf: Sdf,
+    bounds: Aabb,
+    material: Box<dyn Material>,
+    epsilon: RayScalar,
+    /// fraction of the distance stepped, below 1 for fields that overestimate the distance like
+    /// twists and fractals
+    step_scale: RayScalar,
+    max_steps: usize,
+}
+impl Clone for SdfShape {
+    fn clone(&self) -> Self {
+        Self {
+            sdf: self.sdf.clone(),
+            bounds: self.bounds,
+            material: clone_box(self.material.deref()),
+            epsilon: self.epsilon,
+            step_scale: self.step_scale,
+            max_steps: self.max_steps,
+        }
+    }
+}
+impl SdfShape {
+    pub fn new(sdf: Sdf, bounds: Aabb, material: Box<dyn Material>) -> Self {
+        Self {
+            sdf,
+            bounds,
+            material,
+            epsilon: 0.0001,
+            step_scale: 1.0,
+            max_steps: 512,
+        }
+    }
+    pub fn with_epsilon(mut self, epsilon: RayScalar) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+    pub fn with_step_scale(mut self, step_scale: RayScalar) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+}
+impl Hittable for SdfShape {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (mut t, t_end) = self.bounds.ray_interval(*ray, t_min, t_max)?;
+        let speed = ray.direction.magnitude();
+        let start = self.sdf.distance(ray.at(t));
+        // a ray that starts on the surface, like a refracted ray, marches on the side it leaves to
+        let side = if start.abs() >= self.epsilon {
+            start.signum()
+        } else if ray
+            .direction
+            .dot(self.sdf.gradient(ray.at(t), self.epsilon))
+            > 0.0
+        {
+            1.0
+        } else {
+            -1.0
+        };
+        let mut left_surface = start.abs() >= self.epsilon;
+        for _ in 0..self.max_steps {
+            let distance = side * self.sdf.distance(ray.at(t));
+            if distance < self.epsilon {
+                if left_surface {
+                    let position = ray.at(t);
+                    return Some(HitRecord::new(
+                        ray,
+                        position,
+                        self.sdf.gradient(position, self.epsilon),
+                        t,
+                        Point2::origin(),
+                        self.material.as_ref(),
+                        sampler,
+                    ));
+                }
+            } else {
+                left_surface = true;
+            }
+            t += distance.max(self.epsilon) * self.step_scale / speed;
+            if t > t_end {
+                return None;
+            }
+        }
+        None
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+    /// the area of the surface is not known so lights are sampled through the volume of the bounds
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        volume_prob(&ray, &self.bounds)
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        volume_ray(origin, time, &self.bounds, sampler)
+    }
+    fn name(&self) -> String {
+        "SDF Shape".to_string()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        hittable::Sphere,
+        material::Lambertian,
+        pdf::{
+            test::{check_pdf, light_world},
+            LightPdf,
+        },
+        texture::SolidColor,
+    };
+    use std::f64::consts::PI;
+
+    fn material() -> Box<dyn Material> {
+        Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::WHITE,
+            }),
+        })
+    }
+    fn cube_bounds(half_size: RayScalar) -> Aabb {
+        Aabb {
+            minimum: Point3::new(-half_size, -half_size, -half_size),
+            maximum: Point3::new(half_size, half_size, half_size),
+        }
+    }
+    fn assert_near(a: RayScalar, b: RayScalar) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+    #[test]
+    fn primitives() {
+        let p = |x, y, z| Point3::new(x, y, z);
+        assert_near(Sdf::Sphere { radius: 1.0 }.distance(p(0.0, 3.0, 0.0)), 2.0);
+        let cuboid = Sdf::Cuboid {
+            half_size: Vector3::new(1.0, 2.0, 3.0),
+        };
+        assert_near(cuboid.distance(p(0.0, 0.0, 0.0)), -1.0);
+        assert_near(cuboid.distance(p(4.0, 6.0, 0.0)), 5.0);
+        let torus = Sdf::Torus {
+            major_radius: 2.0,
+            minor_radius: 0.5,
+        };
+        assert_near(torus.distance(p(0.0, 0.0, 2.0)), -0.5);
+        assert_near(torus.distance(p(0.0, 0.0, 0.0)), 1.5);
+        let capsule = Sdf::Capsule {
+            start: p(0.0, 0.0, 0.0),
+            end: p(0.0, 2.0, 0.0),
+            radius: 0.5,
+        };
+        assert_near(capsule.distance(p(1.0, 1.0, 0.0)), 0.5);
+        assert_near(capsule.distance(p(0.0, 3.0, 0.0)), 0.5);
+        let cylinder = Sdf::Cylinder {
+            radius: 1.0,
+            half_height: 1.0,
+        };
+        assert_near(cylinder.distance(p(0.0, 3.0, 0.0)), 2.0);
+        assert_near(cylinder.distance(p(4.0, 5.0, 0.0)), 5.0);
+        let plane = Sdf::Plane {
+            normal: Vector3::new(0.0, 2.0, 0.0),
+            offset: 1.0,
+        };
+        assert_near(plane.distance(p(5.0, 3.0, 5.0)), 2.0);
+    }
+    #[test]
+    fn operators() {
+        let sphere = |x| Sdf::Sphere { radius: 1.0 }.translate(Vector3::new(x, 0.0, 0.0));
+        let point = Point3::new(0.0, 1.0, 0.0);
+        let (a, b) = (sphere(-1.0).distance(point), sphere(1.0).distance(point));
+        // hard operators without smoothing
+        assert_near(
+            sphere(-1.0).smooth_union(sphere(1.0), 0.0).distance(point),
+            a.min(b),
+        );
+        assert_near(
+            sphere(-1.0)
+                .smooth_intersection(sphere(1.0), 0.0)
+                .distance(point),
+            a.max(b),
+        );
+        assert_near(
+            sphere(-1.0)
+                .smooth_subtraction(sphere(1.0), 0.0)
+                .distance(point),
+            a.max(-b),
+        );
+        // smoothing only adds material to a union and removes it from an intersection
+        let smooth_union = sphere(-1.0).smooth_union(sphere(1.0), 0.5).distance(point);
+        assert!(smooth_union < a.min(b));
+        let smooth_intersection = sphere(-1.0)
+            .smooth_intersection(sphere(1.0), 0.5)
+            .distance(point);
+        assert!(smooth_intersection > a.max(b));
+        // far from the seam the smooth union is exact
+        assert_near(
+            sphere(-1.0)
+                .smooth_union(sphere(1.0), 0.5)
+                .distance(Point3::new(-5.0, 0.0, 0.0)),
+            3.0,
+        );
+
+        let repeated = Sdf::Sphere { radius: 0.5 }.repeat(Vector3::new(2.0, 0.0, 2.0));
+        assert_near(repeated.distance(Point3::new(4.0, 0.0, -6.0)), -0.5);
+        assert_near(repeated.distance(Point3::new(4.0, 3.0, -6.0)), 2.5);
+
+        let box_shape = Sdf::Cuboid {
+            half_size: Vector3::new(1.0, 10.0, 0.1),
+        };
+        let twisted = box_shape.clone().twist(PI / 2.0);
+        // a quarter turn up the box has rotated onto the x axis
+        let point = Point3::new(0.0, 1.0, 0.9);
+        assert_near(
+            twisted.distance(point),
+            box_shape.distance(Point3::new(0.9, 1.0, 0.0)),
+        );
+    }
+    #[test]
+    fn mandelbulb() {
+        let bulb = Sdf::Mandelbulb {
+            power: 8.0,
+            iterations: 16,
+        };
+        assert!(bulb.distance(Point3::new(0.0, 0.0, 0.0)) <= 0.0);
+        let far = bulb.distance(Point3::new(3.0, 0.0, 0.0));
+        // the estimate is a lower bound of the distance to the fractal inside of radius 1.2
+        assert!(far > 0.0 && far < 3.0, "{}", far);
+    }
+    /// a sphere traced through the field matches the analytic sphere
+    #[test]
+    fn matches_sphere() {
+        let shape = SdfShape::new(Sdf::Sphere { radius: 1.0 }, cube_bounds(1.0), material());
+        let sphere = Sphere {
+            radius: 1.0,
+            origin: Point3::new(0.0, 0.0, 0.0),
+            material: material(),
+        };
+        let mut sampler = Sampler::new(0);
+        for i in 0..100 {
+            let ray = Ray {
+                origin: Point3::new(-5.0, 0.0, 0.0),
+                direction: Vector3::new(5.0, -0.9 + 0.018 * i as RayScalar, 0.3),
+                time: 0.0,
+            };
+            let expected = sphere.hit(&ray, 0.001, RayScalar::MAX, &mut sampler);
+            let hit = shape.hit(&ray, 0.001, RayScalar::MAX, &mut sampler);
+            match (hit, expected) {
+                (Some(hit), Some(expected)) => {
+                    assert!((hit.position - expected.position).magnitude() < 0.001);
+                    assert!((hit.normal - expected.normal).magnitude() < 0.001);
+                    assert!(hit.front_face);
+                }
+                (None, None) => {}
+                (hit, expected) => panic!(
+                    "hit: {:?}, expected: {:?}",
+                    hit.map(|h| h.position),
+                    expected.map(|h| h.position)
+                ),
+            }
+        }
+    }
+    /// a ray continuing from the surface into the shape finds the far side
+    #[test]
+    fn from_surface() {
+        let shape = SdfShape::new(Sdf::Sphere { radius: 1.0 }, cube_bounds(1.0), material());
+        let mut sampler = Sampler::new(0);
+        let ray = Ray {
+            origin: Point3::new(-1.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let hit = shape
+            .hit(&ray, 0.00001, RayScalar::MAX, &mut sampler)
+            .unwrap();
+        assert!((hit.position.x - 1.0).abs() < 0.001);
+        assert!(!hit.front_face);
+        assert!((hit.normal - Vector3::unit_x()).magnitude() < 0.001);
+        // leaving the sphere there is nothing else
+        let ray = Ray {
+            origin: Point3::new(1.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        assert!(shape
+            .hit(&ray, 0.00001, RayScalar::MAX, &mut sampler)
+            .is_none());
+        assert!(shape.hit(&ray, 0.00001, 0.5, &mut sampler).is_none());
+    }
+    #[test]
+    fn light_pdf() {
+        let offset = Vector3::new(0.5, 0.0, 3.0);
+        let shape = SdfShape::new(
+            Sdf::Sphere { radius: 1.0 }.translate(offset),
+            Aabb {
+                minimum: Point3::new(-1.0, -1.0, -1.0) + offset,
+                maximum: Point3::new(1.0, 1.0, 1.0) + offset,
+            },
+            material(),
+        );
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(shape)),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+}
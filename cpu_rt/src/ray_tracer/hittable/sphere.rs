@@ -2,8 +2,8 @@ use super::{Aabb, HitRecord, Hittable, Material};
 use std::collections::HashMap;
 
 use crate::{
-    prelude::{Ray, RayScalar},
-    ray_tracer::{hittable::RayAreaInfo, rand_unit_vec, ray_tracer_info::EntityField},
+    prelude::{OrthoNormalBasis, Ray, RayScalar, Sampler},
+    ray_tracer::{hittable::RayAreaInfo, ray_tracer_info::EntityField},
 };
 
 use cgmath::{num_traits::FloatConst, prelude::*, Point2, Point3, Vector3};
@@ -25,24 +25,14 @@ impl Clone for Sphere {
     }
 }
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
-        let rel_origin = ray.origin - self.origin;
-        let a = ray.direction.dot(ray.direction);
-        let half_b = rel_origin.dot(ray.direction);
-        let c = rel_origin.dot(rel_origin) - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return None;
-        }
-        let sqrt_d = discriminant.sqrt();
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrt_d) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
-        }
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let root = self.nearest_root(ray, t_min, t_max)?;
         let position = ray.at(root);
         let uv = Self::get_sphere_uv((position - self.origin) / self.radius);
         Some(HitRecord::new(
@@ -52,6 +42,7 @@ impl Hittable for Sphere {
             root,
             uv,
             self.material.as_ref(),
+            sampler,
         ))
     }
     fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
@@ -60,27 +51,72 @@ impl Hittable for Sphere {
             maximum: self.origin + Vector3::new(self.radius, self.radius, self.radius),
         })
     }
-    fn prob(&self, ray: Ray) -> RayScalar {
-        let area = RayScalar::PI() * self.radius.powi(2);
-        let to_light = self.origin - ray.origin;
-
-        let distance_squared = to_light.dot(to_light);
-
-        distance_squared / area
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        let t = match self.nearest_root(&ray, 0.0, RayScalar::MAX) {
+            Some(t) => t,
+            None => return 0.0,
+        };
+        match self.cone_cos(ray.origin) {
+            Some(max_cos) => 1.0 / (2.0 * RayScalar::PI() * (1.0 - max_cos)),
+            None => {
+                let to_light = ray.at(t) - ray.origin;
+                let normal = (ray.at(t) - self.origin) / self.radius;
+                let cos_alpha = to_light.normalize().dot(normal).abs();
+                if cos_alpha < 0.00001 {
+                    return 0.0;
+                }
+                to_light.magnitude2() / (cos_alpha * self.area())
+            }
+        }
     }
-
-    fn generate_ray_in_area(&self, origin: Point3<RayScalar>, time: RayScalar) -> RayAreaInfo {
-        let sphere_direction = rand_unit_vec();
-        let end_point = self.origin + self.radius * sphere_direction;
-        let direction = (end_point - origin).normalize();
+    /// From outside picks a direction uniformly in the cone of directions that hit the sphere,
+    /// from inside picks a point uniformly on the sphere
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let (end_point, area) = match self.cone_cos(origin) {
+            Some(max_cos) => {
+                let cos = max_cos + sampler.next_scalar() * (1.0 - max_cos);
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * RayScalar::PI() * sampler.next_scalar();
+                let direction = OrthoNormalBasis::build_from_w(self.origin - origin)
+                    .local(Vector3::new(sin * phi.cos(), sin * phi.sin(), cos))
+                    .normalize();
+                let ray = Ray {
+                    origin,
+                    direction,
+                    time,
+                };
+                // directions on the edge of the cone can miss because of rounding, they touch the
+                // sphere at the point closest to the center
+                let t = self
+                    .nearest_root(&ray, 0.0, RayScalar::MAX)
+                    .unwrap_or_else(|| (self.origin - origin).dot(direction));
+                let end_point = ray.at(t);
+                let normal = (end_point - self.origin).normalize();
+                let cos_alpha = direction.dot(normal).abs().max(0.00001);
+                // area that would give the same probability per solid angle if it was sampled
+                // uniformly
+                let solid_angle = 2.0 * RayScalar::PI() * (1.0 - max_cos);
+                (end_point, solid_angle * t * t / cos_alpha)
+            }
+            None => (
+                self.origin + self.radius * sampler.unit_sphere(),
+                self.area(),
+            ),
+        };
+        let normal = (end_point - self.origin) / self.radius;
         RayAreaInfo {
             to_area: Ray {
                 origin,
-                direction,
+                direction: (end_point - origin).normalize(),
                 time,
             },
-            normal: sphere_direction,
-            area: self.area(),
+            normal,
+            area,
             direction: end_point - origin,
             end_point,
         }
@@ -105,8 +141,40 @@ impl Hittable for Sphere {
 }
 
 impl Sphere {
+    /// gets the distance along the ray to the closest intersection in [t_min, t_max]
+    fn nearest_root(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<RayScalar> {
+        let rel_origin = ray.origin - self.origin;
+        let a = ray.direction.dot(ray.direction);
+        let half_b = rel_origin.dot(ray.direction);
+        let c = rel_origin.dot(rel_origin) - self.radius * self.radius;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrt_d = discriminant.sqrt();
+        let mut root = (-half_b - sqrt_d) / a;
+        if root < t_min || t_max < root {
+            root = (-half_b + sqrt_d) / a;
+            if root < t_min || t_max < root {
+                return None;
+            }
+        }
+        Some(root)
+    }
+    /// Cosine of the angle between the direction to the center and the edge of the sphere as
+    /// seen from the viewer. None if the viewer is inside of the sphere
+    fn cone_cos(&self, viewer: Point3<RayScalar>) -> Option<RayScalar> {
+        let distance_squared = (viewer - self.origin).magnitude2();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared > radius_squared {
+            Some((1.0 - radius_squared / distance_squared).sqrt())
+        } else {
+            None
+        }
+    }
     fn area(&self) -> RayScalar {
-        RayScalar::PI() * self.radius.powi(2)
+        4.0 * RayScalar::PI() * self.radius.powi(2)
     }
     fn get_sphere_uv(point: Vector3<RayScalar>) -> Point2<RayScalar> {
         let theta = (-point.y).acos();
@@ -142,7 +210,13 @@ impl MovingSphere {
     }
 }
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: RayScalar, t_max: RayScalar) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
         let rel_origin = ray.origin - self.center(ray.time);
         let a = ray.direction.dot(ray.direction);
         let half_b = rel_origin.dot(ray.direction);
@@ -169,6 +243,7 @@ impl Hittable for MovingSphere {
             root,
             Sphere::get_sphere_uv(normal),
             self.material.as_ref(),
+            sampler,
         ))
     }
 
@@ -184,10 +259,15 @@ impl Hittable for MovingSphere {
             }),
         )
     }
-    fn prob(&self, _ray: Ray) -> RayScalar {
+    fn prob(&self, _ray: Ray, _sampler: &mut Sampler) -> RayScalar {
         todo!()
     }
-    fn generate_ray_in_area(&self, _origin: Point3<RayScalar>, _time: RayScalar) -> RayAreaInfo {
+    fn generate_ray_in_area(
+        &self,
+        _origin: Point3<RayScalar>,
+        _time: RayScalar,
+        _sampler: &mut Sampler,
+    ) -> RayAreaInfo {
         todo!()
     }
     fn name(&self) -> String {
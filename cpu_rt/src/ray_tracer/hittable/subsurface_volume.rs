@@ -0,0 +1,188 @@
+use super::{super::material::Subsurface, Aabb, HitRecord, Hittable, MaterialEffect, RayAreaInfo};
+use crate::{prelude::*, ray_tracer::pdf::ScatterRecord};
+use cgmath::{prelude::*, Point2, Point3, Vector3};
+use dyn_clone::clone_box;
+use std::ops::Deref;
+
+/// Shape filled with a `Subsurface` material. Light that refracts into the shape takes a random
+/// walk through the medium until it is absorbed or leaves through the surface, the material of
+/// the boundary is not used. Other objects inside of the shape are not dimmed by the medium
+pub struct SubsurfaceVolume {
+    boundary: Box<dyn Hittable>,
+    material: Subsurface,
+}
+impl Clone for SubsurfaceVolume {
+    fn clone(&self) -> Self {
+        Self {
+            boundary: clone_box(self.boundary.deref()),
+            material: self.material.clone(),
+        }
+    }
+}
+impl SubsurfaceVolume {
+    pub fn new(boundary: Box<dyn Hittable>, material: Subsurface) -> Self {
+        Self { boundary, material }
+    }
+    /// scattering event in the medium, the distance was sampled in proportion to the scattering
+    /// so the light is only weighted by the albedo
+    fn scatter_inside(&self, ray: &Ray, t: RayScalar, sampler: &mut Sampler) -> HitRecord {
+        let position = ray.at(t);
+        let scatter_record = ScatterRecord {
+            specular_ray: Some(Ray {
+                origin: position,
+                direction: self.material.phase_function.sample(ray.direction, sampler),
+                time: ray.time,
+            }),
+            attenuation: self.material.albedo.color(Point2::origin(), position),
+            pdf: None,
+            scattering_pdf: |_, _, _| panic!("scattering in the medium is specular"),
+        };
+        HitRecord {
+            position,
+            normal: Vector3::unit_x(),
+            t,
+            front_face: false,
+            uv: Point2::origin(),
+            material_effect: MaterialEffect::Scatter(scatter_record),
+        }
+    }
+}
+impl Hittable for SubsurfaceVolume {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        // the next surface is found even past `t_max` to know if the ray starts inside
+        let surface = self.boundary.hit(ray, t_min, RayScalar::MAX, sampler)?;
+        let surface_hit = |sampler: &mut Sampler| {
+            HitRecord::new(
+                ray,
+                surface.position,
+                surface.normal,
+                surface.t,
+                surface.uv,
+                &self.material,
+                sampler,
+            )
+        };
+        if surface.front_face {
+            return (surface.t <= t_max).then(|| surface_hit(sampler));
+        }
+        let distance = -(1.0 - sampler.next_scalar()).ln() * self.material.mean_free_path;
+        let t = t_min + distance / ray.direction.magnitude();
+        if t < surface.t.min(t_max) {
+            Some(self.scatter_inside(ray, t, sampler))
+        } else if surface.t <= t_max {
+            Some(surface_hit(sampler))
+        } else {
+            None
+        }
+    }
+    fn bounding_box(&self, time_0: RayScalar, time_1: RayScalar) -> Option<Aabb> {
+        self.boundary.bounding_box(time_0, time_1)
+    }
+    fn prob(&self, ray: Ray, sampler: &mut Sampler) -> RayScalar {
+        self.boundary.prob(ray, sampler)
+    }
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        self.boundary.generate_ray_in_area(origin, time, sampler)
+    }
+    fn name(&self) -> String {
+        "Subsurface Volume".to_string()
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        hittable::Sphere, material::Lambertian, phase_function::PhaseFunction, texture::SolidColor,
+    };
+    fn unit_sphere(
+        albedo: RgbColor,
+        mean_free_path: RayScalar,
+        index_refraction: RayScalar,
+    ) -> SubsurfaceVolume {
+        SubsurfaceVolume::new(
+            Box::new(Sphere {
+                radius: 1.0,
+                origin: Point3::new(0.0, 0.0, 0.0),
+                material: Box::new(Lambertian {
+                    albedo: Box::new(SolidColor {
+                        color: RgbColor::WHITE,
+                    }),
+                }),
+            }),
+            Subsurface {
+                albedo: Box::new(SolidColor { color: albedo }),
+                mean_free_path,
+                index_refraction,
+                phase_function: PhaseFunction::HenyeyGreenstein { g: 0.3 },
+            },
+        )
+    }
+    /// average light carried by rays shot at the center of the sphere once they leave it
+    fn throughput(volume: &SubsurfaceVolume, num_samples: usize) -> RgbColor {
+        let mut sampler = Sampler::new(0);
+        let mut sum = RgbColor::BLACK;
+        for _ in 0..num_samples {
+            let mut ray = Ray {
+                origin: Point3::new(-5.0, 0.0, 0.0),
+                direction: Vector3::new(1.0, 0.0, 0.0),
+                time: 0.0,
+            };
+            let mut throughput = RgbColor::WHITE;
+            for _ in 0..10_000 {
+                match volume.hit(&ray, 0.0001, RayScalar::MAX, &mut sampler) {
+                    Some(HitRecord {
+                        material_effect: MaterialEffect::Scatter(scatter_record),
+                        ..
+                    }) => {
+                        throughput = throughput * scatter_record.attenuation;
+                        ray = scatter_record.specular_ray.unwrap();
+                    }
+                    Some(_) => panic!("subsurface volume only scatters"),
+                    None => {
+                        sum += throughput;
+                        break;
+                    }
+                }
+            }
+        }
+        sum / num_samples as f32
+    }
+    /// nothing is lost if the medium does not absorb, however often the light scatters
+    #[test]
+    fn energy_conservation() {
+        for mean_free_path in [0.05, 0.5, 5.0] {
+            let volume = unit_sphere(RgbColor::WHITE, mean_free_path, 1.4);
+            assert_eq!(throughput(&volume, 1_000), RgbColor::WHITE);
+        }
+    }
+    /// Light that scatters is colored by the albedo each time, the light that goes straight
+    /// through follows Beer's law
+    #[test]
+    fn absorption() {
+        let volume = unit_sphere(RgbColor::BLACK, 0.5, 1.0);
+        let straight = throughput(&volume, 20_000);
+        let expected = (-2.0f32 / 0.5).exp();
+        assert!(
+            straight.distance(RgbColor::WHITE * expected) < 0.01,
+            "throughput: {}, expected: {}",
+            straight,
+            expected
+        );
+        // every channel passes with the same probability but is dimmed differently
+        let volume = unit_sphere(RgbColor::new(0.9, 0.5, 0.1), 0.2, 1.4);
+        let colored = throughput(&volume, 5_000);
+        assert!(colored.red > colored.green && colored.green > colored.blue);
+        assert!(colored.red < 0.9 && colored.blue > 0.0, "{}", colored);
+    }
+}
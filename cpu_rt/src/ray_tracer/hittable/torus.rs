@@ -0,0 +1,335 @@
+use super::{surface_prob, Aabb, HitRecord, Hittable, Material, RayAreaInfo};
+use crate::{prelude::*, ray_tracer::ray_tracer_info::EntityField};
+use cgmath::{num_traits::FloatConst, prelude::*, Point2, Point3, Vector3};
+use dyn_clone::clone_box;
+use std::{collections::HashMap, ops::Deref};
+
+fn evaluate(coefficients: &[RayScalar], x: RayScalar) -> RayScalar {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0.0, |sum, coefficient| sum * x + coefficient)
+}
+/// root in [min, max] if the polynomial changes sign between them
+fn bisect(coefficients: &[RayScalar], min: RayScalar, max: RayScalar) -> Option<RayScalar> {
+    const ITERATIONS: usize = 64;
+    let (mut low, mut high) = (min, max);
+    let low_negative = evaluate(coefficients, low) < 0.0;
+    if low_negative == (evaluate(coefficients, high) < 0.0) {
+        return None;
+    }
+    for _ in 0..ITERATIONS {
+        let middle = 0.5 * (low + high);
+        if (evaluate(coefficients, middle) < 0.0) == low_negative {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Some(0.5 * (low + high))
+}
+/// Real roots of the polynomial in [min, max] in increasing order, the coefficients start with the
+/// constant term. The polynomial is split where it turns around, at the roots of its derivative,
+/// so each part has at most one root. Roots where the polynomial only touches zero are skipped
+fn polynomial_roots(coefficients: &[RayScalar], min: RayScalar, max: RayScalar) -> Vec<RayScalar> {
+    match coefficients.len() {
+        0 | 1 => vec![],
+        2 => bisect(coefficients, min, max).into_iter().collect(),
+        _ => {
+            let derivative = coefficients
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(power, coefficient)| power as RayScalar * coefficient)
+                .collect::<Vec<_>>();
+            let mut bounds = vec![min];
+            bounds.extend(polynomial_roots(&derivative, min, max));
+            bounds.push(max);
+            bounds
+                .windows(2)
+                .filter_map(|part| bisect(coefficients, part[0], part[1]))
+                .collect()
+        }
+    }
+}
+/// Ring around `axis` through `center`. The tube of `minor_radius` goes around a circle of
+/// `major_radius`, which should be the larger one. The uv coordinates are the angle around the
+/// axis and the angle around the tube
+pub struct Torus {
+    pub center: Point3<RayScalar>,
+    pub axis: Vector3<RayScalar>,
+    pub major_radius: RayScalar,
+    pub minor_radius: RayScalar,
+    pub material: Box<dyn Material>,
+}
+impl Clone for Torus {
+    fn clone(&self) -> Self {
+        Self {
+            center: self.center,
+            axis: self.axis,
+            major_radius: self.major_radius,
+            minor_radius: self.minor_radius,
+            material: clone_box(self.material.deref()),
+        }
+    }
+}
+impl Torus {
+    fn area(&self) -> RayScalar {
+        4.0 * RayScalar::PI().powi(2) * self.major_radius * self.minor_radius
+    }
+    /// normal and uv at a point in the frame of the torus, where the axis is z
+    fn surface(&self, point: Vector3<RayScalar>) -> (Vector3<RayScalar>, Point2<RayScalar>) {
+        let around_axis = point.y.atan2(point.x);
+        let ring = self.major_radius * Vector3::new(around_axis.cos(), around_axis.sin(), 0.0);
+        let normal = (point - ring).normalize();
+        let around_tube = point
+            .z
+            .atan2((point.x * point.x + point.y * point.y).sqrt() - self.major_radius);
+        let uv = Point2::new(
+            (around_axis / (2.0 * RayScalar::PI())).rem_euclid(1.0),
+            (around_tube / (2.0 * RayScalar::PI())).rem_euclid(1.0),
+        );
+        (normal, uv)
+    }
+    fn intersect(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+    ) -> Option<(RayScalar, Vector3<RayScalar>, Point2<RayScalar>)> {
+        let basis = OrthoNormalBasis::build_from_w(self.axis);
+        let origin = basis.coordinates(ray.origin - self.center);
+        let direction = basis.coordinates(ray.direction);
+        // only the part of the ray inside of the bounding sphere is searched and the origin is
+        // moved there so the coefficients stay small
+        let bound = self.major_radius + self.minor_radius;
+        let a = direction.magnitude2();
+        let half_b = origin.dot(direction);
+        let discriminant = half_b * half_b - a * (origin.magnitude2() - bound * bound);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrt_d = discriminant.sqrt();
+        let start = t_min.max((-half_b - sqrt_d) / a);
+        let end = t_max.min((-half_b + sqrt_d) / a);
+        if start > end {
+            return None;
+        }
+        let origin = origin + start * direction;
+        // (|p|² + R² - r²)² = 4 R² (x² + y²) expanded along the ray
+        let major_squared = self.major_radius.powi(2);
+        let e = origin.magnitude2() + major_squared - self.minor_radius.powi(2);
+        let f = origin.dot(direction);
+        let coefficients = [
+            e * e - 4.0 * major_squared * (origin.x * origin.x + origin.y * origin.y),
+            4.0 * f * e - 8.0 * major_squared * (origin.x * direction.x + origin.y * direction.y),
+            2.0 * a * e + 4.0 * f * f
+                - 4.0 * major_squared * (direction.x * direction.x + direction.y * direction.y),
+            4.0 * a * f,
+            a * a,
+        ];
+        let offset = *polynomial_roots(&coefficients, 0.0, end - start).first()?;
+        let (normal, uv) = self.surface(origin + offset * direction);
+        Some((start + offset, basis.local(normal), uv))
+    }
+}
+impl Hittable for Torus {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t_min: RayScalar,
+        t_max: RayScalar,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        let (t, normal, uv) = self.intersect(ray, t_min, t_max)?;
+        Some(HitRecord::new(
+            ray,
+            ray.at(t),
+            normal,
+            t,
+            uv,
+            self.material.as_ref(),
+            sampler,
+        ))
+    }
+    fn bounding_box(&self, _time_0: RayScalar, _time_1: RayScalar) -> Option<Aabb> {
+        let extent = self.axis.normalize().map(|a| {
+            (self.major_radius + self.minor_radius) * (1.0 - a * a).max(0.0).sqrt()
+                + self.minor_radius * a.abs()
+        });
+        Some(Aabb {
+            minimum: self.center - extent,
+            maximum: self.center + extent,
+        })
+    }
+    fn prob(&self, ray: Ray, _sampler: &mut Sampler) -> RayScalar {
+        surface_prob(&ray, self.area(), |t| {
+            self.intersect(&ray, t, RayScalar::MAX)
+                .map(|(t, normal, _)| (t, normal))
+        })
+    }
+    /// Picks a point uniformly on the surface. The outside of the ring has more area than the
+    /// inside, so angles around the tube are rejected in proportion to the distance from the axis
+    fn generate_ray_in_area(
+        &self,
+        origin: Point3<RayScalar>,
+        time: RayScalar,
+        sampler: &mut Sampler,
+    ) -> RayAreaInfo {
+        let bound = self.major_radius + self.minor_radius;
+        let around_tube = loop {
+            let angle = 2.0 * RayScalar::PI() * sampler.next_scalar();
+            let distance = self.major_radius + self.minor_radius * angle.cos();
+            if sampler.next_scalar() * bound <= distance {
+                break angle;
+            }
+        };
+        let around_axis = 2.0 * RayScalar::PI() * sampler.next_scalar();
+        let normal = Vector3::new(
+            around_tube.cos() * around_axis.cos(),
+            around_tube.cos() * around_axis.sin(),
+            around_tube.sin(),
+        );
+        let ring = self.major_radius * Vector3::new(around_axis.cos(), around_axis.sin(), 0.0);
+        let basis = OrthoNormalBasis::build_from_w(self.axis);
+        RayAreaInfo::to_point(
+            origin,
+            time,
+            self.center + basis.local(ring + self.minor_radius * normal),
+            basis.local(normal),
+            self.area(),
+        )
+    }
+    fn name(&self) -> String {
+        "Torus".to_string()
+    }
+    fn fields(&self) -> HashMap<String, EntityField> {
+        let mut map = HashMap::new();
+        map.insert("center".to_string(), EntityField::Point3(self.center));
+        map.insert(
+            "major radius".to_string(),
+            EntityField::Float(self.major_radius),
+        );
+        map.insert(
+            "minor radius".to_string(),
+            EntityField::Float(self.minor_radius),
+        );
+        map
+    }
+    fn set_field(&mut self, key: String, value: EntityField) {
+        match key.as_str() {
+            "center" => match value {
+                EntityField::Point3(p) => self.center = p,
+                _ => panic!("invalid value type"),
+            },
+            "major radius" => match value {
+                EntityField::Float(v) => self.major_radius = v,
+                _ => panic!("invalid value type"),
+            },
+            "minor radius" => match value {
+                EntityField::Float(v) => self.minor_radius = v,
+                _ => panic!("invalid value type"),
+            },
+            _ => panic!("invalid key name: {}", key),
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        material::DiffuseLight,
+        pdf::{
+            test::{check_pdf, light_world},
+            LightPdf,
+        },
+        texture::SolidColor,
+    };
+
+    fn torus(axis: Vector3<RayScalar>) -> Torus {
+        Torus {
+            center: Point3::new(0.5, 1.5, -0.5),
+            axis,
+            major_radius: 1.0,
+            minor_radius: 0.25,
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+            }),
+        }
+    }
+    #[test]
+    fn roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let roots = polynomial_roots(&[24.0, -50.0, 35.0, -10.0, 1.0], -10.0, 10.0);
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+        assert_eq!(
+            polynomial_roots(&[24.0, -50.0, 35.0, -10.0, 1.0], 1.5, 3.5).len(),
+            2
+        );
+        // x² + 1
+        assert!(polynomial_roots(&[1.0, 0.0, 1.0], -10.0, 10.0).is_empty());
+    }
+    /// a ray through the hole crosses the tube four times
+    #[test]
+    fn hit() {
+        let torus = torus(Vector3::new(0.0, 1.0, 0.0));
+        let mut sampler = Sampler::new(0);
+        let ray = Ray {
+            origin: torus.center + Vector3::new(-5.0, 0.0, 0.0),
+            direction: Vector3::new(2.0, 0.0, 0.0),
+            time: 0.0,
+        };
+        let mut t = 0.001;
+        let mut crossings = vec![];
+        while let Some(hit) = torus.hit(&ray, t, RayScalar::MAX, &mut sampler) {
+            t = hit.t + 0.001;
+            crossings.push(hit);
+        }
+        let expected = [-1.25, -0.75, 0.75, 1.25];
+        assert_eq!(crossings.len(), 4);
+        for (hit, x) in crossings.iter().zip(expected) {
+            assert!((hit.position.x - torus.center.x - x).abs() < 1e-9);
+            let normal_x = if x.abs() > 1.0 {
+                x.signum()
+            } else {
+                -x.signum()
+            };
+            assert!((hit.normal - Vector3::new(normal_x, 0.0, 0.0)).magnitude() < 1e-9);
+        }
+        assert!(crossings[0].front_face && !crossings[1].front_face);
+        // along the axis through the hole
+        let through_hole = Ray {
+            origin: torus.center + Vector3::new(0.0, -5.0, 0.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+            time: 0.0,
+        };
+        assert!(torus
+            .hit(&through_hole, 0.001, RayScalar::MAX, &mut sampler)
+            .is_none());
+    }
+    #[test]
+    fn bounding_box() {
+        let torus = torus(Vector3::new(0.3, 1.0, -0.4));
+        let bounds = torus.bounding_box(0.0, 0.0).unwrap();
+        let mut sampler = Sampler::new(0);
+        for _ in 0..1000 {
+            let point = torus
+                .generate_ray_in_area(Point3::origin(), 0.0, &mut sampler)
+                .end_point;
+            assert!(bounds.contains_point(point), "{:?}", point);
+        }
+    }
+    #[test]
+    fn light_pdf() {
+        check_pdf(
+            &LightPdf {},
+            &light_world(Box::new(torus(Vector3::new(0.3, 1.0, -0.4)))),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Decompression of Blosc frames, which OpenVDB uses by default. Only the LZ4 and zlib codecs
+//! are supported, LZ4 is the one OpenVDB picks
+use super::VdbError;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+const HEADER_SIZE: usize = 16;
+const FLAG_SHUFFLE: u8 = 0x1;
+const FLAG_MEMCPYED: u8 = 0x2;
+const FLAG_BIT_SHUFFLE: u8 = 0x4;
+const FLAG_DONT_SPLIT: u8 = 0x10;
+const CODEC_LZ4: u8 = 1;
+const CODEC_ZLIB: u8 = 3;
+/// blocks are only split into one stream per byte of the type if the streams are long enough
+const MAX_SPLITS: usize = 16;
+const MIN_SPLIT_SIZE: usize = 128;
+
+fn invalid(message: &str) -> VdbError {
+    VdbError::InvalidFile(format!("blosc: {}", message))
+}
+fn read_u32(bytes: &[u8], position: usize) -> Result<usize, VdbError> {
+    bytes
+        .get(position..position + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().expect("four bytes")) as usize)
+        .ok_or(VdbError::UnexpectedEnd)
+}
+pub(super) fn decompress(frame: &[u8], expected_size: usize) -> Result<Vec<u8>, VdbError> {
+    if frame.len() < HEADER_SIZE {
+        return Err(VdbError::UnexpectedEnd);
+    }
+    let flags = frame[2];
+    let type_size = (frame[3] as usize).max(1);
+    let size = read_u32(frame, 4)?;
+    let block_size = read_u32(frame, 8)?;
+    if size != expected_size {
+        return Err(invalid("frame has the wrong size"));
+    }
+    if flags & FLAG_MEMCPYED != 0 {
+        return frame
+            .get(HEADER_SIZE..HEADER_SIZE + size)
+            .map(|data| data.to_vec())
+            .ok_or(VdbError::UnexpectedEnd);
+    }
+    if flags & FLAG_BIT_SHUFFLE != 0 {
+        return Err(VdbError::Unsupported("blosc bit shuffle".to_string()));
+    }
+    let codec = flags >> 5;
+    if codec != CODEC_LZ4 && codec != CODEC_ZLIB {
+        return Err(VdbError::Unsupported(format!("blosc codec {}", codec)));
+    }
+    if block_size == 0 {
+        return Err(invalid("block size is zero"));
+    }
+    let block_count = size.div_ceil(block_size);
+    let mut output = Vec::with_capacity(size);
+    for block in 0..block_count {
+        let start = read_u32(frame, HEADER_SIZE + 4 * block)?;
+        let this_block_size = block_size.min(size - block * block_size);
+        let leftover = this_block_size != block_size;
+        let split_count = if flags & FLAG_DONT_SPLIT == 0
+            && !leftover
+            && type_size <= MAX_SPLITS
+            && this_block_size / type_size >= MIN_SPLIT_SIZE
+        {
+            type_size
+        } else {
+            1
+        };
+        let split_size = this_block_size / split_count;
+        let mut position = start;
+        let mut decompressed = Vec::with_capacity(this_block_size);
+        for _ in 0..split_count {
+            let compressed_size = read_u32(frame, position)?;
+            position += 4;
+            let compressed = frame
+                .get(position..position + compressed_size)
+                .ok_or(VdbError::UnexpectedEnd)?;
+            position += compressed_size;
+            if compressed_size == split_size {
+                decompressed.extend_from_slice(compressed);
+            } else if codec == CODEC_LZ4 {
+                decompressed.append(&mut lz4_decompress(compressed, split_size)?);
+            } else {
+                let start = decompressed.len();
+                ZlibDecoder::new(compressed)
+                    .read_to_end(&mut decompressed)
+                    .map_err(|_| invalid("corrupt zlib stream"))?;
+                if decompressed.len() - start != split_size {
+                    return Err(invalid("zlib stream has the wrong size"));
+                }
+            }
+        }
+        if flags & FLAG_SHUFFLE != 0 && type_size > 1 {
+            output.append(&mut unshuffle(&decompressed, type_size));
+        } else {
+            output.append(&mut decompressed);
+        }
+    }
+    Ok(output)
+}
+/// Undoes the byte shuffle, which stores the first byte of every value, then the second byte of
+/// every value and so on. Bytes left over after the last whole value are not shuffled
+fn unshuffle(shuffled: &[u8], type_size: usize) -> Vec<u8> {
+    let count = shuffled.len() / type_size;
+    let mut output = vec![0; shuffled.len()];
+    for i in 0..count {
+        for byte in 0..type_size {
+            output[i * type_size + byte] = shuffled[byte * count + i];
+        }
+    }
+    output[count * type_size..].copy_from_slice(&shuffled[count * type_size..]);
+    output
+}
+/// decompresses a raw LZ4 block, which is a list of literal runs each followed by a copy of
+/// earlier output
+fn lz4_decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>, VdbError> {
+    fn length(input: &[u8], position: &mut usize, base: usize) -> Result<usize, VdbError> {
+        let mut length = base;
+        if base == 15 {
+            loop {
+                let byte = *input.get(*position).ok_or(VdbError::UnexpectedEnd)?;
+                *position += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    }
+    let mut output = Vec::with_capacity(output_size);
+    let mut position = 0;
+    loop {
+        let token = *input.get(position).ok_or(VdbError::UnexpectedEnd)?;
+        position += 1;
+        let literal_length = length(input, &mut position, (token >> 4) as usize)?;
+        let literals = input
+            .get(position..position + literal_length)
+            .ok_or(VdbError::UnexpectedEnd)?;
+        output.extend_from_slice(literals);
+        position += literal_length;
+        if position == input.len() {
+            break;
+        }
+        let offset = input
+            .get(position..position + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or(VdbError::UnexpectedEnd)?;
+        position += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(invalid("LZ4 match is outside of the output"));
+        }
+        let match_length = length(input, &mut position, (token & 15) as usize)? + 4;
+        if output.len() + match_length > output_size {
+            return Err(invalid("LZ4 block is too long"));
+        }
+        // the match can overlap the bytes it writes so it is copied one byte at a time
+        let start = output.len() - offset;
+        for i in 0..match_length {
+            output.push(output[start + i]);
+        }
+    }
+    if output.len() != output_size {
+        return Err(invalid("LZ4 block has the wrong size"));
+    }
+    Ok(output)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    /// "abc" followed by a match that repeats it three times, then the literal "x"
+    const LZ4_BLOCK: [u8; 9] = [0x35, b'a', b'b', b'c', 3, 0, 0x10, b'x', 0];
+    fn header(flags: u8, type_size: u8, size: u32, block_size: u32) -> Vec<u8> {
+        let mut frame = vec![2, 1, flags, type_size];
+        frame.extend_from_slice(&size.to_le_bytes());
+        frame.extend_from_slice(&block_size.to_le_bytes());
+        // total compressed size, not needed for decompression
+        frame.extend_from_slice(&0u32.to_le_bytes());
+        frame
+    }
+    #[test]
+    fn lz4() {
+        let output = lz4_decompress(&LZ4_BLOCK[..8], 13).unwrap();
+        assert_eq!(output, b"abcabcabcabcx");
+        assert!(lz4_decompress(&LZ4_BLOCK[..8], 12).is_err());
+        // match pointing before the start of the output
+        assert!(lz4_decompress(&[0x10, b'a', 2, 0, 0x00], 10).is_err());
+    }
+    #[test]
+    fn lz4_frame() {
+        let mut frame = header(CODEC_LZ4 << 5 | FLAG_DONT_SPLIT | FLAG_SHUFFLE, 1, 13, 64);
+        frame.extend_from_slice(&((HEADER_SIZE + 4) as u32).to_le_bytes());
+        frame.extend_from_slice(&8u32.to_le_bytes());
+        frame.extend_from_slice(&LZ4_BLOCK[..8]);
+        assert_eq!(decompress(&frame, 13).unwrap(), b"abcabcabcabcx");
+        assert!(decompress(&frame, 14).is_err());
+    }
+    /// a block of floats split into one stream per byte, the streams are stored uncompressed
+    #[test]
+    fn split_shuffled_frame() {
+        let values = (0..200).map(|i| i as f32 * 1.5).collect::<Vec<_>>();
+        let bytes = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let block_size = 512;
+        let mut frame = header(CODEC_LZ4 << 5 | FLAG_SHUFFLE, 4, 800, block_size as u32);
+        let mut blocks = Vec::new();
+        for block in bytes.chunks(block_size) {
+            let count = block.len() / 4;
+            let mut shuffled = vec![0; block.len()];
+            for i in 0..count {
+                for byte in 0..4 {
+                    shuffled[byte * count + i] = block[i * 4 + byte];
+                }
+            }
+            let split_count = if block.len() == block_size { 4 } else { 1 };
+            let mut data = Vec::new();
+            for split in shuffled.chunks(block.len() / split_count) {
+                data.extend_from_slice(&(split.len() as u32).to_le_bytes());
+                data.extend_from_slice(split);
+            }
+            blocks.push(data);
+        }
+        let mut start = HEADER_SIZE + 4 * blocks.len();
+        for block in blocks.iter() {
+            frame.extend_from_slice(&(start as u32).to_le_bytes());
+            start += block.len();
+        }
+        for block in blocks {
+            frame.extend_from_slice(&block);
+        }
+        assert_eq!(decompress(&frame, 800).unwrap(), bytes);
+    }
+    #[test]
+    fn memcpyed_frame() {
+        let mut frame = header(FLAG_MEMCPYED | FLAG_SHUFFLE, 4, 4, 4);
+        frame.extend_from_slice(&[1, 2, 3, 4]);
+        assert_eq!(decompress(&frame, 4).unwrap(), vec![1, 2, 3, 4]);
+    }
+}
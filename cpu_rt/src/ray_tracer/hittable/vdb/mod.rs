@@ -0,0 +1,432 @@
+//! Sparse volumes loaded from OpenVDB (`.vdb`) and NanoVDB (`.nvdb`) files, the format is picked
+//! from the magic number at the start of the file
+mod blosc;
+mod nanovdb;
+mod openvdb;
+
+use super::{
+    super::material::Material, Aabb, DensityField, HeterogeneousMedium, RenderBox, Transform,
+    VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
+};
+use crate::{prelude::*, ray_tracer::phase_function::PhaseFunction};
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use dyn_clone::clone_box;
+use log::error;
+use std::{collections::HashMap, fs::File, io::Read, ops::Deref, path::Path, sync::Arc};
+
+#[derive(Debug)]
+pub enum VdbError {
+    FileSystemError(std::io::Error),
+    /// the file ended in the middle of a grid
+    UnexpectedEnd,
+    InvalidFile(String),
+    /// the file is valid but uses a feature that can not be loaded
+    Unsupported(String),
+    GridNotFound(String),
+}
+impl From<std::io::Error> for VdbError {
+    fn from(error: std::io::Error) -> Self {
+        Self::FileSystemError(error)
+    }
+}
+/// Reads little endian values from a file loaded into memory
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+    fn seek(&mut self, position: usize) -> Result<(), VdbError> {
+        if position > self.bytes.len() {
+            return Err(VdbError::UnexpectedEnd);
+        }
+        self.position = position;
+        Ok(())
+    }
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], VdbError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(VdbError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], VdbError> {
+        Ok(self
+            .bytes(N)?
+            .try_into()
+            .expect("slice has the right length"))
+    }
+    fn u8(&mut self) -> Result<u8, VdbError> {
+        Ok(self.array::<1>()?[0])
+    }
+    fn u16(&mut self) -> Result<u16, VdbError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+    fn u32(&mut self) -> Result<u32, VdbError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+    fn i32(&mut self) -> Result<i32, VdbError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+    fn u64(&mut self) -> Result<u64, VdbError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+    fn i64(&mut self) -> Result<i64, VdbError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+    fn f32(&mut self) -> Result<f32, VdbError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+    fn f64(&mut self) -> Result<f64, VdbError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+    fn coord(&mut self) -> Result<Point3<i32>, VdbError> {
+        Ok(Point3::new(self.i32()?, self.i32()?, self.i32()?))
+    }
+}
+/// number of voxels along each axis of a leaf
+const LEAF_DIM: i32 = 8;
+/// number of voxels in a leaf
+const LEAF_SIZE: usize = 512;
+/// Values of 8x8x8 voxels, indexed with z changing fastest and x slowest like in both VDB formats
+type LeafValues = [f32; LEAF_SIZE];
+fn leaf_index(offset: Vector3<i32>) -> usize {
+    ((offset.x << 6) | (offset.y << 3) | offset.z) as usize
+}
+/// Bit mask of a VDB node, stored as 64 bit words
+struct NodeMask {
+    words: Vec<u64>,
+}
+impl NodeMask {
+    fn read(reader: &mut ByteReader, size: usize) -> Result<Self, VdbError> {
+        let words = (0..size / 64)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { words })
+    }
+    fn is_on(&self, index: usize) -> bool {
+        (self.words[index >> 6] >> (index & 63)) & 1 == 1
+    }
+    fn count_on(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+    fn iter_on(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words.len() * 64).filter(|i| self.is_on(*i))
+    }
+}
+/// Scalar grid of a VDB file. Values are stored in index space, where voxel centers are at the
+/// integer coordinates, and the map of the grid moves index space into the world space of the
+/// file
+#[derive(Clone)]
+pub struct VdbGrid {
+    name: String,
+    background: f32,
+    index_to_world: Matrix4<RayScalar>,
+    world_to_index: Matrix4<RayScalar>,
+    leaves: Arc<HashMap<Point3<i32>, LeafValues>>,
+    /// regions with a single value, keyed by the origin of the region. The regions are 8, 128
+    /// and 4096 voxels wide
+    tiles: Arc<[HashMap<Point3<i32>, f32>; 3]>,
+    /// inclusive index space bounds of the voxels that differ from the background
+    index_min: Point3<i32>,
+    index_max: Point3<i32>,
+    max_value: f32,
+}
+impl VdbGrid {
+    /// log2 of the width of tiles at each level
+    const TILE_LOG2: [i32; 3] = [3, 7, 12];
+    fn new(
+        name: String,
+        background: f32,
+        index_to_world: Matrix4<RayScalar>,
+        leaves: HashMap<Point3<i32>, LeafValues>,
+        tiles: [HashMap<Point3<i32>, f32>; 3],
+    ) -> Result<Self, VdbError> {
+        let world_to_index = index_to_world
+            .invert()
+            .ok_or_else(|| VdbError::InvalidFile(format!("map of grid {} is singular", name)))?;
+        let mut index_min = Point3::new(i32::MAX, i32::MAX, i32::MAX);
+        let mut index_max = Point3::new(i32::MIN, i32::MIN, i32::MIN);
+        let mut max_value = background.max(0.0);
+        let mut include = |origin: Point3<i32>, width: i32, value: f32| {
+            max_value = max_value.max(value);
+            index_min = index_min.zip(origin, |a, b| a.min(b));
+            index_max = index_max.zip(origin, |a, b| a.max(b + width - 1));
+        };
+        for (origin, values) in leaves.iter() {
+            for value in values.iter().filter(|v| **v != background) {
+                include(*origin, LEAF_DIM, *value);
+            }
+        }
+        for (level, tiles) in tiles.iter().enumerate() {
+            for (origin, value) in tiles.iter().filter(|(_, v)| **v != background) {
+                include(*origin, 1 << Self::TILE_LOG2[level], *value);
+            }
+        }
+        if index_min.x > index_max.x {
+            index_min = Point3::new(0, 0, 0);
+            index_max = Point3::new(0, 0, 0);
+        }
+        Ok(Self {
+            name,
+            background,
+            index_to_world,
+            world_to_index,
+            leaves: Arc::new(leaves),
+            tiles: Arc::new(tiles),
+            index_min,
+            index_max,
+            max_value,
+        })
+    }
+    /// loads every grid with scalar values, grids of other types like velocities are skipped
+    pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Vec<Self>, VdbError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+    /// loads the grid with the given name, usually "density" or "temperature"
+    pub fn load<P: AsRef<Path>>(path: P, grid_name: &str) -> Result<Self, VdbError> {
+        Self::load_all(path)?
+            .into_iter()
+            .find(|grid| grid.name == grid_name)
+            .ok_or_else(|| VdbError::GridNotFound(grid_name.to_string()))
+    }
+    fn from_bytes(bytes: &[u8]) -> Result<Vec<Self>, VdbError> {
+        if bytes.starts_with(&openvdb::MAGIC) {
+            openvdb::read(bytes)
+        } else if nanovdb::is_nanovdb(bytes) {
+            nanovdb::read(bytes)
+        } else {
+            Err(VdbError::InvalidFile(
+                "file is not an OpenVDB or NanoVDB file".to_string(),
+            ))
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// value of the voxel at the index space coordinate
+    pub fn value(&self, index: Point3<i32>) -> f32 {
+        let leaf_origin = index.map(|v| v & !(LEAF_DIM - 1));
+        if let Some(leaf) = self.leaves.get(&leaf_origin) {
+            return leaf[leaf_index(index - leaf_origin)];
+        }
+        for (level, tiles) in self.tiles.iter().enumerate() {
+            let origin = index.map(|v| v & !((1 << Self::TILE_LOG2[level]) - 1));
+            if let Some(value) = tiles.get(&origin) {
+                return *value;
+            }
+        }
+        self.background
+    }
+    /// box in the world space of the file around all voxels that differ from the background
+    pub fn world_bounds(&self) -> Aabb {
+        let min = self.index_min.map(|v| v as RayScalar - 0.5);
+        let max = self.index_max.map(|v| v as RayScalar + 0.5);
+        (0..8)
+            .map(|corner| {
+                let index = Point3::new(
+                    if corner & 1 == 0 { min.x } else { max.x },
+                    if corner & 2 == 0 { min.y } else { max.y },
+                    if corner & 4 == 0 { min.z } else { max.z },
+                );
+                Aabb::from_point(Point3::from_homogeneous(
+                    self.index_to_world * index.to_homogeneous(),
+                ))
+            })
+            .reduce(|acc, x| acc.surrounding_box(x))
+            .expect("there are eight corners")
+    }
+    /// Converts the grid into volume voxels. The voxel grid starts at the lowest voxel of the
+    /// grid, so the returned transform is needed to put it where the grid is in the file
+    pub fn to_voxel_grid(
+        &self,
+        density_scale: RayScalar,
+        color: RgbColor,
+        phase_function: PhaseFunction,
+    ) -> (VoxelGrid, Transform) {
+        let mut voxel_grid = VoxelGrid::new();
+        let mut set = |index: Point3<i32>, value: f32| {
+            if value > 0.0 {
+                voxel_grid.set(
+                    Voxel::Volume(VolumeVoxel {
+                        density: value as RayScalar * density_scale,
+                        color,
+                        edge_effect: VolumeEdgeEffect::None,
+                        phase_function,
+                    }),
+                    (index - self.index_min.to_vec()).map(|v| v as u32),
+                )
+            }
+        };
+        for (origin, values) in self.leaves.iter() {
+            for (i, value) in values.iter().enumerate() {
+                let offset = Vector3::new(i as i32 >> 6, (i as i32 >> 3) & 7, i as i32 & 7);
+                set(origin + offset, *value);
+            }
+        }
+        for (level, tiles) in self.tiles.iter().enumerate() {
+            if level == 2 && tiles.values().any(|value| *value > 0.0) {
+                error!(
+                    "tiles of grid {} are too big to convert to voxels",
+                    self.name
+                );
+                continue;
+            }
+            let width = 1 << Self::TILE_LOG2[level];
+            for (origin, value) in tiles.iter() {
+                for x in 0..width {
+                    for y in 0..width {
+                        for z in 0..width {
+                            set(origin + Vector3::new(x, y, z), *value);
+                        }
+                    }
+                }
+            }
+        }
+        // voxel `v` of the voxel grid covers [v, v + 1] while the voxel in the file is centered
+        // on its index
+        let offset = self.index_min.map(|v| v as RayScalar - 0.5).to_vec();
+        let transform = Transform {
+            world_transform: (self.index_to_world * Matrix4::from_translation(offset))
+                .invert()
+                .expect("map is invertible"),
+        };
+        (voxel_grid, transform)
+    }
+}
+impl DensityField for VdbGrid {
+    /// interpolates trilinearly between the voxel centers
+    fn density(&self, position: Point3<RayScalar>) -> RayScalar {
+        let index = Point3::from_homogeneous(self.world_to_index * position.to_homogeneous());
+        let corner = index.map(|v| v.floor());
+        let fraction = index - corner;
+        let corner = corner.map(|v| v as i32);
+        let weight = |offset, fraction| {
+            if offset == 0 {
+                1.0 - fraction
+            } else {
+                fraction
+            }
+        };
+        let mut density = 0.0;
+        for dx in 0..2 {
+            for dy in 0..2 {
+                for dz in 0..2 {
+                    let weight =
+                        weight(dx, fraction.x) * weight(dy, fraction.y) * weight(dz, fraction.z);
+                    if weight > 0.0 {
+                        density +=
+                            weight * self.value(corner + Vector3::new(dx, dy, dz)) as RayScalar;
+                    }
+                }
+            }
+        }
+        density
+    }
+    fn max_density(&self) -> RayScalar {
+        self.max_value as RayScalar
+    }
+}
+impl HeterogeneousMedium {
+    /// Volume that fills the grid, it lives in the world space of the file so it is placed in
+    /// the scene with the transform of its `Object`
+    pub fn from_vdb(density: VdbGrid, phase_function: Box<dyn Material>) -> Self {
+        let bounds = density.world_bounds();
+        let boundary = RenderBox::new(
+            bounds.minimum,
+            bounds.maximum,
+            clone_box(phase_function.deref()),
+        );
+        Self::new(Box::new(boundary), phase_function, Box::new(density))
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{hittable::Hittable, material::Medium, texture::SolidColor};
+    use cgmath::InnerSpace;
+    /// grid with a single leaf with values counting up from zero
+    fn counting_leaf() -> HashMap<Point3<i32>, LeafValues> {
+        let mut values = [0.0; LEAF_SIZE];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = i as f32 / LEAF_SIZE as f32;
+        }
+        HashMap::from([(Point3::new(8, -8, 0), values)])
+    }
+    fn test_grid() -> VdbGrid {
+        let mut tiles: [HashMap<Point3<i32>, f32>; 3] = Default::default();
+        tiles[1].insert(Point3::new(128, 0, 0), 0.5);
+        VdbGrid::new(
+            "density".to_string(),
+            0.0,
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_scale(0.5),
+            counting_leaf(),
+            tiles,
+        )
+        .unwrap()
+    }
+    #[test]
+    fn lookup() {
+        let grid = test_grid();
+        assert_eq!(grid.value(Point3::new(8, -8, 0)), 0.0);
+        assert_eq!(grid.value(Point3::new(9, -8, 2)), 66.0 / 512.0);
+        assert_eq!(grid.value(Point3::new(200, 100, 127)), 0.5);
+        assert_eq!(grid.value(Point3::new(0, 0, 0)), 0.0);
+        assert_eq!(grid.index_min, Point3::new(8, -8, 0));
+        assert_eq!(grid.index_max, Point3::new(255, 127, 127));
+        assert_eq!(grid.max_density(), 511.0 / 512.0);
+    }
+    #[test]
+    fn world_density() {
+        let grid = test_grid();
+        // halfway between index (9, -8, 2) and (10, -8, 2)
+        let position = Point3::new(1.0 + 0.5 * 9.5, 2.0 - 0.5 * 8.0, 3.0 + 0.5 * 2.0);
+        let expected = (66.0 + 130.0) / 2.0 / 512.0;
+        assert!((grid.density(position) - expected).abs() < 1e-6);
+        let bounds = grid.world_bounds();
+        assert!((bounds.minimum.x - (1.0 + 0.5 * 7.5)).abs() < 1e-9);
+        assert!((bounds.maximum.y - (2.0 + 0.5 * 127.5)).abs() < 1e-9);
+    }
+    #[test]
+    fn voxel_grid_transform() {
+        let mut tiles: [HashMap<Point3<i32>, f32>; 3] = Default::default();
+        tiles[0].insert(Point3::new(-16, 0, 0), 0.25);
+        let grid = VdbGrid::new(
+            "density".to_string(),
+            0.0,
+            Matrix4::from_scale(2.0),
+            HashMap::new(),
+            tiles,
+        )
+        .unwrap();
+        let (_voxel_grid, transform) =
+            grid.to_voxel_grid(1.0, RgbColor::WHITE, PhaseFunction::Isotropic);
+        // the center of the first voxel is at the index of the voxel in the file
+        let local = transform * Point3::new(-32.0, 0.0, 0.0);
+        assert!((local - Point3::new(0.5, 0.5, 0.5)).magnitude() < 1e-9);
+    }
+    #[test]
+    fn medium_bounds() {
+        let medium = HeterogeneousMedium::from_vdb(
+            test_grid(),
+            Box::new(Medium {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+                phase_function: PhaseFunction::Isotropic,
+            }),
+        );
+        let bounds = medium.bounding_box(0.0, 0.0).unwrap();
+        assert!(bounds.approx_eq(test_grid().world_bounds()));
+    }
+}
@@ -0,0 +1,425 @@
+//! Reader for NanoVDB files. A file is a list of segments, each is a header and the metadata of
+//! its grids followed by the grids themselves. The grids are stored exactly as they are laid out
+//! in memory so nodes are found through byte offsets
+use super::{ByteReader, LeafValues, NodeMask, VdbError, VdbGrid, LEAF_SIZE};
+use cgmath::{Matrix4, Point3, Vector3};
+use flate2::read::ZlibDecoder;
+use log::error;
+use std::{borrow::Cow, collections::HashMap, io::Read};
+
+/// the first is written by older versions, the second by newer versions for files
+const MAGIC: [&[u8; 8]; 2] = [b"NanoVDB0", b"NanoVDB1"];
+const MAJOR_VERSION: u32 = 32;
+const CODEC_NONE: u16 = 0;
+const CODEC_ZIP: u16 = 1;
+const GRID_TYPE_FLOAT: u32 = 1;
+/// size of the metadata stored before the name of every grid in the segment
+const FILE_METADATA_SIZE: usize = 176;
+
+/// byte offsets into the grid, node and tile structures
+mod layout {
+    pub const GRID_MAP_MATRIX: usize = 384;
+    pub const GRID_MAP_TRANSLATION: usize = 528;
+    pub const GRID_TYPE: usize = 636;
+    pub const TREE: usize = 672;
+    pub const TREE_ROOT_OFFSET: usize = 24;
+    pub const ROOT_TABLE_SIZE: usize = 24;
+    pub const ROOT_BACKGROUND: usize = 28;
+    pub const ROOT_TILES: usize = 64;
+    pub const ROOT_TILE_SIZE: usize = 32;
+    pub const INTERNAL_MASKS: usize = 32;
+    pub const LEAF_VALUES: usize = 96;
+}
+pub(super) fn is_nanovdb(bytes: &[u8]) -> bool {
+    MAGIC.iter().any(|magic| bytes.starts_with(*magic))
+}
+struct FileMetadata {
+    grid_size: usize,
+    file_size: usize,
+    grid_type: u32,
+    name: String,
+}
+impl FileMetadata {
+    fn read(reader: &mut ByteReader) -> Result<Self, VdbError> {
+        let grid_size = reader.u64()? as usize;
+        let file_size = reader.u64()? as usize;
+        let _name_key = reader.u64()?;
+        let _voxel_count = reader.u64()?;
+        let grid_type = reader.u32()?;
+        // grid class, bounding boxes and voxel size
+        reader.bytes(4 + 48 + 24 + 24)?;
+        let name_size = reader.u32()? as usize;
+        // node and tile counts, codec and version
+        reader.bytes(FILE_METADATA_SIZE - 140)?;
+        let name = String::from_utf8_lossy(reader.bytes(name_size)?)
+            .trim_end_matches('\0')
+            .to_string();
+        Ok(Self {
+            grid_size,
+            file_size,
+            grid_type,
+            name,
+        })
+    }
+}
+pub(super) fn read(bytes: &[u8]) -> Result<Vec<VdbGrid>, VdbError> {
+    let mut reader = ByteReader::new(bytes);
+    let mut grids = Vec::new();
+    while !reader.is_empty() {
+        if !is_nanovdb(reader.bytes(8)?) {
+            return Err(VdbError::InvalidFile(
+                "NanoVDB segment has the wrong magic number".to_string(),
+            ));
+        }
+        let version = reader.u32()?;
+        if version >> 21 != MAJOR_VERSION {
+            return Err(VdbError::Unsupported(format!(
+                "NanoVDB major version {}",
+                version >> 21
+            )));
+        }
+        let grid_count = reader.u16()?;
+        let codec = reader.u16()?;
+        let metadata = (0..grid_count)
+            .map(|_| FileMetadata::read(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        for metadata in metadata {
+            let data = reader.bytes(metadata.file_size)?;
+            if metadata.grid_type != GRID_TYPE_FLOAT {
+                continue;
+            }
+            let grid = match codec {
+                CODEC_NONE => Cow::Borrowed(data),
+                CODEC_ZIP => {
+                    // the compressed size is stored before the zip stream
+                    let mut grid = Vec::with_capacity(metadata.grid_size);
+                    ZlibDecoder::new(data.get(8..).ok_or(VdbError::UnexpectedEnd)?)
+                        .read_to_end(&mut grid)
+                        .map_err(|e| VdbError::InvalidFile(format!("zip data: {}", e)))?;
+                    Cow::Owned(grid)
+                }
+                _ => {
+                    error!(
+                        "grid {} is compressed with codec {}, only zip is supported",
+                        metadata.name, codec
+                    );
+                    continue;
+                }
+            };
+            if grid.len() != metadata.grid_size {
+                return Err(VdbError::InvalidFile(format!(
+                    "grid {} should be {} bytes but is {}",
+                    metadata.name,
+                    metadata.grid_size,
+                    grid.len()
+                )));
+            }
+            grids.push(read_grid(&grid, metadata.name)?);
+        }
+    }
+    Ok(grids)
+}
+/// reads a float grid out of its memory layout
+fn read_grid(grid: &[u8], name: String) -> Result<VdbGrid, VdbError> {
+    let mut reader = ByteReader::new(grid);
+    reader.seek(layout::GRID_TYPE)?;
+    if reader.u32()? != GRID_TYPE_FLOAT {
+        return Err(VdbError::InvalidFile(format!(
+            "grid {} is not a float grid",
+            name
+        )));
+    }
+    reader.seek(layout::GRID_MAP_MATRIX)?;
+    let mut matrix = [0.0; 9];
+    for value in matrix.iter_mut() {
+        *value = reader.f64()?;
+    }
+    reader.seek(layout::GRID_MAP_TRANSLATION)?;
+    let translation = Vector3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+    // the matrix is row major and multiplies column vectors
+    let index_to_world = Matrix4::new(
+        matrix[0],
+        matrix[3],
+        matrix[6],
+        0.0,
+        matrix[1],
+        matrix[4],
+        matrix[7],
+        0.0,
+        matrix[2],
+        matrix[5],
+        matrix[8],
+        0.0,
+        translation.x,
+        translation.y,
+        translation.z,
+        1.0,
+    );
+
+    reader.seek(layout::TREE + layout::TREE_ROOT_OFFSET)?;
+    let root = layout::TREE + reader.u64()? as usize;
+    reader.seek(root + layout::ROOT_TABLE_SIZE)?;
+    let table_size = reader.u32()? as usize;
+    reader.seek(root + layout::ROOT_BACKGROUND)?;
+    let background = reader.f32()?;
+
+    let mut tree = TreeReader {
+        reader,
+        background,
+        tiles: Default::default(),
+        leaves: HashMap::new(),
+    };
+    for i in 0..table_size {
+        let tile = root + layout::ROOT_TILES + i * layout::ROOT_TILE_SIZE;
+        tree.reader.seek(tile)?;
+        let origin = key_to_origin(tree.reader.u64()?);
+        let child = tree.reader.i64()?;
+        let _state = tree.reader.u32()?;
+        let value = tree.reader.f32()?;
+        if child != 0 {
+            tree.read_internal(offset(root, child)?, 5, origin)?;
+        } else if value != background {
+            tree.tiles[2].insert(origin, value);
+        }
+    }
+    VdbGrid::new(name, background, index_to_world, tree.leaves, tree.tiles)
+}
+/// origin of a child of the root from the key of its tile
+fn key_to_origin(key: u64) -> Point3<i32> {
+    const MASK: u64 = (1 << 21) - 1;
+    let coordinate = |shift: u64| ((((key >> shift) & MASK) as u32) << 12) as i32;
+    Point3::new(coordinate(42), coordinate(21), coordinate(0))
+}
+fn offset(base: usize, offset: i64) -> Result<usize, VdbError> {
+    base.checked_add_signed(offset as isize)
+        .ok_or_else(|| VdbError::InvalidFile("node offset is outside of the grid".to_string()))
+}
+struct TreeReader<'a> {
+    reader: ByteReader<'a>,
+    background: f32,
+    tiles: [HashMap<Point3<i32>, f32>; 3],
+    leaves: HashMap<Point3<i32>, LeafValues>,
+}
+impl<'a> TreeReader<'a> {
+    /// reads the upper (`log2_dim` 5) or lower (`log2_dim` 4) internal node at `node`
+    fn read_internal(
+        &mut self,
+        node: usize,
+        log2_dim: i32,
+        origin: Point3<i32>,
+    ) -> Result<(), VdbError> {
+        let size = 1usize << (3 * log2_dim);
+        let mask_size = size / 8;
+        self.reader.seek(node + layout::INTERNAL_MASKS)?;
+        let _value_mask = NodeMask::read(&mut self.reader, size)?;
+        let child_mask = NodeMask::read(&mut self.reader, size)?;
+        // the minimum, maximum, average and standard deviation come before the table, which is
+        // aligned to 32 bytes
+        let table = (node + layout::INTERNAL_MASKS + 2 * mask_size + 16).next_multiple_of(32);
+        let child_log2_width = if log2_dim == 5 { 7 } else { 3 };
+        let dim_mask = (1 << log2_dim) - 1;
+        for i in 0..size {
+            let index = i as i32;
+            let child_origin = origin
+                + Vector3::new(
+                    index >> (2 * log2_dim),
+                    (index >> log2_dim) & dim_mask,
+                    index & dim_mask,
+                ) * (1 << child_log2_width);
+            self.reader.seek(table + 8 * i)?;
+            if child_mask.is_on(i) {
+                let child = offset(node, self.reader.i64()?)?;
+                if log2_dim == 5 {
+                    self.read_internal(child, 4, child_origin)?;
+                } else {
+                    self.read_leaf(child, child_origin)?;
+                }
+            } else {
+                let value = self.reader.f32()?;
+                if value != self.background {
+                    let level = if log2_dim == 5 { 1 } else { 0 };
+                    self.tiles[level].insert(child_origin, value);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn read_leaf(&mut self, node: usize, origin: Point3<i32>) -> Result<(), VdbError> {
+        self.reader.seek(node + layout::LEAF_VALUES)?;
+        let mut values: LeafValues = [0.0; LEAF_SIZE];
+        for value in values.iter_mut() {
+            *value = self.reader.f32()?;
+        }
+        self.leaves.insert(origin, values);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn put(bytes: &mut [u8], position: usize, value: &[u8]) {
+        bytes[position..position + value.len()].copy_from_slice(value);
+    }
+    fn key(origin: Point3<i32>) -> u64 {
+        let coordinate = |v: i32| ((v >> 12) as u32 as u64) & ((1 << 21) - 1);
+        coordinate(origin.x) << 42 | coordinate(origin.y) << 21 | coordinate(origin.z)
+    }
+    /// grid with a root tile, an upper node with a tile and a lower node with a tile and a leaf
+    fn grid() -> Vec<u8> {
+        let root = 704;
+        let upper = root + layout::ROOT_TILES + 2 * layout::ROOT_TILE_SIZE;
+        let lower = upper + 8256 + 32768 * 8;
+        let leaf = lower + 1088 + 4096 * 8;
+        let mut bytes = vec![0; leaf + layout::LEAF_VALUES + 4 * LEAF_SIZE];
+        put(&mut bytes, 0, b"NanoVDB0");
+        // world x depends on index y to check the order of the matrix
+        let matrix = [0.5f64, 0.25, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.5];
+        for (i, value) in matrix.iter().enumerate() {
+            put(
+                &mut bytes,
+                layout::GRID_MAP_MATRIX + 8 * i,
+                &value.to_le_bytes(),
+            );
+        }
+        for (i, value) in [1.0f64, 2.0, 3.0].iter().enumerate() {
+            put(
+                &mut bytes,
+                layout::GRID_MAP_TRANSLATION + 8 * i,
+                &value.to_le_bytes(),
+            );
+        }
+        put(
+            &mut bytes,
+            layout::GRID_TYPE,
+            &GRID_TYPE_FLOAT.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            layout::TREE + layout::TREE_ROOT_OFFSET,
+            &((root - layout::TREE) as u64).to_le_bytes(),
+        );
+
+        put(
+            &mut bytes,
+            root + layout::ROOT_TABLE_SIZE,
+            &2u32.to_le_bytes(),
+        );
+        let tile = root + layout::ROOT_TILES;
+        put(
+            &mut bytes,
+            tile,
+            &key(Point3::new(-4096, 0, 4096)).to_le_bytes(),
+        );
+        put(&mut bytes, tile + 20, &0.75f32.to_le_bytes());
+        let tile = tile + layout::ROOT_TILE_SIZE;
+        put(&mut bytes, tile, &key(Point3::new(0, 0, 0)).to_le_bytes());
+        put(&mut bytes, tile + 8, &((upper - root) as i64).to_le_bytes());
+
+        // child 0 is the lower node and the tile at 1 is at (0, 0, 128)
+        put(
+            &mut bytes,
+            upper + layout::INTERNAL_MASKS + 4096,
+            &1u64.to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            upper + 8256,
+            &((lower - upper) as i64).to_le_bytes(),
+        );
+        put(&mut bytes, upper + 8256 + 8, &0.5f32.to_le_bytes());
+
+        // child 288 is the leaf at (8, 16, 0) and the tile at 1 is at (0, 0, 8)
+        put(
+            &mut bytes,
+            lower + layout::INTERNAL_MASKS + 512 + 4 * 8,
+            &(1u64 << 32).to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            lower + 1088 + 288 * 8,
+            &((leaf - lower) as i64).to_le_bytes(),
+        );
+        put(&mut bytes, lower + 1088 + 8, &0.25f32.to_le_bytes());
+
+        for i in 0..LEAF_SIZE {
+            let value = i as f32 / LEAF_SIZE as f32;
+            put(
+                &mut bytes,
+                leaf + layout::LEAF_VALUES + 4 * i,
+                &value.to_le_bytes(),
+            );
+        }
+        bytes
+    }
+    fn segment(codec: u16, grids: &[(&str, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut out = b"NanoVDB1".to_vec();
+        out.extend_from_slice(&(MAJOR_VERSION << 21 | 7 << 10).to_le_bytes());
+        out.extend_from_slice(&(grids.len() as u16).to_le_bytes());
+        out.extend_from_slice(&codec.to_le_bytes());
+        let data = grids
+            .iter()
+            .map(|(_, _, grid)| {
+                if codec == CODEC_ZIP {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(grid).unwrap();
+                    let compressed = encoder.finish().unwrap();
+                    let mut data = (compressed.len() as u64).to_le_bytes().to_vec();
+                    data.extend_from_slice(&compressed);
+                    data
+                } else {
+                    grid.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        for ((name, grid_type, grid), data) in grids.iter().zip(data.iter()) {
+            let mut metadata = vec![0; FILE_METADATA_SIZE];
+            put(&mut metadata, 0, &(grid.len() as u64).to_le_bytes());
+            put(&mut metadata, 8, &(data.len() as u64).to_le_bytes());
+            put(&mut metadata, 32, &grid_type.to_le_bytes());
+            put(&mut metadata, 136, &(name.len() as u32 + 1).to_le_bytes());
+            out.extend_from_slice(&metadata);
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+        }
+        for data in data {
+            out.extend_from_slice(&data);
+        }
+        out
+    }
+    fn check_grid(grid: &VdbGrid, name: &str) {
+        assert_eq!(grid.name(), name);
+        assert_eq!(grid.value(Point3::new(8, 16, 0)), 0.0);
+        assert_eq!(grid.value(Point3::new(9, 16, 2)), 66.0 / 512.0);
+        assert_eq!(grid.value(Point3::new(0, 0, 8)), 0.25);
+        assert_eq!(grid.value(Point3::new(0, 0, 16)), 0.0);
+        assert_eq!(grid.value(Point3::new(100, 127, 255)), 0.5);
+        assert_eq!(grid.value(Point3::new(-1, 100, 4200)), 0.75);
+        let world = grid.index_to_world * Point3::new(0.0, 4.0, 0.0).to_homogeneous();
+        assert_eq!(Point3::from_homogeneous(world), Point3::new(2.0, 4.0, 3.0));
+    }
+    #[test]
+    fn root_key() {
+        let origin = Point3::new(-4096, 8192, -8192 * 512);
+        assert_eq!(key_to_origin(key(origin)), origin);
+    }
+    #[test]
+    fn read_segments() {
+        let file = [
+            segment(
+                CODEC_NONE,
+                &[("v", 6, vec![0; 16]), ("density", GRID_TYPE_FLOAT, grid())],
+            ),
+            segment(CODEC_ZIP, &[("temperature", GRID_TYPE_FLOAT, grid())]),
+        ]
+        .concat();
+        assert!(is_nanovdb(&file));
+        let grids = read(&file).unwrap();
+        assert_eq!(grids.len(), 2);
+        check_grid(&grids[0], "density");
+        check_grid(&grids[1], "temperature");
+        assert!(read(&file[..file.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,652 @@
+//! Reader for the OpenVDB file format. Only float and double trees with the standard 5, 4, 3
+//! configuration are loaded, other grids like velocities are skipped
+use super::{blosc, ByteReader, LeafValues, NodeMask, VdbError, VdbGrid, LEAF_SIZE};
+use crate::prelude::RayScalar;
+use cgmath::{Matrix4, Point3, Vector3};
+use flate2::read::ZlibDecoder;
+use log::error;
+use std::{collections::HashMap, io::Read};
+
+pub(super) const MAGIC: [u8; 8] = [0x20, 0x42, 0x44, 0x56, 0, 0, 0, 0];
+/// oldest version with compression settings for each grid, written since OpenVDB 3
+const MIN_VERSION: u32 = 222;
+const COMPRESS_ZIP: u32 = 0x1;
+const COMPRESS_ACTIVE_MASK: u32 = 0x2;
+const COMPRESS_BLOSC: u32 = 0x4;
+/// separates the name of a grid from the number that makes it unique in the file
+const UNIQUE_NAME_SEPARATOR: char = '\x1e';
+const HALF_FLOAT_SUFFIX: &str = "_HalfFloat";
+/// how the inactive values of a node are stored when the active mask is compressed
+mod inactive_values {
+    pub const NO_MASK_OR_INACTIVE_VALUES: u8 = 0;
+    pub const NO_MASK_AND_ONE_INACTIVE_VALUE: u8 = 2;
+    pub const MASK_AND_NO_INACTIVE_VALUES: u8 = 3;
+    pub const MASK_AND_ONE_INACTIVE_VALUE: u8 = 4;
+    pub const MASK_AND_TWO_INACTIVE_VALUES: u8 = 5;
+    pub const NO_MASK_AND_ALL_VALUES: u8 = 6;
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValueType {
+    Float,
+    Double,
+}
+impl ValueType {
+    fn from_grid_type(grid_type: &str) -> Option<Self> {
+        match grid_type {
+            "Tree_float_5_4_3" => Some(Self::Float),
+            "Tree_double_5_4_3" => Some(Self::Double),
+            _ => None,
+        }
+    }
+    fn read(self, reader: &mut ByteReader) -> Result<f32, VdbError> {
+        match self {
+            Self::Float => reader.f32(),
+            Self::Double => Ok(reader.f64()? as f32),
+        }
+    }
+}
+/// settings shared by all nodes of a grid
+struct GridContext {
+    value_type: ValueType,
+    save_as_half: bool,
+    compression: u32,
+    background: f32,
+}
+struct GridDescriptor {
+    name: String,
+    grid_type: String,
+    save_as_half: bool,
+    instance_parent: String,
+    grid_position: usize,
+    block_position: usize,
+    end_position: usize,
+}
+impl GridDescriptor {
+    fn read(reader: &mut ByteReader) -> Result<Self, VdbError> {
+        let unique_name = read_string(reader)?;
+        let name = unique_name
+            .split(UNIQUE_NAME_SEPARATOR)
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let mut grid_type = read_string(reader)?;
+        let save_as_half = grid_type.ends_with(HALF_FLOAT_SUFFIX);
+        if save_as_half {
+            grid_type.truncate(grid_type.len() - HALF_FLOAT_SUFFIX.len());
+        }
+        let instance_parent = read_string(reader)?;
+        Ok(Self {
+            name,
+            grid_type,
+            save_as_half,
+            instance_parent,
+            grid_position: read_position(reader)?,
+            block_position: read_position(reader)?,
+            end_position: read_position(reader)?,
+        })
+    }
+}
+fn read_position(reader: &mut ByteReader) -> Result<usize, VdbError> {
+    usize::try_from(reader.i64()?)
+        .map_err(|_| VdbError::InvalidFile("negative grid offset".to_string()))
+}
+fn read_string(reader: &mut ByteReader) -> Result<String, VdbError> {
+    let length = reader.u32()? as usize;
+    Ok(String::from_utf8_lossy(reader.bytes(length)?).into_owned())
+}
+fn skip_metadata(reader: &mut ByteReader) -> Result<(), VdbError> {
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let _name = read_string(reader)?;
+        let _type_name = read_string(reader)?;
+        let size = reader.u32()? as usize;
+        reader.bytes(size)?;
+    }
+    Ok(())
+}
+pub(super) fn read(bytes: &[u8]) -> Result<Vec<VdbGrid>, VdbError> {
+    let mut reader = ByteReader::new(bytes);
+    reader.bytes(MAGIC.len())?;
+    let version = reader.u32()?;
+    if version < MIN_VERSION {
+        return Err(VdbError::Unsupported(format!(
+            "OpenVDB file version {}, re-save the file with OpenVDB 3 or newer",
+            version
+        )));
+    }
+    let _library_major = reader.u32()?;
+    let _library_minor = reader.u32()?;
+    if reader.u8()? == 0 {
+        return Err(VdbError::Unsupported(
+            "OpenVDB file without grid offsets".to_string(),
+        ));
+    }
+    let _uuid = reader.bytes(36)?;
+    skip_metadata(&mut reader)?;
+    let grid_count = reader.i32()?;
+    let mut grids = Vec::new();
+    for _ in 0..grid_count {
+        let descriptor = GridDescriptor::read(&mut reader)?;
+        if let Some(value_type) = ValueType::from_grid_type(&descriptor.grid_type) {
+            if descriptor.instance_parent.is_empty() {
+                reader.seek(descriptor.grid_position)?;
+                grids.push(read_grid(&mut reader, &descriptor, value_type)?);
+            } else {
+                error!(
+                    "grid {} is an instance of {}, instances are not supported",
+                    descriptor.name, descriptor.instance_parent
+                );
+            }
+        }
+        reader.seek(descriptor.end_position)?;
+    }
+    Ok(grids)
+}
+fn read_grid(
+    reader: &mut ByteReader,
+    descriptor: &GridDescriptor,
+    value_type: ValueType,
+) -> Result<VdbGrid, VdbError> {
+    let compression = reader.u32()?;
+    skip_metadata(reader)?;
+    let index_to_world = read_transform(reader)?;
+    let _buffer_count = reader.i32()?;
+    let background = value_type.read(reader)?;
+    let context = GridContext {
+        value_type,
+        save_as_half: descriptor.save_as_half,
+        compression,
+        background,
+    };
+    let mut tiles: [HashMap<Point3<i32>, f32>; 3] = Default::default();
+    let mut leaf_origins = Vec::new();
+    let tile_count = reader.u32()?;
+    let child_count = reader.u32()?;
+    for _ in 0..tile_count {
+        let origin = reader.coord()?;
+        let value = value_type.read(reader)?;
+        let _active = reader.u8()?;
+        if value != background {
+            tiles[2].insert(origin, value);
+        }
+    }
+    for _ in 0..child_count {
+        let origin = reader.coord()?;
+        read_internal_topology(
+            reader,
+            &context,
+            InternalLevel::Upper,
+            origin,
+            &mut tiles,
+            &mut leaf_origins,
+        )?;
+    }
+    reader.seek(descriptor.block_position)?;
+    let mut leaves = HashMap::with_capacity(leaf_origins.len());
+    for origin in leaf_origins {
+        let value_mask = NodeMask::read(reader, LEAF_SIZE)?;
+        let values = read_compressed_values(reader, &context, LEAF_SIZE, &value_mask)?;
+        let mut leaf: LeafValues = [0.0; LEAF_SIZE];
+        leaf.copy_from_slice(&values);
+        leaves.insert(origin, leaf);
+    }
+    VdbGrid::new(
+        descriptor.name.clone(),
+        background,
+        index_to_world,
+        leaves,
+        tiles,
+    )
+}
+/// reads the map of the grid as a matrix that moves index space into world space
+fn read_transform(reader: &mut ByteReader) -> Result<Matrix4<RayScalar>, VdbError> {
+    fn vector(reader: &mut ByteReader) -> Result<Vector3<RayScalar>, VdbError> {
+        Ok(Vector3::new(reader.f64()?, reader.f64()?, reader.f64()?))
+    }
+    /// the maps also store the voxel size and values derived from the scale that are not needed
+    const DERIVED_VECTORS: usize = 4;
+    let map_type = read_string(reader)?;
+    match map_type.as_str() {
+        "UniformScaleMap" | "ScaleMap" => {
+            let scale = vector(reader)?;
+            reader.bytes(DERIVED_VECTORS * 24)?;
+            Ok(Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z))
+        }
+        "UniformScaleTranslateMap" | "ScaleTranslateMap" => {
+            let translation = vector(reader)?;
+            let scale = vector(reader)?;
+            reader.bytes(DERIVED_VECTORS * 24)?;
+            Ok(Matrix4::from_translation(translation)
+                * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z))
+        }
+        "TranslationMap" => Ok(Matrix4::from_translation(vector(reader)?)),
+        "AffineMap" | "UnitaryMap" => {
+            // OpenVDB multiplies row vectors so its rows are the columns of the matrix
+            let mut columns = [[0.0; 4]; 4];
+            for column in columns.iter_mut() {
+                for value in column.iter_mut() {
+                    *value = reader.f64()?;
+                }
+            }
+            Ok(Matrix4::from(columns))
+        }
+        _ => Err(VdbError::Unsupported(format!("grid map {}", map_type))),
+    }
+}
+#[derive(Clone, Copy, PartialEq)]
+enum InternalLevel {
+    /// 32 children along each axis, each 128 voxels wide
+    Upper,
+    /// 16 children along each axis, each a leaf
+    Lower,
+}
+impl InternalLevel {
+    fn log2_dim(self) -> i32 {
+        match self {
+            Self::Upper => 5,
+            Self::Lower => 4,
+        }
+    }
+    fn child_log2_width(self) -> i32 {
+        match self {
+            Self::Upper => 7,
+            Self::Lower => 3,
+        }
+    }
+    /// level of the tiles stored in the node
+    fn tile_level(self) -> usize {
+        match self {
+            Self::Upper => 1,
+            Self::Lower => 0,
+        }
+    }
+}
+fn read_internal_topology(
+    reader: &mut ByteReader,
+    context: &GridContext,
+    level: InternalLevel,
+    origin: Point3<i32>,
+    tiles: &mut [HashMap<Point3<i32>, f32>; 3],
+    leaf_origins: &mut Vec<Point3<i32>>,
+) -> Result<(), VdbError> {
+    let log2_dim = level.log2_dim();
+    let size = 1usize << (3 * log2_dim);
+    let child_mask = NodeMask::read(reader, size)?;
+    let value_mask = NodeMask::read(reader, size)?;
+    let values = read_compressed_values(reader, context, size, &value_mask)?;
+    let child_origin = |i: usize| {
+        let i = i as i32;
+        let dim_mask = (1 << log2_dim) - 1;
+        origin
+            + Vector3::new(
+                i >> (2 * log2_dim),
+                (i >> log2_dim) & dim_mask,
+                i & dim_mask,
+            ) * (1 << level.child_log2_width())
+    };
+    for (i, value) in values.iter().enumerate() {
+        if !child_mask.is_on(i) && *value != context.background {
+            tiles[level.tile_level()].insert(child_origin(i), *value);
+        }
+    }
+    for i in child_mask.iter_on() {
+        match level {
+            InternalLevel::Upper => read_internal_topology(
+                reader,
+                context,
+                InternalLevel::Lower,
+                child_origin(i),
+                tiles,
+                leaf_origins,
+            )?,
+            InternalLevel::Lower => {
+                // the mask is stored again with the values of the leaf
+                NodeMask::read(reader, LEAF_SIZE)?;
+                leaf_origins.push(child_origin(i));
+            }
+        }
+    }
+    Ok(())
+}
+/// Reads the values of a node. If the active mask is compressed only the active values are
+/// stored and the inactive ones are described by the metadata byte
+fn read_compressed_values(
+    reader: &mut ByteReader,
+    context: &GridContext,
+    count: usize,
+    value_mask: &NodeMask,
+) -> Result<Vec<f32>, VdbError> {
+    use inactive_values::*;
+    let metadata = reader.u8()?;
+    let mut inactive_0 = if metadata == NO_MASK_OR_INACTIVE_VALUES {
+        context.background
+    } else {
+        -context.background
+    };
+    let mut inactive_1 = context.background;
+    if [
+        NO_MASK_AND_ONE_INACTIVE_VALUE,
+        MASK_AND_ONE_INACTIVE_VALUE,
+        MASK_AND_TWO_INACTIVE_VALUES,
+    ]
+    .contains(&metadata)
+    {
+        inactive_0 = context.value_type.read(reader)?;
+        if metadata == MASK_AND_TWO_INACTIVE_VALUES {
+            inactive_1 = context.value_type.read(reader)?;
+        }
+    }
+    let selection_mask = if [
+        MASK_AND_NO_INACTIVE_VALUES,
+        MASK_AND_ONE_INACTIVE_VALUE,
+        MASK_AND_TWO_INACTIVE_VALUES,
+    ]
+    .contains(&metadata)
+    {
+        Some(NodeMask::read(reader, count)?)
+    } else {
+        None
+    };
+    let stored_count =
+        if context.compression & COMPRESS_ACTIVE_MASK != 0 && metadata != NO_MASK_AND_ALL_VALUES {
+            value_mask.count_on()
+        } else {
+            count
+        };
+    let stored = read_data(reader, context, stored_count)?;
+    if stored_count == count {
+        return Ok(stored);
+    }
+    let mut stored = stored.into_iter();
+    Ok((0..count)
+        .map(|i| {
+            if value_mask.is_on(i) {
+                stored
+                    .next()
+                    .expect("there is a value for every active voxel")
+            } else if selection_mask.as_ref().is_some_and(|mask| mask.is_on(i)) {
+                inactive_1
+            } else {
+                inactive_0
+            }
+        })
+        .collect())
+}
+/// reads `count` values that may be compressed and stored as half floats
+fn read_data(
+    reader: &mut ByteReader,
+    context: &GridContext,
+    count: usize,
+) -> Result<Vec<f32>, VdbError> {
+    let value_size = if context.save_as_half {
+        2
+    } else {
+        match context.value_type {
+            ValueType::Float => 4,
+            ValueType::Double => 8,
+        }
+    };
+    let size = count * value_size;
+    let bytes = if context.compression & (COMPRESS_BLOSC | COMPRESS_ZIP) != 0 {
+        // a negative size means the data did not get smaller so it is stored uncompressed
+        let compressed_size = reader.i64()?;
+        if compressed_size <= 0 {
+            reader
+                .bytes(compressed_size.unsigned_abs() as usize)?
+                .to_vec()
+        } else {
+            let compressed = reader.bytes(compressed_size as usize)?;
+            if context.compression & COMPRESS_BLOSC != 0 {
+                blosc::decompress(compressed, size)?
+            } else {
+                let mut bytes = Vec::with_capacity(size);
+                ZlibDecoder::new(compressed)
+                    .read_to_end(&mut bytes)
+                    .map_err(|e| VdbError::InvalidFile(format!("zip data: {}", e)))?;
+                bytes
+            }
+        }
+    } else {
+        reader.bytes(size)?.to_vec()
+    };
+    if bytes.len() != size {
+        return Err(VdbError::InvalidFile(format!(
+            "expected {} bytes of values, found {}",
+            size,
+            bytes.len()
+        )));
+    }
+    Ok(bytes
+        .chunks_exact(value_size)
+        .map(|value| match value_size {
+            2 => half::f16::from_le_bytes([value[0], value[1]]).to_f32(),
+            4 => f32::from_le_bytes(value.try_into().expect("value is four bytes")),
+            _ => f64::from_le_bytes(value.try_into().expect("value is eight bytes")) as f32,
+        })
+        .collect())
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    /// writes files in the layout read above so the reader can be tested without VDB files
+    struct TestGrid {
+        name: &'static str,
+        grid_type: &'static str,
+        compression: u32,
+        half: bool,
+        background: f32,
+        root_tiles: Vec<(Point3<i32>, f32)>,
+        /// tiles inside of the lower node at the origin, by index in the node
+        lower_tiles: Vec<(usize, f32)>,
+        /// leaves inside of the lower node at the origin, by index in the node, with their
+        /// active values
+        leaves: Vec<(usize, Vec<(usize, f32)>)>,
+    }
+    impl TestGrid {
+        fn float(name: &'static str, compression: u32) -> Self {
+            Self {
+                name,
+                grid_type: "Tree_float_5_4_3",
+                compression,
+                half: false,
+                background: 0.0,
+                root_tiles: vec![(Point3::new(-4096, 0, 4096), 0.75)],
+                lower_tiles: vec![(1, 0.25)],
+                leaves: vec![(288, vec![(0, 1.0), (66, 0.5), (511, 1.5)])],
+            }
+        }
+        fn write_string(out: &mut Vec<u8>, string: &str) {
+            out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            out.extend_from_slice(string.as_bytes());
+        }
+        fn write_mask(out: &mut Vec<u8>, mask: &[bool]) {
+            for word in mask.chunks(64) {
+                let word = word
+                    .iter()
+                    .enumerate()
+                    .fold(0u64, |acc, (i, on)| acc | (*on as u64) << i);
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        fn write_values(&self, out: &mut Vec<u8>, values: &[f32], mask: &[bool]) {
+            out.push(inactive_values::NO_MASK_OR_INACTIVE_VALUES);
+            let stored = values
+                .iter()
+                .zip(mask)
+                .filter(|(_, on)| self.compression & COMPRESS_ACTIVE_MASK == 0 || **on)
+                .flat_map(|(value, _)| {
+                    if self.half {
+                        half::f16::from_f32(*value).to_le_bytes().to_vec()
+                    } else {
+                        value.to_le_bytes().to_vec()
+                    }
+                })
+                .collect::<Vec<_>>();
+            if self.compression & COMPRESS_ZIP != 0 {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&stored).unwrap();
+                let compressed = encoder.finish().unwrap();
+                out.extend_from_slice(&(compressed.len() as i64).to_le_bytes());
+                out.extend_from_slice(&compressed);
+            } else if self.compression & COMPRESS_BLOSC != 0 {
+                // blosc stores data that does not compress without compressing it
+                let mut frame = vec![2, 1, 0x2, if self.half { 2 } else { 4 }];
+                frame.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+                frame.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+                frame.extend_from_slice(&(stored.len() as u32 + 16).to_le_bytes());
+                frame.extend_from_slice(&stored);
+                out.extend_from_slice(&(frame.len() as i64).to_le_bytes());
+                out.extend_from_slice(&frame);
+            } else {
+                out.extend_from_slice(&stored);
+            }
+        }
+        /// writes the grid starting at `start` in the file
+        fn write(&self, start: usize) -> (Vec<u8>, usize) {
+            let mut out = Vec::new();
+            out.extend_from_slice(&self.compression.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            Self::write_string(&mut out, "ScaleTranslateMap");
+            for value in [1.0f64, 2.0, 3.0, 0.5, 0.5, 0.5] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&[0; 96]);
+            out.extend_from_slice(&1i32.to_le_bytes());
+            out.extend_from_slice(&self.background.to_le_bytes());
+            out.extend_from_slice(&(self.root_tiles.len() as u32).to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            for (origin, value) in self.root_tiles.iter() {
+                for v in [origin.x, origin.y, origin.z] {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                out.extend_from_slice(&value.to_le_bytes());
+                out.push(1);
+            }
+            out.extend_from_slice(&[0; 12]);
+            // upper node with the lower node at the origin as its only child
+            let mut child_mask = vec![false; 32768];
+            child_mask[0] = true;
+            Self::write_mask(&mut out, &child_mask);
+            Self::write_mask(&mut out, &[false; 32768]);
+            self.write_values(&mut out, &[self.background; 32768], &[false; 32768]);
+            let mut child_mask = vec![false; 4096];
+            let mut value_mask = vec![false; 4096];
+            let mut values = vec![self.background; 4096];
+            for (index, _) in self.leaves.iter() {
+                child_mask[*index] = true;
+            }
+            for (index, value) in self.lower_tiles.iter() {
+                value_mask[*index] = true;
+                values[*index] = *value;
+            }
+            Self::write_mask(&mut out, &child_mask);
+            Self::write_mask(&mut out, &value_mask);
+            self.write_values(&mut out, &values, &value_mask);
+            let mut leaves = self
+                .leaves
+                .iter()
+                .map(|(index, active)| {
+                    let mut mask = [false; LEAF_SIZE];
+                    let mut values = [self.background; LEAF_SIZE];
+                    for (i, value) in active.iter() {
+                        mask[*i] = true;
+                        values[*i] = *value;
+                    }
+                    (*index, mask, values)
+                })
+                .collect::<Vec<_>>();
+            leaves.sort_by_key(|(index, _, _)| *index);
+            for (_, mask, _) in leaves.iter() {
+                Self::write_mask(&mut out, mask);
+            }
+            let block_position = start + out.len();
+            for (_, mask, values) in leaves.iter() {
+                Self::write_mask(&mut out, mask);
+                self.write_values(&mut out, values, mask);
+            }
+            (out, block_position)
+        }
+    }
+    fn write_file(grids: &[TestGrid]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&224u32.to_le_bytes());
+        out.extend_from_slice(&9u32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.push(1);
+        out.extend_from_slice(&[b'0'; 36]);
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(grids.len() as i32).to_le_bytes());
+        for grid in grids {
+            TestGrid::write_string(&mut out, &format!("{}\x1e0", grid.name));
+            let grid_type = if grid.half {
+                format!("{}{}", grid.grid_type, HALF_FLOAT_SUFFIX)
+            } else {
+                grid.grid_type.to_string()
+            };
+            TestGrid::write_string(&mut out, &grid_type);
+            TestGrid::write_string(&mut out, "");
+            let grid_position = out.len() + 24;
+            let (data, block_position) = grid.write(grid_position);
+            for position in [grid_position, block_position, grid_position + data.len()] {
+                out.extend_from_slice(&(position as i64).to_le_bytes());
+            }
+            out.extend_from_slice(&data);
+        }
+        out
+    }
+    fn check_grid(grid: &VdbGrid, name: &str) {
+        assert_eq!(grid.name(), name);
+        assert_eq!(grid.value(Point3::new(8, 16, 0)), 1.0);
+        assert_eq!(grid.value(Point3::new(9, 16, 2)), 0.5);
+        assert_eq!(grid.value(Point3::new(15, 23, 7)), 1.5);
+        assert_eq!(grid.value(Point3::new(10, 16, 2)), 0.0);
+        // tile of the lower node
+        assert_eq!(grid.value(Point3::new(0, 0, 8)), 0.25);
+        assert_eq!(grid.value(Point3::new(7, 7, 15)), 0.25);
+        assert_eq!(grid.value(Point3::new(0, 0, 16)), 0.0);
+        // tile of the root
+        assert_eq!(grid.value(Point3::new(-1, 100, 4200)), 0.75);
+        assert_eq!(grid.value(Point3::new(-4097, 100, 4200)), 0.0);
+        let world = grid.index_to_world * Point3::new(2.0, 4.0, 6.0).to_homogeneous();
+        assert_eq!(Point3::from_homogeneous(world), Point3::new(2.0, 4.0, 6.0));
+    }
+    #[test]
+    fn uncompressed() {
+        let grids = read(&write_file(&[TestGrid::float("density", 0)])).unwrap();
+        assert_eq!(grids.len(), 1);
+        check_grid(&grids[0], "density");
+    }
+    #[test]
+    fn compressed() {
+        for compression in [
+            COMPRESS_ZIP,
+            COMPRESS_ACTIVE_MASK,
+            COMPRESS_ZIP | COMPRESS_ACTIVE_MASK,
+            COMPRESS_BLOSC | COMPRESS_ACTIVE_MASK,
+        ] {
+            let grids = read(&write_file(&[TestGrid::float("density", compression)])).unwrap();
+            check_grid(&grids[0], "density");
+        }
+    }
+    #[test]
+    fn half_float() {
+        let mut grid = TestGrid::float("temperature", COMPRESS_ACTIVE_MASK | COMPRESS_BLOSC);
+        grid.half = true;
+        let grids = read(&write_file(&[grid])).unwrap();
+        check_grid(&grids[0], "temperature");
+    }
+    /// grids that are not scalar are skipped without reading them
+    #[test]
+    fn skip_unsupported_grid() {
+        let mut velocity = TestGrid::float("v", 0);
+        velocity.grid_type = "Tree_vec3s_5_4_3";
+        let file = write_file(&[velocity, TestGrid::float("density", COMPRESS_ZIP)]);
+        let grids = read(&file).unwrap();
+        assert_eq!(grids.len(), 1);
+        check_grid(&grids[0], "density");
+        assert!(read(&file[..file.len() - 10]).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use super::{
-    pdf::LambertianPDF, rand_unit_vec, reflect, HitRay, HitRecord, Ray, RgbColor, ScatterRecord,
-    Texture,
+    pdf::{IsotropicPdf, LambertianPDF},
+    phase_function::PhaseFunction,
+    reflect, HitRay, HitRecord, Ray, RgbColor, ScatterRecord, Texture,
 };
 use cgmath::{num_traits::*, InnerSpace, Vector3};
 use dyn_clone::{clone_box, DynClone};
@@ -11,7 +12,12 @@ use std::rc::Rc;
 
 pub trait Material: Send + Sync + DynClone {
     fn name(&self) -> &'static str;
-    fn scatter(&self, ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord>;
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord>;
     fn scattering_pdf(
         &self,
         ray_in: Ray,
@@ -51,7 +57,12 @@ impl Material for Lambertian {
     fn name(&self) -> &'static str {
         "Lambertian"
     }
-    fn scatter(&self, _ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _ray_in: Ray,
+        record_in: &HitRay,
+        _sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         let attenuation = self.albedo.color(record_in.uv(), record_in.position());
 
         let scatter_record = ScatterRecord {
@@ -102,13 +113,18 @@ impl Material for Metal {
     fn name(&self) -> &'static str {
         "Metal"
     }
-    fn scatter(&self, ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         let reflected = reflect(ray_in.direction.normalize(), record_in.normal());
 
         if reflected.dot(record_in.normal()) > 0.0 {
             let out_ray = Ray {
                 origin: record_in.position(),
-                direction: reflected + self.fuzz * rand_unit_vec(),
+                direction: reflected + self.fuzz * sampler.unit_vec(),
                 time: ray_in.time,
             };
 
@@ -165,7 +181,12 @@ impl Material for Dielectric {
     fn name(&self) -> &'static str {
         "Dielectric"
     }
-    fn scatter(&self, ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         let refraction_ratio = if record_in.front_face() {
             1.0 / self.index_refraction
         } else {
@@ -176,7 +197,7 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let can_not_refract = (refraction_ratio * sin_theta) > 1.0;
         let direction = if can_not_refract
-            || Self::reflectance(cos_theta, refraction_ratio) > rand::random::<RayScalar>()
+            || Self::reflectance(cos_theta, refraction_ratio) > sampler.next_scalar()
         {
             reflect(unit_direction, record_in.normal())
         } else {
@@ -220,7 +241,12 @@ impl Material for DiffuseLight {
     fn name(&self) -> &'static str {
         "Diffuse Light"
     }
-    fn scatter(&self, _ray_in: Ray, _record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _ray_in: Ray,
+        _record_in: &HitRay,
+        _sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         None
     }
 
@@ -257,7 +283,7 @@ impl Isotropic {
         _record_in: &HitRecord,
         _scattered_ray: Ray,
     ) -> Option<RayScalar> {
-        panic!("should not have scattering")
+        Some(1.0 / (4.0 * RayScalar::PI()))
     }
 }
 impl Clone for Isotropic {
@@ -271,11 +297,68 @@ impl Material for Isotropic {
     fn name(&self) -> &'static str {
         "Isotropic"
     }
-    fn scatter(&self, ray_in: Ray, record_in: &HitRay) -> Option<ScatterRecord> {
+    fn scatter(
+        &self,
+        _ray_in: Ray,
+        record_in: &HitRay,
+        _sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.albedo.color(record_in.uv(), record_in.position()),
+            pdf: Some(Rc::new(IsotropicPdf {})),
+            scattering_pdf: Self::scattering_pdf_fn,
+        })
+    }
+
+    fn scattering_pdf(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRecord,
+        scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        Self::scattering_pdf_fn(ray_in, record_in, scattered_ray)
+    }
+}
+/// Participating medium that scatters light according to a phase function, used as the material
+/// of a `ConstantMedium`
+pub struct Medium {
+    pub albedo: Box<dyn Texture>,
+    pub phase_function: PhaseFunction,
+}
+impl Medium {
+    fn scattering_pdf_fn(
+        _ray_in: Ray,
+        _record_in: &HitRecord,
+        _scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        panic!("material is specular")
+    }
+}
+impl Clone for Medium {
+    fn clone(&self) -> Self {
+        Self {
+            albedo: clone_box(self.albedo.deref()),
+            phase_function: self.phase_function,
+        }
+    }
+}
+impl Material for Medium {
+    fn name(&self) -> &'static str {
+        "Medium"
+    }
+    /// the direction is sampled exactly from the phase function so the light is only weighted by
+    /// the albedo
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
         Some(ScatterRecord {
             specular_ray: Some(Ray {
                 origin: record_in.position(),
-                direction: rand_unit_vec(),
+                direction: self.phase_function.sample(ray_in.direction, sampler),
                 time: ray_in.time,
             }),
             attenuation: self.albedo.color(record_in.uv(), record_in.position()),
@@ -286,10 +369,320 @@ impl Material for Isotropic {
 
     fn scattering_pdf(
         &self,
+        ray_in: Ray,
+        _record_in: &HitRecord,
+        scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        Some(
+            self.phase_function
+                .value(ray_in.direction, scattered_ray.direction),
+        )
+    }
+}
+/// Translucent material like wax, marble, milk or skin. The surface is a smooth dielectric
+/// interface, light that refracts inside is scattered by the medium when the shape is wrapped in a
+/// `SubsurfaceVolume`. On its own the material looks like clear glass
+pub struct Subsurface {
+    /// fraction of light left after each scattering event inside
+    pub albedo: Box<dyn Texture>,
+    /// average distance light travels inside before it scatters
+    pub mean_free_path: RayScalar,
+    pub index_refraction: RayScalar,
+    pub phase_function: PhaseFunction,
+}
+impl Subsurface {
+    fn scattering_pdf_fn(
         _ray_in: Ray,
         _record_in: &HitRecord,
         _scattered_ray: Ray,
     ) -> Option<RayScalar> {
-        panic!("should not have scattering")
+        panic!("material is specular should not have scattering")
+    }
+}
+impl Clone for Subsurface {
+    fn clone(&self) -> Self {
+        Self {
+            albedo: clone_box(self.albedo.deref()),
+            mean_free_path: self.mean_free_path,
+            index_refraction: self.index_refraction,
+            phase_function: self.phase_function,
+        }
+    }
+}
+impl Material for Subsurface {
+    fn name(&self) -> &'static str {
+        "Subsurface"
+    }
+    /// reflects or refracts at the surface, the normal is flipped for rays leaving the shape so
+    /// light can get out again
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        record_in: &HitRay,
+        sampler: &mut Sampler,
+    ) -> Option<ScatterRecord> {
+        let (normal, refraction_ratio) = if record_in.front_face() {
+            (record_in.normal(), 1.0 / self.index_refraction)
+        } else {
+            (-1.0 * record_in.normal(), self.index_refraction)
+        };
+        let unit_direction = ray_in.direction.normalize();
+        let cos_theta = normal.dot(-1.0 * unit_direction).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let can_not_refract = (refraction_ratio * sin_theta) > 1.0;
+        let direction = if can_not_refract
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > sampler.next_scalar()
+        {
+            reflect(unit_direction, normal)
+        } else {
+            Dielectric::refract(unit_direction, normal, refraction_ratio)
+        };
+        Some(ScatterRecord {
+            specular_ray: Some(Ray {
+                origin: record_in.position(),
+                direction,
+                time: ray_in.time,
+            }),
+            attenuation: RgbColor::WHITE,
+            pdf: None,
+            scattering_pdf: Self::scattering_pdf_fn,
+        })
+    }
+
+    fn scattering_pdf(
+        &self,
+        _ray_in: Ray,
+        _record_in: &HitRecord,
+        _scattered_ray: Ray,
+    ) -> Option<RayScalar> {
+        panic!("material is specular should not have scattering")
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        hittable::MaterialEffect,
+        pdf::test::{sun, test_world},
+        texture::SolidColor,
+        World,
+    };
+    use cgmath::{Point2, Point3};
+    const NUM_SAMPLES: usize = 20_000;
+    fn white() -> Box<dyn Texture> {
+        Box::new(SolidColor {
+            color: RgbColor::WHITE,
+        })
+    }
+    /// hits the material at the origin with the normal pointing up
+    fn hit(
+        material: &dyn Material,
+        direction: Vector3<RayScalar>,
+        sampler: &mut Sampler,
+    ) -> (Ray, HitRecord) {
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0) - direction,
+            direction,
+            time: 0.0,
+        };
+        let record = HitRecord::new(
+            &ray,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            Point2::new(0.5, 0.5),
+            material,
+            sampler,
+        );
+        (ray, record)
+    }
+    /// Average light reflected by the material in a world that is white in every direction, gets
+    /// one for materials that do not absorb any light
+    fn furnace(material: &dyn Material, world: &World) -> RayScalar {
+        let mut sampler = Sampler::new(0);
+        let incoming = Vector3::new(0.4, -1.0, 0.2).normalize();
+        let mut sum = 0.0;
+        for _ in 0..NUM_SAMPLES {
+            let (ray, record) = hit(material, incoming, &mut sampler);
+            let scatter_record = match record.material_effect.clone() {
+                MaterialEffect::Scatter(scatter_record) => scatter_record,
+                _ => continue,
+            };
+            let attenuation = scatter_record.attenuation.red as RayScalar;
+            if scatter_record.specular_ray.is_some() {
+                sum += attenuation;
+                continue;
+            }
+            let pdf = scatter_record
+                .pdf
+                .expect("non specular material needs a pdf");
+            if let Some((direction, value)) =
+                pdf.generate(ray, record.position, world, &mut sampler)
+            {
+                let scattered = Ray {
+                    origin: record.position,
+                    direction,
+                    time: 0.0,
+                };
+                if let Some(scattering_pdf) =
+                    (scatter_record.scattering_pdf)(ray, &record, scattered)
+                {
+                    if value > 0.0 {
+                        sum += attenuation * scattering_pdf / value;
+                    }
+                }
+            }
+        }
+        sum / NUM_SAMPLES as RayScalar
+    }
+    #[test]
+    fn white_furnace() {
+        let lambertian = Lambertian { albedo: white() };
+        for world in [
+            test_world(false, None),
+            test_world(true, None),
+            test_world(true, sun()),
+        ] {
+            let reflected = furnace(&lambertian, &world);
+            assert!(
+                (reflected - 1.0).abs() < 0.02,
+                "lambertian reflects {}",
+                reflected
+            );
+        }
+        let world = test_world(false, None);
+        let mirror = Metal {
+            albedo: white(),
+            fuzz: 0.0,
+        };
+        assert!((furnace(&mirror, &world) - 1.0).abs() < 1e-6);
+        // fuzzy reflections below the surface are absorbed
+        let fuzzy = furnace(
+            &Metal {
+                albedo: white(),
+                fuzz: 0.5,
+            },
+            &world,
+        );
+        assert!(
+            fuzzy <= 1.0 && fuzzy > 0.5,
+            "fuzzy metal reflects {}",
+            fuzzy
+        );
+        let glass = Dielectric {
+            index_refraction: 1.5,
+            color: RgbColor::WHITE,
+        };
+        assert!((furnace(&glass, &world) - 1.0).abs() < 1e-6);
+        let isotropic = Isotropic { albedo: white() };
+        assert!((furnace(&isotropic, &world) - 1.0).abs() < 1e-6);
+        let medium = Medium {
+            albedo: white(),
+            phase_function: PhaseFunction::HenyeyGreenstein { g: 0.7 },
+        };
+        assert!((furnace(&medium, &world) - 1.0).abs() < 1e-6);
+        let subsurface = Subsurface {
+            albedo: white(),
+            mean_free_path: 1.0,
+            index_refraction: 1.5,
+            phase_function: PhaseFunction::Isotropic,
+        };
+        assert!((furnace(&subsurface, &world) - 1.0).abs() < 1e-6);
+        // lights only emit
+        let light = DiffuseLight { emit: white() };
+        assert_eq!(furnace(&light, &world), 0.0);
+        let (_ray, record) = hit(&light, -Vector3::unit_y(), &mut Sampler::new(0));
+        assert!(
+            matches!(record.material_effect, MaterialEffect::Emmit(color) if color == RgbColor::WHITE)
+        );
+    }
+    /// light inside of a subsurface material leaves through the surface unless it hits it at a
+    /// grazing angle
+    #[test]
+    fn subsurface_interface() {
+        let subsurface = Subsurface {
+            albedo: white(),
+            mean_free_path: 1.0,
+            index_refraction: 1.5,
+            phase_function: PhaseFunction::Isotropic,
+        };
+        let mut sampler = Sampler::new(0);
+        let direction = |record: HitRecord| match record.material_effect {
+            MaterialEffect::Scatter(scatter_record) => {
+                scatter_record.specular_ray.unwrap().direction
+            }
+            _ => panic!("subsurface material should scatter"),
+        };
+        let (_ray, record) = hit(&subsurface, Vector3::unit_y(), &mut sampler);
+        assert!(!record.front_face);
+        let exits = (0..1000)
+            .filter(|_| direction(hit(&subsurface, Vector3::unit_y(), &mut sampler).1).y > 0.0)
+            .count();
+        // only 4% is reflected at normal incidence
+        assert!((900..1000).contains(&exits), "{} rays left", exits);
+        let grazing = Vector3::new(1.0, 0.1, 0.0).normalize();
+        for _ in 0..100 {
+            let reflected = direction(hit(&subsurface, grazing, &mut sampler).1);
+            assert!((reflected - Vector3::new(grazing.x, -grazing.y, 0.0)).magnitude() < 1e-6);
+        }
+        // rays from outside refract into the surface
+        let refracted = direction(hit(&subsurface, -Vector3::unit_y(), &mut Sampler::new(1)).1);
+        assert!(refracted.y < 0.0);
+    }
+    /// the brdf, the scattering pdf divided by the cosine of the outgoing direction, is the same
+    /// if the directions are swapped
+    #[test]
+    fn reciprocity() {
+        let lambertian = Lambertian { albedo: white() };
+        let mut sampler = Sampler::new(0);
+        let brdf =
+            |incoming: Vector3<RayScalar>, outgoing: Vector3<RayScalar>, sampler: &mut Sampler| {
+                let (ray, record) = hit(&lambertian, -incoming, sampler);
+                let scattered = Ray {
+                    origin: record.position,
+                    direction: outgoing,
+                    time: 0.0,
+                };
+                let scattering_pdf = lambertian
+                    .scattering_pdf(ray, &record, scattered)
+                    .unwrap_or(0.0);
+                scattering_pdf / outgoing.normalize().dot(record.normal).abs()
+            };
+        for _ in 0..100 {
+            let mut direction = || {
+                let direction = sampler.unit_sphere();
+                Vector3::new(direction.x, direction.y.abs().max(0.01), direction.z)
+            };
+            let (a, b) = (direction(), direction());
+            let mut sampler = Sampler::new(1);
+            assert!((brdf(a, b, &mut sampler) - brdf(b, a, &mut sampler)).abs() < 1e-9);
+        }
+    }
+    /// `Material::scattering_pdf` matches the function in the scatter record
+    #[test]
+    fn scattering_pdf_matches_record() {
+        let materials: [Box<dyn Material>; 2] = [
+            Box::new(Lambertian { albedo: white() }),
+            Box::new(Isotropic { albedo: white() }),
+        ];
+        let mut sampler = Sampler::new(0);
+        for material in materials {
+            let (ray, record) = hit(&*material, Vector3::new(0.3, -1.0, 0.0), &mut sampler);
+            let scatter_record = match record.material_effect.clone() {
+                MaterialEffect::Scatter(scatter_record) => scatter_record,
+                _ => panic!("{} should scatter", material.name()),
+            };
+            for _ in 0..100 {
+                let scattered = Ray {
+                    origin: record.position,
+                    direction: sampler.unit_sphere(),
+                    time: 0.0,
+                };
+                assert_eq!(
+                    material.scattering_pdf(ray, &record, scattered),
+                    (scatter_record.scattering_pdf)(ray, &record, scattered)
+                );
+            }
+        }
     }
 }
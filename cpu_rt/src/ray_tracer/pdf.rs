@@ -1,14 +1,17 @@
-use super::{hittable::Hittable, World};
+use super::{
+    hittable::{Hittable, Transform},
+    sun::Sun,
+    World,
+};
 use crate::prelude::*;
 
 use crate::ray_tracer::hittable::HitRecord;
-use crate::ray_tracer::rand_unit_vec;
 use cgmath::{num_traits::FloatConst, InnerSpace, Point3, Vector3};
 
 use std::{fmt, rc::Rc};
 
 pub trait Pdf {
-    fn value(&self, direction: &Ray, world: &World) -> Option<RayScalar>;
+    fn value(&self, direction: &Ray, world: &World, sampler: &mut Sampler) -> Option<RayScalar>;
     /// Checks if the PDF is valid for the given world
     fn is_valid(&self, world: &World) -> bool;
     fn generate(
@@ -16,7 +19,13 @@ pub trait Pdf {
         incoming_ray: Ray,
         hit_point: Point3<RayScalar>,
         world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)>;
+    /// Gives the pdf for a hit on a shape placed in the world with `transform`, returns none if
+    /// the pdf does not depend on the shape, for example sampling the lights
+    fn to_world(&self, _transform: &Transform) -> Option<Rc<dyn Pdf>> {
+        None
+    }
 }
 pub struct CosinePdf {
     pub uvw: OrthoNormalBasis,
@@ -29,7 +38,7 @@ impl CosinePdf {
     }
 }
 impl Pdf for CosinePdf {
-    fn value(&self, ray: &Ray, _world: &World) -> Option<RayScalar> {
+    fn value(&self, ray: &Ray, _world: &World, _sampler: &mut Sampler) -> Option<RayScalar> {
         let cos = ray.direction.dot(self.uvw.w());
         if cos <= 0.0 {
             Some(0.0)
@@ -47,8 +56,9 @@ impl Pdf for CosinePdf {
         _ray: Ray,
         _hit_point: Point3<RayScalar>,
         _world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)> {
-        let direction = self.uvw.local(random_cosine_direction()).normalize();
+        let direction = self.uvw.local(sampler.cosine_direction()).normalize();
         let cos = direction.dot(self.uvw.w());
         let value = if cos <= 0.0 {
             0.0
@@ -58,12 +68,18 @@ impl Pdf for CosinePdf {
 
         Some((direction, value))
     }
+    fn to_world(&self, transform: &Transform) -> Option<Rc<dyn Pdf>> {
+        Some(Rc::new(Self::new(transform.normal_to_world(self.uvw.w()))))
+    }
 }
-//isotropic scattering, used for
+/// isotropic scattering, every direction is equally likely
 pub struct IsotropicPdf {}
+impl IsotropicPdf {
+    const VALUE: RayScalar = 1.0 / (4.0 * std::f64::consts::PI);
+}
 impl Pdf for IsotropicPdf {
-    fn value(&self, _direction: &Ray, _world: &World) -> Option<RayScalar> {
-        Some(1.)
+    fn value(&self, _direction: &Ray, _world: &World, _sampler: &mut Sampler) -> Option<RayScalar> {
+        Some(Self::VALUE)
     }
     fn is_valid(&self, _world: &World) -> bool {
         true
@@ -73,29 +89,25 @@ impl Pdf for IsotropicPdf {
         _incoming_ray: Ray,
         _hit_point: Point3<RayScalar>,
         _world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)> {
-        Some((rand_unit_vec(), 1.))
+        Some((sampler.unit_sphere(), Self::VALUE))
     }
 }
+/// Picks one of the lights and then a point on it. A direction can be generated by any light it
+/// points at, so the value is the average of the probabilities of every light
 pub struct LightPdf {}
 impl Pdf for LightPdf {
-    fn value(&self, ray: &Ray, world: &World) -> Option<RayScalar> {
-        if let Some((light, hit)) = world.nearest_light_hit(ray, ray.time, RayScalar::MAX) {
-            let to_light = hit.position - ray.origin;
-            let light_cos = to_light.normalize().dot(hit.normal).abs();
-
-            if light_cos >= 0.000001 {
-                Some(light.prob(Ray {
-                    origin: ray.origin,
-                    direction: to_light.normalize(),
-                    time: hit.t,
-                }))
-            } else {
-                Some(0.0)
-            }
-        } else {
-            None
+    fn value(&self, ray: &Ray, world: &World, sampler: &mut Sampler) -> Option<RayScalar> {
+        if world.lights.is_empty() {
+            return None;
         }
+        let sum = world
+            .lights
+            .iter()
+            .map(|light| light.prob(*ray, sampler))
+            .sum::<RayScalar>();
+        Some(sum / world.lights.len() as RayScalar)
     }
     fn is_valid(&self, world: &World) -> bool {
         !world.lights.is_empty()
@@ -106,49 +118,54 @@ impl Pdf for LightPdf {
         incoming_ray: Ray,
         hit_point: Point3<RayScalar>,
         world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)> {
         if world.lights.is_empty() {
             return None;
         }
-        let idx = rand_u32(0, world.lights.len() as u32) as usize;
-        let area_info = world.lights[idx].generate_ray_in_area(hit_point, incoming_ray.time);
-
-        let light_cos = area_info
-            .to_area
-            .direction
-            .normalize()
-            .dot(area_info.normal)
-            .abs();
-        let dist_squared = area_info.direction.dot(area_info.direction);
-        if light_cos >= 0.000001 {
-            let value = dist_squared / (light_cos * area_info.area);
-
+        let idx = sampler.u32(0, world.lights.len() as u32) as usize;
+        let area_info =
+            world.lights[idx].generate_ray_in_area(hit_point, incoming_ray.time, sampler);
+        let value = self.value(&area_info.to_area, world, sampler)?;
+        if value > 0.0 {
             Some((area_info.to_area.direction.normalize(), value))
         } else {
             None
         }
     }
 }
+/// Samples the sun, directions in the sun's disk are equally likely. Without a sun every direction
+/// is equally likely
 pub struct SkyPdf {}
-impl SkyPdf {}
+impl SkyPdf {
+    /// cosine of the angle between the center and edge of the sun
+    fn min_cos(sun: &Sun) -> RayScalar {
+        sun.radius.cos()
+    }
+    fn sun_value(sun: &Sun) -> RayScalar {
+        1.0 / (2.0 * RayScalar::PI() * (1.0 - Self::min_cos(sun)))
+    }
+}
 impl Pdf for SkyPdf {
-    fn value(&self, direction: &Ray, world: &World) -> Option<RayScalar> {
-        if world.sun.is_none() {
-            Some(1.0)
-        } else {
-            let sun = world.sun.unwrap();
-            let sun_vector = sun.make_direction_vector();
-            let min_cos_distance = sun.radius.cos();
-            if direction.direction.dot(sun_vector) >= min_cos_distance {
-                Some(1.0)
-            } else {
-                Some(0.0)
+    fn value(&self, direction: &Ray, world: &World, _sampler: &mut Sampler) -> Option<RayScalar> {
+        match world.sun {
+            Some(sun) => {
+                let cos = direction
+                    .direction
+                    .normalize()
+                    .dot(sun.make_direction_vector());
+                if cos >= Self::min_cos(&sun) {
+                    Some(Self::sun_value(&sun))
+                } else {
+                    Some(0.0)
+                }
             }
+            None => Some(IsotropicPdf::VALUE),
         }
     }
 
-    fn is_valid(&self, _world: &World) -> bool {
-        true
+    fn is_valid(&self, world: &World) -> bool {
+        world.sun.is_some()
     }
 
     fn generate(
@@ -156,39 +173,18 @@ impl Pdf for SkyPdf {
         _incoming_ray: Ray,
         _hit_point: Point3<RayScalar>,
         world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)> {
-        /// generates theta and r inside of unit circle
-        fn gen_unit_circle() -> (RayScalar, RayScalar) {
-            let rand_r = rand_scalar(0.0, 1.0);
-            let rand_theta = rand_scalar(0.0, 2.0 * RayScalar::PI());
-            (rand_r.sqrt(), rand_theta)
-        }
-        if world.sun.is_none() {
-            let rand_vector = rand_unit_vec();
-            Some((rand_vector, 4.0 * RayScalar::PI()))
-        } else {
-            let sun = world.sun.unwrap();
-
-            let (r, theta) = gen_unit_circle();
-
-            let r = r * sun.radius;
-            let sun_vector = sun.make_direction_vector();
-            let cross_vector = sun_vector.cross(Vector3::new(0.0, 0.0, 1.0));
-            if cross_vector.magnitude() < 0.01 {
-                panic!()
+        match world.sun {
+            Some(sun) => {
+                let cos = 1.0 - sampler.next_scalar() * (1.0 - Self::min_cos(&sun));
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * RayScalar::PI() * sampler.next_scalar();
+                let basis = OrthoNormalBasis::build_from_w(sun.make_direction_vector());
+                let direction = basis.local(Vector3::new(sin * phi.cos(), sin * phi.sin(), cos));
+                Some((direction, Self::sun_value(&sun)))
             }
-            let k = cross_vector.normalize();
-            let v_rot = r.cos() * sun_vector
-                + (k.cross(sun_vector)) * theta.sin()
-                + k * (k.dot(sun_vector)) * (1.0 - theta.cos());
-
-            let area = RayScalar::PI() * sun.radius.powi(2);
-
-            let area_sphere = 4.0 * RayScalar::PI();
-
-            // rotation vector, https://en.wikipedia.org/wiki/Rodrigues'_rotation_formula
-            //
-            Some((v_rot, area / area_sphere))
+            None => Some((sampler.unit_sphere(), IsotropicPdf::VALUE)),
         }
     }
 }
@@ -207,28 +203,33 @@ impl LambertianPDF {
         }
     }
 }
-impl Pdf for LambertianPDF {
-    fn value(&self, direction: &Ray, world: &World) -> Option<RayScalar> {
-        let mut value = 0.0;
-        let mut count = 0;
-        for v in [
-            self.sin_pdf.value(direction, world),
-            self.light_pdf.value(direction, world),
-            self.sky_pdf.value(direction, world),
+impl LambertianPDF {
+    /// the pdfs that can be sampled in the world
+    fn valid_pdfs(&self, world: &World) -> Vec<&dyn Pdf> {
+        [
+            &self.sin_pdf as &dyn Pdf,
+            &self.light_pdf as &dyn Pdf,
+            &self.sky_pdf as &dyn Pdf,
         ]
-        .iter()
-        .filter_map(|v| *v)
-        {
-            value += v;
-            count += 1;
-        }
-        Some(value / count as RayScalar)
+        .into_iter()
+        .filter(|pdf| pdf.is_valid(world))
+        .collect()
+    }
+}
+/// Picks one of the valid pdfs with equal probability, so the value is the average of the values
+/// of the valid pdfs
+impl Pdf for LambertianPDF {
+    fn value(&self, direction: &Ray, world: &World, sampler: &mut Sampler) -> Option<RayScalar> {
+        let pdfs = self.valid_pdfs(world);
+        let sum = pdfs
+            .iter()
+            .map(|pdf| pdf.value(direction, world, sampler).unwrap_or(0.0))
+            .sum::<RayScalar>();
+        Some(sum / pdfs.len() as RayScalar)
     }
 
     fn is_valid(&self, world: &World) -> bool {
         self.sin_pdf.is_valid(world)
-            && self.light_pdf.is_valid(world)
-            && self.sky_pdf.is_valid(world)
     }
 
     fn generate(
@@ -236,49 +237,29 @@ impl Pdf for LambertianPDF {
         incoming_ray: Ray,
         hit_point: Point3<RayScalar>,
         world: &World,
+        sampler: &mut Sampler,
     ) -> Option<(Vector3<RayScalar>, RayScalar)> {
-        let r = rand_u32(0, 3);
-        let v = match r {
-            0 => self.sin_pdf.generate(incoming_ray, hit_point, world),
-            1 => self.light_pdf.generate(incoming_ray, hit_point, world),
-            2 => self.sky_pdf.generate(incoming_ray, hit_point, world),
-            _ => panic!(),
+        let pdfs = self.valid_pdfs(world);
+        let index = sampler.u32(0, pdfs.len() as u32) as usize;
+        let (out_direction, pdf) = pdfs[index].generate(incoming_ray, hit_point, world, sampler)?;
+        let value_ray = Ray {
+            origin: hit_point,
+            direction: out_direction,
+            time: incoming_ray.time,
         };
-        if let Some((out_direction, pdf)) = v {
-            let mut sum: RayScalar = 0.0;
-            let mut total = 0;
-            let value_ray = Ray {
-                origin: hit_point,
-                direction: out_direction,
-                time: 0.0,
-            };
-            let values = match r {
-                0 => [
-                    self.light_pdf.value(&value_ray, world),
-                    self.sky_pdf.value(&value_ray, world),
-                ],
-                1 => [
-                    self.sin_pdf.value(&value_ray, world),
-                    self.sky_pdf.value(&value_ray, world),
-                ],
-                2 => [
-                    self.light_pdf.value(&value_ray, world),
-                    self.sin_pdf.value(&value_ray, world),
-                ],
-                _ => panic!(),
-            };
-            for value in values.iter().flatten() {
-                sum += value;
-                total += 1;
-            }
-            if total != 0 {
-                Some((out_direction, (sum + pdf) / (total + 1) as RayScalar))
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        let others = pdfs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, pdf)| pdf.value(&value_ray, world, sampler).unwrap_or(0.0))
+            .sum::<RayScalar>();
+        Some((out_direction, (pdf + others) / pdfs.len() as RayScalar))
+    }
+    /// only the cosine part depends on the shape, the lights and sky are already in world space
+    fn to_world(&self, transform: &Transform) -> Option<Rc<dyn Pdf>> {
+        Some(Rc::new(Self::new(
+            transform.normal_to_world(self.sin_pdf.uvw.w()),
+        )))
     }
 }
 #[derive(Clone)]
@@ -297,3 +278,257 @@ impl fmt::Debug for ScatterRecord {
             .finish()
     }
 }
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        background::ConstantColor,
+        camera::{Camera, CameraInfo},
+        hittable::{Hittable, Object, Sphere, Transform, XZRect},
+        material::DiffuseLight,
+        texture::SolidColor,
+        WorldInfo,
+    };
+    const THETA_BINS: usize = 16;
+    const PHI_BINS: usize = 32;
+    /// each bin is integrated with this many points along each axis
+    const COARSE_RESOLUTION: usize = 24;
+    /// points along each axis for bins where the value changes quickly
+    const FINE_RESOLUTION: usize = 96;
+    const NUM_SAMPLES: usize = 100_000;
+    /// Builds an empty world with a constant background. The light is a square above the origin
+    /// and a sphere to the side
+    pub(crate) fn test_world(lights: bool, sun: Option<Sun>) -> World {
+        let light_material = || {
+            Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: RgbColor::WHITE,
+                }),
+            })
+        };
+        WorldInfo {
+            objects: vec![],
+            lights: if lights {
+                vec![
+                    Object::new(
+                        Box::new(XZRect::new(
+                            -1.0,
+                            1.0,
+                            -0.5,
+                            1.5,
+                            2.0,
+                            light_material(),
+                            false,
+                        )),
+                        Transform::identity(),
+                    ),
+                    Object::new(
+                        Box::new(Sphere {
+                            radius: 1.0,
+                            origin: Point3::new(-3.0, 0.5, 0.0),
+                            material: light_material(),
+                        }),
+                        Transform::identity(),
+                    ),
+                ]
+            } else {
+                vec![]
+            },
+            background: Box::new(ConstantColor {
+                color: RgbColor::WHITE,
+            }),
+            camera: Camera::new(CameraInfo::default()),
+            sun,
+        }
+        .build_world()
+    }
+    /// world lit only by the shape
+    pub(crate) fn light_world(light: Box<dyn Hittable>) -> World {
+        WorldInfo {
+            objects: vec![],
+            lights: vec![Object::new(light, Transform::identity())],
+            background: Box::new(ConstantColor {
+                color: RgbColor::WHITE,
+            }),
+            camera: Camera::new(CameraInfo::default()),
+            sun: None,
+        }
+        .build_world()
+    }
+    fn direction(theta: RayScalar, phi: RayScalar) -> Vector3<RayScalar> {
+        Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+    fn bin(direction: Vector3<RayScalar>) -> usize {
+        let direction = direction.normalize();
+        let theta = direction.z.clamp(-1.0, 1.0).acos();
+        let phi = direction
+            .y
+            .atan2(direction.x)
+            .rem_euclid(2.0 * RayScalar::PI());
+        let theta_bin =
+            ((theta / RayScalar::PI() * THETA_BINS as RayScalar) as usize).min(THETA_BINS - 1);
+        let phi_bin =
+            ((phi / (2.0 * RayScalar::PI()) * PHI_BINS as RayScalar) as usize).min(PHI_BINS - 1);
+        theta_bin * PHI_BINS + phi_bin
+    }
+    /// Integrates the value of the pdf over each bin of directions. Bins where the value changes
+    /// quickly, like the edges of lights, are integrated again with more points
+    fn integrate_bins(pdf: &dyn Pdf, world: &World, origin: Point3<RayScalar>) -> Vec<RayScalar> {
+        let mut sampler = Sampler::new(0);
+        let bin_theta = RayScalar::PI() / THETA_BINS as RayScalar;
+        let bin_phi = 2.0 * RayScalar::PI() / PHI_BINS as RayScalar;
+        let mut integrate = |theta_bin: usize, phi_bin: usize, resolution: usize| {
+            let d_theta = bin_theta / resolution as RayScalar;
+            let d_phi = bin_phi / resolution as RayScalar;
+            let mut sum = 0.0;
+            let (mut min, mut max) = (RayScalar::MAX, 0.0 as RayScalar);
+            for i in 0..resolution {
+                let theta = theta_bin as RayScalar * bin_theta + (i as RayScalar + 0.5) * d_theta;
+                for j in 0..resolution {
+                    let phi = phi_bin as RayScalar * bin_phi + (j as RayScalar + 0.5) * d_phi;
+                    let ray = Ray {
+                        origin,
+                        direction: direction(theta, phi),
+                        time: 0.0,
+                    };
+                    let value = pdf.value(&ray, world, &mut sampler).unwrap_or(0.0);
+                    min = min.min(value);
+                    max = max.max(value);
+                    sum += value * theta.sin() * d_theta * d_phi;
+                }
+            }
+            (sum, max > 1.5 * min)
+        };
+        (0..THETA_BINS)
+            .flat_map(|theta_bin| (0..PHI_BINS).map(move |phi_bin| (theta_bin, phi_bin)))
+            .map(|(theta_bin, phi_bin)| {
+                let (sum, varies) = integrate(theta_bin, phi_bin, COARSE_RESOLUTION);
+                if varies {
+                    integrate(theta_bin, phi_bin, FINE_RESOLUTION).0
+                } else {
+                    sum
+                }
+            })
+            .collect()
+    }
+    /// Checks that the directions from `generate` follow the distribution given by `value` with
+    /// Pearson's chi-square test and that `value` integrates to one
+    pub(crate) fn check_pdf(pdf: &dyn Pdf, world: &World, origin: Point3<RayScalar>) {
+        let expected = integrate_bins(pdf, world, origin);
+        let total = expected.iter().sum::<RayScalar>();
+        assert!((total - 1.0).abs() < 0.01, "integrates to {}", total);
+
+        let mut sampler = Sampler::new(1);
+        let incoming = Ray {
+            origin: origin + Vector3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+        };
+        let mut observed = vec![0.0; THETA_BINS * PHI_BINS];
+        for _ in 0..NUM_SAMPLES {
+            let (direction, generated_value) = pdf
+                .generate(incoming, origin, world, &mut sampler)
+                .expect("failed to generate direction");
+            // the value given with the direction matches the value of the direction
+            let ray = Ray {
+                origin,
+                direction,
+                time: 0.0,
+            };
+            let value = pdf.value(&ray, world, &mut sampler).unwrap_or(0.0);
+            assert!(
+                (generated_value - value).abs() <= 1e-4 * value.max(1.0),
+                "generated value: {}, value: {}",
+                generated_value,
+                value
+            );
+            observed[bin(direction)] += 1.0;
+        }
+        // bins expected to get few samples are merged so the test statistic is valid
+        let mut statistic = 0.0;
+        let mut degrees_of_freedom = 0;
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (observed, expected) in observed.iter().zip(expected.iter()) {
+            let expected = expected * NUM_SAMPLES as RayScalar;
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                statistic += (observed - expected).powi(2) / expected;
+                degrees_of_freedom += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            degrees_of_freedom += 1;
+        }
+        let degrees_of_freedom = degrees_of_freedom as RayScalar - 1.0;
+        // the Wilson-Hilferty transform makes the statistic close to a standard normal
+        let variance = 2.0 / (9.0 * degrees_of_freedom);
+        let z = ((statistic / degrees_of_freedom).cbrt() - (1.0 - variance)) / variance.sqrt();
+        assert!(
+            z < 4.0,
+            "chi square statistic {} with {} degrees of freedom, z = {}",
+            statistic,
+            degrees_of_freedom,
+            z
+        );
+    }
+    pub(crate) fn sun() -> Option<Sun> {
+        Some(Sun {
+            phi: 0.8,
+            theta: 0.3,
+            radius: 0.4,
+        })
+    }
+    #[test]
+    fn cosine_pdf() {
+        let world = test_world(false, None);
+        check_pdf(
+            &CosinePdf::new(Vector3::new(0.3, 0.2, 1.0).normalize()),
+            &world,
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+    #[test]
+    fn isotropic_pdf() {
+        let world = test_world(false, None);
+        check_pdf(&IsotropicPdf {}, &world, Point3::new(0.0, 0.0, 0.0));
+    }
+    #[test]
+    fn sky_pdf() {
+        check_pdf(
+            &SkyPdf {},
+            &test_world(false, sun()),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+        check_pdf(
+            &SkyPdf {},
+            &test_world(false, None),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+    }
+    #[test]
+    fn light_pdf() {
+        let world = test_world(true, None);
+        check_pdf(&LightPdf {}, &world, Point3::new(0.0, 0.0, 0.0));
+        // inside of the sphere light
+        check_pdf(&LightPdf {}, &world, Point3::new(-3.2, 0.3, 0.1));
+    }
+    #[test]
+    fn lambertian_pdf() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        for (lights, sun) in [(false, None), (true, None), (false, sun()), (true, sun())] {
+            let world = test_world(lights, sun);
+            check_pdf(
+                &LambertianPDF::new(normal),
+                &world,
+                Point3::new(0.0, 0.0, 0.0),
+            );
+        }
+    }
+}
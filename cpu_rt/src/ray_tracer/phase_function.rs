@@ -0,0 +1,191 @@
+use crate::prelude::*;
+use cgmath::{num_traits::FloatConst, InnerSpace, Vector3};
+
+/// How light scattered inside of a participating medium is spread out. Angles are measured from
+/// the direction the light was travelling in, so a positive asymmetry `g` scatters light forward
+/// and a negative one scatters it back towards where it came from
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PhaseFunction {
+    /// every direction is equally likely
+    #[default]
+    Isotropic,
+    /// Henyey-Greenstein lobe, `g` is the average cosine of the scattering angle in (-1, 1)
+    HenyeyGreenstein { g: RayScalar },
+    /// mix of a forward and a backward Henyey-Greenstein lobe, used for clouds that mostly scatter
+    /// forward but still have a bright back side
+    DoubleHenyeyGreenstein {
+        forward_g: RayScalar,
+        backward_g: RayScalar,
+        /// fraction of the light scattered by the forward lobe
+        forward_weight: RayScalar,
+    },
+    /// scattering by particles much smaller than the wavelength, like air
+    Rayleigh,
+    /// Cornette-Shanks approximation of scattering by particles about the size of the
+    /// wavelength, like haze and fog
+    Mie { g: RayScalar },
+}
+impl PhaseFunction {
+    /// probability per solid angle of light travelling in `direction_in` scattering into
+    /// `direction_out`
+    pub fn value(
+        &self,
+        direction_in: Vector3<RayScalar>,
+        direction_out: Vector3<RayScalar>,
+    ) -> RayScalar {
+        let cos = direction_in
+            .normalize()
+            .dot(direction_out.normalize())
+            .clamp(-1.0, 1.0);
+        match *self {
+            Self::Isotropic => 1.0 / (4.0 * RayScalar::PI()),
+            Self::HenyeyGreenstein { g } => henyey_greenstein(cos, g),
+            Self::DoubleHenyeyGreenstein {
+                forward_g,
+                backward_g,
+                forward_weight,
+            } => {
+                forward_weight * henyey_greenstein(cos, forward_g)
+                    + (1.0 - forward_weight) * henyey_greenstein(cos, backward_g)
+            }
+            Self::Rayleigh => 3.0 / (16.0 * RayScalar::PI()) * (1.0 + cos * cos),
+            Self::Mie { g } => {
+                henyey_greenstein(cos, g) * 3.0 * (1.0 + cos * cos) / (2.0 * (2.0 + g * g))
+            }
+        }
+    }
+    /// picks the direction light travelling in `direction_in` scatters into, the directions
+    /// follow `value`
+    pub fn sample(
+        &self,
+        direction_in: Vector3<RayScalar>,
+        sampler: &mut Sampler,
+    ) -> Vector3<RayScalar> {
+        let cos = match *self {
+            Self::Isotropic => 1.0 - 2.0 * sampler.next_scalar(),
+            Self::HenyeyGreenstein { g } => sample_henyey_greenstein(g, sampler),
+            Self::DoubleHenyeyGreenstein {
+                forward_g,
+                backward_g,
+                forward_weight,
+            } => {
+                if sampler.next_scalar() < forward_weight {
+                    sample_henyey_greenstein(forward_g, sampler)
+                } else {
+                    sample_henyey_greenstein(backward_g, sampler)
+                }
+            }
+            Self::Rayleigh => {
+                // inverts the cdf (cos^3 + 3 cos + 4) / 8 with Cardano's formula
+                let q = 4.0 * sampler.next_scalar() - 2.0;
+                let a = (q + (q * q + 1.0).sqrt()).cbrt();
+                (a - 1.0 / a).clamp(-1.0, 1.0)
+            }
+            Self::Mie { g } => loop {
+                // Cornette-Shanks is Henyey-Greenstein times (1 + cos^2) so samples of
+                // Henyey-Greenstein are kept with a probability proportional to that
+                let cos = sample_henyey_greenstein(g, sampler);
+                if 2.0 * sampler.next_scalar() < 1.0 + cos * cos {
+                    break cos;
+                }
+            },
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * RayScalar::PI() * sampler.next_scalar();
+        OrthoNormalBasis::build_from_w(direction_in).local(Vector3::new(
+            sin * phi.cos(),
+            sin * phi.sin(),
+            cos,
+        ))
+    }
+}
+fn henyey_greenstein(cos: RayScalar, g: RayScalar) -> RayScalar {
+    let denominator = 1.0 + g * g - 2.0 * g * cos;
+    (1.0 - g * g) / (4.0 * RayScalar::PI() * denominator * denominator.sqrt())
+}
+/// gets the cosine of the scattering angle by inverting the cdf
+fn sample_henyey_greenstein(g: RayScalar, sampler: &mut Sampler) -> RayScalar {
+    let u = sampler.next_scalar();
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+    let square = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - square * square) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ray_tracer::{
+        pdf::{test::check_pdf, test::test_world, Pdf},
+        World,
+    };
+    use cgmath::Point3;
+    /// lets the phase functions be checked like the other pdfs
+    struct PhasePdf {
+        phase_function: PhaseFunction,
+        direction_in: Vector3<RayScalar>,
+    }
+    impl Pdf for PhasePdf {
+        fn value(&self, ray: &Ray, _world: &World, _sampler: &mut Sampler) -> Option<RayScalar> {
+            Some(self.phase_function.value(self.direction_in, ray.direction))
+        }
+        fn is_valid(&self, _world: &World) -> bool {
+            true
+        }
+        fn generate(
+            &self,
+            _incoming_ray: Ray,
+            _hit_point: Point3<RayScalar>,
+            _world: &World,
+            sampler: &mut Sampler,
+        ) -> Option<(Vector3<RayScalar>, RayScalar)> {
+            let direction = self.phase_function.sample(self.direction_in, sampler);
+            Some((
+                direction,
+                self.phase_function.value(self.direction_in, direction),
+            ))
+        }
+    }
+    const PHASE_FUNCTIONS: [PhaseFunction; 7] = [
+        PhaseFunction::Isotropic,
+        PhaseFunction::HenyeyGreenstein { g: 0.6 },
+        PhaseFunction::HenyeyGreenstein { g: -0.3 },
+        PhaseFunction::DoubleHenyeyGreenstein {
+            forward_g: 0.7,
+            backward_g: -0.4,
+            forward_weight: 0.8,
+        },
+        PhaseFunction::Rayleigh,
+        PhaseFunction::Mie { g: 0.5 },
+        PhaseFunction::Mie { g: 0.0 },
+    ];
+    #[test]
+    fn phase_function_pdf() {
+        let world = test_world(false, None);
+        for phase_function in PHASE_FUNCTIONS {
+            check_pdf(
+                &PhasePdf {
+                    phase_function,
+                    direction_in: Vector3::new(0.3, -0.2, 1.0).normalize(),
+                },
+                &world,
+                Point3::new(0.0, 0.0, 0.0),
+            );
+        }
+    }
+    /// the asymmetry of Henyey-Greenstein is the average cosine of the scattering angle
+    #[test]
+    fn henyey_greenstein_asymmetry() {
+        let mut sampler = Sampler::new(0);
+        let direction_in = Vector3::new(0.0, 1.0, 0.0);
+        for g in [-0.5, 0.0, 0.8] {
+            let phase_function = PhaseFunction::HenyeyGreenstein { g };
+            let num_samples = 20_000;
+            let mean = (0..num_samples)
+                .map(|_| phase_function.sample(direction_in, &mut sampler).y)
+                .sum::<RayScalar>()
+                / num_samples as RayScalar;
+            assert!((mean - g).abs() < 0.02, "g: {}, mean cosine: {}", g, mean);
+        }
+    }
+}
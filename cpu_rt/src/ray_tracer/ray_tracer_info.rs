@@ -38,4 +38,9 @@ pub enum EntityField {
     Point3(Point3<RayScalar>),
     Angle(Vector3<RayScalar>),
     Float(RayScalar),
+    /// one value selected out of a list of options
+    Choice {
+        selected: String,
+        options: Vec<String>,
+    },
 }
@@ -20,6 +20,8 @@ pub enum SceneSaveError {
     SystemTimeError(std::time::SystemTimeError),
     UuidParseError(uuid::Error),
     NotFoundInDatabase(String),
+    /// file was saved by a newer version of the program
+    UnsupportedVersion(u32),
 }
 impl From<SqliteError> for SceneSaveError {
     fn from(error: SqliteError) -> Self {
@@ -45,7 +47,9 @@ pub(crate) struct SceneFile {
     database_connection: Connection,
 }
 impl SceneFile {
-    const CURRENT_VERSION: u32 = 0;
+    /// version 1: projection of the camera
+    /// version 2: aperture shape, chromatic aberration and distortion of the camera
+    const CURRENT_VERSION: u32 = 2;
     /// creates new scene file from ray tracer
     fn new(save_path: PathBuf) -> Result<Self, SceneSaveError> {
         if save_path.exists() {
@@ -106,15 +110,23 @@ impl SceneFile {
         Ok(())
     }
     fn load(path: PathBuf) -> Result<WorldInfo, SceneSaveError> {
-        let connection = Connection::open_with_flags(
+        let mut connection = Connection::open_with_flags(
             path,
             OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
         )?;
-        let mut statement = connection.prepare("SELECT scene_id, camera_id FROM scene")?;
-        let (scene_id, camera_id): (Uuid, Uuid) = statement
-            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
-            .next()
-            .unwrap()?;
+        let version: u32 =
+            connection.query_row("SELECT version FROM metadata", [], |row| row.get(0))?;
+        if version > Self::CURRENT_VERSION {
+            return Err(SceneSaveError::UnsupportedVersion(version));
+        }
+        // the tables of older versions are updated in a transaction that is never committed so
+        // the file itself is left alone
+        let connection = connection.transaction()?;
+        Camera::migrate(version, &connection)?;
+        let (scene_id, camera_id): (Uuid, Uuid) =
+            connection.query_row("SELECT scene_id, camera_id FROM scene", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
 
         info!("todo create objects and lights");
 
@@ -148,3 +160,22 @@ impl SceneFileBuilder {
         SceneFile::load(self.save_path)
     }
 }
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn load_examples() {
+        let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+        let world = SceneFile::builder(examples.join("voxel_city_big.mscene"))
+            .load()
+            .unwrap();
+        assert_eq!(
+            world.camera.info().projection,
+            crate::ray_tracer::camera::Projection::Perspective
+        );
+        // the scene table of this file is empty, it gives an error instead of a panic
+        assert!(SceneFile::builder(examples.join("sinnoh.mscene"))
+            .load()
+            .is_err());
+    }
+}
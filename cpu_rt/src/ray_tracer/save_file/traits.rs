@@ -25,6 +25,10 @@ pub(crate) trait Savable: Sized {
             .collect())
     }
     fn load(id: Uuid, connection: &Connection) -> Result<Vec<Self>, SceneSaveError>;
+    /// updates the tables written by an older version of the scene file
+    fn migrate(_version: u32, _connection: &Connection) -> Result<(), SceneSaveError> {
+        Ok(())
+    }
     /// loads first instance of self found in database
     fn load_one(id: Uuid, connection: &Connection) -> Result<Self, SceneSaveError> {
         let mut items = Self::load(id, connection)?;
@@ -37,6 +41,27 @@ pub(crate) trait Savable: Sized {
         }
     }
 }
+/// adds a column that tables of older scene files do not have yet
+pub(crate) fn add_missing_column(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), SceneSaveError> {
+    let exists = connection
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists([column])?;
+    if !exists {
+        connection.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            (),
+        )?;
+    }
+    Ok(())
+}
 impl<T: Savable> DynSavable for T {
     fn database_name(&self) -> String {
         Self::database_name().to_string()
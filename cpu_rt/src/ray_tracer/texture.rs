@@ -1,5 +1,4 @@
 use crate::prelude::*;
-use crate::ray_tracer::rand_vec;
 use cgmath::{InnerSpace, Point2, Point3, Vector3};
 use dyn_clone::clone_box;
 use std::{default::Default, ops::Deref};
@@ -77,18 +76,37 @@ pub struct Perlin {
 }
 impl Perlin {
     const POINT_COUNT: usize = 256;
-    fn perlin_generate_perm() -> [usize; Self::POINT_COUNT] {
+    /// creates the noise from a seed, the same seed always gives the same noise
+    pub fn with_seed(seed: u64) -> Self {
+        let mut sampler = Sampler::new(seed);
+        let mut ran_float = [Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }; Self::POINT_COUNT];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..Self::POINT_COUNT {
+            ran_float[i] = sampler.vec().normalize();
+        }
+        Self {
+            ran_float,
+            perm_x: Self::perlin_generate_perm(&mut sampler),
+            perm_y: Self::perlin_generate_perm(&mut sampler),
+            perm_z: Self::perlin_generate_perm(&mut sampler),
+        }
+    }
+    fn perlin_generate_perm(sampler: &mut Sampler) -> [usize; Self::POINT_COUNT] {
         let mut p = [0; Self::POINT_COUNT];
         #[allow(clippy::needless_range_loop)]
         for i in 0..Self::POINT_COUNT {
             p[i] = i;
         }
-        Self::permute(&mut p, Self::POINT_COUNT);
+        Self::permute(&mut p, Self::POINT_COUNT, sampler);
         p
     }
-    fn permute(a: &mut [usize; Self::POINT_COUNT], n: usize) {
+    fn permute(a: &mut [usize; Self::POINT_COUNT], n: usize, sampler: &mut Sampler) {
         for i in (0..n).rev() {
-            let target = rand_u32(0, i as u32 + 1) as usize;
+            let target = sampler.u32(0, i as u32 + 1) as usize;
             a.swap(i, target);
         }
     }
@@ -161,21 +179,7 @@ impl Perlin {
 }
 impl Default for Perlin {
     fn default() -> Self {
-        let mut ran_float = [Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-        }; Self::POINT_COUNT];
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..Self::POINT_COUNT {
-            ran_float[i] = rand_vec().normalize();
-        }
-        Self {
-            ran_float,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
-        }
+        Self::with_seed(0)
     }
 }
 impl Texture for Perlin {
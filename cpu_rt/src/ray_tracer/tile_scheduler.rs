@@ -0,0 +1,306 @@
+use crate::prelude::*;
+use cgmath::Point2;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+/// Order tiles are rendered in during each pass
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    /// rows from the top of the image
+    Scanline,
+    /// outwards from the center of the image
+    Spiral,
+    /// along a Hilbert curve, keeps tiles rendered close in time close on screen
+    Hilbert,
+}
+impl TileOrder {
+    pub fn names() -> [String; 3] {
+        [
+            "Scanline".to_string(),
+            "Spiral".to_string(),
+            "Hilbert".to_string(),
+        ]
+    }
+}
+impl std::str::FromStr for TileOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Scanline" => Ok(Self::Scanline),
+            "Spiral" => Ok(Self::Spiral),
+            "Hilbert" => Ok(Self::Hilbert),
+            _ => Err(format!("invalid name: {}", s)),
+        }
+    }
+}
+/// Region of the image, tiles on the right and top edge may be smaller than the tile size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub offset: Point2<usize>,
+    pub width: usize,
+    pub height: usize,
+}
+/// number of samples rendered in a tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileProgress {
+    pub tile: Tile,
+    pub num_samples: usize,
+    /// true if a thread is currently rendering the tile
+    pub active: bool,
+}
+/// splits the image into tiles in the given order
+pub(crate) fn make_tiles(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    order: TileOrder,
+) -> Vec<Tile> {
+    assert!(tile_size > 0, "tile size must be greater than zero");
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+    // y = 0 is the bottom of the image so rows are listed from the top
+    let mut positions = (0..tiles_y)
+        .rev()
+        .flat_map(|y| (0..tiles_x).map(move |x| Point2::new(x, y)))
+        .collect::<Vec<_>>();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center_x = (tiles_x as RayScalar - 1.0) / 2.0;
+            let center_y = (tiles_y as RayScalar - 1.0) / 2.0;
+            let key = |p: &Point2<usize>| {
+                let x = p.x as RayScalar - center_x;
+                let y = p.y as RayScalar - center_y;
+                (x.abs().max(y.abs()), y.atan2(x))
+            };
+            positions.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let size = tiles_x.max(tiles_y).next_power_of_two();
+            positions.sort_by_key(|p| hilbert_index(size, p.x, p.y));
+        }
+    }
+    positions
+        .into_iter()
+        .map(|p| {
+            let offset = Point2::new(p.x * tile_size, p.y * tile_size);
+            Tile {
+                offset,
+                width: tile_size.min(width - offset.x),
+                height: tile_size.min(height - offset.y),
+            }
+        })
+        .collect()
+}
+/// distance along the Hilbert curve filling a `size` by `size` grid, `size` must be a power of two
+fn hilbert_index(size: usize, x: usize, y: usize) -> usize {
+    let (mut x, mut y) = (x, y);
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the sub curve has the right orientation
+        if ry == 0 {
+            if rx == 1 {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+#[derive(Debug)]
+struct PassState {
+    pass: usize,
+    /// tiles in the pass that are not finished yet
+    remaining: usize,
+}
+/// Hands out tiles to render threads. Every pass renders each tile once, tiles are dealt out to
+/// the threads round robin and a thread that runs out takes tiles from the back of the other
+/// queues
+pub(crate) struct TileScheduler {
+    tiles: Vec<Tile>,
+    /// tile indices along with the pass they were queued in
+    queues: Vec<Mutex<VecDeque<(usize, usize)>>>,
+    state: Mutex<PassState>,
+    pass_finished: Condvar,
+    /// tile each thread is rendering, `usize::MAX` if it is idle
+    active: Vec<AtomicUsize>,
+}
+impl TileScheduler {
+    pub(crate) fn new(tiles: Vec<Tile>, num_threads: usize) -> Self {
+        assert!(num_threads > 0, "need at least one thread");
+        let scheduler = Self {
+            queues: (0..num_threads)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(PassState {
+                pass: 0,
+                remaining: tiles.len(),
+            }),
+            pass_finished: Condvar::new(),
+            active: (0..num_threads)
+                .map(|_| AtomicUsize::new(usize::MAX))
+                .collect(),
+            tiles,
+        };
+        scheduler.fill_queues(0);
+        scheduler
+    }
+    pub(crate) fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+    fn fill_queues(&self, pass: usize) {
+        let mut queues = self
+            .queues
+            .iter()
+            .map(|queue| queue.lock().expect("failed to get tile queue"))
+            .collect::<Vec<_>>();
+        let num_queues = queues.len();
+        for queue in queues.iter_mut() {
+            queue.clear();
+        }
+        for tile_index in 0..self.tiles.len() {
+            queues[tile_index % num_queues].push_back((tile_index, pass));
+        }
+    }
+    /// Gets the next tile for the thread and the pass it belongs to, returns none if every tile
+    /// in the pass has been handed out
+    pub(crate) fn next_tile(&self, thread_index: usize) -> Option<(usize, usize)> {
+        let num_queues = self.queues.len();
+        let own = self.queues[thread_index]
+            .lock()
+            .expect("failed to get tile queue")
+            .pop_front();
+        let (tile_index, pass) = own.or_else(|| {
+            (1..num_queues).find_map(|i| {
+                self.queues[(thread_index + i) % num_queues]
+                    .lock()
+                    .expect("failed to get tile queue")
+                    .pop_back()
+            })
+        })?;
+        self.active[thread_index].store(tile_index, Ordering::Relaxed);
+        Some((tile_index, pass))
+    }
+    /// marks the thread's tile as done, starts the next pass once every tile is finished
+    pub(crate) fn finish_tile(&self, thread_index: usize, pass: usize) {
+        self.active[thread_index].store(usize::MAX, Ordering::Relaxed);
+        let mut state = self.state.lock().expect("failed to get pass state");
+        if state.pass != pass {
+            return;
+        }
+        state.remaining -= 1;
+        if state.remaining == 0 {
+            self.start_pass(&mut state);
+        }
+    }
+    /// starts over from the first tile, used when the scene changes
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.lock().expect("failed to get pass state");
+        self.start_pass(&mut state);
+    }
+    fn start_pass(&self, state: &mut PassState) {
+        state.pass += 1;
+        self.fill_queues(state.pass);
+        state.remaining = self.tiles.len();
+        self.pass_finished.notify_all();
+    }
+    /// blocks until the next pass starts or the timeout runs out
+    pub(crate) fn wait_for_pass(&self, pass: usize, timeout: Duration) {
+        let state = self.state.lock().expect("failed to get pass state");
+        let _state = self
+            .pass_finished
+            .wait_timeout_while(state, timeout, |state| state.pass == pass)
+            .expect("failed to get pass state");
+    }
+    pub(crate) fn current_pass(&self) -> usize {
+        self.state.lock().expect("failed to get pass state").pass
+    }
+    /// true if a thread is rendering the tile
+    pub(crate) fn is_active(&self, tile_index: usize) -> bool {
+        self.active
+            .iter()
+            .any(|active| active.load(Ordering::Relaxed) == tile_index)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn covers_image(tiles: &[Tile], width: usize, height: usize) -> bool {
+        let mut count = vec![0; width * height];
+        for tile in tiles {
+            for x in tile.offset.x..tile.offset.x + tile.width {
+                for y in tile.offset.y..tile.offset.y + tile.height {
+                    count[x + y * width] += 1;
+                }
+            }
+        }
+        count.iter().all(|c| *c == 1)
+    }
+    #[test]
+    fn tiles_cover_image() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = make_tiles(100, 70, 16, order);
+            assert_eq!(tiles.len(), 7 * 5);
+            assert!(covers_image(&tiles, 100, 70));
+        }
+    }
+    #[test]
+    fn spiral_starts_in_center() {
+        let tiles = make_tiles(48, 48, 16, TileOrder::Spiral);
+        assert_eq!(tiles[0].offset, Point2::new(16, 16));
+    }
+    #[test]
+    fn hilbert_tiles_are_adjacent() {
+        let tiles = make_tiles(128, 128, 16, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dx = pair[0].offset.x.abs_diff(pair[1].offset.x);
+            let dy = pair[0].offset.y.abs_diff(pair[1].offset.y);
+            assert_eq!(dx + dy, 16);
+        }
+    }
+    #[test]
+    fn steals_work() {
+        let scheduler = TileScheduler::new(make_tiles(64, 64, 16, TileOrder::Scanline), 4);
+        let pass = scheduler.current_pass();
+        // one thread takes every tile in the pass, including the other threads' tiles
+        let mut rendered = vec![];
+        for _ in 0..16 {
+            let (tile_index, tile_pass) = scheduler.next_tile(0).unwrap();
+            assert_eq!(tile_pass, pass);
+            assert!(scheduler.is_active(tile_index));
+            rendered.push(tile_index);
+            scheduler.finish_tile(0, tile_pass);
+        }
+        rendered.sort();
+        assert_eq!(rendered, (0..16).collect::<Vec<_>>());
+        assert_eq!(scheduler.current_pass(), pass + 1);
+        assert!(scheduler.next_tile(1).is_some());
+    }
+    #[test]
+    fn reset_ignores_old_tiles() {
+        let scheduler = TileScheduler::new(make_tiles(32, 32, 16, TileOrder::Scanline), 1);
+        let (_, old_pass) = scheduler.next_tile(0).unwrap();
+        scheduler.reset();
+        let pass = scheduler.current_pass();
+        scheduler.finish_tile(0, old_pass);
+        // the new pass still needs all four tiles
+        for _ in 0..4 {
+            let (_, tile_pass) = scheduler.next_tile(0).unwrap();
+            assert_eq!(tile_pass, pass);
+            scheduler.finish_tile(0, tile_pass);
+        }
+        assert_eq!(scheduler.current_pass(), pass + 1);
+    }
+}
@@ -1,4 +1,5 @@
 mod cornell_smoke;
+mod csg;
 mod demo;
 mod dielectric;
 
@@ -6,19 +7,24 @@ mod easy_cornell_box;
 mod easy_scene;
 mod empty_scene;
 mod fast_oct_tree;
+mod heightfield;
 mod light_demo;
 
 mod one_sphere;
+mod primitives;
 mod random_scene;
+mod sdf;
+mod subsurface;
 mod two_spheres;
 
 use super::sun::Sun;
 use super::{
     background::Sky,
-    bvh::BvhTree,
+    bvh::{Aabb, BvhTree},
     camera::{Camera, CameraInfo},
     hittable::*,
     material::*,
+    phase_function::PhaseFunction,
     ray_tracer_info::{EntityField, WorldEntityCollection},
     texture::*,
     Background, ConstantColor, HitRecord, Hittable,
@@ -31,10 +37,12 @@ mod world_prelude {
         background::Sky,
         camera::{Camera, CameraInfo},
         hittable::{
-            Object, SolidVoxel, Sphere, Transform, VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
+            FbmDensity, HeterogeneousMedium, Hittable, Object, SolidVoxel, Sphere, Transform,
+            VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
         },
-        material::DiffuseLight,
-        texture::SolidColor,
+        material::{DiffuseLight, Medium},
+        phase_function::PhaseFunction,
+        texture::{Perlin, SolidColor},
     };
     pub(crate) use crate::prelude::{RayScalar, RgbColor};
 }
@@ -100,17 +108,25 @@ impl World {
         ray: &Ray,
         t_min: RayScalar,
         t_max: RayScalar,
+        sampler: &mut Sampler,
     ) -> Option<(Object, HitRecord)> {
         self.lights
             .iter()
-            .map(|light| (light.clone(), light.hit(ray, t_min, t_max)))
+            .map(|light| (light.clone(), light.hit(ray, t_min, t_max, sampler)))
             .filter(|(_light, hit_opt)| hit_opt.is_some())
             .map(|(light, hit_opt)| (light, hit_opt.unwrap()))
             .reduce(|acc, x| if acc.1.t < x.1.t { acc } else { x })
     }
 
-    pub fn nearest_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.bvh.hit(ray, t_min as RayScalar, t_max as RayScalar)
+    pub fn nearest_hit(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord> {
+        self.bvh
+            .hit(ray, t_min as RayScalar, t_max as RayScalar, sampler)
     }
     pub fn get_entity_info(&self) -> WorldEntityCollection {
         WorldEntityCollection {
@@ -119,6 +135,22 @@ impl World {
         }
     }
 
+    /// Focuses the camera on the object seen at the given screen coordinates, returns the new
+    /// focus distance or none if nothing was hit
+    pub fn autofocus(&mut self, u: RayScalar, v: RayScalar) -> Option<RayScalar> {
+        let ray = self.camera.get_focus_ray(u, v)?;
+        let hit = self.nearest_hit(&ray, 0.001, f32::MAX, &mut Sampler::new(0))?;
+        self.camera.focus_on(hit.position)
+    }
+    /// sets the transform of an object, the bvh is refit automatically
+    pub fn set_entity_transform(&mut self, index: usize, transform: Transform) {
+        self.bvh.set_transform(index, transform)
+    }
+    /// fully rebuilds the bvh, can give a better tree after many edits
+    pub fn rebuild_bvh(&mut self) {
+        self.bvh
+            .rebuild(self.camera.start_time(), self.camera.end_time())
+    }
     pub fn set_camera_data(&mut self, key: String, value: EntityField) {
         self.camera.set_field(key, value);
     }
@@ -196,6 +228,26 @@ pub fn get_scenarios() -> Scenarios {
             name: "Dielectric Demonstration, High Refraction".to_string(),
             f: dielectric::dielectric_refraction,
         }),
+        Box::new(ScenarioFn {
+            name: "CSG Demonstration".to_string(),
+            f: csg::csg_demo,
+        }),
+        Box::new(ScenarioFn {
+            name: "SDF Demonstration".to_string(),
+            f: sdf::sdf_demo,
+        }),
+        Box::new(ScenarioFn {
+            name: "Primitive Demonstration".to_string(),
+            f: primitives::primitive_demo,
+        }),
+        Box::new(ScenarioFn {
+            name: "Heightfield Demonstration".to_string(),
+            f: heightfield::heightfield_demo,
+        }),
+        Box::new(ScenarioFn {
+            name: "Subsurface Demonstration".to_string(),
+            f: subsurface::subsurface_demo,
+        }),
         Box::new(ScenarioFn {
             name: "Light Demonstration".to_string(),
             f: light_demo::light_demo,
@@ -256,6 +308,10 @@ pub fn get_scenarios() -> Scenarios {
             name: "Fast Oct Tree City".to_string(),
             f: fast_oct_tree::city,
         }),
+        Box::new(ScenarioFn {
+            name: "Instanced City".to_string(),
+            f: fast_oct_tree::instanced_city,
+        }),
     ];
     let map: HashMap<String, Box<dyn ScenarioCtor>> = scenes
         .drain(..)
@@ -1,6 +1,6 @@
 use super::{
-    Camera, CameraInfo, ConstantColor, ConstantMedium, DiffuseLight, Isotropic, Lambertian, Object,
-    RenderBox, SolidColor, Sphere, Transform, WorldInfo, XYRect, XZRect, YZRect,
+    Camera, CameraInfo, ConstantColor, ConstantMedium, DiffuseLight, Lambertian, Medium, Object,
+    PhaseFunction, RenderBox, SolidColor, Sphere, Transform, WorldInfo, XYRect, XZRect, YZRect,
 };
 use crate::prelude::*;
 use cgmath::{prelude::*, Point3, Vector3};
@@ -103,10 +103,11 @@ pub fn cornell_smoke() -> WorldInfo {
                         Point3::new(165.0, 330.0, 165.0),
                         white.clone(),
                     )),
-                    Box::new(Isotropic {
+                    Box::new(Medium {
                         albedo: Box::new(SolidColor {
                             color: RgbColor::new(0.0, 0.0, 0.0),
                         }),
+                        phase_function: PhaseFunction::HenyeyGreenstein { g: 0.6 },
                     }),
                     0.01,
                 )),
@@ -119,10 +120,11 @@ pub fn cornell_smoke() -> WorldInfo {
                         origin: Point3::new(0.0, 0.0, 0.0),
                         material: white.clone(),
                     }),
-                    Box::new(Isotropic {
+                    Box::new(Medium {
                         albedo: Box::new(SolidColor {
                             color: RgbColor::new(0.5, 0.0, 0.0),
                         }),
+                        phase_function: PhaseFunction::HenyeyGreenstein { g: 0.6 },
                     }),
                     0.01,
                 )),
@@ -153,6 +155,7 @@ pub fn cornell_smoke() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -0,0 +1,112 @@
+use super::{
+    Camera, CameraInfo, Csg, DiffuseLight, Hittable, Lambertian, Material, Metal, Object,
+    RenderBox, Sky, SolidColor, Sphere, Transform, WorldInfo, XZRect,
+};
+use crate::prelude::*;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+fn lambertian(color: RgbColor) -> Box<dyn Material> {
+    Box::new(Lambertian {
+        albedo: Box::new(SolidColor { color }),
+    })
+}
+fn sphere(origin: Point3<RayScalar>, radius: RayScalar) -> Box<dyn Hittable> {
+    Box::new(Sphere {
+        radius,
+        origin,
+        material: lambertian(RgbColor::WHITE),
+    })
+}
+/// a hollow sphere cut open, a wall with a round window and a lens
+pub fn csg_demo() -> WorldInfo {
+    let look_at = Point3::<RayScalar>::new(0.0, 0.8, 0.0);
+    let origin = Point3::<RayScalar>::new(10.0, 5.0, 3.0);
+    let focus_distance = (look_at - origin).magnitude();
+
+    let floor = Object::new(
+        Box::new(XZRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            lambertian(RgbColor::new(0.5, 0.5, 0.5)),
+            false,
+        )),
+        Transform::identity(),
+    );
+    let light = Object::new(
+        Box::new(Sphere {
+            radius: 1.5,
+            origin: Point3::new(3.0, 6.0, -4.0),
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: 8.0 * RgbColor::WHITE,
+                }),
+            }),
+        }),
+        Transform::identity(),
+    );
+    let hollow_sphere = Csg::difference(
+        sphere(Point3::new(0.0, 0.8, 1.8), 0.8),
+        sphere(Point3::new(0.0, 0.8, 1.8), 0.7),
+        lambertian(RgbColor::new(0.8, 0.3, 0.2)),
+    );
+    // the top is cut off to see into the sphere
+    let hollow_sphere = Object::new(
+        Box::new(Csg::difference(
+            Box::new(hollow_sphere),
+            Box::new(RenderBox::new(
+                Point3::new(-1.0, 1.2, 0.8),
+                Point3::new(1.0, 2.0, 2.8),
+                lambertian(RgbColor::WHITE),
+            )),
+            lambertian(RgbColor::new(0.8, 0.3, 0.2)),
+        )),
+        Transform::identity(),
+    );
+    let window = Object::new(
+        Box::new(Csg::difference(
+            Box::new(RenderBox::new(
+                Point3::new(-1.5, 0.0, -0.8),
+                Point3::new(-1.3, 2.0, 0.8),
+                lambertian(RgbColor::WHITE),
+            )),
+            sphere(Point3::new(-1.4, 1.0, 0.0), 0.5),
+            lambertian(RgbColor::new(0.9, 0.9, 0.8)),
+        )),
+        Transform::identity(),
+    );
+    let lens = Object::new(
+        Box::new(Csg::intersection(
+            sphere(Point3::new(0.0, 0.8, -1.0), 1.0),
+            sphere(Point3::new(0.0, 0.8, -2.4), 1.0),
+            Box::new(Metal {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::new(0.8, 0.8, 0.9),
+                }),
+                fuzz: 0.05,
+            }),
+        )),
+        Transform::identity(),
+    );
+    WorldInfo {
+        objects: vec![floor, light.clone(), hollow_sphere, window, lens],
+        lights: vec![light],
+        background: Box::new(Sky { intensity: 0.2 }),
+        camera: Camera::new(CameraInfo {
+            aspect_ratio: 1.0,
+            fov: 30.0,
+            origin,
+            look_at,
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance,
+            start_time: 0.0,
+            end_time: 0.0,
+            ..Default::default()
+        }),
+        sun: None,
+    }
+}
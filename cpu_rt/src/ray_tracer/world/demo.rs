@@ -61,6 +61,7 @@ pub fn new_demo(mut special_item: Vec<Object>) -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
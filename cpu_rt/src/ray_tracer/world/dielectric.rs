@@ -85,6 +85,7 @@ pub fn dielectric_demo(refraction: RayScalar) -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -101,6 +101,7 @@ pub fn easy_cornell_box() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
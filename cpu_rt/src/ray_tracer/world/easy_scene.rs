@@ -176,6 +176,7 @@ pub fn easy_scene() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
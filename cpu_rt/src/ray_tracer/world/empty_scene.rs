@@ -27,6 +27,7 @@ pub fn empty_scene() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
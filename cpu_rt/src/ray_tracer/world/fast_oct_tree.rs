@@ -1,15 +1,14 @@
 use super::{
     world_prelude::{
-        Camera, CameraInfo, ConstantColor, DiffuseLight, Object, RayScalar, RgbColor, Sky,
-        SolidColor, SolidVoxel, Sphere, Transform, VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
+        Camera, CameraInfo, ConstantColor, DiffuseLight, FbmDensity, HeterogeneousMedium, Hittable,
+        Medium, Object, Perlin, PhaseFunction, RayScalar, RgbColor, Sky, SolidColor, SolidVoxel,
+        Sphere, Transform, VolumeEdgeEffect, VolumeVoxel, Voxel, VoxelGrid,
     },
     WorldInfo,
 };
-use crate::prelude::IterBox;
 use cgmath::{prelude::*, Point2, Point3, Vector3};
 
-use log::info;
-use std::cmp::max;
+use std::sync::Arc;
 pub fn fast_oct_tree_sphere() -> WorldInfo {
     //let origin = Point3::<RayScalar>::new(1.0, 1.0, 1.0);
     let origin = Point3::<RayScalar>::new(-100.0, 10., 100.0);
@@ -57,6 +56,7 @@ pub fn fast_oct_tree_sphere() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -96,10 +96,41 @@ pub fn sinnoh() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
 }
+/// smoke that fills the ellipsoid of its density and mostly scatters light forward
+fn plume(
+    center: Point3<RayScalar>,
+    radii: Vector3<RayScalar>,
+    density: FbmDensity,
+) -> HeterogeneousMedium {
+    let radius = radii.x.max(radii.y).max(radii.z);
+    HeterogeneousMedium::new(
+        Box::new(Sphere {
+            radius,
+            origin: center,
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: RgbColor::BLACK,
+                }),
+            }),
+        }),
+        Box::new(Medium {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.8, 0.8, 0.8),
+            }),
+            phase_function: PhaseFunction::DoubleHenyeyGreenstein {
+                forward_g: 0.7,
+                backward_g: -0.3,
+                forward_weight: 0.85,
+            },
+        }),
+        Box::new(density),
+    )
+}
 pub fn explosion() -> WorldInfo {
     let look_at = Point3::<RayScalar>::new(50.0, 10.0, 50.0);
 
@@ -133,25 +164,58 @@ pub fn explosion() -> WorldInfo {
         h.max(0.0).min((MAX_Y - 1) as f32) as isize
     }
     let mut tree = VoxelGrid::new();
-    for x in 0..100 {
-        for z in 0..100 {
-            let h = height(x, z);
-            for y in 0..=h {
-                let albedo = if y < 9 {
+    tree.fill_with(
+        Point3::new(0, 0, 0),
+        Point3::new(100, MAX_Y as u32, 100),
+        |position| {
+            (position.y as isize <= height(position.x as isize, position.z as isize)).then(|| {
+                let albedo = if position.y < 9 {
                     RgbColor::new(0.65, 0.8, 0.05)
                 } else {
                     RgbColor::new(0.65, 0.05, 0.05)
                 };
-                tree.set(
-                    Voxel::Solid(SolidVoxel::Lambertian { albedo }),
-                    Point3::new(x as u32, y as u32, z as u32),
-                );
-            }
-        }
-    }
+                Voxel::Solid(SolidVoxel::Lambertian { albedo })
+            })
+        },
+    );
+    let smoke_center = Point3::new(50.0, 42.0, 50.0);
+    let smoke_radii = Vector3::new(9.0, 12.0, 9.0);
+    let smoke = plume(
+        smoke_center,
+        smoke_radii,
+        FbmDensity {
+            noise: Perlin::with_seed(1),
+            center: smoke_center,
+            radii: smoke_radii,
+            frequency: 0.25,
+            octaves: 5,
+            density: 0.4,
+        },
+    )
+    .with_absorption(RgbColor::new(0.08, 0.1, 0.12));
+    // fire around the lava, it only glows where it absorbs light
+    let fire_center = Point3::new(50.0, 30.0, 50.0);
+    let fire_radii = Vector3::new(6.0, 7.0, 6.0);
+    let fire = plume(
+        fire_center,
+        fire_radii,
+        FbmDensity {
+            noise: Perlin::with_seed(2),
+            center: fire_center,
+            radii: fire_radii,
+            frequency: 0.4,
+            octaves: 4,
+            density: 0.5,
+        },
+    )
+    .with_scattering(0.1)
+    .with_absorption(RgbColor::new(0.5, 0.6, 0.7))
+    .with_emission(RgbColor::new(20.0, 6.0, 1.0));
     WorldInfo {
         objects: vec![
             Object::new(Box::new(tree), Transform::identity()),
+            Object::new(Box::new(smoke), Transform::identity()),
+            Object::new(Box::new(fire), Transform::identity()),
             lava_light.clone(),
         ],
         lights: vec![lava_light],
@@ -166,6 +230,7 @@ pub fn explosion() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -195,18 +260,13 @@ pub fn cube_world() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    for i in 3..6 {
-        for j in 3..6 {
-            for k in 3..6 {
-                tree.set(
-                    Voxel::Solid(SolidVoxel::Lambertian {
-                        albedo: RgbColor::new(0.65, 0.05, 0.05),
-                    }),
-                    Point3::new(i, j, k),
-                )
-            }
-        }
-    }
+    tree.fill_aabb(
+        Point3::new(3, 3, 3),
+        Point3::new(6, 6, 6),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.65, 0.05, 0.05),
+        }),
+    );
     for pos in [
         Point3::new(0, 0, 0),
         Point3::new(0, 1, 0),
@@ -238,6 +298,7 @@ pub fn cube_world() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -272,33 +333,64 @@ pub fn volcano() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    let mut max_data_len = 0;
-    for (i, position) in IterBox::from_xyz(1000, 1000, 1000).iter().enumerate() {
-        max_data_len = max(tree.stats().arena_stats.num_deleted_elements, max_data_len);
-        if i % 10_000_000 == 0 {
-            info!("i: {}", i);
-            info!("max data len: {}", max_data_len);
-        }
-        let position_float = position.map(|v| v as RayScalar);
-        let mountain_cone = cone(position_float, Point3::new(500., 300., 500.), -1.);
-        let crater = cone(position_float, Point3::new(500., 125., 500.), 0.3);
-        if mountain_cone && (!crater || position.y < 150) {
-            let value = if crater {
-                Voxel::Solid(SolidVoxel::Lambertian {
-                    albedo: RgbColor::from_color_hex("#ffbb00") * 80.,
-                })
-            } else {
-                Voxel::Solid(SolidVoxel::Lambertian {
-                    albedo: RgbColor::from_color_hex("#5f1515"),
-                })
-            };
-            tree.set(value, position);
-        }
-    }
+    tree.fill_with(
+        Point3::new(0, 0, 0),
+        Point3::new(1000, 1000, 1000),
+        |position| {
+            let position_float = position.map(|v| v as RayScalar);
+            let mountain_cone = cone(position_float, Point3::new(500., 300., 500.), -1.);
+            let crater = cone(position_float, Point3::new(500., 125., 500.), 0.3);
+            (mountain_cone && (!crater || position.y < 150)).then(|| {
+                if crater {
+                    Voxel::Solid(SolidVoxel::Lambertian {
+                        albedo: RgbColor::from_color_hex("#ffbb00") * 80.,
+                    })
+                } else {
+                    Voxel::Solid(SolidVoxel::Lambertian {
+                        albedo: RgbColor::from_color_hex("#5f1515"),
+                    })
+                }
+            })
+        },
+    );
 
+    let smoke_center = Point3::new(500.0, 380.0, 500.0);
+    let smoke_radii = Vector3::new(110.0, 220.0, 110.0);
+    let smoke = plume(
+        smoke_center,
+        smoke_radii,
+        FbmDensity {
+            noise: Perlin::with_seed(3),
+            center: smoke_center,
+            radii: smoke_radii,
+            frequency: 0.015,
+            octaves: 6,
+            density: 0.05,
+        },
+    )
+    .with_absorption(RgbColor::new(1.5, 1.6, 1.8));
+    let fire_center = Point3::new(500.0, 170.0, 500.0);
+    let fire_radii = Vector3::new(80.0, 35.0, 80.0);
+    let fire = plume(
+        fire_center,
+        fire_radii,
+        FbmDensity {
+            noise: Perlin::with_seed(4),
+            center: fire_center,
+            radii: fire_radii,
+            frequency: 0.03,
+            octaves: 4,
+            density: 0.03,
+        },
+    )
+    .with_scattering(0.2)
+    .with_absorption(RgbColor::new(0.6, 0.7, 0.8))
+    .with_emission(RgbColor::new(8.0, 2.5, 0.4));
     WorldInfo {
         objects: vec![
             Object::new(Box::new(tree), Transform::identity()),
+            Object::new(Box::new(smoke), Transform::identity()),
+            Object::new(Box::new(fire), Transform::identity()),
             top_light.clone(),
         ],
         lights: vec![top_light],
@@ -315,6 +407,7 @@ pub fn volcano() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -344,24 +437,21 @@ pub fn volume_two_density() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    for pos in IterBox::from_xyz(10, 9, 10).start_xyz(0, 1, 0).iter() {
-        tree.set(
-            Voxel::Volume(VolumeVoxel {
-                density: if pos.y < 5 { 0.3 } else { 0.6 },
-                color: RgbColor::new(0.5, 0.05, 0.5),
-                edge_effect: VolumeEdgeEffect::None,
-            }),
-            pos,
-        );
-    }
-    for pos in IterBox::from_xyz(6, 6, 6).start_xyz(3, 3, 3).iter() {
-        tree.set(
-            Voxel::Solid(SolidVoxel::Lambertian {
-                albedo: RgbColor::new(0.65, 0.05, 0.05),
-            }),
-            pos,
-        );
-    }
+    tree.fill_with(Point3::new(0, 1, 0), Point3::new(10, 9, 10), |pos| {
+        Some(Voxel::Volume(VolumeVoxel {
+            density: if pos.y < 5 { 0.3 } else { 0.6 },
+            color: RgbColor::new(0.5, 0.05, 0.5),
+            edge_effect: VolumeEdgeEffect::None,
+            phase_function: PhaseFunction::Isotropic,
+        }))
+    });
+    tree.fill_aabb(
+        Point3::new(3, 3, 3),
+        Point3::new(6, 6, 6),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.65, 0.05, 0.05),
+        }),
+    );
     tree.set(
         Voxel::Solid(SolidVoxel::Lambertian {
             albedo: RgbColor::new(0.65, 0.05, 0.05),
@@ -401,6 +491,7 @@ pub fn volume_two_density() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -430,29 +521,28 @@ pub fn volume_lambertian() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    for position in IterBox::from_xyz(10, 9, 10).start_xyz(0, 1, 0).iter() {
-        tree.set(
-            Voxel::Volume(VolumeVoxel {
-                density: 0.3,
-                color: RgbColor::new(0.5, 0.05, 0.5),
-                edge_effect: VolumeEdgeEffect::Solid {
-                    hit_probability: 0.6,
-                    solid_material: SolidVoxel::Lambertian {
-                        albedo: RgbColor::new(0.5, 0.05, 0.5),
-                    },
+    tree.fill_aabb(
+        Point3::new(0, 1, 0),
+        Point3::new(10, 9, 10),
+        Voxel::Volume(VolumeVoxel {
+            density: 0.3,
+            color: RgbColor::new(0.5, 0.05, 0.5),
+            edge_effect: VolumeEdgeEffect::Solid {
+                hit_probability: 0.6,
+                solid_material: SolidVoxel::Lambertian {
+                    albedo: RgbColor::new(0.5, 0.05, 0.5),
                 },
-            }),
-            position,
-        );
-    }
-    for position in IterBox::from_xyz(6, 6, 6).start_xyz(3, 3, 3).iter() {
-        tree.set(
-            Voxel::Solid(SolidVoxel::Lambertian {
-                albedo: RgbColor::new(0.65, 0.05, 0.05),
-            }),
-            position,
-        )
-    }
+            },
+            phase_function: PhaseFunction::Isotropic,
+        }),
+    );
+    tree.fill_aabb(
+        Point3::new(3, 3, 3),
+        Point3::new(6, 6, 6),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.65, 0.05, 0.05),
+        }),
+    );
     tree.set(
         Voxel::Solid(SolidVoxel::Lambertian {
             albedo: RgbColor::new(0.65, 0.05, 0.05),
@@ -491,6 +581,7 @@ pub fn volume_lambertian() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -518,30 +609,29 @@ pub fn volume_metal() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    for position in IterBox::from_xyz(10, 9, 10).start_xyz(0, 1, 0).iter() {
-        tree.set(
-            Voxel::Volume(VolumeVoxel {
-                density: 0.3,
-                color: RgbColor::new(0.5, 0.05, 0.5),
-                edge_effect: VolumeEdgeEffect::Solid {
-                    hit_probability: 0.6,
-                    solid_material: SolidVoxel::Reflect {
-                        albedo: RgbColor::new(0.5, 0.05, 0.5),
-                        fuzz: 0.3,
-                    },
+    tree.fill_aabb(
+        Point3::new(0, 1, 0),
+        Point3::new(10, 9, 10),
+        Voxel::Volume(VolumeVoxel {
+            density: 0.3,
+            color: RgbColor::new(0.5, 0.05, 0.5),
+            edge_effect: VolumeEdgeEffect::Solid {
+                hit_probability: 0.6,
+                solid_material: SolidVoxel::Reflect {
+                    albedo: RgbColor::new(0.5, 0.05, 0.5),
+                    fuzz: 0.3,
                 },
-            }),
-            position,
-        );
-    }
-    for position in IterBox::from_xyz(6, 6, 6).start_xyz(3, 3, 3).iter() {
-        tree.set(
-            Voxel::Solid(SolidVoxel::Lambertian {
-                albedo: RgbColor::new(0.65, 0.05, 0.05),
-            }),
-            position,
-        );
-    }
+            },
+            phase_function: PhaseFunction::Isotropic,
+        }),
+    );
+    tree.fill_aabb(
+        Point3::new(3, 3, 3),
+        Point3::new(6, 6, 6),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.65, 0.05, 0.05),
+        }),
+    );
     tree.set(
         Voxel::Solid(SolidVoxel::Lambertian {
             albedo: RgbColor::new(0.65, 0.05, 0.05),
@@ -560,14 +650,13 @@ pub fn volume_metal() -> WorldInfo {
         }),
         Point3::new(5, 5, 5),
     );
-    for position in IterBox::from_xyz(40, 1, 40).iter() {
-        tree.set(
-            Voxel::Solid(SolidVoxel::Lambertian {
-                albedo: RgbColor::new(0.9, 0.9, 0.9),
-            }),
-            position,
-        )
-    }
+    tree.fill_aabb(
+        Point3::new(0, 0, 0),
+        Point3::new(40, 1, 40),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.9, 0.9, 0.9),
+        }),
+    );
     WorldInfo {
         objects: vec![
             Object::new(Box::new(tree), Transform::identity()),
@@ -588,6 +677,7 @@ pub fn volume_metal() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -615,11 +705,10 @@ pub fn volume_ice() -> WorldInfo {
         Transform::identity(),
     );
     let mut tree = VoxelGrid::new();
-    for position in IterBox::from_xyz(10, 9, 10).start_xyz(0, 1, 0).iter() {
-        let offset = Vector3::new(10, 0, 10);
-        let ice_color = RgbColor::from_color_hex("#06068dff");
-        let snow_color = RgbColor::from_color_hex("#ffffffff");
-        let value = if position.y >= 8 {
+    let ice_color = RgbColor::from_color_hex("#06068dff");
+    let snow_color = RgbColor::from_color_hex("#ffffffff");
+    tree.fill_with(Point3::new(10, 1, 10), Point3::new(20, 9, 20), |position| {
+        Some(if position.y >= 8 {
             Voxel::Volume(VolumeVoxel {
                 density: 0.001,
                 color: snow_color,
@@ -627,6 +716,7 @@ pub fn volume_ice() -> WorldInfo {
                     hit_probability: 1.,
                     solid_material: SolidVoxel::Lambertian { albedo: snow_color },
                 },
+                phase_function: PhaseFunction::Isotropic,
             })
         } else {
             Voxel::Volume(VolumeVoxel {
@@ -639,10 +729,10 @@ pub fn volume_ice() -> WorldInfo {
                         fuzz: 0.1,
                     },
                 },
+                phase_function: PhaseFunction::Isotropic,
             })
-        };
-        tree.set(value, position + offset);
-    }
+        })
+    });
     tree.set(
         Voxel::Solid(SolidVoxel::Lambertian {
             albedo: RgbColor::new(0.65, 0.05, 0.05),
@@ -655,18 +745,15 @@ pub fn volume_ice() -> WorldInfo {
         }),
         Point3::new(0, 1, 0),
     );
-    for position in IterBox::from_xyz(40, 0, 40).iter() {
+    tree.fill_with(Point3::new(0, 0, 0), Point3::new(40, 1, 40), |position| {
         let v = (position.x + position.z) & 0x1;
         let color = match v {
             0 => RgbColor::from_color_hex("#ffffffff"),
             1 => RgbColor::from_color_hex("#fc0202ff"),
             _ => panic!("can never get here"),
         };
-        tree.set(
-            Voxel::Solid(SolidVoxel::Lambertian { albedo: color }),
-            position,
-        );
-    }
+        Some(Voxel::Solid(SolidVoxel::Lambertian { albedo: color }))
+    });
     WorldInfo {
         objects: vec![
             Object::new(Box::new(tree), Transform::identity()),
@@ -685,6 +772,7 @@ pub fn volume_ice() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -710,57 +798,44 @@ pub fn gold_cube() -> WorldInfo {
     );
     let cube_size = Vector3::new(16, 16, 16);
     let mut world = VoxelGrid::new();
-    for position in IterBox::from_xyz(128, 1, 128).iter() {
-        world.set(
-            Voxel::Solid(SolidVoxel::Lambertian {
-                albedo: RgbColor {
-                    red: 0.8,
-                    green: 0.8,
-                    blue: 0.8,
-                },
-            }),
-            position,
-        );
-    }
+    world.fill_aabb(
+        Point3::new(0, 0, 0),
+        Point3::new(128, 1, 128),
+        Voxel::Solid(SolidVoxel::Lambertian {
+            albedo: RgbColor {
+                red: 0.8,
+                green: 0.8,
+                blue: 0.8,
+            },
+        }),
+    );
     let g1_offset = Point3::new(30, 1, 64);
-    for position in IterBox::from_point(g1_offset + cube_size)
-        .start(g1_offset)
-        .iter()
-    {
-        world.set(
-            Voxel::Solid(SolidVoxel::Reflect {
-                albedo: RgbColor::new(1., 254. / 255., 0.),
-                fuzz: 0.8,
-            }),
-            position,
-        );
-    }
+    world.fill_aabb(
+        g1_offset,
+        g1_offset + cube_size,
+        Voxel::Solid(SolidVoxel::Reflect {
+            albedo: RgbColor::new(1., 254. / 255., 0.),
+            fuzz: 0.8,
+        }),
+    );
     let g2_offset = Point3::new(60, 1, 64);
-    for position in IterBox::from_point(g2_offset + cube_size)
-        .start(g2_offset)
-        .iter()
-    {
-        world.set(
-            Voxel::Solid(SolidVoxel::Reflect {
-                albedo: RgbColor::new(1., 254. / 255., 0.),
-                fuzz: 0.4,
-            }),
-            position,
-        );
-    }
+    world.fill_aabb(
+        g2_offset,
+        g2_offset + cube_size,
+        Voxel::Solid(SolidVoxel::Reflect {
+            albedo: RgbColor::new(1., 254. / 255., 0.),
+            fuzz: 0.4,
+        }),
+    );
     let g3_offset = Point3::new(90, 1, 64);
-    for position in IterBox::from_point(g3_offset + cube_size)
-        .start(g3_offset)
-        .iter()
-    {
-        world.set(
-            Voxel::Solid(SolidVoxel::Reflect {
-                albedo: RgbColor::new(1., 254. / 255., 0.),
-                fuzz: 0.1,
-            }),
-            position,
-        );
-    }
+    world.fill_aabb(
+        g3_offset,
+        g3_offset + cube_size,
+        Voxel::Solid(SolidVoxel::Reflect {
+            albedo: RgbColor::new(1., 254. / 255., 0.),
+            fuzz: 0.1,
+        }),
+    );
     WorldInfo {
         objects: vec![
             Object::new(Box::new(world), Transform::identity()),
@@ -780,6 +855,7 @@ pub fn gold_cube() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -811,16 +887,51 @@ pub fn apartment_building() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
         sun: None,
     }
 }
-pub fn city() -> WorldInfo {
-    const ROAD_WIDTH: u32 = 9;
+const CITY_ROAD_WIDTH: u32 = 9;
+const CITY_CELL_SIZE: u32 = 64;
+const CITY_NUMBER_GRIDS: u32 = 5;
+/// roads of the city and the building placed on every block
+fn city_parts() -> (VoxelGrid, VoxelGrid) {
     const ROAD_LENGTH: u32 = 400;
     const LINE_POSITION: u32 = 4;
-    const CELL_SIZE: u32 = 64;
-    const NUMBER_GRIDS: u32 = 5;
+    let building0 =
+        VoxelGrid::load_vox("./voxel_assets/city/skyscraper0.vox").expect("failed to load");
+    let mut grid = VoxelGrid::new();
+    for i in 0..CITY_NUMBER_GRIDS {
+        let x_offset = i * (CITY_CELL_SIZE + CITY_ROAD_WIDTH);
+        grid.fill_with(
+            Point3::new(x_offset, 0, 0),
+            Point3::new(CITY_ROAD_WIDTH + x_offset, 1, ROAD_LENGTH),
+            |point| {
+                let albedo = if point.x == LINE_POSITION {
+                    RgbColor::from_color_hex("#e4af00")
+                } else {
+                    RgbColor::from_color_hex("#363636")
+                };
+                Some(Voxel::Solid(SolidVoxel::Lambertian { albedo }))
+            },
+        );
+        grid.fill_with(
+            Point3::new(0, 0, x_offset),
+            Point3::new(ROAD_LENGTH, 1, 20 + x_offset),
+            |point| {
+                let albedo = if point.z == LINE_POSITION {
+                    RgbColor::from_color_hex("#e4af00")
+                } else {
+                    RgbColor::from_color_hex("#363636")
+                };
+                Some(Voxel::Solid(SolidVoxel::Lambertian { albedo }))
+            },
+        );
+    }
+    (grid, building0)
+}
+fn city_world(objects: Vec<Object>) -> WorldInfo {
     let origin = Point3::<RayScalar>::new(500.0, 100.0, -400.0);
 
     let look_at = Point3::new(50.0, 0.0, 0.0);
@@ -829,41 +940,8 @@ pub fn city() -> WorldInfo {
         let t = look_at - origin;
         (t.dot(t)).sqrt()
     };
-    let building0 =
-        VoxelGrid::load_vox("./voxel_assets/city/skyscraper0.vox").expect("failed to load");
-    let mut grid = VoxelGrid::new();
-    for i in 0..NUMBER_GRIDS {
-        let x_offset = i * (CELL_SIZE + ROAD_WIDTH);
-        for point in IterBox::from_xyz(ROAD_WIDTH + x_offset, 1, ROAD_LENGTH)
-            .start_xyz(x_offset, 0, 0)
-            .iter()
-        {
-            let albedo = if point.x == LINE_POSITION {
-                RgbColor::from_color_hex("#e4af00")
-            } else {
-                RgbColor::from_color_hex("#363636")
-            };
-            grid.set(Voxel::Solid(SolidVoxel::Lambertian { albedo }), point);
-        }
-        let x_offset = i * (CELL_SIZE + ROAD_WIDTH);
-        for point in IterBox::from_xyz(ROAD_LENGTH, 1, 20 + x_offset)
-            .start_xyz(0, 0, x_offset)
-            .iter()
-        {
-            let albedo = if point.z == LINE_POSITION {
-                RgbColor::from_color_hex("#e4af00")
-            } else {
-                RgbColor::from_color_hex("#363636")
-            };
-            grid.set(Voxel::Solid(SolidVoxel::Lambertian { albedo }), point);
-        }
-    }
-    let grid = grid.combine(
-        &building0,
-        Vector3::new(ROAD_WIDTH as i32, 1, ROAD_WIDTH as i32),
-    );
     WorldInfo {
-        objects: vec![Object::new(Box::new(grid), Transform::identity())],
+        objects,
         lights: vec![],
         background: Box::new(Sky { intensity: 0.6 }),
         camera: Camera::new(CameraInfo {
@@ -876,8 +954,37 @@ pub fn city() -> WorldInfo {
             focus_distance,
             start_time: 0.0,
             end_time: 0.0,
+            ..Default::default()
         }),
 
         sun: None,
     }
 }
+pub fn city() -> WorldInfo {
+    let (grid, building0) = city_parts();
+    let grid = grid.combine(
+        &building0,
+        Vector3::new(CITY_ROAD_WIDTH as i32, 1, CITY_ROAD_WIDTH as i32),
+    );
+    city_world(vec![Object::new(Box::new(grid), Transform::identity())])
+}
+/// Same roads as the city with a building on every block, the blocks share the building geometry
+pub fn instanced_city() -> WorldInfo {
+    let (grid, building0) = city_parts();
+    let building0: Arc<dyn Hittable + Send> = Arc::new(building0);
+    let mut objects = vec![Object::new(Box::new(grid), Transform::identity())];
+    for i in 0..CITY_NUMBER_GRIDS {
+        for j in 0..CITY_NUMBER_GRIDS {
+            let offset = Vector3::new(
+                (CITY_ROAD_WIDTH + i * (CITY_CELL_SIZE + CITY_ROAD_WIDTH)) as RayScalar,
+                1.0,
+                (CITY_ROAD_WIDTH + j * (CITY_CELL_SIZE + CITY_ROAD_WIDTH)) as RayScalar,
+            );
+            objects.push(Object::instance(
+                building0.clone(),
+                Transform::identity().translate(offset),
+            ));
+        }
+    }
+    city_world(objects)
+}
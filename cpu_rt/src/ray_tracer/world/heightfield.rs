@@ -0,0 +1,84 @@
+use super::{
+    Camera, CameraInfo, DiffuseLight, Heightfield, ImageTexture, Lambertian, Object, Sky,
+    SolidColor, SolidVoxel, Sphere, Transform, WorldInfo,
+};
+use crate::prelude::*;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+/// rolling hills with a ridge through the middle
+fn height(x: RayScalar, z: RayScalar) -> RayScalar {
+    let hills = 0.8 * (0.5 * x).sin() * (0.4 * z).cos() + 0.3 * (1.3 * x + 0.7 * z).sin();
+    let ridge = 2.5 * (-0.08 * (x + 0.5 * z).powi(2)).exp();
+    1.5 + hills + ridge
+}
+/// The same terrain twice, as a smooth heightfield textured with a map on the left and as voxels
+/// on the right
+pub fn heightfield_demo() -> WorldInfo {
+    let look_at = Point3::<RayScalar>::new(0.0, 1.5, 0.0);
+    let origin = Point3::<RayScalar>::new(4.0, 12.0, 22.0);
+    let focus_distance = (look_at - origin).magnitude();
+
+    let smooth = Heightfield::from_fn(
+        Point3::new(-10.0, 0.0, -10.0),
+        Vector3::new(10.0, 4.0, 20.0),
+        129,
+        257,
+        height,
+        Box::new(Lambertian {
+            albedo: Box::new(ImageTexture::new("./assets/earthmap.jpg")),
+        }),
+    );
+    let voxel_terrain = Heightfield::from_fn(
+        Point3::new(0.0, 0.0, -10.0),
+        Vector3::new(10.0, 4.0, 20.0),
+        129,
+        257,
+        height,
+        Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.4, 0.6, 0.3),
+            }),
+        }),
+    );
+    let (voxels, voxel_transform) = voxel_terrain.to_voxel_grid(
+        0.25,
+        SolidVoxel::Lambertian {
+            albedo: RgbColor::new(0.4, 0.6, 0.3),
+        },
+    );
+    let sun = Object::new(
+        Box::new(Sphere {
+            radius: 4.0,
+            origin: Point3::new(-20.0, 30.0, 10.0),
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: 15.0 * RgbColor::WHITE,
+                }),
+            }),
+        }),
+        Transform::identity(),
+    );
+    WorldInfo {
+        objects: vec![
+            Object::new(Box::new(smooth), Transform::identity()),
+            Object::new(Box::new(voxels), voxel_transform),
+            sun.clone(),
+        ],
+        lights: vec![sun],
+        background: Box::new(Sky { intensity: 0.4 }),
+        camera: Camera::new(CameraInfo {
+            aspect_ratio: 1.0,
+            fov: 45.0,
+            origin,
+            look_at,
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance,
+            start_time: 0.0,
+            end_time: 0.0,
+            ..Default::default()
+        }),
+        sun: None,
+    }
+}
@@ -0,0 +1,123 @@
+use super::{
+    Camera, CameraInfo, Cone, Cylinder, DiffuseLight, Disk, InfinitePlane, Lambertian, Material,
+    Metal, Object, Quad, Sky, SolidColor, Torus, Transform, WorldInfo,
+};
+use crate::prelude::*;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+fn lambertian(color: RgbColor) -> Box<dyn Material> {
+    Box::new(Lambertian {
+        albedo: Box::new(SolidColor { color }),
+    })
+}
+fn light(intensity: f32) -> Box<dyn Material> {
+    Box::new(DiffuseLight {
+        emit: Box::new(SolidColor {
+            color: intensity * RgbColor::WHITE,
+        }),
+    })
+}
+/// every analytic primitive on a ground plane, lit by a tilted quad and a disk
+pub fn primitive_demo() -> WorldInfo {
+    let look_at = Point3::<RayScalar>::new(0.0, 0.7, 0.0);
+    let origin = Point3::<RayScalar>::new(9.0, 5.0, 4.0);
+    let focus_distance = (look_at - origin).magnitude();
+
+    let ground = Object::new(
+        Box::new(InfinitePlane {
+            point: Point3::new(0.0, 0.0, 0.0),
+            normal: Vector3::unit_y(),
+            material: lambertian(RgbColor::new(0.5, 0.5, 0.5)),
+        }),
+        Transform::identity(),
+    );
+    // faces down and towards the scene
+    let quad_light = Object::new(
+        Box::new(Quad {
+            corner: Point3::new(-2.0, 5.0, -3.0),
+            u: Vector3::new(1.5, 0.8, 0.0),
+            v: Vector3::new(0.0, 0.0, 2.5),
+            material: light(6.0),
+        }),
+        Transform::identity(),
+    );
+    let disk_light = Object::new(
+        Box::new(Disk {
+            center: Point3::new(-1.0, 2.8, 3.2),
+            normal: Vector3::new(1.0, -2.3, -2.7),
+            radius: 0.6,
+            material: light(6.0),
+        }),
+        Transform::identity(),
+    );
+    let cylinder = Object::new(
+        Box::new(Cylinder {
+            base: Point3::new(-1.5, 0.0, 1.5),
+            axis: Vector3::new(0.0, 1.4, 0.0),
+            radius: 0.5,
+            material: lambertian(RgbColor::new(0.8, 0.3, 0.2)),
+        }),
+        Transform::identity(),
+    );
+    let cone = Object::new(
+        Box::new(Cone {
+            base: Point3::new(-1.5, 0.0, -1.2),
+            axis: Vector3::new(0.0, 1.6, 0.0),
+            radius: 0.6,
+            material: lambertian(RgbColor::new(0.2, 0.6, 0.3)),
+        }),
+        Transform::identity(),
+    );
+    // leaning on its side
+    let torus = Object::new(
+        Box::new(Torus {
+            center: Point3::new(0.8, 0.5, 0.2),
+            axis: Vector3::new(1.0, 0.6, 0.2),
+            major_radius: 0.6,
+            minor_radius: 0.2,
+            material: Box::new(Metal {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::new(0.8, 0.7, 0.3),
+                }),
+                fuzz: 0.1,
+            }),
+        }),
+        Transform::identity(),
+    );
+    let tile = Object::new(
+        Box::new(Quad {
+            corner: Point3::new(1.5, 0.001, 1.3),
+            u: Vector3::new(-0.4, 0.0, 1.2),
+            v: Vector3::new(1.2, 0.0, 0.4),
+            material: lambertian(RgbColor::new(0.3, 0.4, 0.8)),
+        }),
+        Transform::identity(),
+    );
+    WorldInfo {
+        objects: vec![
+            ground,
+            quad_light.clone(),
+            disk_light.clone(),
+            cylinder,
+            cone,
+            torus,
+            tile,
+        ],
+        lights: vec![quad_light, disk_light],
+        background: Box::new(Sky { intensity: 0.2 }),
+        camera: Camera::new(CameraInfo {
+            aspect_ratio: 1.0,
+            fov: 35.0,
+            origin,
+            look_at,
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance,
+            start_time: 0.0,
+            end_time: 0.0,
+            ..Default::default()
+        }),
+        sun: None,
+    }
+}
@@ -63,11 +63,13 @@ pub fn random_scene() -> WorldInfo {
     let objects = (-11..11)
         .flat_map(|a| {
             (-11..11).filter_map::<Object, _>(move |b| {
-                let choose_mat = rand::random::<RayScalar>();
+                // each cell gets its own sampler so the scene is the same every time
+                let mut sampler = Sampler::new(((a + 11) * 22 + b + 11) as u64);
+                let choose_mat = sampler.next_scalar();
                 let center = Point3::new(
-                    a as RayScalar + 0.9 * rand::random::<RayScalar>(),
+                    a as RayScalar + 0.9 * sampler.next_scalar(),
                     0.2,
-                    b as RayScalar + 0.9 * rand::random::<RayScalar>(),
+                    b as RayScalar + 0.9 * sampler.next_scalar(),
                 );
                 let check = center - Point3::new(4.0, 0.2, 0.0);
                 if check.dot(check).sqrt() > 0.9 {
@@ -76,12 +78,12 @@ pub fn random_scene() -> WorldInfo {
                             Box::new(MovingSphere {
                                 radius: 0.2,
                                 center_0: center,
-                                center_1: center + Vector3::new(0.0, rand_scalar(0.0, 0.5), 0.0),
+                                center_1: center + Vector3::new(0.0, sampler.scalar(0.0, 0.5), 0.0),
                                 time_0: 0.0,
                                 time_1: 1.0,
                                 material: Box::new(Lambertian {
                                     albedo: Box::new(SolidColor {
-                                        color: RgbColor::random(),
+                                        color: RgbColor::random(&mut sampler),
                                     }),
                                 }),
                             }),
@@ -94,9 +96,9 @@ pub fn random_scene() -> WorldInfo {
                                 origin: center,
                                 material: Box::new(Metal {
                                     albedo: Box::new(SolidColor {
-                                        color: RgbColor::random(),
+                                        color: RgbColor::random(&mut sampler),
                                     }),
-                                    fuzz: rand::random::<RayScalar>() * 0.5 + 0.5,
+                                    fuzz: sampler.next_scalar() * 0.5 + 0.5,
                                 }),
                             }),
                             Transform::identity(),
@@ -136,6 +138,7 @@ pub fn random_scene() -> WorldInfo {
             focus_distance: 10.,
             start_time: 0.0,
             end_time: 0.,
+            ..Default::default()
         }),
         sun: None,
     }
@@ -0,0 +1,157 @@
+use super::{
+    Aabb, Camera, CameraInfo, DiffuseLight, Lambertian, Material, Metal, Object, Sdf, SdfShape,
+    Sky, SolidColor, Sphere, Transform, WorldInfo, XZRect,
+};
+use crate::prelude::*;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+fn lambertian(color: RgbColor) -> Box<dyn Material> {
+    Box::new(Lambertian {
+        albedo: Box::new(SolidColor { color }),
+    })
+}
+/// shape in a box of `half_size` around `center`
+fn shape(
+    sdf: Sdf,
+    center: Point3<RayScalar>,
+    half_size: RayScalar,
+    material: Box<dyn Material>,
+) -> SdfShape {
+    let half_size = Vector3::new(half_size, half_size, half_size);
+    SdfShape::new(
+        sdf.translate(center.to_vec()),
+        Aabb {
+            minimum: center - half_size,
+            maximum: center + half_size,
+        },
+        material,
+    )
+}
+/// a blob, a twisted column, a torus with capsules and a mandelbulb
+pub fn sdf_demo() -> WorldInfo {
+    let look_at = Point3::<RayScalar>::new(0.0, 0.8, 0.0);
+    let origin = Point3::<RayScalar>::new(10.0, 5.0, 3.0);
+    let focus_distance = (look_at - origin).magnitude();
+
+    let floor = Object::new(
+        Box::new(XZRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            lambertian(RgbColor::new(0.5, 0.5, 0.5)),
+            false,
+        )),
+        Transform::identity(),
+    );
+    let light = Object::new(
+        Box::new(Sphere {
+            radius: 1.5,
+            origin: Point3::new(3.0, 6.0, -4.0),
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: 8.0 * RgbColor::WHITE,
+                }),
+            }),
+        }),
+        Transform::identity(),
+    );
+    let blob = Sdf::Sphere { radius: 0.5 }
+        .smooth_union(
+            Sdf::Sphere { radius: 0.35 }.translate(Vector3::new(0.0, 0.5, 0.3)),
+            0.3,
+        )
+        .smooth_subtraction(
+            Sdf::Cylinder {
+                radius: 0.15,
+                half_height: 1.0,
+            },
+            0.1,
+        );
+    let blob = shape(
+        blob,
+        Point3::new(0.0, 0.6, 1.8),
+        1.0,
+        lambertian(RgbColor::new(0.8, 0.3, 0.2)),
+    );
+    let column = Sdf::Cuboid {
+        half_size: Vector3::new(0.4, 0.8, 0.15),
+    }
+    .twist(1.5);
+    let column = shape(
+        column,
+        Point3::new(-1.4, 0.8, 0.0),
+        0.9,
+        lambertian(RgbColor::new(0.9, 0.9, 0.8)),
+    )
+    .with_step_scale(0.7);
+    let ring = Sdf::Torus {
+        major_radius: 0.6,
+        minor_radius: 0.12,
+    }
+    .smooth_union(
+        Sdf::Capsule {
+            start: Point3::new(0.0, 0.0, -0.1),
+            end: Point3::new(0.0, 0.0, 0.1),
+            radius: 0.1,
+        }
+        .repeat(Vector3::new(0.4, 0.0, 0.4))
+        .smooth_intersection(
+            Sdf::Cylinder {
+                radius: 0.5,
+                half_height: 0.2,
+            },
+            0.05,
+        ),
+        0.1,
+    );
+    let ring = shape(
+        ring,
+        Point3::new(1.6, 0.2, 0.2),
+        0.8,
+        Box::new(Metal {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.8, 0.7, 0.3),
+            }),
+            fuzz: 0.2,
+        }),
+    );
+    let bulb = shape(
+        Sdf::Mandelbulb {
+            power: 8.0,
+            iterations: 8,
+        },
+        Point3::new(0.0, 1.15, -1.8),
+        1.2,
+        lambertian(RgbColor::new(0.3, 0.5, 0.8)),
+    )
+    .with_epsilon(0.001)
+    .with_step_scale(0.8);
+    WorldInfo {
+        objects: vec![
+            floor,
+            light.clone(),
+            Object::new(Box::new(blob), Transform::identity()),
+            Object::new(Box::new(column), Transform::identity()),
+            Object::new(Box::new(ring), Transform::identity()),
+            Object::new(Box::new(bulb), Transform::identity()),
+        ],
+        lights: vec![light],
+        background: Box::new(Sky { intensity: 0.2 }),
+        camera: Camera::new(CameraInfo {
+            aspect_ratio: 1.0,
+            fov: 35.0,
+            origin,
+            look_at,
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance,
+            start_time: 0.0,
+            end_time: 0.0,
+            ..Default::default()
+        }),
+        sun: None,
+    }
+}
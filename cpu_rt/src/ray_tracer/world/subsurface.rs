@@ -0,0 +1,111 @@
+use super::{
+    Camera, CameraInfo, DiffuseLight, Lambertian, Object, PhaseFunction, Sky, SolidColor, Sphere,
+    Subsurface, SubsurfaceVolume, Transform, WorldInfo, XZRect,
+};
+use crate::prelude::*;
+
+use cgmath::{prelude::*, Point3, Vector3};
+
+/// marble, wax and a translucent plastic lit from behind by a large light
+pub fn subsurface_demo() -> WorldInfo {
+    let look_at = Point3::<RayScalar>::new(0.0, 0.7, 0.0);
+    let origin = Point3::<RayScalar>::new(10.0, 6.0, 2.0);
+    let focus_distance = (look_at - origin).magnitude();
+
+    let floor = Object::new(
+        Box::new(XZRect::new(
+            -10.0,
+            10.0,
+            -10.0,
+            10.0,
+            0.0,
+            Box::new(Lambertian {
+                albedo: Box::new(SolidColor {
+                    color: RgbColor::new(0.5, 0.5, 0.5),
+                }),
+            }),
+            false,
+        )),
+        Transform::identity(),
+    );
+    let light = Object::new(
+        Box::new(Sphere {
+            radius: 1.5,
+            origin: Point3::new(-3.0, 4.0, 0.0),
+            material: Box::new(DiffuseLight {
+                emit: Box::new(SolidColor {
+                    color: 8.0 * RgbColor::WHITE,
+                }),
+            }),
+        }),
+        Transform::identity(),
+    );
+    let sphere = |z: RayScalar, material: Subsurface| {
+        Object::new(
+            Box::new(SubsurfaceVolume::new(
+                Box::new(Sphere {
+                    radius: 0.7,
+                    origin: Point3::new(0.0, 0.7, z),
+                    material: Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: RgbColor::WHITE,
+                        }),
+                    }),
+                }),
+                material,
+            )),
+            Transform::identity(),
+        )
+    };
+    let marble = sphere(
+        -1.6,
+        Subsurface {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.99, 0.98, 0.96),
+            }),
+            mean_free_path: 0.02,
+            index_refraction: 1.5,
+            phase_function: PhaseFunction::Isotropic,
+        },
+    );
+    let wax = sphere(
+        0.0,
+        Subsurface {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.98, 0.85, 0.6),
+            }),
+            mean_free_path: 0.1,
+            index_refraction: 1.45,
+            phase_function: PhaseFunction::HenyeyGreenstein { g: 0.3 },
+        },
+    );
+    let plastic = sphere(
+        1.6,
+        Subsurface {
+            albedo: Box::new(SolidColor {
+                color: RgbColor::new(0.5, 0.8, 0.98),
+            }),
+            mean_free_path: 0.05,
+            index_refraction: 1.5,
+            phase_function: PhaseFunction::HenyeyGreenstein { g: 0.6 },
+        },
+    );
+    WorldInfo {
+        objects: vec![floor, light.clone(), marble, wax, plastic],
+        lights: vec![light],
+        background: Box::new(Sky { intensity: 0.2 }),
+        camera: Camera::new(CameraInfo {
+            aspect_ratio: 1.0,
+            fov: 25.0,
+            origin,
+            look_at,
+            up_vector: Vector3::unit_y(),
+            aperture: 0.00001,
+            focus_distance,
+            start_time: 0.0,
+            end_time: 0.0,
+            ..Default::default()
+        }),
+        sun: None,
+    }
+}
@@ -0,0 +1,176 @@
+//! Renders every scenario at a low resolution with a fixed seed and compares it against the
+//! reference images in `tests/reference_images`. After a change that is meant to change the
+//! renders the references are replaced with the current renders by running
+//!
+//! ```text
+//! BLESS_REFERENCES=1 cargo test -p lib_minya --release --test reference_images -- --include-ignored
+//! ```
+//!
+//! When a render does not match, the render and an image of the difference are written to
+//! `target/tmp/reference_images`
+use image::{Rgb, RgbImage};
+use lib_minya::{prelude::ParallelImage, ray_tracer::RayTracer};
+use std::path::{Path, PathBuf};
+
+const SIZE: usize = 48;
+const NUM_SAMPLES: u32 = 16;
+const SEED: u64 = 0;
+/// Largest allowed root mean square error between the blurred render and reference with colors
+/// in [0, 1]. The blur keeps a few pixels that take a different path, for example because of
+/// floating point differences between platforms, from failing the test. Rendering with another
+/// seed gives errors of up to 0.07 so changes to which random numbers are used need a re-bless
+const TOLERANCE: f32 = 0.01;
+/// radius of the box blur applied before comparing
+const BLUR_RADIUS: i32 = 1;
+/// scenarios that take too long to build to check on every test run
+const SLOW_SCENARIOS: [&str; 2] = ["Fast Oct Tree Sinnoh", "Fast Oct Tree Volcano"];
+
+fn reference_directory() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reference_images")
+}
+fn output_directory() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("reference_images")
+}
+/// "Fast Oct Tree 2 density" -> "fast_oct_tree_2_density"
+fn file_name(scenario: &str) -> String {
+    scenario
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+fn render(scenario: &str) -> RgbImage {
+    let ray_tracer = RayTracer::builder()
+        .set_scenario(scenario.to_string())
+        .set_seed(SEED)
+        .build();
+    let mut image = ParallelImage::new_black(SIZE, SIZE);
+    for sample_index in 0..NUM_SAMPLES {
+        ray_tracer.trace_image(&mut image, sample_index);
+    }
+    image.to_image(NUM_SAMPLES as usize)
+}
+fn blur(image: &RgbImage) -> Vec<[f32; 3]> {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let mut out = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for dy in -BLUR_RADIUS..=BLUR_RADIUS {
+                for dx in -BLUR_RADIUS..=BLUR_RADIUS {
+                    let (px, py) = (x + dx, y + dy);
+                    if px < 0 || py < 0 || px >= width || py >= height {
+                        continue;
+                    }
+                    let pixel = image.get_pixel(px as u32, py as u32);
+                    for (s, channel) in sum.iter_mut().zip(pixel.0.iter()) {
+                        *s += *channel as f32 / 255.0;
+                    }
+                    count += 1.0;
+                }
+            }
+            out.push(sum.map(|s| s / count));
+        }
+    }
+    out
+}
+fn root_mean_square_error(render: &RgbImage, reference: &RgbImage) -> f32 {
+    let render = blur(render);
+    let reference = blur(reference);
+    let squared_error = render
+        .iter()
+        .zip(reference.iter())
+        .flat_map(|(a, b)| a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)))
+        .sum::<f32>();
+    (squared_error / (3 * render.len()) as f32).sqrt()
+}
+/// absolute difference of the images made four times brighter
+fn difference_image(render: &RgbImage, reference: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(render.width(), render.height(), |x, y| {
+        let a = render.get_pixel(x, y).0;
+        let b = reference.get_pixel(x, y).0;
+        Rgb([0, 1, 2].map(|i| (a[i].abs_diff(b[i]) as u32 * 4).min(255) as u8))
+    })
+}
+/// Renders the scenarios and compares them against the references, or replaces the references
+/// if `BLESS_REFERENCES` is set
+fn check_scenarios(filter: impl Fn(&str) -> bool) {
+    // scenarios load their assets relative to the root of the repository
+    std::env::set_current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(".."))
+        .expect("failed to move to repository root");
+    let bless = std::env::var_os("BLESS_REFERENCES").is_some();
+    let mut scenarios = RayTracer::builder()
+        .build()
+        .get_info()
+        .scenarios
+        .into_iter()
+        .map(|scenario| scenario.name)
+        .filter(|name| filter(name))
+        .collect::<Vec<_>>();
+    scenarios.sort();
+    if bless {
+        std::fs::create_dir_all(reference_directory()).expect("failed to create directory");
+    }
+    let mut failures = vec![];
+    for scenario in scenarios {
+        let name = file_name(&scenario);
+        let reference_path = reference_directory().join(format!("{}.png", name));
+        let image = render(&scenario);
+        if bless {
+            image
+                .save(&reference_path)
+                .expect("failed to save reference");
+            continue;
+        }
+        let reference = match image::open(&reference_path) {
+            Ok(reference) => reference.into_rgb8(),
+            Err(e) => {
+                failures.push(format!(
+                    "{}: failed to load reference {:?}, reason: {}",
+                    scenario, reference_path, e
+                ));
+                continue;
+            }
+        };
+        if reference.dimensions() != image.dimensions() {
+            failures.push(format!(
+                "{}: reference is {:?} but render is {:?}",
+                scenario,
+                reference.dimensions(),
+                image.dimensions()
+            ));
+            continue;
+        }
+        let error = root_mean_square_error(&image, &reference);
+        if error > TOLERANCE {
+            std::fs::create_dir_all(output_directory()).expect("failed to create directory");
+            let render_path = output_directory().join(format!("{}.png", name));
+            let difference_path = output_directory().join(format!("{}_difference.png", name));
+            image.save(&render_path).expect("failed to save render");
+            difference_image(&image, &reference)
+                .save(&difference_path)
+                .expect("failed to save difference");
+            failures.push(format!(
+                "{}: error {} is above {}, render saved to {:?} and difference to {:?}",
+                scenario, error, TOLERANCE, render_path, difference_path
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "renders do not match the reference images, if the change is intended run with \
+         BLESS_REFERENCES=1 to update them\n{}",
+        failures.join("\n")
+    );
+}
+#[test]
+fn reference_images() {
+    check_scenarios(|name| !SLOW_SCENARIOS.contains(&name));
+}
+#[test]
+#[ignore = "building the scenarios takes minutes"]
+fn slow_reference_images() {
+    check_scenarios(|name| SLOW_SCENARIOS.contains(&name));
+}
@@ -32,6 +32,9 @@ impl GuiCtx {
         for message in self.receiver_messages.try_iter() {
             match message {
                 GuiSendMessage::UpdateRayTracerInfo(info) => self.state.set_ray_tracer_info(info),
+                GuiSendMessage::UpdateTileProgress(progress) => {
+                    self.state.set_tile_progress(progress)
+                }
             }
         }
         self.egui_mq.run(ctx, |_mq_ctx, egui_ctx| {
@@ -51,6 +54,7 @@ impl GuiCtx {
                 });
             egui::TopBottomPanel::bottom("play pause").show(egui_ctx, |ui| {
                 let _pressed = ui.button("PAUSE").changed();
+                self.state.render_progress(ui);
             });
         });
     }
@@ -4,6 +4,7 @@ use crate::messages::GuiPushMessage;
 use cgmath::{Point3, Vector3};
 use lib_minya::ray_tracer::{
     ray_tracer_info::{Entity, EntityField, EntityInfo, RayTracerInfo},
+    tile_scheduler::TileProgress,
     LogMessage,
 };
 
@@ -12,6 +13,7 @@ use std::sync::mpsc::Sender;
 pub struct GuiState {
     info: RayTracerInfo,
     message_chanel: Sender<GuiPushMessage>,
+    tile_progress: Vec<TileProgress>,
 }
 impl GuiState {
     pub fn new(info: &RayTracerInfo, message_chanel: Sender<GuiPushMessage>) -> Self {
@@ -20,11 +22,89 @@ impl GuiState {
         Self {
             info,
             message_chanel,
+            tile_progress: Vec::new(),
         }
     }
     pub fn set_ray_tracer_info(&mut self, info: RayTracerInfo) {
         self.info = info;
     }
+    pub fn set_tile_progress(&mut self, tile_progress: Vec<TileProgress>) {
+        self.tile_progress = tile_progress;
+    }
+    /// shows the number of samples in each tile, tiles being rendered are outlined
+    pub fn render_progress(&mut self, ui: &mut egui::Ui) {
+        const MAP_SIZE: f32 = 100.0;
+        if self.tile_progress.is_empty() {
+            ui.label("no tiles rendered yet");
+            return;
+        }
+        let min_samples = self
+            .tile_progress
+            .iter()
+            .map(|progress| progress.num_samples)
+            .min()
+            .unwrap();
+        let max_samples = self
+            .tile_progress
+            .iter()
+            .map(|progress| progress.num_samples)
+            .max()
+            .unwrap();
+        let active = self
+            .tile_progress
+            .iter()
+            .filter(|progress| progress.active)
+            .count();
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "samples: {} - {}, active tiles: {}",
+                min_samples, max_samples, active
+            ));
+            let image_width = self
+                .tile_progress
+                .iter()
+                .map(|progress| progress.tile.offset.x + progress.tile.width)
+                .max()
+                .unwrap() as f32;
+            let image_height = self
+                .tile_progress
+                .iter()
+                .map(|progress| progress.tile.offset.y + progress.tile.height)
+                .max()
+                .unwrap() as f32;
+            let scale = MAP_SIZE / image_width.max(image_height);
+            let (response, painter) = ui.allocate_painter(
+                egui::vec2(image_width * scale, image_height * scale),
+                egui::Sense::hover(),
+            );
+            let origin = response.rect.min;
+            for progress in self.tile_progress.iter() {
+                let tile = progress.tile;
+                // the image is stored bottom up
+                let rect = egui::Rect::from_min_size(
+                    origin
+                        + egui::vec2(
+                            tile.offset.x as f32 * scale,
+                            (image_height - (tile.offset.y + tile.height) as f32) * scale,
+                        ),
+                    egui::vec2(tile.width as f32 * scale, tile.height as f32 * scale),
+                );
+                let brightness = if max_samples > 0 {
+                    progress.num_samples as f32 / max_samples as f32
+                } else {
+                    0.0
+                };
+                painter.rect_filled(rect, 0.0, egui::Rgba::from_gray(0.8 * brightness));
+                if progress.active {
+                    painter.rect_stroke(
+                        rect,
+                        0.0,
+                        egui::Stroke::new(1.0, egui::Rgba::from_rgb(1.0, 0.6, 0.1)),
+                    );
+                }
+            }
+        });
+    }
 
     pub fn entity_side_bar(&mut self, ui: &mut egui::Ui) {
         egui::CollapsingHeader::new("Main Camera")
@@ -101,8 +181,39 @@ impl GuiState {
                                     .expect("failed to send value");
                             }
                         }
+                        EntityField::Choice { selected, options } => {
+                            let mut value = selected.clone();
+                            egui::ComboBox::from_id_source(format!("camera_{}", field_name))
+                                .selected_text(&value)
+                                .show_ui(ui, |ui| {
+                                    for option in options.iter() {
+                                        ui.selectable_value(&mut value, option.clone(), option);
+                                    }
+                                });
+                            if value != *selected {
+                                let field = EntityField::Choice {
+                                    selected: value,
+                                    options: options.clone(),
+                                };
+                                self.info
+                                    .loaded_entities
+                                    .main_camera
+                                    .set_field(field_name.to_string(), field.clone());
+                                self.message_chanel
+                                    .send(GuiPushMessage::SetCameraData((
+                                        field_name.clone(),
+                                        field,
+                                    )))
+                                    .expect("failed to send value");
+                            }
+                        }
                     }
                 }
+                if ui.button("Autofocus").clicked() {
+                    self.message_chanel
+                        .send(GuiPushMessage::Autofocus)
+                        .expect("failed to send autofocus");
+                }
             });
         ui.separator();
         let entities = self.info.loaded_entities.clone();
@@ -161,6 +272,26 @@ impl GuiState {
                                 update_values.push((field_name.clone(), EntityField::Float(value)));
                             }
                         }
+                        EntityField::Choice { selected, options } => {
+                            ui.label(field_name);
+                            let mut value = selected.clone();
+                            egui::ComboBox::from_id_source(format!("{}_{}", field_name, index))
+                                .selected_text(&value)
+                                .show_ui(ui, |ui| {
+                                    for option in options.iter() {
+                                        ui.selectable_value(&mut value, option.clone(), option);
+                                    }
+                                });
+                            if value != *selected {
+                                update_values.push((
+                                    field_name.clone(),
+                                    EntityField::Choice {
+                                        selected: value,
+                                        options: options.clone(),
+                                    },
+                                ));
+                            }
+                        }
                     }
                 }
                 for (field_name, field_value) in update_values {
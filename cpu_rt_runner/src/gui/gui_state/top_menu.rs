@@ -1,6 +1,12 @@
 use super::GuiState;
 use crate::messages::GuiPushMessage;
-use lib_minya::ray_tracer::{CurrentShader, RayTracer};
+use lib_minya::{
+    prelude::SamplerKind,
+    ray_tracer::{
+        filter::{Filter, FilterKind},
+        CurrentShader, RayTracer,
+    },
+};
 use log::{error, info};
 use std::str::FromStr;
 impl GuiState {
@@ -89,6 +95,30 @@ impl GuiState {
                     }
                 }
             });
+            ui.menu_button("Sampler", |ui| {
+                for s in SamplerKind::names() {
+                    if ui.button(&s).clicked() {
+                        self.message_chanel
+                            .send(GuiPushMessage::SetSampler(
+                                SamplerKind::from_str(&s).unwrap(),
+                            ))
+                            .expect("failed to send");
+                    }
+                }
+            });
+            ui.menu_button("Filter", |ui| {
+                for s in FilterKind::names() {
+                    if ui.button(&s).clicked() {
+                        let kind = FilterKind::from_str(&s).unwrap();
+                        self.message_chanel
+                            .send(GuiPushMessage::SetFilter(Filter::new(
+                                kind,
+                                kind.default_radius(),
+                            )))
+                            .expect("failed to send");
+                    }
+                }
+            });
         });
     }
 }
@@ -73,10 +73,23 @@ impl Handler {
                         GuiPushMessage::SetShader(s) => {
                             receiver.set_shader(s);
                         }
+                        GuiPushMessage::SetSampler(s) => {
+                            receiver.set_sampler(s);
+                        }
+                        GuiPushMessage::SetFilter(f) => {
+                            receiver.set_filter(f);
+                        }
 
                         GuiPushMessage::SetCameraData((key, value)) => {
                             receiver.set_camera_data(key, value);
                         }
+                        GuiPushMessage::Autofocus => {
+                            receiver.autofocus();
+                            par_img = ParallelImage::new_black(1000, 1000);
+                            sender
+                                .send(GuiSendMessage::UpdateRayTracerInfo(receiver.get_info()))
+                                .expect("failed to send message to gui");
+                        }
                         GuiPushMessage::SetEntityInfo {
                             entity_index,
                             field_name,
@@ -103,6 +116,9 @@ impl Handler {
 
                 if let Some(img) = receiver.receive() {
                     par_img = img;
+                    sender
+                        .send(GuiSendMessage::UpdateTileProgress(receiver.tile_progress()))
+                        .expect("failed to send message to gui");
                 }
                 let mut process_image = par_img.clone();
 
@@ -1,6 +1,11 @@
-use lib_minya::ray_tracer::{
-    ray_tracer_info::{EntityField, RayTracerInfo},
-    CurrentShader,
+use lib_minya::{
+    prelude::SamplerKind,
+    ray_tracer::{
+        filter::Filter,
+        ray_tracer_info::{EntityField, RayTracerInfo},
+        tile_scheduler::TileProgress,
+        CurrentShader,
+    },
 };
 
 /// Messages that the gui sends to the ray tracer.
@@ -9,7 +14,13 @@ pub enum GuiPushMessage {
     LoadScenario(String),
     SaveFile(std::path::PathBuf),
     SetShader(CurrentShader),
+    /// changes how the random numbers used for rendering are picked
+    SetSampler(SamplerKind),
+    /// changes the reconstruction filter used to weight samples of the pixels around them
+    SetFilter(Filter),
     SetCameraData((String, EntityField)),
+    /// focuses the camera on the center of the screen
+    Autofocus,
     SetEntityInfo {
         entity_index: usize,
         field_name: String,
@@ -24,4 +35,6 @@ pub enum GuiPushMessage {
 #[derive(Clone, Debug, PartialEq)]
 pub enum GuiSendMessage {
     UpdateRayTracerInfo(RayTracerInfo),
+    /// samples rendered in each tile
+    UpdateTileProgress(Vec<TileProgress>),
 }